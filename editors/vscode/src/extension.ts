@@ -136,9 +136,10 @@ async function commandExportCurrentPdf(): Promise<void> {
 async function commandPinMain(isPin: boolean): Promise<void> {
     if (!isPin) {
         await client?.sendRequest("workspace/executeCommand", {
-            command: "typst-lsp.doPinMain",
-            arguments: ["detached"],
+            command: "typst-lsp.doUnpinMain",
+            arguments: [],
         });
+        return;
     }
 
     const activeEditor = window.activeTextEditor;
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use std::{fmt, path::PathBuf};
 
 use anyhow::bail;
@@ -7,6 +8,7 @@ use serde::Deserialize;
 use serde_json::{Map, Value};
 use tower_lsp::lsp_types::{
     self, ConfigurationItem, InitializeParams, PositionEncodingKind, Registration, Url,
+    WorkspaceFoldersChangeEvent,
 };
 use tracing::warn;
 
@@ -40,6 +42,8 @@ pub enum ExportPdfMode {
     OnPinnedMainSave,
     OnType,
     OnPinnedMainType,
+    #[serde(rename = "onDocumentClose")]
+    OnClose,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
@@ -50,6 +54,94 @@ pub enum SemanticTokensMode {
     Enable,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Options for logging to a file, in addition to logging to the client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFileOptions {
+    /// If unset, no log file is written. A relative path is resolved against the server's cache
+    /// directory.
+    pub path: Option<PathBuf>,
+    pub level: LogLevel,
+}
+
+/// Which fonts to load
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FontOptions {
+    /// Skip the fonts bundled with the server, so output matches the Typst CLI run with only
+    /// system fonts
+    pub ignore_embedded: bool,
+    pub ignore_system: bool,
+}
+
+/// Options for exporting pages as PNG images
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportPngOptions {
+    /// Resolution in pixels per inch. Typst measures in points, 72 to an inch.
+    pub ppi: f32,
+}
+
+impl Default for ExportPngOptions {
+    fn default() -> Self {
+        Self { ppi: 144.0 }
+    }
+}
+
+/// How the formatter indents lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatterIndent {
+    /// Indent with this many spaces
+    Spaces(usize),
+    /// Indent with tabs
+    Tabs,
+}
+
+/// Overrides for the formatter's settings. Unset options fall back to the project's
+/// `typstfmt.toml`, then to the editor's formatting options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FormatterOptions {
+    pub line_width: Option<usize>,
+    pub indent: Option<FormatterIndent>,
+}
+
+/// How to download packages from the package registry. Read once, when the server starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageDownloadOptions {
+    pub timeout_secs: u64,
+    /// Proxy for all requests. If unset, the system's proxy configuration is used.
+    pub proxy: Option<String>,
+    /// Base URL of the package registry, if not the official one
+    pub registry_url: Option<String>,
+    /// Namespaces besides `preview` to download from a custom registry
+    pub registry_namespaces: Vec<String>,
+    /// Never download packages, only using those already on this machine
+    pub offline: bool,
+}
+
+impl Default for PackageDownloadOptions {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            proxy: None,
+            registry_url: None,
+            registry_namespaces: Vec::new(),
+            offline: false,
+        }
+    }
+}
+
+pub const DEFAULT_ON_TYPE_DEBOUNCE_MS: u64 = 200;
+pub const DEFAULT_CACHE_EVICTION_AGE: usize = 30;
+
 pub type Listener<T> = Box<dyn FnMut(&T) -> BoxFuture<anyhow::Result<()>> + Send + Sync>;
 
 const CONFIG_ITEMS: &[&str] = &[
@@ -57,17 +149,82 @@ const CONFIG_ITEMS: &[&str] = &[
     "rootPath",
     "semanticTokens",
     "experimentalFormatterMode",
+    "completion.prioritize",
+    "profileTypstThread",
+    "diagnostics.publishIntervalMs",
+    "exportPng",
+    "onTypeFormatting",
+    "exportOutputPath",
+    "packages",
+    "packageRegistryUrl",
+    "packageRegistryNamespaces",
+    "offline",
+    "localPackageRoots",
+    "onTypeDebounceMs",
+    "formatterLineWidth",
+    "formatterIndent",
+    "lintUnused",
+    "cacheEvictionAge",
+    "sourceEncodingFallback",
+    "mainFile",
+    "logFile",
+    "logLevel",
+    "ignoreEmbeddedFonts",
+    "ignoreSystemFonts",
+    "ignoreUnknownFonts",
+    "preview",
 ];
 
 #[derive(Default)]
 pub struct Config {
     pub main_file: Option<Url>,
+    /// The main file last given by the `mainFile` setting. The setting only pins the main file
+    /// when it changes, so resending the same settings doesn't undo pinning with the command.
+    configured_main_file: Option<Url>,
     pub export_pdf: ExportPdfMode,
+    /// As configured, so it may be relative. See [`Config::resolved_root_path`].
     pub root_path: Option<PathBuf>,
+    /// Roots of the workspace folders, in the order the client gave them
+    workspace_roots: Vec<Url>,
     pub semantic_tokens: SemanticTokensMode,
     pub formatter: ExperimentalFormatterMode,
+    pub formatter_options: FormatterOptions,
+    /// Completion labels or kinds (e.g. `figure`, `function`) to list before other completions
+    pub completion_priorities: Vec<String>,
+    pub profile_typst_thread: bool,
+    /// Minimum time between publishing diagnostics. `0` publishes after every compile.
+    pub diagnostics_publish_interval_ms: u64,
+    pub export_png: ExportPngOptions,
+    /// Where to write exported PDFs, as a template with `{name}` and `{dir}` placeholders. If unset,
+    /// PDFs are written next to their source.
+    pub export_output_path: Option<String>,
+    /// Whether to adjust indentation and close math as the user types
+    pub on_type_formatting: bool,
+    pub package_downloads: PackageDownloadOptions,
+    /// Extra directories to find packages in, laid out like the user's `typst/packages` directory
+    pub local_package_roots: Vec<PathBuf>,
+    /// How long to wait after the last edit before compiling in the `onType` export modes. If
+    /// unset, [`DEFAULT_ON_TYPE_DEBOUNCE_MS`] is used.
+    pub on_type_debounce_ms: Option<u64>,
+    /// Whether to warn about unused top-level bindings and imports in compiled files
+    pub lint_unused: bool,
+    /// Whether to leave out warnings about font families which aren't among the loaded fonts
+    pub ignore_unknown_fonts: bool,
+    /// How many compiles Typst's cached results survive without being used. If unset,
+    /// [`DEFAULT_CACHE_EVICTION_AGE`] is used.
+    pub cache_eviction_age: Option<usize>,
+    /// Whether to read local sources which aren't UTF-8 as Latin-1. Read once, when the server
+    /// starts.
+    pub source_encoding_fallback: bool,
+    pub log_file: LogFileOptions,
+    pub fonts: FontOptions,
+    /// Whether to serve a live preview of compiled documents on a local port
+    pub preview: bool,
     semantic_tokens_listeners: Vec<Listener<SemanticTokensMode>>,
     formatter_listeners: Vec<Listener<ExperimentalFormatterMode>>,
+    profile_typst_thread_listeners: Vec<Listener<bool>>,
+    log_file_listeners: Vec<Listener<LogFileOptions>>,
+    preview_listeners: Vec<Listener<bool>>,
 }
 
 impl Config {
@@ -105,6 +262,18 @@ impl Config {
         self.formatter_listeners.push(listener);
     }
 
+    pub fn listen_profile_typst_thread(&mut self, listener: Listener<bool>) {
+        self.profile_typst_thread_listeners.push(listener);
+    }
+
+    pub fn listen_log_file(&mut self, listener: Listener<LogFileOptions>) {
+        self.log_file_listeners.push(listener);
+    }
+
+    pub fn listen_preview(&mut self, listener: Listener<bool>) {
+        self.preview_listeners.push(listener);
+    }
+
     pub async fn update(&mut self, update: &Value) -> anyhow::Result<()> {
         if let Value::Object(update) = update {
             self.update_by_map(update).await
@@ -114,6 +283,8 @@ impl Config {
     }
 
     pub async fn update_by_map(&mut self, update: &Map<String, Value>) -> anyhow::Result<()> {
+        let update = &Self::unwrap_section(update);
+
         let export_pdf = update
             .get("exportPdf")
             .map(ExportPdfMode::deserialize)
@@ -122,6 +293,17 @@ impl Config {
             self.export_pdf = export_pdf;
         }
 
+        let export_output_path = update.get("exportOutputPath");
+        if let Some(export_output_path) = export_output_path {
+            if export_output_path.is_null() {
+                self.export_output_path = None;
+            }
+            if let Some(export_output_path) = export_output_path.as_str() {
+                self.export_output_path =
+                    Some(export_output_path.to_owned()).filter(|path| !path.is_empty());
+            }
+        }
+
         let root_path = update.get("rootPath");
         if let Some(root_path) = root_path {
             if root_path.is_null() {
@@ -154,10 +336,290 @@ impl Config {
             self.formatter = formatter;
         }
 
+        let completion_priorities = Self::get_nested(update, "completion.prioritize")
+            .map(Vec::<String>::deserialize)
+            .and_then(Result::ok);
+        if let Some(completion_priorities) = completion_priorities {
+            self.completion_priorities = completion_priorities;
+        }
+
+        let profile_typst_thread = update
+            .get("profileTypstThread")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(profile_typst_thread) = profile_typst_thread {
+            for listener in &mut self.profile_typst_thread_listeners {
+                listener(&profile_typst_thread).await?;
+            }
+            self.profile_typst_thread = profile_typst_thread;
+        }
+
+        let diagnostics_publish_interval_ms =
+            Self::get_nested(update, "diagnostics.publishIntervalMs")
+                .map(u64::deserialize)
+                .and_then(Result::ok);
+        if let Some(diagnostics_publish_interval_ms) = diagnostics_publish_interval_ms {
+            self.diagnostics_publish_interval_ms = diagnostics_publish_interval_ms;
+        }
+
+        let export_png_ppi = Self::get_nested(update, "exportPng.ppi")
+            .map(f32::deserialize)
+            .and_then(Result::ok)
+            .filter(|ppi| ppi.is_finite() && *ppi > 0.0);
+        if let Some(export_png_ppi) = export_png_ppi {
+            self.export_png.ppi = export_png_ppi;
+        }
+
+        let on_type_formatting = update
+            .get("onTypeFormatting")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(on_type_formatting) = on_type_formatting {
+            self.on_type_formatting = on_type_formatting;
+        }
+
+        let lint_unused = update
+            .get("lintUnused")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(lint_unused) = lint_unused {
+            self.lint_unused = lint_unused;
+        }
+
+        let ignore_unknown_fonts = update
+            .get("ignoreUnknownFonts")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(ignore_unknown_fonts) = ignore_unknown_fonts {
+            self.ignore_unknown_fonts = ignore_unknown_fonts;
+        }
+
+        let package_timeout_secs = Self::get_nested(update, "packages.timeoutSecs")
+            .map(u64::deserialize)
+            .and_then(Result::ok)
+            .filter(|timeout_secs| *timeout_secs > 0);
+        if let Some(package_timeout_secs) = package_timeout_secs {
+            self.package_downloads.timeout_secs = package_timeout_secs;
+        }
+
+        let package_proxy = Self::get_nested(update, "packages.proxy");
+        if let Some(package_proxy) = package_proxy {
+            if package_proxy.is_null() {
+                self.package_downloads.proxy = None;
+            }
+            if let Some(package_proxy) = package_proxy.as_str() {
+                self.package_downloads.proxy =
+                    Some(package_proxy.to_owned()).filter(|proxy| !proxy.is_empty());
+            }
+        }
+
+        let package_registry_url = update.get("packageRegistryUrl");
+        if let Some(package_registry_url) = package_registry_url {
+            if package_registry_url.is_null() {
+                self.package_downloads.registry_url = None;
+            }
+            if let Some(package_registry_url) = package_registry_url.as_str() {
+                self.package_downloads.registry_url =
+                    Some(package_registry_url.to_owned()).filter(|url| !url.is_empty());
+            }
+        }
+
+        let package_registry_namespaces = update
+            .get("packageRegistryNamespaces")
+            .map(Vec::<String>::deserialize)
+            .and_then(Result::ok);
+        if let Some(package_registry_namespaces) = package_registry_namespaces {
+            self.package_downloads.registry_namespaces = package_registry_namespaces;
+        }
+
+        let offline = update
+            .get("offline")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(offline) = offline {
+            self.package_downloads.offline = offline;
+        }
+
+        let local_package_roots = update
+            .get("localPackageRoots")
+            .map(Vec::<PathBuf>::deserialize)
+            .and_then(Result::ok);
+        if let Some(local_package_roots) = local_package_roots {
+            self.local_package_roots = local_package_roots;
+        }
+
+        let on_type_debounce_ms = update
+            .get("onTypeDebounceMs")
+            .map(u64::deserialize)
+            .and_then(Result::ok);
+        if let Some(on_type_debounce_ms) = on_type_debounce_ms {
+            self.on_type_debounce_ms = Some(on_type_debounce_ms);
+        }
+
+        let cache_eviction_age = update
+            .get("cacheEvictionAge")
+            .map(usize::deserialize)
+            .and_then(Result::ok);
+        if let Some(cache_eviction_age) = cache_eviction_age {
+            self.cache_eviction_age = Some(cache_eviction_age);
+        }
+
+        let source_encoding_fallback = update
+            .get("sourceEncodingFallback")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(source_encoding_fallback) = source_encoding_fallback {
+            self.source_encoding_fallback = source_encoding_fallback;
+        }
+
+        let formatter_line_width = update.get("formatterLineWidth");
+        if let Some(formatter_line_width) = formatter_line_width {
+            if formatter_line_width.is_null() {
+                self.formatter_options.line_width = None;
+            }
+            if let Some(formatter_line_width) = formatter_line_width.as_u64() {
+                self.formatter_options.line_width = Some(formatter_line_width as usize);
+            }
+        }
+
+        // Either a number of spaces or `"tab"`
+        let formatter_indent = update.get("formatterIndent");
+        if let Some(formatter_indent) = formatter_indent {
+            if formatter_indent.is_null() {
+                self.formatter_options.indent = None;
+            }
+            if let Some(spaces) = formatter_indent.as_u64() {
+                self.formatter_options.indent = Some(FormatterIndent::Spaces(spaces as usize));
+            }
+            if formatter_indent.as_str() == Some("tab") {
+                self.formatter_options.indent = Some(FormatterIndent::Tabs);
+            }
+        }
+
+        let mut log_file = self.log_file.clone();
+        let log_file_path = update.get("logFile");
+        if let Some(log_file_path) = log_file_path {
+            if log_file_path.is_null() {
+                log_file.path = None;
+            }
+            if let Some(log_file_path) = log_file_path.as_str() {
+                log_file.path =
+                    Some(PathBuf::from(log_file_path)).filter(|path| !path.as_os_str().is_empty());
+            }
+        }
+        let log_level = update
+            .get("logLevel")
+            .map(LogLevel::deserialize)
+            .and_then(Result::ok);
+        if let Some(log_level) = log_level {
+            log_file.level = log_level;
+        }
+        if log_file != self.log_file {
+            for listener in &mut self.log_file_listeners {
+                listener(&log_file).await?;
+            }
+            self.log_file = log_file;
+        }
+
+        let mut fonts = self.fonts;
+        let ignore_embedded_fonts = update
+            .get("ignoreEmbeddedFonts")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(ignore_embedded_fonts) = ignore_embedded_fonts {
+            fonts.ignore_embedded = ignore_embedded_fonts;
+        }
+        let ignore_system_fonts = update
+            .get("ignoreSystemFonts")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(ignore_system_fonts) = ignore_system_fonts {
+            fonts.ignore_system = ignore_system_fonts;
+        }
+        self.fonts = fonts;
+
+        let preview = update
+            .get("preview")
+            .map(bool::deserialize)
+            .and_then(Result::ok);
+        if let Some(preview) = preview.filter(|preview| *preview != self.preview) {
+            for listener in &mut self.preview_listeners {
+                listener(&preview).await?;
+            }
+            self.preview = preview;
+        }
+
+        // Either a URI or a path, which may be relative to the first workspace folder
+        let main_file = update.get("mainFile");
+        if let Some(main_file) = main_file {
+            let configured_main_file = if main_file.is_null() {
+                None
+            } else if let Some(main_file) = main_file.as_str().filter(|uri| !uri.is_empty()) {
+                let uri = self.main_file_uri(main_file);
+                if uri.is_none() {
+                    warn!("main file {main_file} is not a valid URI or path");
+                }
+                uri
+            } else {
+                None
+            };
+            if configured_main_file != self.configured_main_file {
+                self.main_file = configured_main_file.clone();
+                self.configured_main_file = configured_main_file;
+            }
+        }
+
         self.validate_main_file();
         Ok(())
     }
 
+    fn main_file_uri(&self, main_file: &str) -> Option<Url> {
+        if let Ok(uri) = Url::parse(main_file) {
+            return Some(uri);
+        }
+
+        let path = PathBuf::from(main_file);
+        if path.is_absolute() {
+            return Url::from_file_path(path).ok();
+        }
+        let first_root = self.workspace_roots.first()?.to_file_path().ok()?;
+        Url::from_file_path(first_root.join(path)).ok()
+    }
+
+    pub fn on_type_debounce(&self) -> Duration {
+        Duration::from_millis(
+            self.on_type_debounce_ms
+                .unwrap_or(DEFAULT_ON_TYPE_DEBOUNCE_MS),
+        )
+    }
+
+    pub fn cache_eviction_age(&self) -> usize {
+        self.cache_eviction_age
+            .unwrap_or(DEFAULT_CACHE_EVICTION_AGE)
+    }
+
+    /// Some clients send settings nested under a `typst-lsp` object, like VS Code does, while others
+    /// send them at the top level. Settings in the section take precedence over top-level ones.
+    fn unwrap_section(update: &Map<String, Value>) -> Map<String, Value> {
+        let mut unwrapped = update.clone();
+        if let Some(Value::Object(section)) = unwrapped.remove("typst-lsp") {
+            unwrapped.extend(section);
+        }
+        unwrapped
+    }
+
+    /// Gets a value for a dotted key like `completion.prioritize`. Clients may send such a setting
+    /// either flat, under the full dotted key, or nested as `{ "completion": { "prioritize": .. } }`.
+    fn get_nested<'a>(update: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+        if let Some(value) = update.get(key) {
+            return Some(value);
+        }
+
+        let mut segments = key.split('.');
+        let first = update.get(segments.next()?)?;
+        segments.try_fold(first, |value, segment| value.get(segment))
+    }
+
     pub async fn update_main_file(&mut self, main_file: Option<Url>) -> anyhow::Result<()> {
         self.main_file = main_file;
 
@@ -165,30 +627,93 @@ impl Config {
         Ok(())
     }
 
+    pub fn set_workspace_roots(&mut self, workspace_roots: Vec<Url>) {
+        self.workspace_roots = workspace_roots;
+        self.validate_main_file();
+    }
+
+    pub fn handle_workspace_folders_change_event(&mut self, event: &WorkspaceFoldersChangeEvent) {
+        let removed = event.removed.iter().map(|folder| &folder.uri).collect_vec();
+
+        let workspace_roots = self
+            .workspace_roots
+            .iter()
+            .filter(|root| !removed.contains(root))
+            .chain(event.added.iter().map(|folder| &folder.uri))
+            .cloned()
+            .collect();
+        self.set_workspace_roots(workspace_roots);
+    }
+
+    /// The configured root path. A relative path is resolved against the first workspace folder.
+    pub fn resolved_root_path(&self) -> Option<PathBuf> {
+        let root_path = self.root_path.as_ref()?;
+        if root_path.is_absolute() {
+            return Some(root_path.clone());
+        }
+
+        let first_root = self.workspace_roots.first()?.to_file_path().ok()?;
+        Some(first_root.join(root_path))
+    }
+
+    /// Discards the main file if it is outside both the configured root path and every workspace
+    /// folder
     fn validate_main_file(&mut self) {
-        if let Some(main_file) = &self.main_file {
-            if let Some(root_path) = &self.root_path {
-                if let Ok(main_file) = main_file.to_file_path() {
-                    if !main_file.starts_with(root_path) {
-                        warn!(
-                            "main file {main_file} is not in the workspace root {root_path}",
-                            main_file = main_file.display(),
-                            root_path = root_path.display(),
-                        );
-                        self.main_file = None;
-                    }
-                }
-            }
+        let Some(main_file) = &self.main_file else {
+            return;
+        };
+        let Ok(main_path) = main_file.to_file_path() else {
+            return;
+        };
+
+        let roots = self
+            .resolved_root_path()
+            .into_iter()
+            .chain(
+                self.workspace_roots
+                    .iter()
+                    .filter_map(|root| root.to_file_path().ok()),
+            )
+            .collect_vec();
+        if roots.is_empty() || roots.iter().any(|root| main_path.starts_with(root)) {
+            return;
         }
+
+        warn!(
+            "main file {main_file} is not in the root path or any workspace folder",
+            main_file = main_path.display(),
+        );
+        self.main_file = None;
     }
 }
 
 impl fmt::Debug for Config {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Config")
+            .field("main_file", &self.main_file)
             .field("export_pdf", &self.export_pdf)
             .field("formatter", &self.formatter)
+            .field("formatter_options", &self.formatter_options)
             .field("semantic_tokens", &self.semantic_tokens)
+            .field("completion_priorities", &self.completion_priorities)
+            .field("profile_typst_thread", &self.profile_typst_thread)
+            .field(
+                "diagnostics_publish_interval_ms",
+                &self.diagnostics_publish_interval_ms,
+            )
+            .field("export_png", &self.export_png)
+            .field("export_output_path", &self.export_output_path)
+            .field("on_type_formatting", &self.on_type_formatting)
+            .field("lint_unused", &self.lint_unused)
+            .field("ignore_unknown_fonts", &self.ignore_unknown_fonts)
+            .field("package_downloads", &self.package_downloads)
+            .field("local_package_roots", &self.local_package_roots)
+            .field("on_type_debounce_ms", &self.on_type_debounce_ms)
+            .field("log_file", &self.log_file)
+            .field("cache_eviction_age", &self.cache_eviction_age)
+            .field("source_encoding_fallback", &self.source_encoding_fallback)
+            .field("fonts", &self.fonts)
+            .field("preview", &self.preview)
             .field(
                 "semantic_tokens_listeners",
                 &format_args!("Vec[len = {}]", self.semantic_tokens_listeners.len()),
@@ -197,6 +722,10 @@ impl fmt::Debug for Config {
                 "formatter_listeners",
                 &format_args!("Vec[len = {}]", self.formatter_listeners.len()),
             )
+            .field(
+                "profile_typst_thread_listeners",
+                &format_args!("Vec[len = {}]", self.profile_typst_thread_listeners.len()),
+            )
             .finish()
     }
 }
@@ -234,6 +763,7 @@ pub struct ConstConfig {
     pub supports_semantic_tokens_dynamic_registration: bool,
     pub supports_document_formatting_dynamic_registration: bool,
     pub supports_config_change_registration: bool,
+    pub supports_hierarchical_document_symbols: bool,
 }
 
 impl ConstConfig {
@@ -256,6 +786,130 @@ impl From<&InitializeParams> for ConstConfig {
             supports_document_formatting_dynamic_registration: params
                 .supports_document_formatting_dynamic_registration(),
             supports_config_change_registration: params.supports_config_change_registration(),
+            supports_hierarchical_document_symbols: params.supports_hierarchical_document_symbols(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::lsp_types::WorkspaceFolder;
+
+    use super::*;
+
+    fn uri(path: &str) -> Url {
+        Url::from_file_path(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cache_eviction_age_can_disable_caching() {
+        let mut config = Config::default();
+        assert_eq!(config.cache_eviction_age(), DEFAULT_CACHE_EVICTION_AGE);
+
+        let update = serde_json::json!({ "cacheEvictionAge": 0 });
+        config
+            .update_by_map(update.as_object().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(config.cache_eviction_age(), 0);
+    }
+
+    #[tokio::test]
+    async fn initial_config_pins_main_file() {
+        let mut config = Config::default();
+        config.set_workspace_roots(vec![uri("/project")]);
+
+        let init = serde_json::json!({ "mainFile": "thesis/main.typ" });
+        config.update(&init).await.unwrap();
+        assert_eq!(config.main_file, Some(uri("/project/thesis/main.typ")));
+
+        // Resending the same setting keeps a main file pinned since then by the command
+        config.update_main_file(None).await.unwrap();
+        config.update(&init).await.unwrap();
+        assert_eq!(config.main_file, None);
+
+        let update = serde_json::json!({ "mainFile": "file:///project/other.typ" });
+        config.update(&update).await.unwrap();
+        assert_eq!(config.main_file, Some(uri("/project/other.typ")));
+    }
+
+    #[tokio::test]
+    async fn main_file_in_second_workspace_folder_is_kept() {
+        let mut config = Config::default();
+        config.set_workspace_roots(vec![uri("/first"), uri("/second")]);
+
+        config
+            .update_main_file(Some(uri("/second/main.typ")))
+            .await
+            .unwrap();
+
+        assert_eq!(config.main_file, Some(uri("/second/main.typ")));
+    }
+
+    #[tokio::test]
+    async fn main_file_outside_roots_is_discarded() {
+        let mut config = Config::default();
+        config.set_workspace_roots(vec![uri("/first"), uri("/second")]);
+
+        config
+            .update_main_file(Some(uri("/elsewhere/main.typ")))
+            .await
+            .unwrap();
+
+        assert_eq!(config.main_file, None);
+    }
+
+    #[tokio::test]
+    async fn removing_workspace_folder_discards_main_file() {
+        let mut config = Config::default();
+        config.set_workspace_roots(vec![uri("/first"), uri("/second")]);
+        config
+            .update_main_file(Some(uri("/second/main.typ")))
+            .await
+            .unwrap();
+
+        config.handle_workspace_folders_change_event(&WorkspaceFoldersChangeEvent {
+            added: Vec::new(),
+            removed: vec![WorkspaceFolder {
+                uri: uri("/second"),
+                name: "second".to_owned(),
+            }],
+        });
+
+        assert_eq!(config.main_file, None);
+    }
+
+    #[test]
+    fn relative_root_path_resolves_against_first_folder() {
+        let mut config = Config::default();
+        config.set_workspace_roots(vec![uri("/first"), uri("/second")]);
+        config.root_path = Some(PathBuf::from("docs"));
+
+        assert_eq!(
+            config.resolved_root_path(),
+            Some(PathBuf::from("/first/docs"))
+        );
+    }
+
+    #[tokio::test]
+    async fn nested_and_top_level_settings_agree() {
+        let settings = serde_json::json!({
+            "exportPdf": "onType",
+            "rootPath": "docs",
+            "completion": { "prioritize": ["heading"] },
+        });
+        let nested = serde_json::json!({ "typst-lsp": settings.clone() });
+
+        let mut top_level_config = Config::default();
+        top_level_config.update(&settings).await.unwrap();
+        let mut nested_config = Config::default();
+        nested_config.update(&nested).await.unwrap();
+
+        assert_eq!(top_level_config.export_pdf, ExportPdfMode::OnType);
+        assert_eq!(
+            format!("{top_level_config:?}"),
+            format!("{nested_config:?}")
+        );
+    }
+}
@@ -22,6 +22,8 @@ pub trait InitializeParamsExt {
     fn document_formatting_capabilities(&self) -> Option<&DocumentFormattingClientCapabilities>;
     fn supports_semantic_tokens_dynamic_registration(&self) -> bool;
     fn supports_document_formatting_dynamic_registration(&self) -> bool;
+    fn supports_hierarchical_document_symbols(&self) -> bool;
+    fn supports_work_done_progress(&self) -> bool;
     fn root_uris(&self) -> Vec<Url>;
 }
 
@@ -73,6 +75,23 @@ impl InitializeParamsExt for InitializeParams {
             .unwrap_or(false)
     }
 
+    fn supports_hierarchical_document_symbols(&self) -> bool {
+        self.capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.document_symbol.as_ref())
+            .and_then(|document_symbol| document_symbol.hierarchical_document_symbol_support)
+            .unwrap_or(false)
+    }
+
+    fn supports_work_done_progress(&self) -> bool {
+        self.capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false)
+    }
+
     #[allow(deprecated)] // `self.root_path` is marked as deprecated
     fn root_uris(&self) -> Vec<Url> {
         match self.workspace_folders.as_ref() {
@@ -104,9 +123,15 @@ pub trait PathExt {
     fn is_typst(&self) -> bool;
 }
 
+/// Extensions of Typst sources: markup files and `.typc` script files. Scripts are parsed in code
+/// mode when compiled; see [`Project::read_source_by_id`](crate::workspace::project::Project::read_source_by_id).
+const TYPST_EXTENSIONS: &[&str] = &["typ", "typc"];
+
 impl PathExt for Path {
     fn is_typst(&self) -> bool {
-        self.extension().map_or(false, |ext| ext == "typ")
+        self.extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|ext| TYPST_EXTENSIONS.contains(&ext))
     }
 }
 
@@ -1,16 +1,21 @@
+use std::sync::Arc;
+
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{reload, Registry};
 
-use crate::server::log::LspLayer;
+use crate::server::log::LogLayers;
+use crate::server::profiling::{ThreadStats, ThreadStatsLayer};
 
-pub fn tracing_init() -> reload::Handle<Option<LspLayer>, Registry> {
-    let (lsp_layer, lsp_layer_handle) = reload::Layer::new(None);
+pub fn tracing_init(thread_stats: Arc<ThreadStats>) -> reload::Handle<LogLayers, Registry> {
+    let (lsp_layer, lsp_layer_handle) = reload::Layer::new(LogLayers::default());
     let jaeger_layer = jaeger::init();
+    let thread_stats_layer = ThreadStatsLayer::new(thread_stats);
 
     tracing_subscriber::registry()
         .with(lsp_layer)
         .with(jaeger_layer)
+        .with(thread_stats_layer)
         .init();
 
     lsp_layer_handle
@@ -57,50 +57,45 @@ pub type TypstCompletion = typst_ide::Completion;
 pub type TypstCompletionKind = typst_ide::CompletionKind;
 
 pub mod lsp_to_typst {
-    use typst::syntax::Source;
+    use typst::syntax::{is_newline, Source};
 
     use super::*;
 
+    /// Converts a position to a byte offset. Columns past the end of a line, including the end
+    /// position clients send for a whole line, are clamped to the end of the line's content,
+    /// before its line ending. So a position never lands between the `\r` and `\n` of a CRLF line
+    /// ending, and edits keep the line endings the file already uses. Lines past the end of the
+    /// file map to the end of the file.
     pub fn position_to_offset(
         lsp_position: LspPosition,
         lsp_position_encoding: LspPositionEncoding,
         typst_source: &Source,
     ) -> TypstOffset {
-        match lsp_position_encoding {
-            LspPositionEncoding::Utf8 => {
-                let line_index = lsp_position.line as usize;
-                let column_index = lsp_position.character as usize;
-                typst_source
-                    .line_column_to_byte(line_index, column_index)
-                    .unwrap()
-            }
-            LspPositionEncoding::Utf16 => {
-                // We have a line number and a UTF-16 offset into that line. We want a byte offset into
-                // the file.
-                //
-                // Typst's `Source` provides several UTF-16 methods:
-                //  - `len_utf16` for the length of the file
-                //  - `byte_to_utf16` to convert a byte offset from the start of the file to a UTF-16
-                //       offset from the start of the file
-                //  - `utf16_to_byte` to do the opposite of `byte_to_utf16`
-                //
-                // Unfortunately, none of these address our needs well, so we do some math instead. This
-                // is not the fastest possible implementation, but it's the most reasonable without
-                // access to the internal state of `Source`.
-
-                // TODO: Typst's `Source` could easily provide an implementation of the method we need
-                //   here. Submit a PR against `typst` to add it, then update this if/when merged.
-
-                let line_index = lsp_position.line as usize;
-                let utf16_offset_in_line = lsp_position.character as usize;
+        let line_index = lsp_position.line as usize;
+        let Some(line_range) = typst_source.line_to_range(line_index) else {
+            return typst_source.text().len();
+        };
+        let line = &typst_source.text()[line_range.clone()];
+        let content = line.trim_end_matches(is_newline);
 
-                let byte_line_offset = typst_source.line_to_byte(line_index).unwrap();
-                let utf16_line_offset = typst_source.byte_to_utf16(byte_line_offset).unwrap();
-                let utf16_offset = utf16_line_offset + utf16_offset_in_line;
+        // The column counts characters for UTF-8, and UTF-16 code units for UTF-16
+        let char_len = |c: char| match lsp_position_encoding {
+            LspPositionEncoding::Utf8 => 1,
+            LspPositionEncoding::Utf16 => c.len_utf16(),
+        };
 
-                typst_source.utf16_to_byte(utf16_offset).unwrap()
-            }
-        }
+        let column = lsp_position.character as usize;
+        let mut units = 0;
+        let offset_in_line = content
+            .char_indices()
+            .find(|&(_, c)| {
+                let reached = units >= column;
+                units += char_len(c);
+                reached
+            })
+            .map_or(content.len(), |(offset, _)| offset);
+
+        line_range.start + offset_in_line
     }
 
     pub fn range(lsp_range: &LspRange, source: &Source) -> TypstRange {
@@ -124,10 +119,11 @@ pub mod typst_to_lsp {
     use futures::{future, stream, StreamExt, TryFutureExt, TryStreamExt};
     use itertools::{Format, Itertools};
     use lazy_static::lazy_static;
-    use regex::{Captures, Regex};
+    use regex::Regex;
     use tower_lsp::lsp_types::{
-        CompletionTextEdit, DiagnosticRelatedInformation, Documentation, InsertTextFormat,
-        LanguageString, Location, MarkedString, MarkupContent, MarkupKind, TextEdit,
+        CodeDescription, CompletionItemLabelDetails, CompletionTextEdit,
+        DiagnosticRelatedInformation, Documentation, InsertTextFormat, LanguageString, Location,
+        MarkedString, MarkupContent, MarkupKind, NumberOrString, TextEdit,
     };
     use tracing::error;
     use typst::diag::{EcoString, Tracepoint};
@@ -135,7 +131,9 @@ pub mod typst_to_lsp {
     use typst::syntax::{FileId, Source, Spanned};
 
     use crate::config::ConstConfig;
+    use crate::ext::PathExt;
     use crate::server::diagnostics::DiagnosticsMap;
+    use crate::server::references::label_uses;
     use crate::workspace::project::Project;
 
     use super::*;
@@ -197,26 +195,90 @@ pub mod typst_to_lsp {
         }
     }
 
+    /// Name of a completion kind, as users may write it when configuring completion priorities
+    fn completion_kind_name(typst_completion_kind: &TypstCompletionKind) -> &'static str {
+        match typst_completion_kind {
+            TypstCompletionKind::Syntax => "syntax",
+            TypstCompletionKind::Func => "function",
+            TypstCompletionKind::Param => "param",
+            TypstCompletionKind::Constant => "constant",
+            TypstCompletionKind::Symbol(_) => "symbol",
+            TypstCompletionKind::Type => "type",
+        }
+    }
+
+    /// Chooses sort text so that completions whose label or kind appear in `priorities` are listed
+    /// first, in the order given. Other completions keep sorting by label after them. If there are
+    /// no priorities, returns `None` so the client's default ordering is untouched.
+    fn completion_sort_text(
+        typst_completion: &TypstCompletion,
+        priorities: &[String],
+    ) -> Option<String> {
+        if priorities.is_empty() {
+            return None;
+        }
+
+        let kind_name = completion_kind_name(&typst_completion.kind);
+        let rank = priorities.iter().position(|priority| {
+            priority == typst_completion.label.as_str() || priority == kind_name
+        });
+
+        Some(match rank {
+            Some(rank) => format!("0{rank:04}{}", typst_completion.label),
+            None => format!("1{}", typst_completion.label),
+        })
+    }
+
     lazy_static! {
         static ref TYPST_SNIPPET_PLACEHOLDER_RE: Regex = Regex::new(r"\$\{(.*?)\}").unwrap();
     }
 
-    /// Adds numbering to placeholders in snippets
-    fn snippet(typst_snippet: &EcoString) -> String {
-        let mut counter = 1;
-        let result =
-            TYPST_SNIPPET_PLACEHOLDER_RE.replace_all(typst_snippet.as_str(), |cap: &Captures| {
-                let substitution = format!("${{{}:{}}}", counter, &cap[1]);
-                counter += 1;
-                substitution
-            });
+    /// Adds numbering to placeholders in snippets, keeping their text as the default, and escapes
+    /// characters with special meaning in LSP snippets
+    pub(super) fn snippet(typst_snippet: &EcoString) -> String {
+        let typst_snippet = typst_snippet.as_str();
+        let mut result = String::with_capacity(typst_snippet.len());
+        let mut last_end = 0;
+
+        for (counter, cap) in TYPST_SNIPPET_PLACEHOLDER_RE
+            .captures_iter(typst_snippet)
+            .enumerate()
+        {
+            let placeholder = cap.get(0).unwrap();
+            result.push_str(&escape_snippet_text(
+                &typst_snippet[last_end..placeholder.start()],
+                &['$', '\\'],
+            ));
+            result.push_str(&format!(
+                "${{{}:{}}}",
+                counter + 1,
+                escape_snippet_text(&cap[1], &['$', '}', '\\'])
+            ));
+            last_end = placeholder.end();
+        }
+        result.push_str(&escape_snippet_text(
+            &typst_snippet[last_end..],
+            &['$', '\\'],
+        ));
+
+        result
+    }
 
-        result.to_string()
+    fn escape_snippet_text(text: &str, special: &[char]) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
     }
 
     pub fn completion(
         typst_completion: &TypstCompletion,
         lsp_replace: LspRawRange,
+        priorities: &[String],
     ) -> LspCompletion {
         let typst_snippet = typst_completion
             .apply
@@ -225,10 +287,27 @@ pub mod typst_to_lsp {
         let lsp_snippet = snippet(typst_snippet);
         let text_edit = CompletionTextEdit::Edit(TextEdit::new(lsp_replace, lsp_snippet));
 
+        // Show the glyph of symbols next to their name, like `arrow.r →`
+        let glyph = match typst_completion.kind {
+            TypstCompletionKind::Symbol(glyph) => Some(glyph.to_string()),
+            _ => None,
+        };
+        let detail = typst_completion
+            .detail
+            .as_ref()
+            .map(String::from)
+            .or_else(|| glyph.clone());
+        let label_details = glyph.map(|glyph| CompletionItemLabelDetails {
+            detail: Some(format!(" {glyph}")),
+            description: None,
+        });
+
         LspCompletion {
             label: typst_completion.label.to_string(),
+            label_details,
             kind: Some(completion_kind(typst_completion.kind.clone())),
-            detail: typst_completion.detail.as_ref().map(String::from),
+            detail,
+            sort_text: completion_sort_text(typst_completion, priorities),
             text_edit: Some(text_edit),
             insert_text_format: Some(InsertTextFormat::SNIPPET),
             ..Default::default()
@@ -238,10 +317,11 @@ pub mod typst_to_lsp {
     pub fn completions(
         typst_completions: &[TypstCompletion],
         lsp_replace: LspRawRange,
+        priorities: &[String],
     ) -> Vec<LspCompletion> {
         typst_completions
             .iter()
-            .map(|typst_completion| completion(typst_completion, lsp_replace))
+            .map(|typst_completion| completion(typst_completion, lsp_replace, priorities))
             .collect_vec()
     }
 
@@ -293,6 +373,7 @@ pub mod typst_to_lsp {
     async fn diagnostic(
         project: &Project,
         typst_diagnostic: &TypstDiagnostic,
+        dependencies: &[FileId],
         const_config: &ConstConfig,
     ) -> anyhow::Result<(LspUri, LspDiagnostic)> {
         let Some((id, span)) = diagnostic_span_id(typst_diagnostic) else {
@@ -310,12 +391,24 @@ pub mod typst_to_lsp {
         let typst_hints = &typst_diagnostic.hints;
         let lsp_message = format!("{typst_message}{}", diagnostic_hints(typst_hints));
 
-        let tracepoints =
+        let mut tracepoints =
             diagnostic_related_information(project, typst_diagnostic, const_config).await?;
+        tracepoints.extend(
+            duplicate_label_related_information(project, dependencies, typst_message, const_config)
+                .await,
+        );
+
+        let (code, docs) = diagnostic_code(typst_message).unzip();
+        let code_description = docs
+            .flatten()
+            .and_then(|docs| LspUri::parse(docs).ok())
+            .map(|href| CodeDescription { href });
 
         let diagnostic = LspDiagnostic {
             range: lsp_range.raw_range,
             severity: Some(lsp_severity),
+            code: code.map(|code| NumberOrString::String(code.to_owned())),
+            code_description,
             message: lsp_message,
             source: Some("typst".to_owned()),
             related_information: Some(tracepoints),
@@ -325,6 +418,122 @@ pub mod typst_to_lsp {
         Ok((uri, diagnostic))
     }
 
+    /// Codes of common diagnostics, by how their message starts, and the docs explaining them
+    const DIAGNOSTIC_CODES: &[(&str, &str, Option<&str>)] = &[
+        (
+            "unknown variable",
+            "unknown-variable",
+            Some("https://typst.app/docs/reference/scripting/#bindings"),
+        ),
+        (
+            "unclosed delimiter",
+            "unclosed-delimiter",
+            Some("https://typst.app/docs/reference/syntax/"),
+        ),
+        (
+            "unexpected argument",
+            "unexpected-argument",
+            Some("https://typst.app/docs/reference/foundations/function/"),
+        ),
+        (
+            "missing argument",
+            "missing-argument",
+            Some("https://typst.app/docs/reference/foundations/function/"),
+        ),
+        ("file not found", "file-not-found", None),
+        (
+            "unknown font family",
+            "unknown-font-family",
+            Some("https://typst.app/docs/reference/text/text/#parameters-font"),
+        ),
+        (
+            "label `<",
+            "label",
+            Some("https://typst.app/docs/reference/foundations/label/"),
+        ),
+    ];
+
+    /// Typst's diagnostics have no codes, so a stable code is derived from the message, letting
+    /// editors group diagnostics and link to the docs
+    pub(super) fn diagnostic_code(message: &str) -> Option<(&'static str, Option<&'static str>)> {
+        // Like `expected length, found string`. Syntax errors like `expected comma` have no
+        // `found` part.
+        if message.starts_with("expected ") && message.contains(", found ") {
+            return Some((
+                "type-mismatch",
+                Some("https://typst.app/docs/reference/foundations/type/"),
+            ));
+        }
+
+        DIAGNOSTIC_CODES
+            .iter()
+            .find(|(prefix, _, _)| message.starts_with(prefix))
+            .map(|&(_, code, docs)| (code, docs))
+    }
+
+    lazy_static! {
+        static ref DUPLICATE_LABEL_RE: Regex =
+            Regex::new(r"^label `<(.*?)>` occurs multiple times").unwrap();
+    }
+
+    /// Typst reports a label defined more than once where the label is used, without pointing
+    /// at its definitions, so these are found in the source to be shown alongside the error
+    pub(super) fn duplicate_label_definitions(source: &Source, message: &str) -> Vec<TypstRange> {
+        let Some(captures) = DUPLICATE_LABEL_RE.captures(message) else {
+            return Vec::new();
+        };
+        let name = &captures[1];
+
+        // The whole label, including its `<>`
+        label_uses(source)
+            .into_iter()
+            .filter(|label| label.is_label && label.name.as_str() == name)
+            .map(|label| label.range.start - 1..label.range.end + 1)
+            .collect()
+    }
+
+    /// A label can be defined more than once across the files of a document, so every file the
+    /// document depends on is searched for its definitions
+    async fn duplicate_label_related_information(
+        project: &Project,
+        dependencies: &[FileId],
+        message: &str,
+        const_config: &ConstConfig,
+    ) -> Vec<DiagnosticRelatedInformation> {
+        if !DUPLICATE_LABEL_RE.is_match(message) {
+            return Vec::new();
+        }
+
+        let mut related = Vec::new();
+        for &id in dependencies {
+            // Other dependencies, like images and bibliographies, can't define labels
+            if !id.vpath().as_rootless_path().is_typst() {
+                continue;
+            }
+            let Ok(uri) = project.full_id_to_uri(project.fill_id(id)).await else {
+                continue;
+            };
+            let Ok(source) = project.read_source_by_uri(&uri) else {
+                continue;
+            };
+
+            related.extend(
+                duplicate_label_definitions(&source, message)
+                    .into_iter()
+                    .map(|typst_range| DiagnosticRelatedInformation {
+                        location: Location {
+                            uri: uri.clone(),
+                            range: range(typst_range, &source, const_config.position_encoding)
+                                .raw_range,
+                        },
+                        message: "label defined here".to_owned(),
+                    }),
+            );
+        }
+
+        related
+    }
+
     fn diagnostic_span_id(typst_diagnostic: &TypstDiagnostic) -> Option<(FileId, TypstSpan)> {
         iter::once(typst_diagnostic.span)
             .chain(typst_diagnostic.trace.iter().map(|trace| trace.span))
@@ -367,14 +576,17 @@ pub mod typst_to_lsp {
             .format("")
     }
 
+    /// Converts the errors and warnings of a run of Typst. `dependencies` are the files read by
+    /// the run, as given by `ProjectWorld::dependencies`.
     pub async fn diagnostics<'a>(
         project: &Project,
         errors: impl IntoIterator<Item = &'a TypstDiagnostic>,
+        dependencies: &[FileId],
         const_config: &ConstConfig,
     ) -> DiagnosticsMap {
         stream::iter(errors)
             .then(|error| {
-                diagnostic(project, error, const_config)
+                diagnostic(project, error, dependencies, const_config)
                     .map_err(move |conversion_err| (conversion_err, error))
             })
             .inspect_err(|(conversion_err, typst_err)| error!(%conversion_err, ?typst_err, "could not convert Typst error to diagnostic"))
@@ -385,6 +597,23 @@ pub mod typst_to_lsp {
             .into_group_map()
     }
 
+    /// Converts the syntax errors in the source's syntax tree to diagnostics. This only needs the
+    /// parsed source, so it works even when the document cannot be compiled.
+    pub fn syntax_diagnostics(source: &Source, const_config: &ConstConfig) -> Vec<LspDiagnostic> {
+        source
+            .root()
+            .errors()
+            .into_iter()
+            .map(|error| LspDiagnostic {
+                range: diagnostic_range(source, error.span, const_config).raw_range,
+                severity: Some(LspSeverity::ERROR),
+                message: format!("{}{}", error.message, diagnostic_hints(&error.hints)),
+                source: Some("typst".to_owned()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
     pub fn tooltip(typst_tooltip: &TypstTooltip) -> LspHoverContents {
         let lsp_marked_string = match typst_tooltip {
             TypstTooltip::Text(text) => MarkedString::String(text.to_string()),
@@ -434,15 +663,168 @@ pub mod typst_to_lsp {
 
 #[cfg(test)]
 mod test {
+    use tower_lsp::lsp_types::{CompletionTextEdit, InitializeParams};
     use typst::syntax::Source;
 
-    use crate::config::PositionEncoding;
+    use crate::config::{ConstConfig, PositionEncoding};
     use crate::lsp_typst_boundary::lsp_to_typst;
 
     use super::*;
 
     const ENCODING_TEST_STRING: &str = "test 🥺 test";
 
+    #[test]
+    fn common_diagnostics_have_codes() {
+        let (code, docs) = typst_to_lsp::diagnostic_code("unknown variable: foo").unwrap();
+        assert_eq!(code, "unknown-variable");
+        assert!(docs.is_some_and(|docs| LspUri::parse(docs).is_ok()));
+
+        let (code, _) = typst_to_lsp::diagnostic_code("expected length, found string").unwrap();
+        assert_eq!(code, "type-mismatch");
+
+        assert_eq!(typst_to_lsp::diagnostic_code("expected comma"), None);
+    }
+
+    #[test]
+    fn duplicate_label_points_at_definitions() {
+        let source = Source::detached("= Intro <intro>\n= Again <intro>\nSee @intro <other>");
+
+        let definitions = typst_to_lsp::duplicate_label_definitions(
+            &source,
+            "label `<intro>` occurs multiple times in the document",
+        );
+
+        assert_eq!(definitions, vec![8..15, 24..31]);
+        assert!(
+            typst_to_lsp::duplicate_label_definitions(&source, "unknown variable: x").is_empty()
+        );
+    }
+
+    #[test]
+    fn unclosed_math_has_syntax_diagnostic() {
+        let source = Source::detached("Text\n$x + 1");
+        let const_config = ConstConfig::from(&InitializeParams::default());
+
+        let diagnostics = typst_to_lsp::syntax_diagnostics(&source, &const_config);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(LspSeverity::ERROR));
+        assert!(diagnostics[0].message.contains("unclosed"));
+    }
+
+    #[test]
+    fn snippet_placeholders_keep_defaults() {
+        let snippet = typst_to_lsp::snippet(&"rect(width: ${w}, height: ${h})".into());
+        assert_eq!(snippet, "rect(width: ${1:w}, height: ${2:h})");
+
+        let snippet = typst_to_lsp::snippet(&"$${x} \\$".into());
+        assert_eq!(snippet, "\\$${1:x} \\\\\\$");
+    }
+
+    #[test]
+    fn completion_replaces_typed_prefix() {
+        let source = Source::detached("#gri");
+        let replace = typst_to_lsp::range(1..4, &source, PositionEncoding::Utf16).raw_range;
+        let typst_completion = TypstCompletion {
+            kind: TypstCompletionKind::Func,
+            label: "grid".into(),
+            apply: None,
+            detail: None,
+        };
+
+        let completion = typst_to_lsp::completion(&typst_completion, replace, &[]);
+
+        let Some(CompletionTextEdit::Edit(edit)) = completion.text_edit else {
+            panic!("expected a text edit");
+        };
+        assert_eq!(edit.range, replace);
+        assert_eq!(edit.new_text, "grid");
+        assert!(completion.insert_text.is_none());
+    }
+
+    #[test]
+    fn symbol_completion_shows_glyph() {
+        let source = Source::detached("#sym.arrow.r");
+        let replace = typst_to_lsp::range(5..12, &source, PositionEncoding::Utf16).raw_range;
+        let typst_completion = TypstCompletion {
+            kind: TypstCompletionKind::Symbol('→'),
+            label: "arrow.r".into(),
+            apply: None,
+            detail: None,
+        };
+
+        let completion = typst_to_lsp::completion(&typst_completion, replace, &[]);
+
+        assert_eq!(completion.detail.as_deref(), Some("→"));
+        let label_details = completion.label_details.unwrap();
+        assert_eq!(label_details.detail.as_deref(), Some(" →"));
+    }
+
+    #[test]
+    fn prioritized_completions_sort_first_in_order() {
+        let source = Source::detached("#");
+        let replace = typst_to_lsp::range(1..1, &source, PositionEncoding::Utf16).raw_range;
+        let mut priorities: Vec<_> = (0..10).map(|i| format!("unused{i}")).collect();
+        priorities.splice(2..2, ["zeta".to_owned()]);
+        priorities.push("omega".to_owned());
+        let sort_text = |label: &str| {
+            let typst_completion = TypstCompletion {
+                kind: TypstCompletionKind::Func,
+                label: label.into(),
+                apply: None,
+                detail: None,
+            };
+            typst_to_lsp::completion(&typst_completion, replace, &priorities)
+                .sort_text
+                .unwrap()
+        };
+
+        let zeta = sort_text("zeta");
+        let omega = sort_text("omega");
+        let alpha = sort_text("alpha");
+
+        assert_eq!(priorities.iter().position(|p| p == "omega"), Some(11));
+        assert!(zeta < omega, "rank 2 should sort before rank 11");
+        assert!(
+            omega < alpha,
+            "prioritized should sort before unprioritized"
+        );
+    }
+
+    #[test]
+    fn crlf_round_trip() {
+        let source = Source::detached("ab\r\ncd\r\n");
+
+        for offset in [0, 1, 2, 4, 5, 6, 8] {
+            let position =
+                typst_to_lsp::offset_to_position(offset, PositionEncoding::Utf16, &source);
+            let round_trip =
+                lsp_to_typst::position_to_offset(position, PositionEncoding::Utf16, &source);
+            assert_eq!(round_trip, offset, "offset {offset} at {position:?}");
+        }
+    }
+
+    #[test]
+    fn crlf_line_end_is_before_line_ending() {
+        let source = Source::detached("ab\r\ncd");
+
+        for character in [2, 3, 100] {
+            let position = LspPosition::new(0, character);
+            for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16] {
+                assert_eq!(
+                    lsp_to_typst::position_to_offset(position, encoding, &source),
+                    2
+                );
+            }
+        }
+
+        let past_end = LspPosition::new(5, 0);
+        assert_eq!(
+            lsp_to_typst::position_to_offset(past_end, PositionEncoding::Utf16, &source),
+            6
+        );
+    }
+
     #[test]
     fn utf16_position_to_utf8_offset() {
         let source = Source::detached(ENCODING_TEST_STRING);
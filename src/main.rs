@@ -1,8 +1,11 @@
 #![recursion_limit = "256"]
 
+use std::sync::Arc;
+
 use bpaf::{construct, OptionParser, Parser};
 use logging::{tracing_init, tracing_shutdown};
-use server::log::LspLayer;
+use server::log::LogLayers;
+use server::profiling::ThreadStats;
 use server::TypstServer;
 use tower_lsp::{LspService, Server};
 use tracing_subscriber::{reload, Registry};
@@ -19,20 +22,25 @@ pub const TYPST_VERSION: &str = env!("TYPST_VERSION");
 
 #[tokio::main]
 async fn main() {
-    let lsp_tracing_layer_handle = tracing_init();
-    run(lsp_tracing_layer_handle).await;
+    let thread_stats = Arc::new(ThreadStats::default());
+    let lsp_tracing_layer_handle = tracing_init(Arc::clone(&thread_stats));
+    run(lsp_tracing_layer_handle, thread_stats).await;
     tracing_shutdown();
 }
 
 #[tracing::instrument(skip_all)]
-async fn run(lsp_tracing_layer_handle: reload::Handle<Option<LspLayer>, Registry>) {
+async fn run(
+    lsp_tracing_layer_handle: reload::Handle<LogLayers, Registry>,
+    thread_stats: Arc<ThreadStats>,
+) {
     let _args = arg_parser().run();
 
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) =
-        LspService::new(move |client| TypstServer::new(client, lsp_tracing_layer_handle));
+    let (service, socket) = LspService::new(move |client| {
+        TypstServer::new(client, lsp_tracing_layer_handle, thread_stats)
+    });
 
     Server::new(stdin, stdout, socket).serve(service).await;
 }
@@ -0,0 +1,268 @@
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hasher;
+use std::iter;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, CompletionTextEdit, TextEdit, Url};
+use tracing::warn;
+use typst::syntax::{ast, FileId, LinkedNode, Source, SyntaxKind};
+
+use crate::ext::PathExt;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, LspRawRange};
+use crate::workspace::fs::local::LocalFs;
+
+use super::completion::label_start;
+use super::TypstServer;
+
+/// An entry in a bibliography file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BibEntry {
+    pub key: String,
+    pub title: Option<String>,
+}
+
+/// Parsed bibliography files, by file, along with a hash of the contents they were parsed from. A
+/// file is only parsed again when its contents change, and then replaces its previous entries.
+#[derive(Debug, Default)]
+pub struct BibliographyCache {
+    entries: parking_lot::Mutex<HashMap<FileId, (u128, Arc<Vec<BibEntry>>)>>,
+}
+
+impl BibliographyCache {
+    /// Parses the bibliography file, or gets its entries from a previous parse. The format is
+    /// chosen by the file's extension, like Typst does.
+    pub fn entries(&self, id: FileId, path: &str, bytes: &[u8]) -> Arc<Vec<BibEntry>> {
+        let mut hasher = SipHasher13::new();
+        hasher.write(path.as_bytes());
+        hasher.write(bytes);
+        let hash = hasher.finish128().as_u128();
+
+        let mut entries = self.entries.lock();
+        match entries.get(&id) {
+            Some((cached_hash, parsed)) if *cached_hash == hash => Arc::clone(parsed),
+            _ => {
+                let parsed = Arc::new(parse_bibliography(path, bytes));
+                entries.insert(id, (hash, Arc::clone(&parsed)));
+                parsed
+            }
+        }
+    }
+}
+
+fn parse_bibliography(path: &str, bytes: &[u8]) -> Vec<BibEntry> {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return Vec::new();
+    };
+
+    let library = if path.ends_with(".bib") {
+        hayagriva::io::from_biblatex_str(text).map_err(|errors| format!("{errors:?}"))
+    } else if path.ends_with(".yml") || path.ends_with(".yaml") {
+        hayagriva::io::from_yaml_str(text).map_err(|error| error.to_string())
+    } else {
+        return Vec::new();
+    };
+
+    match library {
+        Ok(library) => library
+            .iter()
+            .map(|entry| BibEntry {
+                key: entry.key().to_owned(),
+                title: entry.title().map(|title| title.value.to_string()),
+            })
+            .collect(),
+        Err(err) => {
+            warn!(%err, path, "could not parse bibliography");
+            Vec::new()
+        }
+    }
+}
+
+impl TypstServer {
+    /// Completes keys from the bibliography files loaded with `bibliography`, when a reference or a
+    /// label in `cite` is being typed. Typst only completes keys from the last compiled document,
+    /// so this covers keys before the document first compiles.
+    pub async fn get_bibliography_completions(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Vec<CompletionItem>>> {
+        let position_encoding = self.position_encoding();
+
+        let replace_range = self.scope_with_source(uri).await?.run(|source, _| {
+            let offset = lsp_to_typst::position_to_offset(position, position_encoding, source);
+            let start = label_start(source, offset)?;
+            let start = typst_to_lsp::offset_to_position(start, position_encoding, source);
+            Some(LspRawRange::new(start, position))
+        });
+        let Some(replace_range) = replace_range else {
+            return Ok(None);
+        };
+
+        let mut entries = Vec::new();
+        for source_uri in self.document_source_uris(uri).await {
+            let Ok(scope) = self.scope_with_source(&source_uri).await else {
+                continue;
+            };
+            let source_entries = scope
+                .run2(|source, project| async move {
+                    let mut entries = Vec::new();
+                    for (id, path) in bibliography_files(&source) {
+                        match project.read_bytes_by_id(id).await {
+                            Ok(bytes) => entries.extend(
+                                self.bibliographies
+                                    .entries(id, &path, bytes.as_slice())
+                                    .iter()
+                                    .cloned(),
+                            ),
+                            Err(err) => warn!(%err, path, "could not read bibliography"),
+                        }
+                    }
+                    entries
+                })
+                .await;
+            entries.extend(source_entries);
+        }
+
+        let completions = entries
+            .into_iter()
+            .unique_by(|entry| entry.key.clone())
+            .map(|entry| CompletionItem {
+                label: entry.key.clone(),
+                kind: Some(CompletionItemKind::REFERENCE),
+                detail: entry.title,
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    replace_range,
+                    entry.key,
+                ))),
+                ..Default::default()
+            })
+            .collect();
+
+        Ok(Some(completions))
+    }
+
+    /// The sources of the documents the file is part of, where its bibliography may be loaded.
+    /// These are the pinned main file, or otherwise the compiled documents depending on the file,
+    /// along with the files they depended on when last compiled. The file itself is always
+    /// included, in case it wasn't compiled yet.
+    async fn document_source_uris(&self, uri: &Url) -> BTreeSet<Url> {
+        let main = self.main_url().await;
+        let dependencies = self.dependencies.read();
+        let mains = match &main {
+            Some(main) => vec![main.clone()],
+            None => dependencies.dependents_of(uri),
+        };
+
+        iter::once(uri.clone())
+            .chain(main)
+            .chain(
+                mains
+                    .iter()
+                    .flat_map(|main| dependencies.dependencies_of(main)),
+            )
+            .filter(|uri| LocalFs::uri_to_path(uri).map_or(true, |path| path.is_typst()))
+            .collect()
+    }
+}
+
+/// The files loaded by `bibliography` calls in the source, with the paths as written
+fn bibliography_files(source: &Source) -> Vec<(FileId, String)> {
+    let mut paths = Vec::new();
+    collect_bibliography_paths(&LinkedNode::new(source.root()), &mut paths);
+
+    paths
+        .into_iter()
+        .map(|path| (source.id().join(&path), path))
+        .collect()
+}
+
+fn collect_bibliography_paths(node: &LinkedNode, paths: &mut Vec<String>) {
+    if node.kind() == SyntaxKind::FuncCall {
+        if let Some(call) = node.cast::<ast::FuncCall>() {
+            let is_bibliography = matches!(call.callee(), ast::Expr::Ident(ident) if ident.as_str() == "bibliography");
+            let first_positional = call.args().items().find_map(|arg| match arg {
+                ast::Arg::Pos(expr) => Some(expr),
+                _ => None,
+            });
+            match first_positional {
+                Some(ast::Expr::Str(path)) if is_bibliography => paths.push(path.get().into()),
+                Some(ast::Expr::Array(array)) if is_bibliography => {
+                    paths.extend(array.items().filter_map(|item| match item {
+                        ast::ArrayItem::Pos(ast::Expr::Str(path)) => Some(path.get().into()),
+                        _ => None,
+                    }))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for child in node.children() {
+        collect_bibliography_paths(&child, paths);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use typst::syntax::VirtualPath;
+
+    use super::*;
+
+    const BIB: &str = r#"
+@article{netwok,
+    title = {At-scale impact of the {Net Wok}},
+    author = {Astley, Rick},
+    year = {2020},
+}
+
+@book{kopka,
+    title = {A Guide to LaTeX},
+    author = {Kopka, Helmut},
+    year = {2004},
+}
+"#;
+
+    #[test]
+    fn bib_entries_have_keys_and_titles() {
+        let cache = BibliographyCache::default();
+        let id = FileId::new(None, VirtualPath::new("refs.bib"));
+        let entries = cache.entries(id, "refs.bib", BIB.as_bytes());
+
+        let keys: Vec<_> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, ["netwok", "kopka"]);
+        assert_eq!(entries[1].title.as_deref(), Some("A Guide to LaTeX"));
+
+        // Parsed entries are reused for unchanged contents
+        let again = cache.entries(id, "refs.bib", BIB.as_bytes());
+        assert!(Arc::ptr_eq(&entries, &again));
+    }
+
+    #[test]
+    fn changed_file_replaces_its_entries() {
+        let cache = BibliographyCache::default();
+        let id = FileId::new(None, VirtualPath::new("refs.bib"));
+        cache.entries(id, "refs.bib", BIB.as_bytes());
+
+        let changed = BIB.replace("netwok", "netwok2");
+        let entries = cache.entries(id, "refs.bib", changed.as_bytes());
+
+        assert_eq!(entries[0].key, "netwok2");
+        assert_eq!(cache.entries.lock().len(), 1);
+    }
+
+    #[test]
+    fn bibliography_paths_are_found() {
+        let source = Source::detached(
+            "See @net\n#bibliography((\"refs.bib\", \"more.yml\"))\n#bibliography(\"other.bib\")",
+        );
+
+        let paths: Vec<_> = bibliography_files(&source)
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect();
+
+        assert_eq!(paths, ["refs.bib", "more.yml", "other.bib"]);
+    }
+}
@@ -0,0 +1,420 @@
+use std::iter;
+
+use tower_lsp::lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, SymbolKind, Url,
+};
+use typst::diag::EcoString;
+use typst::syntax::{ast, FileId, LinkedNode, Source, SyntaxKind};
+use typst::World;
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, LspRawRange, TypstRange};
+use crate::workspace::project::Project;
+
+use super::definition::{
+    definition_of, ident_at, is_ident, Definition, DefinitionKind, SourceLoader,
+};
+use super::TypstServer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallableKind {
+    /// A function bound by `let`
+    Function,
+    /// A whole file, which makes the calls outside of any function
+    File,
+}
+
+/// Something which makes or receives calls
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Callable {
+    pub name: EcoString,
+    pub id: FileId,
+    pub kind: CallableKind,
+    /// The range of the whole `let` binding
+    pub range: TypstRange,
+    /// The range of the function's name
+    pub selection_range: TypstRange,
+}
+
+impl Callable {
+    fn file(source: &Source) -> Self {
+        let name = source
+            .id()
+            .vpath()
+            .as_rootless_path()
+            .file_name()
+            .map_or_else(EcoString::new, |name| name.to_string_lossy().into());
+
+        Self {
+            name,
+            id: source.id(),
+            kind: CallableKind::File,
+            range: 0..source.len_bytes(),
+            selection_range: 0..0,
+        }
+    }
+}
+
+/// Callables along with the ranges of the calls made to or from them
+pub type Calls = Vec<(Callable, Vec<TypstRange>)>;
+
+impl TypstServer {
+    /// Finds the function named at `position`, to start a call hierarchy from
+    #[tracing::instrument(skip(self))]
+    pub async fn get_call_hierarchy_items(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Vec<CallHierarchyItem>>> {
+        let position_encoding = self.position_encoding();
+        let (project, full_id) = self.project_and_full_id(uri).await?;
+
+        let function = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| {
+                let source = world.source(full_id.into()).ok()?;
+                let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+                function_at(&world, &source, offset)
+            })
+            .await;
+        let Some(function) = function else {
+            return Ok(None);
+        };
+
+        let item = self.call_hierarchy_item(&project, &function).await?;
+        Ok(Some(vec![item]))
+    }
+
+    /// Finds the calls to the function of the item across the workspace
+    #[tracing::instrument(skip(self))]
+    pub async fn get_incoming_calls(
+        &self,
+        item: CallHierarchyItem,
+    ) -> anyhow::Result<Vec<CallHierarchyIncomingCall>> {
+        let position_encoding = self.position_encoding();
+        let (project, full_id) = self.project_and_full_id(&item.uri).await?;
+        let files = self.workspace_sources().await;
+
+        let calls = self
+            .thread_with_world(&item.uri)
+            .await?
+            .run(move |world| {
+                let source = world.source(full_id.into()).ok()?;
+                let offset = lsp_to_typst::position_to_offset(
+                    item.selection_range.start,
+                    position_encoding,
+                    &source,
+                );
+                let function = function_at(&world, &source, offset)?;
+
+                let sources: Vec<_> = files
+                    .into_iter()
+                    .filter_map(|(_, id)| world.source(id).ok())
+                    .collect();
+                Some(incoming_calls(&world, &sources, &function))
+            })
+            .await
+            .unwrap_or_default();
+
+        let mut incoming = Vec::with_capacity(calls.len());
+        for (caller, ranges) in calls {
+            let (from, from_ranges) = self.call_hierarchy_calls(&project, &caller, ranges).await?;
+            incoming.push(CallHierarchyIncomingCall { from, from_ranges });
+        }
+
+        Ok(incoming)
+    }
+
+    /// Finds the functions called by the function of the item
+    #[tracing::instrument(skip(self))]
+    pub async fn get_outgoing_calls(
+        &self,
+        item: CallHierarchyItem,
+    ) -> anyhow::Result<Vec<CallHierarchyOutgoingCall>> {
+        let position_encoding = self.position_encoding();
+        let (project, full_id) = self.project_and_full_id(&item.uri).await?;
+        let source = project.read_source_by_uri(&item.uri)?;
+
+        let calls = self
+            .thread_with_world(&item.uri)
+            .await?
+            .run(move |world| {
+                let source = world.source(full_id.into()).ok()?;
+                let offset = lsp_to_typst::position_to_offset(
+                    item.selection_range.start,
+                    position_encoding,
+                    &source,
+                );
+                let function = function_at(&world, &source, offset)?;
+                Some(outgoing_calls(&world, &function))
+            })
+            .await
+            .unwrap_or_default();
+
+        let mut outgoing = Vec::with_capacity(calls.len());
+        for (callee, ranges) in calls {
+            // Calls are made from the item's function, so the ranges are in its file
+            let from_ranges = ranges
+                .into_iter()
+                .map(|range| typst_to_lsp::range(range, &source, position_encoding).raw_range)
+                .collect();
+            let to = self.call_hierarchy_item(&project, &callee).await?;
+            outgoing.push(CallHierarchyOutgoingCall { to, from_ranges });
+        }
+
+        Ok(outgoing)
+    }
+
+    async fn call_hierarchy_calls(
+        &self,
+        project: &Project,
+        callable: &Callable,
+        ranges: Vec<TypstRange>,
+    ) -> anyhow::Result<(CallHierarchyItem, Vec<LspRawRange>)> {
+        let position_encoding = self.position_encoding();
+        let item = self.call_hierarchy_item(project, callable).await?;
+        let source = project.read_source_by_uri(&item.uri)?;
+
+        let ranges = ranges
+            .into_iter()
+            .map(|range| typst_to_lsp::range(range, &source, position_encoding).raw_range)
+            .collect();
+
+        Ok((item, ranges))
+    }
+
+    async fn call_hierarchy_item(
+        &self,
+        project: &Project,
+        callable: &Callable,
+    ) -> anyhow::Result<CallHierarchyItem> {
+        let position_encoding = self.position_encoding();
+        let uri = project.full_id_to_uri(project.fill_id(callable.id)).await?;
+        let source = project.read_source_by_uri(&uri)?;
+
+        let kind = match callable.kind {
+            CallableKind::Function => SymbolKind::FUNCTION,
+            CallableKind::File => SymbolKind::FILE,
+        };
+
+        Ok(CallHierarchyItem {
+            name: callable.name.to_string(),
+            kind,
+            tags: None,
+            detail: None,
+            range: typst_to_lsp::range(callable.range.clone(), &source, position_encoding)
+                .raw_range,
+            selection_range: typst_to_lsp::range(
+                callable.selection_range.clone(),
+                &source,
+                position_encoding,
+            )
+            .raw_range,
+            uri,
+            data: None,
+        })
+    }
+}
+
+/// Gets the function named by the identifier at `offset`, if it was defined by a `let`
+pub fn function_at(loader: &dyn SourceLoader, source: &Source, offset: usize) -> Option<Callable> {
+    let root = LinkedNode::new(source.root());
+    let ident = ident_at(&root, offset)?;
+    let definition = definition_of(loader, source, &ident)?;
+    function_of_definition(loader, &definition)
+}
+
+/// Finds the calls to the function in the sources, grouped by the function or file making them
+pub fn incoming_calls(loader: &dyn SourceLoader, sources: &[Source], function: &Callable) -> Calls {
+    let target = Definition {
+        id: function.id,
+        range: function.selection_range.clone(),
+        kind: DefinitionKind::Binding,
+    };
+
+    let mut calls = Calls::new();
+    for source in sources {
+        let root = LinkedNode::new(source.root());
+        for callee in callees(&root) {
+            if definition_of(loader, source, &callee).as_ref() != Some(&target) {
+                continue;
+            }
+
+            let caller = iter::successors(callee.parent().cloned(), |node| node.parent().cloned())
+                .find_map(|node| let_function(source, &node))
+                .unwrap_or_else(|| Callable::file(source));
+            add_call(&mut calls, caller, callee.range());
+        }
+    }
+
+    calls
+}
+
+/// Finds the functions called in the body of the function, grouped by function. A recursive
+/// function is listed once, like any other.
+pub fn outgoing_calls(loader: &dyn SourceLoader, function: &Callable) -> Calls {
+    let Some(source) = loader.load_source(function.id) else {
+        return Calls::new();
+    };
+    let root = LinkedNode::new(source.root());
+    let Some(binding) = root
+        .leaf_at(function.selection_range.start + 1)
+        .and_then(|ident| {
+            iter::successors(Some(ident), |node| node.parent().cloned())
+                .find(|node| node.kind() == SyntaxKind::LetBinding)
+        })
+    else {
+        return Calls::new();
+    };
+
+    let mut calls = Calls::new();
+    for callee in callees(&binding) {
+        let Some(called) = definition_of(loader, &source, &callee)
+            .and_then(|definition| function_of_definition(loader, &definition))
+        else {
+            continue;
+        };
+        add_call(&mut calls, called, callee.range());
+    }
+
+    calls
+}
+
+fn add_call(calls: &mut Calls, callable: Callable, range: TypstRange) {
+    match calls.iter_mut().find(|(existing, _)| *existing == callable) {
+        Some((_, ranges)) => ranges.push(range),
+        None => calls.push((callable, vec![range])),
+    }
+}
+
+/// The identifiers naming the functions called in the node, like `f` in `f(x)` or `module.f(x)`
+fn callees<'a>(node: &LinkedNode<'a>) -> Vec<LinkedNode<'a>> {
+    let mut found = Vec::new();
+    collect_callees(node, &mut found);
+    found
+}
+
+fn collect_callees<'a>(node: &LinkedNode<'a>, found: &mut Vec<LinkedNode<'a>>) {
+    if node.kind() == SyntaxKind::FuncCall {
+        let callee = node.children().next().and_then(|callee| {
+            if is_ident(&callee) {
+                Some(callee)
+            } else if callee.kind() == SyntaxKind::FieldAccess {
+                callee.children().last().filter(is_ident)
+            } else {
+                None
+            }
+        });
+        found.extend(callee);
+    }
+
+    for child in node.children() {
+        collect_callees(&child, found);
+    }
+}
+
+/// The function a definition binds, if it binds one with `let`
+fn function_of_definition(loader: &dyn SourceLoader, definition: &Definition) -> Option<Callable> {
+    if definition.kind != DefinitionKind::Binding {
+        return None;
+    }
+
+    let source = loader.load_source(definition.id)?;
+    let root = LinkedNode::new(source.root());
+    let ident = root.leaf_at(definition.range.start + 1)?;
+    let function = iter::successors(ident.parent().cloned(), |node| node.parent().cloned())
+        .find(|node| node.kind() == SyntaxKind::LetBinding)
+        .and_then(|binding| let_function(&source, &binding))?;
+
+    (function.selection_range == definition.range).then_some(function)
+}
+
+/// The function bound by the node, if it is a `let` binding of a function
+fn let_function(source: &Source, node: &LinkedNode) -> Option<Callable> {
+    let binding = node.cast::<ast::LetBinding>()?;
+    let name = match binding.kind() {
+        ast::LetBindingKind::Closure(name) => name,
+        ast::LetBindingKind::Normal(ast::Pattern::Normal(ast::Expr::Ident(name)))
+            if matches!(binding.init(), Some(ast::Expr::Closure(_))) =>
+        {
+            name
+        }
+        _ => return None,
+    };
+
+    Some(Callable {
+        name: name.get().clone(),
+        id: source.id(),
+        kind: CallableKind::Function,
+        range: node.range(),
+        selection_range: source.range(name.span())?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::definition::test::Fixture;
+
+    use super::*;
+
+    fn function(fixture: &Fixture, path: &str, needle: &str) -> Callable {
+        let source = fixture.source(path);
+        let offset = source.text().find(needle).unwrap();
+        function_at(fixture, source, offset + 1).unwrap()
+    }
+
+    fn names(calls: &Calls) -> Vec<(&str, usize)> {
+        calls
+            .iter()
+            .map(|(callable, ranges)| (callable.name.as_str(), ranges.len()))
+            .collect()
+    }
+
+    #[test]
+    fn calls_between_functions() {
+        let fixture = Fixture::default()
+            .with(
+                "lib.typ",
+                "#let double(x) = 2 * x\n#let quadruple(x) = double(double(x))",
+            )
+            .with(
+                "main.typ",
+                "#import \"lib.typ\": double\n#double(4) #double(5)",
+            );
+        let sources = [
+            fixture.source("lib.typ").clone(),
+            fixture.source("main.typ").clone(),
+        ];
+
+        let double = function(&fixture, "lib.typ", "double");
+        assert_eq!(double.range, 1..22);
+        assert_eq!(double.selection_range, 5..11);
+
+        let incoming = incoming_calls(&fixture, &sources, &double);
+        assert_eq!(names(&incoming), [("quadruple", 2), ("main.typ", 2)]);
+        assert_eq!(incoming[1].0.kind, CallableKind::File);
+
+        let quadruple = function(&fixture, "lib.typ", "quadruple");
+        let outgoing = outgoing_calls(&fixture, &quadruple);
+        assert_eq!(names(&outgoing), [("double", 2)]);
+        assert_eq!(outgoing[0].0, double);
+
+        assert!(outgoing_calls(&fixture, &double).is_empty());
+    }
+
+    #[test]
+    fn recursive_function_is_listed_once() {
+        let fixture = Fixture::default().with(
+            "main.typ",
+            "#let fib(n) = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }",
+        );
+
+        let fib = function(&fixture, "main.typ", "fib");
+
+        let outgoing = outgoing_calls(&fixture, &fib);
+        assert_eq!(names(&outgoing), [("fib", 2)]);
+
+        let incoming = incoming_calls(&fixture, &[fixture.source("main.typ").clone()], &fib);
+        assert_eq!(names(&incoming), [("fib", 2)]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+use std::iter;
+
+use itertools::Itertools;
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CreateFile, CreateFileOptions, Diagnostic,
+    DocumentChangeOperation, DocumentChanges, ResourceOp, TextEdit, Url, WorkspaceEdit,
+};
+use typst::diag::EcoString;
+use typst::syntax::{ast, LinkedNode, Source, SyntaxKind, VirtualPath};
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspRange, LspRawRange, TypstRange};
+
+use super::definition::collect_used_names;
+use super::TypstServer;
+
+impl TypstServer {
+    /// Gets quick fixes for the given diagnostics in a source, and refactorings for the code at
+    /// `range`
+    #[tracing::instrument(skip(self, diagnostics))]
+    pub async fn get_code_actions(
+        &self,
+        uri: &Url,
+        range: LspRawRange,
+        diagnostics: &[Diagnostic],
+    ) -> anyhow::Result<Vec<CodeActionOrCommand>> {
+        let position_encoding = self.position_encoding();
+
+        let (source, package) = {
+            let workspace = self.read_workspace().await?;
+            let full_id = workspace.full_id(uri)?;
+            // Files in external packages can't be changed, so there's nothing to do there
+            if full_id.spec().is_some() {
+                return Ok(Vec::new());
+            }
+            let source = workspace.read_source(uri)?;
+            let package = workspace
+                .package_manager()
+                .package(full_id.package())
+                .await?;
+            (source, package)
+        };
+
+        let mut actions: Vec<CodeActionOrCommand> = diagnostics
+            .iter()
+            .filter(|diagnostic| is_missing_file(diagnostic))
+            .filter_map(|diagnostic| {
+                let range = LspRange::new(diagnostic.range, position_encoding);
+                let vpath = missing_file_path(&source, lsp_to_typst::range(&range, &source))?;
+                // Fails if the path escapes the project root
+                let target = package.vpath_to_uri(&vpath).ok()?;
+                Some(create_file_action(target, diagnostic.clone()).into())
+            })
+            .collect();
+
+        let offset = lsp_to_typst::position_to_offset(range.start, position_encoding, &source);
+        if let Some((vpath, wildcard)) = wildcard_import_at(&source, offset) {
+            let imported = package.vpath_to_uri(&vpath)?;
+            if let Some(edit) = self
+                .explicit_import_edit(&source, &imported, wildcard)
+                .await
+            {
+                let edit = TextEdit {
+                    range: typst_to_lsp::range(edit.0, &source, position_encoding).raw_range,
+                    new_text: edit.1,
+                };
+                actions.push(explicit_import_action(uri.clone(), edit).into());
+            }
+        }
+
+        Ok(actions)
+    }
+
+    /// Evaluates the imported module to find what it exports, then lists the exports `source`
+    /// uses in place of the wildcard
+    async fn explicit_import_edit(
+        &self,
+        source: &Source,
+        imported: &Url,
+        wildcard: TypstRange,
+    ) -> Option<(TypstRange, String)> {
+        let (module, _) = self.eval_source(imported).await.ok()?;
+        let exports: Vec<_> = module?
+            .scope()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        explicit_imports(source, wildcard, &exports)
+    }
+}
+
+fn is_missing_file(diagnostic: &Diagnostic) -> bool {
+    diagnostic.message.starts_with("file not found")
+}
+
+/// If `range` is the path of an import or include, gets the path of the file it refers to
+pub fn missing_file_path(source: &Source, range: TypstRange) -> Option<VirtualPath> {
+    let root = LinkedNode::new(source.root());
+    let node = root.leaf_at(range.start + 1)?;
+    if !matches!(
+        node.parent_kind(),
+        Some(SyntaxKind::ModuleImport | SyntaxKind::ModuleInclude)
+    ) {
+        return None;
+    }
+
+    let path = node.cast::<ast::Str>()?.get();
+    Some(source.id().vpath().join(path.as_str()))
+}
+
+/// If `offset` is in an import of a file with a wildcard, like `#import "file.typ": *`, gets the
+/// path of the imported file and the range of the `*`
+pub fn wildcard_import_at(source: &Source, offset: usize) -> Option<(VirtualPath, TypstRange)> {
+    let root = LinkedNode::new(source.root());
+    let leaf = root.leaf_at(offset)?;
+    let import = iter::successors(Some(leaf), |node| node.parent().cloned())
+        .find(|node| node.kind() == SyntaxKind::ModuleImport)?;
+
+    let ast_import = import.cast::<ast::ModuleImport>()?;
+    if !matches!(ast_import.imports(), Some(ast::Imports::Wildcard)) {
+        return None;
+    }
+    let ast::Expr::Str(path) = ast_import.source() else {
+        return None;
+    };
+    let path = path.get();
+    if path.starts_with('@') {
+        return None;
+    }
+
+    let wildcard = import
+        .children()
+        .find(|child| child.kind() == SyntaxKind::Star)?;
+    Some((source.id().vpath().join(path.as_str()), wildcard.range()))
+}
+
+/// Lists the names from `exports` which `source` uses, to replace a wildcard import. Returns
+/// `None` if none of them are used, since an empty list isn't valid.
+pub fn explicit_imports(
+    source: &Source,
+    wildcard: TypstRange,
+    exports: &[EcoString],
+) -> Option<(TypstRange, String)> {
+    let mut used = HashSet::new();
+    collect_used_names(&LinkedNode::new(source.root()), &HashSet::new(), &mut used);
+
+    let names = exports
+        .iter()
+        .filter(|name| used.contains(name.as_str()))
+        .join(", ");
+    (!names.is_empty()).then_some((wildcard, names))
+}
+
+fn explicit_import_action(uri: Url, edit: TextEdit) -> CodeAction {
+    CodeAction {
+        title: "Import used names explicitly".to_owned(),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, vec![edit])])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn create_file_action(target: Url, diagnostic: Diagnostic) -> CodeAction {
+    let name = target
+        .path_segments()
+        .and_then(Iterator::last)
+        .unwrap_or_default()
+        .to_owned();
+
+    let create = ResourceOp::Create(CreateFile {
+        uri: target,
+        options: Some(CreateFileOptions {
+            overwrite: Some(false),
+            ignore_if_exists: Some(true),
+        }),
+        annotation_id: None,
+    });
+
+    CodeAction {
+        title: format!("Create `{name}`"),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic]),
+        edit: Some(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(create),
+            ])),
+            ..Default::default()
+        }),
+        is_preferred: Some(true),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use typst::syntax::FileId;
+
+    use crate::ext::UrlExt;
+
+    use super::*;
+
+    fn import_target(text: &str, path: &str) -> Option<VirtualPath> {
+        let id = FileId::new(None, VirtualPath::new("chapters/main.typ"));
+        let source = Source::new(id, text.to_owned());
+        let start = text.find(path).unwrap() - 1;
+        missing_file_path(&source, start..start + path.len() + 2)
+    }
+
+    #[test]
+    fn import_resolves_to_sibling() {
+        let root = Url::parse("file:///project").unwrap();
+
+        let vpath = import_target("#import \"chapter2.typ\"", "chapter2.typ").unwrap();
+        let target = root.join_rooted(&vpath).unwrap();
+
+        assert_eq!(target.as_str(), "file:///project/chapters/chapter2.typ");
+    }
+
+    #[test]
+    fn wildcard_import_lists_used_exports() {
+        let text = "#import \"lib.typ\": *\n#a and #c.b";
+        let source = Source::new(
+            FileId::new(None, VirtualPath::new("main.typ")),
+            text.to_owned(),
+        );
+        let exports = ["a".into(), "b".into(), "c".into()];
+
+        let (vpath, wildcard) = wildcard_import_at(&source, 3).unwrap();
+        let edit = explicit_imports(&source, wildcard, &exports);
+
+        assert_eq!(vpath, VirtualPath::new("lib.typ"));
+        assert_eq!(edit, Some((19..20, "a, c".to_owned())));
+    }
+
+    #[test]
+    fn import_outside_root_is_rejected() {
+        let root = Url::parse("file:///project").unwrap();
+
+        let vpath = import_target("#include \"../../outside.typ\"", "../../outside.typ").unwrap();
+
+        assert!(root.join_rooted(&vpath).is_err());
+    }
+}
@@ -1,11 +1,21 @@
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tower_lsp::jsonrpc;
 use tower_lsp::{
-    jsonrpc::{Error, Result},
+    jsonrpc::{Error, ErrorCode, Result},
     lsp_types::Url,
 };
 use tracing::{error, info};
+use typst::diag::EcoString;
+use typst::model::Document;
+use typst::syntax::package::PackageSpec;
 
+use super::diagnostics::DiagnosticsMap;
+use super::export::{
+    EmbeddedSources, EmptyDocument, PageOutOfRange, ThumbnailOptions, MAX_THUMBNAIL_COLUMNS,
+    MAX_THUMBNAIL_WIDTH,
+};
+use super::query::InvalidSelector;
 use super::TypstServer;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,6 +23,20 @@ pub enum LspCommand {
     ExportPdf,
     ClearCache,
     PinMain,
+    ExportThumbnails,
+    ListStyleRules,
+    ThreadStats,
+    RenderedOutline,
+    InstantiateTemplate,
+    ExportWithSource,
+    CheckPackageUpdates,
+    ExportPng,
+    ListFonts,
+    Compile,
+    ExportPdfPage,
+    Query,
+    UnpinMain,
+    DocumentOutline,
 }
 
 impl From<LspCommand> for String {
@@ -21,6 +45,20 @@ impl From<LspCommand> for String {
             LspCommand::ExportPdf => "typst-lsp.doPdfExport".to_string(),
             LspCommand::ClearCache => "typst-lsp.doClearCache".to_string(),
             LspCommand::PinMain => "typst-lsp.doPinMain".to_string(),
+            LspCommand::ExportThumbnails => "typst-lsp.exportThumbnails".to_string(),
+            LspCommand::ListStyleRules => "typst-lsp.listStyleRules".to_string(),
+            LspCommand::ThreadStats => "typst-lsp.threadStats".to_string(),
+            LspCommand::RenderedOutline => "typst-lsp.renderedOutline".to_string(),
+            LspCommand::InstantiateTemplate => "typst-lsp.instantiateTemplate".to_string(),
+            LspCommand::ExportWithSource => "typst-lsp.exportWithSource".to_string(),
+            LspCommand::CheckPackageUpdates => "typst-lsp.checkPackageUpdates".to_string(),
+            LspCommand::ExportPng => "typst-lsp.exportPng".to_string(),
+            LspCommand::ListFonts => "typst-lsp.listFonts".to_string(),
+            LspCommand::Compile => "typst-lsp.compile".to_string(),
+            LspCommand::ExportPdfPage => "typst-lsp.exportPdfPage".to_string(),
+            LspCommand::Query => "typst-lsp.query".to_string(),
+            LspCommand::UnpinMain => "typst-lsp.doUnpinMain".to_string(),
+            LspCommand::DocumentOutline => "typst-lsp.documentOutline".to_string(),
         }
     }
 }
@@ -31,6 +69,20 @@ impl LspCommand {
             "typst-lsp.doPdfExport" => Some(Self::ExportPdf),
             "typst-lsp.doClearCache" => Some(Self::ClearCache),
             "typst-lsp.doPinMain" => Some(Self::PinMain),
+            "typst-lsp.exportThumbnails" => Some(Self::ExportThumbnails),
+            "typst-lsp.listStyleRules" => Some(Self::ListStyleRules),
+            "typst-lsp.threadStats" => Some(Self::ThreadStats),
+            "typst-lsp.renderedOutline" => Some(Self::RenderedOutline),
+            "typst-lsp.instantiateTemplate" => Some(Self::InstantiateTemplate),
+            "typst-lsp.exportWithSource" => Some(Self::ExportWithSource),
+            "typst-lsp.checkPackageUpdates" => Some(Self::CheckPackageUpdates),
+            "typst-lsp.exportPng" => Some(Self::ExportPng),
+            "typst-lsp.listFonts" => Some(Self::ListFonts),
+            "typst-lsp.compile" => Some(Self::Compile),
+            "typst-lsp.exportPdfPage" => Some(Self::ExportPdfPage),
+            "typst-lsp.query" => Some(Self::Query),
+            "typst-lsp.doUnpinMain" => Some(Self::UnpinMain),
+            "typst-lsp.documentOutline" => Some(Self::DocumentOutline),
             _ => None,
         }
     }
@@ -40,23 +92,58 @@ impl LspCommand {
             Self::ExportPdf.into(),
             Self::ClearCache.into(),
             Self::PinMain.into(),
+            Self::ExportThumbnails.into(),
+            Self::ListStyleRules.into(),
+            Self::ThreadStats.into(),
+            Self::RenderedOutline.into(),
+            Self::InstantiateTemplate.into(),
+            Self::ExportWithSource.into(),
+            Self::CheckPackageUpdates.into(),
+            Self::ExportPng.into(),
+            Self::ListFonts.into(),
+            Self::Compile.into(),
+            Self::ExportPdfPage.into(),
+            Self::Query.into(),
+            Self::UnpinMain.into(),
+            Self::DocumentOutline.into(),
         ]
     }
 }
 
+/// Parse the file URI clients pass as the first argument to most commands.
+fn uri_argument(arguments: &[Value]) -> Result<Url> {
+    if arguments.is_empty() {
+        return Err(Error::invalid_params("Missing file URI argument"));
+    }
+    let Some(file_uri) = arguments.first().and_then(|v| v.as_str()) else {
+        return Err(Error::invalid_params("Missing file URI as first argument"));
+    };
+    Url::parse(file_uri).map_err(|_| Error::invalid_params("Parameter is not a valid URI"))
+}
+
+/// The result of the compile command. The page count is `None` if compilation failed.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CompileResult {
+    page_count: Option<usize>,
+    diagnostics: DiagnosticsMap,
+}
+
+impl CompileResult {
+    fn new(document: Option<&Document>, diagnostics: DiagnosticsMap) -> Self {
+        Self {
+            page_count: document.map(|document| document.pages.len()),
+            diagnostics,
+        }
+    }
+}
+
 /// Here are implemented the handlers for each command.
 impl TypstServer {
     /// Export the current document as a PDF file. The client is responsible for passing the correct file URI.
     #[tracing::instrument(skip(self))]
     pub async fn command_export_pdf(&self, arguments: Vec<Value>) -> Result<()> {
-        if arguments.is_empty() {
-            return Err(Error::invalid_params("Missing file URI argument"));
-        }
-        let Some(file_uri) = arguments.first().and_then(|v| v.as_str()) else {
-            return Err(Error::invalid_params("Missing file URI as first argument"));
-        };
-        let file_uri = Url::parse(file_uri)
-            .map_err(|_| Error::invalid_params("Parameter is not a valid URI"))?;
+        let file_uri = uri_argument(&arguments)?;
 
         self.run_export(&file_uri).await.map_err(|err| {
             error!(%err, "could not export PDF");
@@ -66,16 +153,40 @@ impl TypstServer {
         Ok(())
     }
 
-    /// Clear all cached resources.
+    /// Clear all cached resources. An object argument like `{ "fonts": true, "packages": true }`
+    /// also searches for fonts again and deletes downloaded packages.
     #[tracing::instrument(skip_all)]
-    pub async fn command_clear_cache(&self, _arguments: Vec<Value>) -> Result<()> {
-        self.workspace().write().await.clear().map_err(|err| {
+    pub async fn command_clear_cache(&self, arguments: Vec<Value>) -> Result<()> {
+        let options = clear_cache_options(&arguments)?;
+
+        let mut workspace = self
+            .initialized_workspace()
+            .map_err(|err| {
+                error!(%err, "could not clear cache");
+                jsonrpc::Error::internal_error()
+            })?
+            .write()
+            .await;
+        if options.packages {
+            workspace.clear_downloaded_packages().map_err(|err| {
+                error!(%err, "could not delete downloaded packages");
+                jsonrpc::Error::internal_error()
+            })?;
+        }
+        workspace.clear().map_err(|err| {
             error!(%err, "could not clear cache");
             jsonrpc::Error::internal_error()
         })?;
+        drop(workspace);
 
         self.typst(|_| comemo::evict(0)).await;
 
+        // Searching again picks up newly installed fonts, and recompiles with them
+        if options.fonts {
+            let fonts = self.config.read().await.fonts;
+            self.reload_fonts(fonts).await;
+        }
+
         Ok(())
     }
 
@@ -97,6 +208,18 @@ impl TypstServer {
             )
         };
 
+        self.pin_main(file_uri).await
+    }
+
+    /// Unpin the main file, so each file is compiled on its own again. The same as pinning
+    /// `detached`.
+    #[tracing::instrument(skip_all)]
+    pub async fn command_unpin_main(&self) -> Result<()> {
+        self.pin_main(None).await
+    }
+
+    async fn pin_main(&self, file_uri: Option<Url>) -> Result<()> {
+        let previous_main = self.main_url().await;
         let update_result = self.config.write().await.update_main_file(file_uri).await;
 
         update_result.map_err(|err| {
@@ -104,11 +227,360 @@ impl TypstServer {
             jsonrpc::Error::internal_error()
         })?;
 
-        info!(
-            "main file pinned: {main_url:?}",
-            main_url = self.main_url().await
-        );
+        self.notify_pinned_main(previous_main).await;
+
+        let main_url = self.main_url().await;
+        info!("main file pinned: {main_url:?}");
+
+        // Compile the pinned file right away, so diagnostics and exports reflect it before the
+        // next edit
+        if let Some(main_url) = main_url {
+            if let Err(err) = self.on_source_changed(&main_url).await {
+                error!(%err, %main_url, "could not compile pinned main file");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Export a contact sheet of thumbnails of every page. The client is responsible for passing
+    /// the correct file URI. The number of columns and the width of each thumbnail in pixels may
+    /// optionally follow as the second and third arguments.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_export_thumbnails(&self, arguments: Vec<Value>) -> Result<()> {
+        let file_uri = uri_argument(&arguments)?;
+        let options = thumbnail_options(&arguments)?;
+
+        self.run_thumbnails_export(&file_uri, options)
+            .await
+            .map_err(|err| {
+                error!(%err, "could not export thumbnails");
+                jsonrpc::Error::internal_error()
+            })?;
 
         Ok(())
     }
+
+    /// List every `set` and `show` rule in the given file and the files it imports or includes,
+    /// grouped by file.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_list_style_rules(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+
+        let rules = self.list_style_rules(&file_uri).await.map_err(|err| {
+            error!(%err, "could not list style rules");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        serde_json::to_value(rules).map_err(|err| {
+            error!(%err, "could not serialize style rules");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// Summarize timings of operations run on the Typst thread. Timings are only recorded while
+    /// `profileTypstThread` is enabled.
+    #[tracing::instrument(skip_all)]
+    pub async fn command_thread_stats(&self, _arguments: Vec<Value>) -> Result<Value> {
+        serde_json::to_value(self.thread_stats.summary()).map_err(|err| {
+            error!(%err, "could not serialize thread stats");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// Compile the given file and get its outline as rendered, falling back to syntactic symbols
+    /// if compilation fails.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_rendered_outline(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+
+        let outline = self.rendered_outline(&file_uri).await.map_err(|err| {
+            error!(%err, "could not get rendered outline");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        serde_json::to_value(outline).map_err(|err| {
+            error!(%err, "could not serialize rendered outline");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// List the files a template package would create in a new project, without writing them. The
+    /// only argument is the package spec, like `@preview/example:0.1.0`.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_instantiate_template(&self, arguments: Vec<Value>) -> Result<Value> {
+        let Some(spec) = arguments.first().and_then(|v| v.as_str()) else {
+            return Err(Error::invalid_params(
+                "Missing package spec as first argument",
+            ));
+        };
+        let spec: PackageSpec = spec
+            .parse()
+            .map_err(|err: EcoString| Error::invalid_params(err.to_string()))?;
+
+        let scaffold = self.instantiate_template(&spec).await.map_err(|err| {
+            error!(%err, %spec, "could not instantiate template");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        serde_json::to_value(scaffold).map_err(|err| {
+            error!(%err, "could not serialize template files");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// Export the document as a PDF with its source files attached. An optional second argument,
+    /// `"main"` or `"all"`, chooses whether to attach only the given file or every project file the
+    /// document depends on. Defaults to `"all"`.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_export_with_source(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+
+        let sources = match arguments.get(1) {
+            Some(sources) => EmbeddedSources::deserialize(sources).map_err(|_| {
+                Error::invalid_params("Second argument must be \"main\" or \"all\"")
+            })?,
+            None => EmbeddedSources::default(),
+        };
+
+        let export = self
+            .export_pdf_with_source(&file_uri, sources)
+            .await
+            .map_err(|err| {
+                error!(%err, "could not export PDF with source");
+                jsonrpc::Error::internal_error()
+            })?;
+
+        serde_json::to_value(export).map_err(|err| {
+            error!(%err, "could not serialize exported files");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// List `@preview` package imports in the workspace which have a newer version available.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_check_package_updates(&self) -> Result<Value> {
+        let updates = self.check_package_updates().await.map_err(|err| {
+            error!(%err, "could not check for package updates");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        serde_json::to_value(updates).map_err(|err| {
+            error!(%err, "could not serialize package updates");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// Export each page of the document as a PNG image, at the resolution set by `exportPng.ppi`.
+    /// The client is responsible for passing the correct file URI.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_export_png(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+        let ppi = self.config.read().await.export_png.ppi;
+
+        let png_uris = self.run_png_export(&file_uri, ppi).await.map_err(|err| {
+            if err.is::<EmptyDocument>() {
+                return Error {
+                    code: ErrorCode::InvalidRequest,
+                    message: err.to_string().into(),
+                    data: None,
+                };
+            }
+            error!(%err, "could not export PNG");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        serde_json::to_value(png_uris).map_err(|err| {
+            error!(%err, "could not serialize PNG URIs");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// Export a single page of the document as a PDF. The second argument is the page number,
+    /// counting from 1. Returns the URI of the PDF.
+    #[tracing::instrument(skip_all)]
+    pub async fn command_export_pdf_page(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+        let Some(page) = arguments.get(1).and_then(Value::as_u64) else {
+            return Err(Error::invalid_params(
+                "Missing page number as second argument",
+            ));
+        };
+
+        let pdf_uri = self
+            .run_pdf_page_export(&file_uri, page as usize)
+            .await
+            .map_err(|err| {
+                if err.is::<PageOutOfRange>() {
+                    return Error::invalid_params(err.to_string());
+                }
+                error!(%err, "could not export PDF page");
+                jsonrpc::Error::internal_error()
+            })?;
+
+        serde_json::to_value(pdf_uri).map_err(|err| {
+            error!(%err, "could not serialize PDF URI");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// Compile the document and find the elements matching a selector, like `typst query`. The
+    /// second argument is the selector, like `heading` or `<label>`, and the optional third
+    /// argument is a field to get from each element instead of the whole element.
+    #[tracing::instrument(skip_all)]
+    pub async fn command_query(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+        let Some(selector) = arguments.get(1).and_then(Value::as_str) else {
+            return Err(Error::invalid_params("Missing selector as second argument"));
+        };
+        let field = arguments.get(2).and_then(Value::as_str).map(str::to_owned);
+
+        let results = self
+            .run_query(&file_uri, selector.to_owned(), field)
+            .await
+            .map_err(|err| {
+                if err.is::<InvalidSelector>() {
+                    return Error::invalid_params(err.to_string());
+                }
+                error!(%err, "could not query document");
+                jsonrpc::Error::internal_error()
+            })?;
+
+        Ok(Value::Array(results))
+    }
+
+    /// Compile the given file and list the headings of the document, with their levels, numbers,
+    /// pages, and where they are written.
+    #[tracing::instrument(skip(self))]
+    pub async fn command_document_outline(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+
+        let outline = self.document_outline(&file_uri).await.map_err(|err| {
+            error!(%err, "could not get document outline");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        serde_json::to_value(outline).map_err(|err| {
+            error!(%err, "could not serialize document outline");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// List every loaded font, with its family, style, weight, and path, or no path if it is
+    /// embedded
+    #[tracing::instrument(skip_all)]
+    pub async fn command_list_fonts(&self) -> Result<Value> {
+        let workspace = self.read_workspace().await.map_err(|err| {
+            error!(%err, "could not list fonts");
+            jsonrpc::Error::internal_error()
+        })?;
+        let fonts = workspace.font_manager().fonts_metadata();
+
+        serde_json::to_value(fonts).map_err(|err| {
+            error!(%err, "could not serialize fonts");
+            jsonrpc::Error::internal_error()
+        })
+    }
+
+    /// Compile a file and return its diagnostics and page count, without publishing the diagnostics
+    #[tracing::instrument(skip(self))]
+    pub async fn command_compile(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = uri_argument(&arguments)?;
+
+        let (document, diagnostics) = self.compile_source(&file_uri).await.map_err(|err| {
+            error!(%err, %file_uri, "could not compile");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        serde_json::to_value(CompileResult::new(document.as_deref(), diagnostics)).map_err(|err| {
+            error!(%err, "could not serialize compile result");
+            jsonrpc::Error::internal_error()
+        })
+    }
+}
+
+/// What to clear besides the usual caches
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct ClearCacheOptions {
+    /// Search for fonts again, picking up newly installed fonts
+    fonts: bool,
+    /// Delete downloaded packages
+    packages: bool,
+}
+
+/// Reads the options from the first object argument. Other arguments, like the URI the editor
+/// sends, are ignored.
+fn clear_cache_options(arguments: &[Value]) -> Result<ClearCacheOptions> {
+    let Some(options) = arguments.iter().find(|argument| argument.is_object()) else {
+        return Ok(ClearCacheOptions::default());
+    };
+
+    serde_json::from_value(options.clone())
+        .map_err(|err| Error::invalid_params(format!("Invalid cache clearing options: {err}")))
+}
+
+/// Reads the optional number of columns and thumbnail width following the URI, rejecting values
+/// which would make a contact sheet too large to render
+fn thumbnail_options(arguments: &[Value]) -> Result<ThumbnailOptions> {
+    let mut options = ThumbnailOptions::default();
+    if let Some(columns) = bounded_argument(arguments, 1, "columns", MAX_THUMBNAIL_COLUMNS)? {
+        options.columns = columns as usize;
+    }
+    if let Some(width) = bounded_argument(arguments, 2, "width", MAX_THUMBNAIL_WIDTH)? {
+        options.width = width;
+    }
+    Ok(options)
+}
+
+/// Gets the integer argument at `index`, if there is one, checking it is between 1 and `max`
+fn bounded_argument(
+    arguments: &[Value],
+    index: usize,
+    name: &str,
+    max: u32,
+) -> Result<Option<u32>> {
+    let Some(value) = arguments.get(index).and_then(Value::as_u64) else {
+        return Ok(None);
+    };
+
+    match u32::try_from(value) {
+        Ok(value) if (1..=max).contains(&value) => Ok(Some(value)),
+        _ => Err(Error::invalid_params(format!(
+            "`{name}` must be between 1 and {max}, but is {value}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packages_are_only_deleted_when_asked() {
+        let uri = Value::from("file:///project/main.typ");
+
+        let options = clear_cache_options(&[uri.clone()]).unwrap();
+        assert_eq!(options, ClearCacheOptions::default());
+
+        let options = clear_cache_options(&[uri, serde_json::json!({ "fonts": true })]).unwrap();
+        assert!(options.fonts);
+        assert!(!options.packages);
+
+        assert!(clear_cache_options(&[serde_json::json!({ "packages": "yes" })]).is_err());
+    }
+
+    #[test]
+    fn thumbnail_options_are_bounded() {
+        let uri = Value::from("file:///project/main.typ");
+
+        let options = thumbnail_options(&[uri.clone(), Value::from(2), Value::from(300)]).unwrap();
+        assert_eq!((options.columns, options.width), (2, 300));
+
+        for (columns, width) in [(0, 200), (4, u64::from(u32::MAX) + 1), (4, 100_000)] {
+            let arguments = [uri.clone(), Value::from(columns), Value::from(width)];
+            let err = thumbnail_options(&arguments).unwrap_err();
+            assert_eq!(err.code, jsonrpc::ErrorCode::InvalidParams);
+        }
+    }
 }
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::Url;
+use typst::model::Document;
+
+/// Sent after each compile, so editors can show how long compiling took, like in a status bar
+#[derive(Debug)]
+pub enum CompileStatusNotification {}
+
+impl Notification for CompileStatusNotification {
+    type Params = CompileStatus;
+    const METHOD: &'static str = "$/typstCompileStatus";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompileStatus {
+    /// The main file of the compiled document
+    pub uri: Url,
+    pub elapsed_ms: u64,
+    pub success: bool,
+    /// `None` if compilation failed
+    pub page_count: Option<usize>,
+}
+
+impl CompileStatus {
+    pub fn new(uri: Url, elapsed: Duration, document: Option<&Document>) -> Self {
+        Self {
+            uri,
+            elapsed_ms: elapsed.as_millis() as u64,
+            success: document.is_some(),
+            page_count: document.map(|document| document.pages.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use typst::layout::{Abs, Frame, Page, Size};
+
+    use super::*;
+
+    #[test]
+    fn status_carries_page_count() {
+        let uri = Url::parse("file:///project/main.typ").unwrap();
+        let page = Page {
+            frame: Frame::hard(Size::new(Abs::pt(10.0), Abs::pt(10.0))),
+            numbering: None,
+            number: 1,
+        };
+        let document = Document {
+            pages: vec![page.clone(), page],
+            ..Default::default()
+        };
+
+        let status = CompileStatus::new(uri.clone(), Duration::from_millis(42), Some(&document));
+        let params = serde_json::to_value(status).unwrap();
+        assert_eq!(
+            params,
+            serde_json::json!({
+                "uri": uri,
+                "elapsedMs": 42,
+                "success": true,
+                "pageCount": 2,
+            })
+        );
+
+        let failed = CompileStatus::new(uri, Duration::ZERO, None);
+        assert!(!failed.success);
+        assert_eq!(failed.page_count, None);
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Documentation, MarkupContent,
+    MarkupKind, TextEdit, Url,
+};
+use typst::foundations::{Scope, Value};
+use typst::syntax::{ast, LinkedNode, Source, SyntaxKind};
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, LspRawRange};
+use crate::workspace::fs::local::LocalFs;
+use crate::workspace::TYPST_STDLIB;
+
+use super::references::{is_label_char, label_uses};
+use super::TypstServer;
+
+const SOURCE_EXTENSIONS: &[&str] = &["typ", "typc"];
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "svg"];
+
+lazy_static! {
+    /// Documentation of standard library functions and types, by name. Built once, so resolving a
+    /// completion is only a lookup.
+    static ref STDLIB_DOCS: HashMap<&'static str, &'static str> = {
+        let mut docs = HashMap::new();
+        add_docs(&mut docs, TYPST_STDLIB.math.scope());
+        // Global definitions take precedence over math ones with the same name
+        add_docs(&mut docs, TYPST_STDLIB.global.scope());
+        docs
+    };
+}
+
+fn add_docs(docs: &mut HashMap<&'static str, &'static str>, scope: &'static Scope) {
+    for (name, value) in scope.iter() {
+        let doc = match value {
+            Value::Func(func) => func.docs(),
+            Value::Type(ty) => Some(ty.docs()),
+            _ => None,
+        };
+        if let Some(doc) = doc {
+            docs.insert(name.as_str(), doc);
+        }
+    }
+}
+
+/// Data sent to the client with a completion, which it sends back when resolving the completion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CompletionData {
+    /// Name of the standard library function or type the completion refers to
+    stdlib: String,
+}
+
+/// Marks completions of standard library functions and types, so their documentation can be
+/// filled in when the client resolves them. Sending documentation up front would make completion
+/// lists much larger.
+pub fn attach_resolve_data(completions: &mut [CompletionItem]) {
+    for completion in completions {
+        let is_func_or_type = matches!(
+            completion.kind,
+            Some(CompletionItemKind::FUNCTION | CompletionItemKind::CLASS)
+        );
+        if !is_func_or_type || !STDLIB_DOCS.contains_key(completion.label.as_str()) {
+            continue;
+        }
+
+        let data = CompletionData {
+            stdlib: completion.label.clone(),
+        };
+        completion.data = serde_json::to_value(data).ok();
+    }
+}
+
+/// Fills in the documentation of a completion marked by [`attach_resolve_data`]. Other completions
+/// are returned unchanged.
+pub fn resolve_completion(mut completion: CompletionItem) -> CompletionItem {
+    let data = completion
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<CompletionData>(data).ok());
+    let docs = data.and_then(|data| STDLIB_DOCS.get(data.stdlib.as_str()).copied());
+
+    if let Some(docs) = docs {
+        completion.documentation = Some(Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: docs.to_owned(),
+        }));
+    }
+
+    completion
+}
+
+impl TypstServer {
+    /// Whether to complete after `:` was typed at the position, which is only in call arguments
+    pub async fn colon_completes(&self, uri: &Url, position: LspPosition) -> anyhow::Result<bool> {
+        let position_encoding = self.position_encoding();
+        let in_args = self.scope_with_source(uri).await?.run(|source, _| {
+            let offset = lsp_to_typst::position_to_offset(position, position_encoding, source);
+            in_args(source, offset)
+        });
+        Ok(in_args)
+    }
+
+    /// Completes paths to local files in strings given to `#import`, `#include`, and `image`
+    pub async fn get_path_completions(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Vec<CompletionItem>>> {
+        let position_encoding = self.position_encoding();
+
+        // Only files on the local filesystem have siblings we can list
+        let Ok(file_path) = LocalFs::uri_to_path(uri) else {
+            return Ok(None);
+        };
+        let root = {
+            let workspace = self.read_workspace().await?;
+            let full_id = workspace.full_id(uri)?;
+            let package = workspace
+                .package_manager()
+                .package(full_id.package())
+                .await?;
+            LocalFs::uri_to_path(package.root())?
+        };
+
+        let completions = self.scope_with_source(uri).await?.run(|source, _| {
+            let offset = lsp_to_typst::position_to_offset(position, position_encoding, source);
+            let (start, mut completions) = path_completions(source, offset, &file_path, &root)?;
+
+            let start = typst_to_lsp::offset_to_position(start, position_encoding, source);
+            let replace_range = LspRawRange::new(start, position);
+            for completion in &mut completions {
+                completion.text_edit = Some(CompletionTextEdit::Edit(TextEdit::new(
+                    replace_range,
+                    completion.label.clone(),
+                )));
+            }
+
+            Some(completions)
+        });
+
+        Ok(completions)
+    }
+}
+
+/// The offset at which the text replaced by a completion at the offset ends. Completing in the middle
+/// of an identifier replaces the rest of it too, so `#gr|i` completes to `#grid` and not
+/// `#gridi`.
+pub fn replace_end(source: &Source, offset: usize) -> usize {
+    let Some(leaf) = LinkedNode::new(source.root()).leaf_at(offset) else {
+        return offset;
+    };
+
+    let is_ident = matches!(leaf.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent);
+    if is_ident && leaf.range().contains(&offset) {
+        leaf.range().end
+    } else {
+        offset
+    }
+}
+
+/// The offset at which the name of the reference or label being typed at the offset starts. Returns
+/// `None` outside references and labels in markup, and outside labels in `cite` calls, so typing
+/// `<` elsewhere doesn't offer labels.
+pub fn label_start(source: &Source, offset: usize) -> Option<usize> {
+    let leaf = LinkedNode::new(source.root()).leaf_at(offset)?;
+    if matches!(leaf.kind(), SyntaxKind::RefMarker | SyntaxKind::Label) {
+        return Some(leaf.offset() + 1);
+    }
+
+    // An unfinished label or reference is lexed as text or an error, while `<` in code is an
+    // operator
+    let before = &source.text()[..offset];
+    let start = before.trim_end_matches(is_label_char).len();
+    let after_marker = before[..start].ends_with(['<', '@']);
+    let is_text = matches!(leaf.kind(), SyntaxKind::Text | SyntaxKind::Error);
+    let in_text = after_marker && is_text && in_markup(&leaf);
+    let in_cite = before[..start].ends_with('<') && in_call(&leaf, "cite");
+    (in_text || in_cite).then_some(start)
+}
+
+/// Lists the labels attached in the source, for completing the reference or label being typed at
+/// the offset, along with the offset at which the label name starts. Typst only completes labels
+/// known to the last compiled document, which misses labels added since then.
+pub fn label_completions(
+    source: &Source,
+    offset: usize,
+) -> Option<(usize, Vec<typst_ide::Completion>)> {
+    let start = label_start(source, offset)?;
+    let typed = &source.text()[start..offset];
+    let completions = label_uses(source)
+        .into_iter()
+        .filter(|label| label.is_label && label.name.as_str() != typed)
+        .map(|label| label.name)
+        .unique()
+        .map(|name| typst_ide::Completion {
+            kind: typst_ide::CompletionKind::Constant,
+            label: name,
+            apply: None,
+            detail: None,
+        })
+        .collect();
+
+    Some((start, completions))
+}
+
+/// Whether the offset is in the arguments of a call in code or math, where typing `:` starts a
+/// named argument. Elsewhere, `:` is usually prose, which shouldn't trigger completion.
+pub fn in_args(source: &Source, offset: usize) -> bool {
+    let Some(leaf) = LinkedNode::new(source.root()).leaf_at(offset) else {
+        return false;
+    };
+
+    let mut ancestor = leaf.parent();
+    while let Some(node) = ancestor {
+        match node.kind() {
+            SyntaxKind::Args => return true,
+            SyntaxKind::Markup => return false,
+            _ => ancestor = node.parent(),
+        }
+    }
+    false
+}
+
+/// Whether the node is in the arguments of a call to the function with the name
+fn in_call(node: &LinkedNode, name: &str) -> bool {
+    let mut ancestor = node.parent();
+    while let Some(node) = ancestor {
+        if let Some(call) = node.cast::<ast::FuncCall>() {
+            if matches!(call.callee(), ast::Expr::Ident(ident) if ident.as_str() == name) {
+                return true;
+            }
+        }
+        ancestor = node.parent();
+    }
+    false
+}
+
+/// Whether the node is in markup, rather than in code or math
+fn in_markup(node: &LinkedNode) -> bool {
+    let mut ancestor = node.parent();
+    while let Some(node) = ancestor {
+        match node.kind() {
+            SyntaxKind::Markup => return true,
+            SyntaxKind::Math | SyntaxKind::Code | SyntaxKind::Args | SyntaxKind::Params => {
+                return false
+            }
+            _ => ancestor = node.parent(),
+        }
+    }
+    false
+}
+
+/// Lists the entries of the directory being typed in a path string at the offset, along with the
+/// offset at which the entry name starts. Paths starting with `/` are relative to the root, and
+/// other paths are relative to the file. Entries outside the root are never listed.
+fn path_completions(
+    source: &Source,
+    offset: usize,
+    file_path: &Path,
+    root: &Path,
+) -> Option<(usize, Vec<CompletionItem>)> {
+    let leaf = LinkedNode::new(source.root()).leaf_at(offset)?;
+    let range = leaf.range();
+    if leaf.kind() != SyntaxKind::Str || offset <= range.start || offset >= range.end {
+        return None;
+    }
+
+    let extensions = path_extensions(&leaf)?;
+
+    // Skip the opening quote
+    let typed = &source.text()[range.start + 1..offset];
+    if typed.starts_with('@') {
+        // Package imports are completed by Typst
+        return None;
+    }
+    let (typed_dir, _) = typed.rsplit_once('/').unwrap_or(("", typed));
+
+    let dir = match typed.strip_prefix('/') {
+        Some(_) => root.join(typed_dir.trim_start_matches('/')),
+        None => file_path.parent()?.join(typed_dir),
+    };
+    let dir = normalize(&dir);
+    if !dir.starts_with(root) {
+        return None;
+    }
+
+    let mut completions: Vec<_> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') {
+                return None;
+            }
+
+            let path = entry.path();
+            if path.is_dir() {
+                return Some(CompletionItem {
+                    label: format!("{name}/"),
+                    kind: Some(CompletionItemKind::FOLDER),
+                    ..Default::default()
+                });
+            }
+
+            let extension = path.extension()?.to_str()?.to_lowercase();
+            extensions
+                .contains(&extension.as_str())
+                .then(|| CompletionItem {
+                    label: name,
+                    kind: Some(CompletionItemKind::FILE),
+                    ..Default::default()
+                })
+        })
+        .collect();
+    completions.sort_by(|a, b| a.label.cmp(&b.label));
+
+    let start = offset - typed.len() + typed_dir.len() + usize::from(typed.contains('/'));
+    Some((start, completions))
+}
+
+/// The extensions of files which make sense in the string, if it is a path
+fn path_extensions(string: &LinkedNode) -> Option<&'static [&'static str]> {
+    let parent = string.parent()?;
+
+    if let Some(import) = parent.cast::<ast::ModuleImport>() {
+        return (import.source().span() == string.span()).then_some(SOURCE_EXTENSIONS);
+    }
+    if let Some(include) = parent.cast::<ast::ModuleInclude>() {
+        return (include.source().span() == string.span()).then_some(SOURCE_EXTENSIONS);
+    }
+
+    let args = parent.cast::<ast::Args>()?;
+    let call = parent.parent()?.cast::<ast::FuncCall>()?;
+    let ast::Expr::Ident(callee) = call.callee() else {
+        return None;
+    };
+    let path = args.items().find_map(|arg| match arg {
+        ast::Arg::Pos(expr) => Some(expr),
+        _ => None,
+    })?;
+
+    (callee.as_str() == "image" && path.span() == string.span()).then_some(IMAGE_EXTENSIONS)
+}
+
+/// Resolves `.` and `..` in the path without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn grid_round_trip() {
+        let mut completions = vec![CompletionItem {
+            label: "grid".to_owned(),
+            kind: Some(CompletionItemKind::FUNCTION),
+            ..Default::default()
+        }];
+
+        attach_resolve_data(&mut completions);
+        assert!(completions[0].documentation.is_none());
+
+        // The client sends the completion back as JSON
+        let sent = serde_json::to_value(&completions[0]).unwrap();
+        let received: CompletionItem = serde_json::from_value(sent).unwrap();
+        let resolved = resolve_completion(received);
+
+        let Some(Documentation::MarkupContent(docs)) = resolved.documentation else {
+            panic!("expected markdown documentation");
+        };
+        assert_eq!(docs.kind, MarkupKind::Markdown);
+        assert!(docs.value.contains("grid"));
+    }
+
+    fn label_names(text: &str, offset: usize) -> Option<(usize, Vec<String>)> {
+        let source = Source::detached(text);
+        let (start, completions) = label_completions(&source, offset)?;
+        let names = completions
+            .into_iter()
+            .map(|completion| completion.label.to_string())
+            .collect();
+        Some((start, names))
+    }
+
+    #[test]
+    fn labels_complete_references() {
+        let text = "= Intro <intro>\n= Usage <usage>\nSee @in";
+        let (start, names) = label_names(text, text.len()).unwrap();
+        assert_eq!(start, text.len() - 2);
+        assert_eq!(names, ["intro", "usage"]);
+
+        let text = "= Intro <intro>\nSee @";
+        let (start, names) = label_names(text, text.len()).unwrap();
+        assert_eq!(start, text.len());
+        assert_eq!(names, ["intro"]);
+    }
+
+    #[test]
+    fn labels_not_offered_outside_references() {
+        let text = "= Intro <intro>\nIf $a < b$ then";
+        assert!(label_names(text, text.find('<').unwrap() + 1).is_none());
+        assert!(label_names(text, text.len()).is_none());
+
+        let text = "= Intro <intro>\n#let x = (1 <";
+        assert!(label_names(text, text.len()).is_none());
+    }
+
+    #[test]
+    fn colon_in_args_but_not_prose() {
+        let after_colon = |text: &str| {
+            let source = Source::detached(text);
+            in_args(&source, text.find(':').unwrap() + 1)
+        };
+
+        assert!(after_colon("#text(fill: red)[x]"));
+        assert!(after_colon("$attach(a, t: b)$"));
+        assert!(!after_colon("Note: this is prose"));
+        assert!(!after_colon("#text(red)[Note: prose]"));
+    }
+
+    #[test]
+    fn replace_rest_of_identifier() {
+        let source = Source::detached("#gri and more");
+        assert_eq!(replace_end(&source, 3), 4);
+        assert_eq!(replace_end(&source, 4), 4);
+        assert_eq!(replace_end(&source, 8), 8);
+    }
+
+    fn path_labels(text: &str, offset: usize, root: &Path) -> Option<(usize, Vec<String>)> {
+        let source = Source::detached(text);
+        let (start, completions) = path_completions(&source, offset, &root.join("main.typ"), root)?;
+        Some((
+            start,
+            completions
+                .into_iter()
+                .map(|completion| completion.label)
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir(root.join("figures")).unwrap();
+        for file in [
+            "main.typ",
+            "chapter.typ",
+            "logo.png",
+            "notes.txt",
+            "figures/plot.svg",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let (start, labels) = path_labels(r#"#include "c""#, 11, root).unwrap();
+        assert_eq!(start, 10);
+        assert_eq!(labels, ["chapter.typ", "figures/", "main.typ"]);
+
+        let (start, labels) = path_labels(r#"#image("figures/")"#, 16, root).unwrap();
+        assert_eq!(start, 16);
+        assert_eq!(labels, ["plot.svg"]);
+
+        let (_, labels) = path_labels(r#"#image("/")"#, 9, root).unwrap();
+        assert_eq!(labels, ["figures/", "logo.png"]);
+
+        assert!(path_labels(r#"#image("../")"#, 11, root).is_none());
+        assert!(path_labels(r#"#text("")"#, 7, root).is_none());
+    }
+}
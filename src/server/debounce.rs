@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+/// Lets only the last of a burst of calls for each key through, once no newer call has been made
+/// for some time
+#[derive(Debug)]
+pub struct Debouncer<K> {
+    pending: parking_lot::Mutex<HashMap<K, (u64, CancellationToken)>>,
+    next_id: AtomicU64,
+}
+
+impl<K> Default for Debouncer<K> {
+    fn default() -> Self {
+        Self {
+            pending: Default::default(),
+            next_id: Default::default(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> Debouncer<K> {
+    /// Waits for `delay`, returning whether no newer call was made for `key` in the meantime. A
+    /// superseded call returns `false` as soon as the newer call is made.
+    pub async fn wait(&self, key: K, delay: Duration) -> bool {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        let previous = self.pending.lock().insert(key.clone(), (id, token.clone()));
+        if let Some((_, previous)) = previous {
+            previous.cancel();
+        }
+
+        tokio::select! {
+            _ = token.cancelled() => false,
+            _ = tokio::time::sleep(delay) => {
+                let mut pending = self.pending.lock();
+                // A newer call may have been made just as the delay ran out
+                let is_latest = pending.get(&key).is_some_and(|(latest, _)| *latest == id);
+                if is_latest {
+                    pending.remove(&key);
+                }
+                is_latest
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn rapid_edits_collapse_into_one_compile() {
+        let debouncer = Debouncer::default();
+        let edit = |n: u64| {
+            let debouncer = &debouncer;
+            async move {
+                tokio::time::sleep(Duration::from_millis(n * 10)).await;
+                debouncer.wait("main.typ", Duration::from_millis(100)).await
+            }
+        };
+
+        let compiles = tokio::join!(edit(0), edit(1), edit(2));
+        assert_eq!(compiles, (false, false, true));
+
+        // Once the burst is over, the next edit compiles again
+        assert!(debouncer.wait("main.typ", Duration::ZERO).await);
+    }
+}
@@ -0,0 +1,583 @@
+use std::collections::HashSet;
+use std::iter;
+
+use tower_lsp::lsp_types::{Location, Url};
+use typst::foundations::Bytes;
+use typst::syntax::ast::AstNode;
+use typst::syntax::package::{PackageManifest, PackageSpec};
+use typst::syntax::{ast, FileId, LinkedNode, Source, Span, SyntaxKind, VirtualPath};
+use typst::World;
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+use crate::workspace::world::ProjectWorld;
+
+use super::TypstServer;
+
+/// How many imports deep to follow a name before giving up, in case of cyclic imports
+const MAX_IMPORT_DEPTH: usize = 16;
+
+/// Reads the files which imports refer to
+pub trait SourceLoader {
+    fn load_source(&self, id: FileId) -> Option<Source>;
+    fn load_bytes(&self, id: FileId) -> Option<Bytes>;
+}
+
+impl SourceLoader for ProjectWorld {
+    fn load_source(&self, id: FileId) -> Option<Source> {
+        self.source(id).ok()
+    }
+
+    fn load_bytes(&self, id: FileId) -> Option<Bytes> {
+        self.file(id).ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    /// A name bound by `let`, a parameter, a loop, or an import
+    Binding,
+    /// A whole module, bound by an import without items
+    Module,
+}
+
+/// Where a name is bound
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub id: FileId,
+    /// The range of the binding identifier. For modules, this is the empty range at the start of
+    /// the module's file.
+    pub range: TypstRange,
+    pub kind: DefinitionKind,
+}
+
+impl Definition {
+    fn binding(source: &Source, span: Span) -> Option<Self> {
+        Some(Self {
+            id: source.id(),
+            range: source.range(span)?,
+            kind: DefinitionKind::Binding,
+        })
+    }
+
+    fn module(id: FileId) -> Self {
+        Self {
+            id,
+            range: 0..0,
+            kind: DefinitionKind::Module,
+        }
+    }
+}
+
+impl TypstServer {
+    /// Finds where the identifier at `position` is bound, following imports into other files and
+    /// packages
+    #[tracing::instrument(skip(self))]
+    pub async fn goto_definition(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Location>> {
+        let position_encoding = self.position_encoding();
+        let (project, full_id) = self.project_and_full_id(uri).await?;
+
+        let definition = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| {
+                let source = world.source(full_id.into()).ok()?;
+                let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+                find_definition(&world, &source, offset)
+            })
+            .await;
+        let Some(definition) = definition else {
+            return Ok(None);
+        };
+
+        let uri = project
+            .full_id_to_uri(project.fill_id(definition.id))
+            .await?;
+        let source = project.read_source_by_uri(&uri)?;
+        let range = typst_to_lsp::range(definition.range, &source, position_encoding).raw_range;
+
+        Ok(Some(Location { uri, range }))
+    }
+}
+
+/// Finds where the identifier at `offset` is bound
+pub fn find_definition(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    offset: usize,
+) -> Option<Definition> {
+    let root = LinkedNode::new(source.root());
+    let ident = ident_at(&root, offset)?;
+    definition_of(loader, source, &ident)
+}
+
+/// Gets the identifier at `offset`, if any. The offset may be just before or just after it.
+pub fn ident_at<'a>(root: &LinkedNode<'a>, offset: usize) -> Option<LinkedNode<'a>> {
+    [offset, offset + 1]
+        .into_iter()
+        .filter_map(|offset| root.leaf_at(offset))
+        .find(is_ident)
+}
+
+pub fn is_ident(node: &LinkedNode) -> bool {
+    matches!(node.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent)
+}
+
+/// Collects the names of identifiers used in code and math, other than the `declarations`. Field
+/// names are left out, since `a.b` doesn't use a variable named `b`. Strings and labels aren't
+/// identifiers, so names only mentioned in them don't count as used.
+pub fn collect_used_names<'a>(
+    node: &LinkedNode<'a>,
+    declarations: &HashSet<Span>,
+    used: &mut HashSet<&'a str>,
+) {
+    let is_field = node
+        .parent()
+        .and_then(|parent| parent.cast::<ast::FieldAccess>())
+        .is_some_and(|access| access.field().span() == node.span());
+
+    if is_ident(node) && !is_field && !declarations.contains(&node.span()) {
+        used.insert(node.get().text().as_str());
+    }
+
+    for child in node.children() {
+        collect_used_names(&child, declarations, used);
+    }
+}
+
+/// Finds where an identifier in `source` is bound. This is purely syntactic, so names which are
+/// only known after evaluation, like those from the standard library, have no definition.
+pub fn definition_of(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    ident: &LinkedNode,
+) -> Option<Definition> {
+    // In `module.item`, `item` is bound in the module
+    if let Some(parent) = ident.parent() {
+        if let Some(access) = parent.cast::<ast::FieldAccess>() {
+            if access.field().span() == ident.span() {
+                let target = parent
+                    .children()
+                    .find(|child| child.span() == access.target().to_untyped().span())
+                    .filter(is_ident)?;
+                let module = definition_of(loader, source, &target)?;
+                return match module.kind {
+                    DefinitionKind::Module => lookup_top_level(loader, module.id, ident.text(), 0),
+                    DefinitionKind::Binding => None,
+                };
+            }
+        }
+    }
+
+    resolve(loader, source, ident, ident.text(), 0)
+}
+
+/// Walks outwards from `node` through the statements before it and the scopes around it, looking
+/// for the nearest binding of `name`
+fn resolve(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    node: &LinkedNode,
+    name: &str,
+    depth: usize,
+) -> Option<Definition> {
+    if let Some(definition) = declaration_at(loader, source, node, depth) {
+        return Some(definition);
+    }
+
+    let mut node = node.clone();
+    while let Some(parent) = node.parent().cloned() {
+        let mut sibling = node.prev_sibling();
+        while let Some(statement) = sibling {
+            if let Some(definition) = statement_definition(loader, source, &statement, name, depth)
+            {
+                return Some(definition);
+            }
+            sibling = statement.prev_sibling();
+        }
+
+        if let Some(definition) = enclosing_definition(source, &parent, &node, name) {
+            return Some(definition);
+        }
+
+        node = parent;
+    }
+
+    None
+}
+
+/// If `ident` is itself where a name is bound, gets its definition. Names bound by importing an
+/// item resolve to the item in the imported module.
+fn declaration_at(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    ident: &LinkedNode,
+    depth: usize,
+) -> Option<Definition> {
+    let span = ident.span();
+    let ancestors = iter::successors(ident.parent(), |node| node.parent());
+
+    for ancestor in ancestors {
+        if let Some(import) = ancestor.cast::<ast::ModuleImport>() {
+            if import_bindings(import)
+                .iter()
+                .any(|bound| bound.span() == span)
+            {
+                return import_definition(loader, source, import, ident.text(), depth);
+            }
+
+            // The original name in `item as renamed` refers to the item in the module
+            let is_original_name = match import.imports() {
+                Some(ast::Imports::Items(items)) => {
+                    items.iter().any(|item| item.original_name().span() == span)
+                }
+                _ => false,
+            };
+            if is_original_name {
+                let module = import_target(loader, source.id(), import.source())?;
+                return lookup_top_level(loader, module, ident.text(), depth + 1);
+            }
+
+            return None;
+        }
+
+        if bound_idents(ancestor)
+            .iter()
+            .any(|bound| bound.span() == span)
+        {
+            return Definition::binding(source, span);
+        }
+    }
+
+    None
+}
+
+/// Whether `name` is already bound in the scope where `definition` binds its own name, so that
+/// renaming the definition to `name` would make one of the bindings unreachable
+pub fn is_bound_alongside(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    definition: &Definition,
+    name: &str,
+) -> bool {
+    let root = LinkedNode::new(source.root());
+    let Some(ident) = root.leaf_at(definition.range.start + 1) else {
+        return false;
+    };
+    let span = ident.span();
+
+    for ancestor in iter::successors(ident.parent(), |node| node.parent()) {
+        let is_function_name = ancestor
+            .cast::<ast::Closure>()
+            .and_then(|closure| closure.name())
+            .is_some_and(|closure_name| closure_name.span() == span);
+        let is_scope =
+            ancestor.cast::<ast::Closure>().is_some() || ancestor.cast::<ast::ForLoop>().is_some();
+
+        // Parameters and loop variables share a scope with each other, but a function's own name
+        // is bound for the code after its `let`
+        if is_scope && !is_function_name {
+            return bound_idents(ancestor)
+                .iter()
+                .any(|bound| bound.get() == name);
+        }
+
+        let is_statement = ancestor.cast::<ast::LetBinding>().is_some()
+            || ancestor.cast::<ast::ModuleImport>().is_some();
+        if is_statement {
+            let Some(scope) = ancestor.parent() else {
+                return false;
+            };
+            return scope.children().any(|statement| {
+                statement_definition(loader, source, &statement, name, 0).is_some()
+            });
+        }
+    }
+
+    false
+}
+
+/// Identifiers bound where they are written by a node, whether for the code after it or for its
+/// own body
+fn bound_idents<'a>(node: &'a LinkedNode) -> Vec<ast::Ident<'a>> {
+    if let Some(binding) = node.cast::<ast::LetBinding>() {
+        let_bindings(binding)
+    } else if let Some(closure) = node.cast::<ast::Closure>() {
+        closure_bindings(closure)
+    } else if let Some(for_loop) = node.cast::<ast::ForLoop>() {
+        for_loop.pattern().bindings()
+    } else {
+        Vec::new()
+    }
+}
+
+pub fn let_bindings(binding: ast::LetBinding) -> Vec<ast::Ident> {
+    match binding.kind() {
+        ast::LetBindingKind::Normal(pattern) => pattern.bindings(),
+        ast::LetBindingKind::Closure(ident) => vec![ident],
+    }
+}
+
+fn closure_bindings(closure: ast::Closure) -> Vec<ast::Ident> {
+    let params = closure.params().children().flat_map(|param| match param {
+        ast::Param::Pos(pattern) => pattern.bindings(),
+        ast::Param::Named(named) => vec![named.name()],
+        ast::Param::Sink(spread) => spread.sink_ident().into_iter().collect(),
+    });
+
+    closure.name().into_iter().chain(params).collect()
+}
+
+/// Names bound by an import, other than by a wildcard or the module's default name
+pub fn import_bindings(import: ast::ModuleImport) -> Vec<ast::Ident> {
+    let items = match import.imports() {
+        Some(ast::Imports::Items(items)) => items.iter().map(|item| item.bound_name()).collect(),
+        _ => Vec::new(),
+    };
+
+    import.new_name().into_iter().chain(items).collect()
+}
+
+/// Finds the binding of `name` by a statement, which makes it visible to the statements after it
+fn statement_definition(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    statement: &LinkedNode,
+    name: &str,
+    depth: usize,
+) -> Option<Definition> {
+    if let Some(binding) = statement.cast::<ast::LetBinding>() {
+        let ident = let_bindings(binding)
+            .into_iter()
+            .rev()
+            .find(|ident| ident.get() == name)?;
+        Definition::binding(source, ident.span())
+    } else if let Some(import) = statement.cast::<ast::ModuleImport>() {
+        import_definition(loader, source, import, name, depth)
+    } else {
+        None
+    }
+}
+
+/// Finds the binding of `name` by a node for one of its children, like a closure's parameters for
+/// its body
+fn enclosing_definition(
+    source: &Source,
+    parent: &LinkedNode,
+    child: &LinkedNode,
+    name: &str,
+) -> Option<Definition> {
+    let bindings = if let Some(closure) = parent.cast::<ast::Closure>() {
+        closure_bindings(closure)
+    } else if let Some(for_loop) = parent.cast::<ast::ForLoop>() {
+        // The iterable can't see the loop's own bindings
+        if child.span() != for_loop.body().to_untyped().span() {
+            return None;
+        }
+        for_loop.pattern().bindings()
+    } else {
+        return None;
+    };
+
+    let ident = bindings
+        .into_iter()
+        .rev()
+        .find(|ident| ident.get() == name)?;
+    Definition::binding(source, ident.span())
+}
+
+fn import_definition(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    import: ast::ModuleImport,
+    name: &str,
+    depth: usize,
+) -> Option<Definition> {
+    let module = import_target(loader, source.id(), import.source());
+
+    if let Some(new_name) = import.new_name() {
+        if new_name.get() == name {
+            return module
+                .map(Definition::module)
+                .or_else(|| Definition::binding(source, new_name.span()));
+        }
+    }
+
+    match import.imports() {
+        Some(ast::Imports::Wildcard) => lookup_top_level(loader, module?, name, depth + 1),
+        Some(ast::Imports::Items(items)) => {
+            let item = items.iter().find(|item| item.bound_name().get() == name)?;
+            module
+                .and_then(|module| {
+                    lookup_top_level(loader, module, item.original_name().get(), depth + 1)
+                })
+                .or_else(|| Definition::binding(source, item.bound_name().span()))
+        }
+        None if import.new_name().is_none() => {
+            let default_name = default_module_name(import.source())?;
+            (default_name == name).then(|| module.map(Definition::module))?
+        }
+        None => None,
+    }
+}
+
+/// The file an import refers to. For packages, this is the package's entrypoint.
+fn import_target(loader: &dyn SourceLoader, current: FileId, source: ast::Expr) -> Option<FileId> {
+    let ast::Expr::Str(path) = source else {
+        return None;
+    };
+    let path = path.get();
+
+    if !path.starts_with('@') {
+        return Some(current.join(&path));
+    }
+
+    let spec: PackageSpec = path.parse().ok()?;
+    let manifest_id = FileId::new(Some(spec.clone()), VirtualPath::new("typst.toml"));
+    let manifest = loader.load_bytes(manifest_id)?;
+    let manifest: PackageManifest = toml::from_str(std::str::from_utf8(&manifest).ok()?).ok()?;
+
+    Some(FileId::new(
+        Some(spec),
+        VirtualPath::new(manifest.package.entrypoint.as_str()),
+    ))
+}
+
+/// The name an import without items or `as` binds the module to, like `utils` for `"utils.typ"`
+fn default_module_name(source: ast::Expr) -> Option<String> {
+    let ast::Expr::Str(path) = source else {
+        return None;
+    };
+    let path = path.get();
+
+    if path.starts_with('@') {
+        let spec: PackageSpec = path.parse().ok()?;
+        return Some(spec.name.to_string());
+    }
+
+    let stem = std::path::Path::new(path.as_str()).file_stem()?;
+    Some(stem.to_string_lossy().into_owned())
+}
+
+/// Finds the last top-level binding of `name` in a module, which is what importing it gives
+pub fn lookup_top_level(
+    loader: &dyn SourceLoader,
+    module: FileId,
+    name: &str,
+    depth: usize,
+) -> Option<Definition> {
+    if depth > MAX_IMPORT_DEPTH {
+        return None;
+    }
+
+    let source = loader.load_source(module)?;
+    let root = LinkedNode::new(source.root());
+    let statements: Vec<_> = root.children().collect();
+
+    statements
+        .iter()
+        .rev()
+        .find_map(|statement| statement_definition(loader, &source, statement, name, depth))
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Serves sources from memory, as if they were files in one project
+    #[derive(Default)]
+    pub struct Fixture {
+        sources: HashMap<FileId, Source>,
+    }
+
+    impl Fixture {
+        pub fn with(self, path: &str, text: &str) -> Self {
+            self.with_id(Self::id(path), text)
+        }
+
+        pub fn with_id(mut self, id: FileId, text: &str) -> Self {
+            self.sources.insert(id, Source::new(id, text.to_owned()));
+            self
+        }
+
+        pub fn id(path: &str) -> FileId {
+            FileId::new(None, VirtualPath::new(path))
+        }
+
+        pub fn source(&self, path: &str) -> &Source {
+            &self.sources[&Self::id(path)]
+        }
+    }
+
+    impl SourceLoader for Fixture {
+        fn load_source(&self, id: FileId) -> Option<Source> {
+            self.sources.get(&id).cloned()
+        }
+
+        fn load_bytes(&self, id: FileId) -> Option<Bytes> {
+            let source = self.sources.get(&id)?;
+            Some(Bytes::from(source.text().as_bytes().to_vec()))
+        }
+    }
+
+    /// Finds the definition of the `n`th occurrence of `needle` in `path`
+    fn definition(fixture: &Fixture, path: &str, needle: &str, n: usize) -> Option<Definition> {
+        let source = fixture.source(path);
+        let (offset, _) = source.text().match_indices(needle).nth(n).unwrap();
+        find_definition(fixture, source, offset + 1)
+    }
+
+    #[test]
+    fn local_binding() {
+        let fixture = Fixture::default().with("main.typ", "#let x = 1\n#x");
+
+        let definition = definition(&fixture, "main.typ", "x", 1).unwrap();
+        assert_eq!(definition.id, Fixture::id("main.typ"));
+        assert_eq!(definition.range, 5..6);
+    }
+
+    #[test]
+    fn parameter_shadows_outer_binding() {
+        let fixture = Fixture::default().with("main.typ", "#let a = 1\n#let f(a) = a + 1\n#a");
+
+        let inner = definition(&fixture, "main.typ", "a", 2).unwrap();
+        assert_eq!(inner.range, 18..19);
+
+        let outer = definition(&fixture, "main.typ", "a", 3).unwrap();
+        assert_eq!(outer.range, 5..6);
+    }
+
+    #[test]
+    fn binding_in_block_is_not_visible_after_it() {
+        let fixture = Fixture::default().with("main.typ", "#{ let y = 1 }\n#y");
+
+        assert_eq!(definition(&fixture, "main.typ", "y", 1), None);
+    }
+
+    #[test]
+    fn imported_item() {
+        let fixture = Fixture::default()
+            .with("main.typ", "#import \"lib.typ\": add\n#add(1, 2)")
+            .with("lib.typ", "#let add(a, b) = a + b");
+
+        let definition = definition(&fixture, "main.typ", "add", 1).unwrap();
+        assert_eq!(definition.id, Fixture::id("lib.typ"));
+        assert_eq!(definition.range, 5..8);
+    }
+
+    #[test]
+    fn module_field_access() {
+        let fixture = Fixture::default()
+            .with("main.typ", "#import \"lib.typ\"\n#lib.value")
+            .with("lib.typ", "#let value = 2");
+
+        let definition = definition(&fixture, "main.typ", "value", 0).unwrap();
+        assert_eq!(definition.id, Fixture::id("lib.typ"));
+        assert_eq!(definition.range, 5..10);
+    }
+}
@@ -1,60 +1,138 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
 use futures::future::join_all;
+use itertools::Itertools;
+use tokio::sync::Mutex;
 use tower_lsp::lsp_types::{Diagnostic, Url};
 use tower_lsp::Client;
 
+use crate::lsp_typst_boundary::typst_to_lsp;
+
 use super::TypstServer;
 
 pub type DiagnosticsMap = HashMap<Url, Vec<Diagnostic>>;
 
 impl TypstServer {
     pub async fn update_all_diagnostics(&self, diagnostics: DiagnosticsMap) {
-        self.diagnostics.lock().await.publish(diagnostics).await;
+        let interval =
+            Duration::from_millis(self.config.read().await.diagnostics_publish_interval_ms);
+        DiagnosticsManager::publish_throttled(&self.diagnostics, diagnostics, interval).await;
+    }
+
+    /// Publishes syntax errors in one source right away, without waiting for a compile. Other
+    /// sources keep their diagnostics.
+    pub async fn publish_syntax_diagnostics(&self, uri: &Url, diagnostics: Vec<Diagnostic>) {
+        self.diagnostics
+            .lock()
+            .await
+            .publish_for(uri.clone(), diagnostics)
+            .await;
+    }
+
+    /// Finds the syntax errors in a source without compiling it
+    pub async fn syntax_diagnostics(&self, uri: &Url) -> anyhow::Result<Vec<Diagnostic>> {
+        let const_config = self.const_config().context("server not initialized")?;
+        let diagnostics = self
+            .scope_with_source(uri)
+            .await?
+            .run(|source, _| typst_to_lsp::syntax_diagnostics(source, const_config));
+        Ok(diagnostics)
+    }
+}
+
+/// Adds diagnostics for `uri` to `diagnostics`, skipping any which are already there. Syntax
+/// errors are reported both by the parse-only pass and by compilation, so they would otherwise
+/// appear twice.
+pub fn merge_diagnostics(diagnostics: &mut DiagnosticsMap, uri: &Url, extra: Vec<Diagnostic>) {
+    let existing = diagnostics.entry(uri.clone()).or_default();
+    for diagnostic in extra {
+        let duplicate = existing
+            .iter()
+            .any(|other| other.range == diagnostic.range && other.message == diagnostic.message);
+        if !duplicate {
+            existing.push(diagnostic);
+        }
     }
 }
 
 pub struct DiagnosticsManager {
     client: Client,
-    last_published_for: Vec<Url>,
+    /// Sources which had some diagnostic in the last publish
+    last_published_for: HashSet<Url>,
+    last_published_at: Option<Instant>,
+    /// The latest diagnostics held back by throttling, which a scheduled task will publish
+    pending: Option<DiagnosticsMap>,
 }
 
 impl DiagnosticsManager {
     pub fn new(client: Client) -> Self {
         Self {
             client,
-            last_published_for: Vec::new(),
+            last_published_for: HashSet::new(),
+            last_published_at: None,
+            pending: None,
         }
     }
 
+    /// Publishes diagnostics at most once per `interval`. Diagnostics arriving sooner are held
+    /// back and published once the interval has passed, replacing any which were held back before,
+    /// so the latest diagnostics are always published eventually.
+    pub async fn publish_throttled(
+        this: &Arc<Mutex<Self>>,
+        next_diagnostics: DiagnosticsMap,
+        interval: Duration,
+    ) {
+        let mut manager = this.lock().await;
+
+        let wait = manager
+            .last_published_at
+            .map(|last| interval.saturating_sub(last.elapsed()))
+            .unwrap_or_default();
+        if wait.is_zero() {
+            manager.publish(next_diagnostics).await;
+            return;
+        }
+
+        let already_scheduled = manager.pending.replace(next_diagnostics).is_some();
+        if already_scheduled {
+            return;
+        }
+
+        let this = Arc::clone(this);
+        tokio::spawn(async move {
+            tokio::time::sleep(wait).await;
+            let mut manager = this.lock().await;
+            // Diagnostics may have been published directly in the meantime, superseding these
+            if let Some(pending) = manager.pending.take() {
+                manager.publish(pending).await;
+            }
+        });
+    }
+
     pub async fn publish(&mut self, next_diagnostics: DiagnosticsMap) {
-        let should_clear = self.should_clear(&next_diagnostics);
+        self.pending = None;
+        self.last_published_at = Some(Instant::now());
+
+        let should_clear = should_clear(&self.last_published_for, &next_diagnostics).collect_vec();
         self.push(should_clear).await;
 
-        // We just used the cache, and won't need it again, so we can update it now
-        self.update_cache(&next_diagnostics);
+        self.last_published_for = with_diagnostics(&next_diagnostics);
 
         self.push(next_diagnostics).await;
     }
 
-    /// Gets sources which had some diagnostic published last time, but not this time. The LSP
-    /// specifies that files will not have diagnostics updated, including removed, without an
-    /// explicit update, so we need to send an empty `Vec` of diagnostics to these sources.
-    fn should_clear<'a>(
-        &'a self,
-        next_diagnostics: &'a DiagnosticsMap,
-    ) -> impl Iterator<Item = (Url, Vec<Diagnostic>)> + 'a {
-        self.last_published_for
-            .iter()
-            .filter(|uri| !next_diagnostics.contains_key(uri))
-            .cloned()
-            .map(|uri| (uri, vec![]))
-    }
+    /// Publishes diagnostics for a single source, leaving the others as they were published
+    pub async fn publish_for(&mut self, uri: Url, diagnostics: Vec<Diagnostic>) {
+        if diagnostics.is_empty() {
+            self.last_published_for.remove(&uri);
+        } else {
+            self.last_published_for.insert(uri.clone());
+        }
 
-    fn update_cache(&mut self, next_diagnostics: &DiagnosticsMap) {
-        self.last_published_for.clear();
-        self.last_published_for
-            .extend(next_diagnostics.keys().cloned());
+        self.push([(uri, diagnostics)]).await;
     }
 
     async fn push(&self, diagnostics: impl IntoIterator<Item = (Url, Vec<Diagnostic>)>) {
@@ -64,3 +142,61 @@ impl DiagnosticsManager {
         join_all(futures).await;
     }
 }
+
+/// Gets sources which had some diagnostic published last time, but not this time. The LSP
+/// specifies that files will not have diagnostics updated, including removed, without an explicit
+/// update, so we need to send an empty `Vec` of diagnostics to these sources.
+fn should_clear<'a>(
+    last_published_for: &'a HashSet<Url>,
+    next_diagnostics: &'a DiagnosticsMap,
+) -> impl Iterator<Item = (Url, Vec<Diagnostic>)> + 'a {
+    last_published_for
+        .iter()
+        .filter(|uri| !next_diagnostics.contains_key(uri))
+        .cloned()
+        .map(|uri| (uri, vec![]))
+}
+
+/// Gets sources with at least one diagnostic
+fn with_diagnostics(diagnostics: &DiagnosticsMap) -> HashSet<Url> {
+    diagnostics
+        .iter()
+        .filter(|(_, diagnostics)| !diagnostics.is_empty())
+        .map(|(uri, _)| uri.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::lsp_types::Range;
+
+    use super::*;
+
+    #[test]
+    fn fixed_error_in_imported_file_is_cleared() {
+        let main = Url::parse("file:///project/main.typ").unwrap();
+        let imported = Url::parse("file:///project/imported.typ").unwrap();
+        let error = Diagnostic::new_simple(Range::default(), "unknown variable".to_owned());
+
+        let broken = DiagnosticsMap::from([(imported.clone(), vec![error])]);
+        let published_for = with_diagnostics(&broken);
+
+        let fixed = DiagnosticsMap::from([(main, vec![])]);
+        let cleared: Vec<_> = should_clear(&published_for, &fixed).collect();
+
+        assert_eq!(cleared, vec![(imported, vec![])]);
+        assert!(with_diagnostics(&fixed).is_empty());
+    }
+
+    #[test]
+    fn merged_diagnostics_are_not_duplicated() {
+        let main = Url::parse("file:///project/main.typ").unwrap();
+        let error = Diagnostic::new_simple(Range::default(), "unclosed delimiter".to_owned());
+        let other = Diagnostic::new_simple(Range::default(), "expected expression".to_owned());
+        let mut diagnostics = DiagnosticsMap::from([(main.clone(), vec![error.clone()])]);
+
+        merge_diagnostics(&mut diagnostics, &main, vec![error.clone(), other.clone()]);
+
+        assert_eq!(diagnostics[&main], vec![error, other]);
+    }
+}
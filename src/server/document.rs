@@ -1,31 +1,147 @@
 use anyhow::bail;
 use tower_lsp::lsp_types::Url;
+use tracing::{error, info, warn};
 
-use crate::config::ExportPdfMode;
+use crate::config::{ExportPdfMode, FontOptions};
+use crate::workspace::create_font_manager;
 
+use super::diagnostics::{merge_diagnostics, DiagnosticsMap};
+use super::export::ThumbnailOptions;
 use super::TypstServer;
 
+/// A compilation to run in response to a change in some source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileTarget {
+    pub uri: Url,
+    pub export: bool,
+}
+
+impl CompileTarget {
+    /// When a main file is pinned, it is the document being worked on, so it is compiled instead
+    /// of the changed file, which may only be a part of it. `OnType` is the exception, since it
+    /// exports whichever file is edited.
+    pub fn on_change(export_pdf: ExportPdfMode, main: Option<&Url>, changed: &Url) -> Self {
+        match export_pdf {
+            ExportPdfMode::OnType => Self {
+                uri: changed.clone(),
+                export: true,
+            },
+            ExportPdfMode::OnPinnedMainType => Self {
+                uri: main.unwrap_or(changed).clone(),
+                export: main.is_some(),
+            },
+            _ => Self {
+                uri: main.unwrap_or(changed).clone(),
+                export: false,
+            },
+        }
+    }
+
+    /// Only `OnPinnedMainSave` compiles a pinned main file instead of the saved file. Returns
+    /// `None` if saving shouldn't compile.
+    pub fn on_save(export_pdf: ExportPdfMode, main: Option<&Url>, saved: &Url) -> Option<Self> {
+        let uri = match export_pdf {
+            ExportPdfMode::OnSave => saved,
+            ExportPdfMode::OnPinnedMainSave => main.unwrap_or(saved),
+            _ => return None,
+        };
+
+        Some(Self {
+            uri: uri.clone(),
+            export: true,
+        })
+    }
+}
+
 impl TypstServer {
     pub async fn on_source_changed(&self, uri: &Url) -> anyhow::Result<()> {
-        let config = self.config.read().await;
-        match config.export_pdf {
-            ExportPdfMode::OnType => self.run_diagnostics_and_export(uri).await?,
-            ExportPdfMode::OnPinnedMainType => {
-                if let Some(main_uri) = self.main_url().await {
-                    self.run_diagnostics_and_export(&main_uri).await?
-                } else {
-                    self.run_diagnostics(uri).await?
-                }
+        let (target, debounce) = {
+            let config = self.config.read().await;
+            let target =
+                CompileTarget::on_change(config.export_pdf, config.main_file.as_ref(), uri);
+            let debounce = matches!(
+                config.export_pdf,
+                ExportPdfMode::OnType | ExportPdfMode::OnPinnedMainType
+            )
+            .then(|| config.on_type_debounce());
+            (target, debounce)
+        };
+
+        // Syntax errors are shown right away, since waiting for the debounce and the compile would
+        // leave the user without feedback while typing. Without any, the file keeps its diagnostics
+        // from the last compile until the next one replaces them.
+        let syntax = self.syntax_diagnostics(uri).await?;
+        if !syntax.is_empty() {
+            self.publish_syntax_diagnostics(uri, syntax).await;
+        }
+
+        let generation = self.compile_generations.next(&target.uri);
+
+        // Compiling on every keystroke lags while typing quickly, so only the last of a burst of
+        // edits compiles
+        if let Some(delay) = debounce {
+            if !self.on_type_compiles.wait(target.uri.clone(), delay).await {
+                return Ok(());
+            }
+        }
+
+        let compiled = match self
+            .compile_source_if_current(&target.uri, generation)
+            .await
+        {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                // Syntax errors can still be shown when the document can't be compiled at all
+                let syntax = self.syntax_diagnostics(uri).await?;
+                self.update_all_diagnostics(DiagnosticsMap::from([(uri.clone(), syntax)]))
+                    .await;
+                return Err(err);
             }
-            _ => {
-                self.run_diagnostics(self.main_url().await.as_ref().unwrap_or(uri))
-                    .await?
+        };
+        let Some((document, mut diagnostics, status)) = compiled else {
+            return Ok(());
+        };
+
+        // The edited file may not be reached when compiling a pinned main file, so its syntax
+        // errors are found separately
+        merge_diagnostics(&mut diagnostics, uri, self.syntax_diagnostics(uri).await?);
+        self.update_all_diagnostics(diagnostics).await;
+        self.report_compile_status(status).await;
+        // Only current compiles of the document being worked on reach the preview
+        if let Some(document) = &document {
+            self.preview.push(document.clone());
+        }
+        if target.export {
+            match document {
+                Some(document) => self.export_pdf(&target.uri, document).await?,
+                None => bail!("failed to generate document after compilation"),
             }
         }
 
         Ok(())
     }
 
+    /// Loads the fonts the options allow, and compiles the documents again with them. Fonts are
+    /// loaded before locking the workspace, since searching for system fonts is slow.
+    pub async fn reload_fonts(&self, options: FontOptions) {
+        let fonts = match tokio::task::spawn_blocking(move || create_font_manager(options)).await {
+            Ok(fonts) => fonts,
+            Err(err) => {
+                error!(%err, "could not load fonts");
+                return;
+            }
+        };
+        let Some(workspace) = self.workspace() else {
+            warn!("not reloading fonts before the server is initialized");
+            return;
+        };
+        workspace.write().await.set_fonts(fonts);
+        info!(?options, "reloaded fonts");
+
+        let mains = self.dependencies.read().mains();
+        self.recompile_all(mains).await;
+    }
+
     pub async fn run_export(&self, uri: &Url) -> anyhow::Result<()> {
         let (document, _) = self.compile_source(uri).await?;
         match document {
@@ -36,10 +152,41 @@ impl TypstServer {
         Ok(())
     }
 
+    pub async fn run_thumbnails_export(
+        &self,
+        uri: &Url,
+        options: ThumbnailOptions,
+    ) -> anyhow::Result<()> {
+        let (document, _) = self.compile_source(uri).await?;
+        match document {
+            Some(document) => self.export_thumbnails(uri, document, options).await?,
+            None => bail!("failed to generate document after compilation"),
+        }
+
+        Ok(())
+    }
+
+    pub async fn run_pdf_page_export(&self, uri: &Url, page: usize) -> anyhow::Result<Url> {
+        let (document, _) = self.compile_source(uri).await?;
+        match document {
+            Some(document) => self.export_pdf_page(uri, document, page).await,
+            None => bail!("failed to generate document after compilation"),
+        }
+    }
+
+    pub async fn run_png_export(&self, uri: &Url, ppi: f32) -> anyhow::Result<Vec<Url>> {
+        let (document, _) = self.compile_source(uri).await?;
+        match document {
+            Some(document) => self.export_png(uri, document, ppi).await,
+            None => bail!("failed to generate document after compilation"),
+        }
+    }
+
     pub async fn run_diagnostics_and_export(&self, uri: &Url) -> anyhow::Result<()> {
-        let (document, diagnostics) = self.compile_source(uri).await?;
+        let (document, diagnostics, status) = self.compile_source_with_status(uri).await?;
 
         self.update_all_diagnostics(diagnostics).await;
+        self.report_compile_status(status).await;
         if let Some(document) = document {
             self.export_pdf(uri, document).await?;
         } else {
@@ -48,12 +195,66 @@ impl TypstServer {
 
         Ok(())
     }
+}
 
-    pub async fn run_diagnostics(&self, uri: &Url) -> anyhow::Result<()> {
-        let (_, diagnostics) = self.compile_source(uri).await?;
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        self.update_all_diagnostics(diagnostics).await;
+    #[test]
+    fn pinned_main_is_compiled_instead_of_edited_file() {
+        let main = Url::parse("file:///project/main.typ").unwrap();
+        let chapter = Url::parse("file:///project/chapter.typ").unwrap();
 
-        Ok(())
+        let pinned =
+            CompileTarget::on_change(ExportPdfMode::OnPinnedMainType, Some(&main), &chapter);
+        let unpinned = CompileTarget::on_change(ExportPdfMode::OnPinnedMainType, None, &chapter);
+        let on_save = CompileTarget::on_change(ExportPdfMode::OnSave, Some(&main), &chapter);
+        let on_type = CompileTarget::on_change(ExportPdfMode::OnType, Some(&main), &chapter);
+
+        assert_eq!(
+            pinned,
+            CompileTarget {
+                uri: main.clone(),
+                export: true
+            }
+        );
+        assert_eq!(
+            unpinned,
+            CompileTarget {
+                uri: chapter.clone(),
+                export: false
+            }
+        );
+        assert_eq!(
+            on_type,
+            CompileTarget {
+                uri: chapter,
+                export: true
+            }
+        );
+        assert_eq!(
+            on_save,
+            CompileTarget {
+                uri: main,
+                export: false
+            }
+        );
+    }
+
+    #[test]
+    fn pinned_main_is_compiled_on_save() {
+        let main = Url::parse("file:///project/main.typ").unwrap();
+        let chapter = Url::parse("file:///project/chapters/one.typ").unwrap();
+
+        let pinned = CompileTarget::on_save(ExportPdfMode::OnPinnedMainSave, Some(&main), &chapter);
+        let unpinned = CompileTarget::on_save(ExportPdfMode::OnPinnedMainSave, None, &chapter);
+        let on_save = CompileTarget::on_save(ExportPdfMode::OnSave, Some(&main), &chapter);
+        let on_type = CompileTarget::on_save(ExportPdfMode::OnType, Some(&main), &chapter);
+
+        assert_eq!(pinned.map(|target| target.uri), Some(main));
+        assert_eq!(unpinned.map(|target| target.uri), Some(chapter.clone()));
+        assert_eq!(on_save.map(|target| target.uri), Some(chapter));
+        assert_eq!(on_type, None);
     }
 }
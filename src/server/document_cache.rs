@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tower_lsp::lsp_types::Url;
+use typst::model::Document;
+
+/// The latest document compiled from each main file. Completion and hover look up the document
+/// compiled from the same main file as the source they are working on, so documents from other
+/// projects or main files don't leak in.
+#[derive(Debug, Default)]
+pub struct DocumentCache {
+    documents: parking_lot::Mutex<HashMap<Url, Arc<Document>>>,
+}
+
+impl DocumentCache {
+    pub fn insert(&self, main_uri: Url, document: Arc<Document>) {
+        self.documents.lock().insert(main_uri, document);
+    }
+
+    pub fn get(&self, main_uri: &Url) -> Option<Arc<Document>> {
+        self.documents.lock().get(main_uri).cloned()
+    }
+
+    pub fn remove(&self, main_uri: &Url) {
+        self.documents.lock().remove(main_uri);
+    }
+
+    pub fn clear(&self) {
+        self.documents.lock().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn documents_are_kept_per_main_file() {
+        let cache = DocumentCache::default();
+        let first_uri = Url::parse("file:///project/first.typ").unwrap();
+        let second_uri = Url::parse("file:///project/second.typ").unwrap();
+        let first = Arc::new(Document::default());
+        let second = Arc::new(Document::default());
+
+        cache.insert(first_uri.clone(), first.clone());
+        cache.insert(second_uri.clone(), second.clone());
+
+        assert!(Arc::ptr_eq(&cache.get(&first_uri).unwrap(), &first));
+        assert!(Arc::ptr_eq(&cache.get(&second_uri).unwrap(), &second));
+
+        cache.remove(&first_uri);
+
+        assert!(cache.get(&first_uri).is_none());
+        assert!(Arc::ptr_eq(&cache.get(&second_uri).unwrap(), &second));
+    }
+}
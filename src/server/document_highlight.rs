@@ -0,0 +1,92 @@
+use tower_lsp::lsp_types::{DocumentHighlight, DocumentHighlightKind, Url};
+use typst::syntax::Source;
+use typst::World;
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+
+use super::definition::SourceLoader;
+use super::references::{occurrences, symbol_at};
+use super::TypstServer;
+
+impl TypstServer {
+    /// Highlights where the symbol at `position` is named in the same file. Unlike references,
+    /// other files in the workspace aren't searched.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_document_highlights(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Vec<DocumentHighlight>> {
+        let position_encoding = self.position_encoding();
+        let (_, full_id) = self.project_and_full_id(uri).await?;
+
+        let highlights = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| {
+                let Ok(source) = world.source(full_id.into()) else {
+                    return Vec::new();
+                };
+                let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+
+                document_highlights(&world, &source, offset)
+                    .into_iter()
+                    .map(|(range, kind)| DocumentHighlight {
+                        range: typst_to_lsp::range(range, &source, position_encoding).raw_range,
+                        kind: Some(kind),
+                    })
+                    .collect()
+            })
+            .await;
+
+        Ok(highlights)
+    }
+}
+
+/// Finds each place in `source` where the symbol at `offset` is named. The declaration is a write,
+/// and every other occurrence is a read.
+pub fn document_highlights(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    offset: usize,
+) -> Vec<(TypstRange, DocumentHighlightKind)> {
+    let Some(symbol) = symbol_at(loader, source, offset) else {
+        return Vec::new();
+    };
+
+    occurrences(loader, source, &symbol)
+        .into_iter()
+        .map(|occurrence| {
+            let kind = if occurrence.is_declaration {
+                DocumentHighlightKind::WRITE
+            } else {
+                DocumentHighlightKind::READ
+            };
+            (occurrence.range, kind)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::definition::test::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn binding_with_two_uses() {
+        let fixture = Fixture::default().with("main.typ", "#let x = 1\n#x + #x");
+        let source = fixture.source("main.typ");
+
+        let highlights = document_highlights(&fixture, source, 17);
+
+        assert_eq!(
+            highlights,
+            vec![
+                (5..6, DocumentHighlightKind::WRITE),
+                (12..13, DocumentHighlightKind::READ),
+                (17..18, DocumentHighlightKind::READ),
+            ]
+        );
+    }
+}
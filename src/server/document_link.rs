@@ -0,0 +1,147 @@
+use tower_lsp::lsp_types::{DocumentLink, Url};
+use typst::syntax::{ast, FileId, LinkedNode, Source, SyntaxKind};
+
+use crate::lsp_typst_boundary::{typst_to_lsp, TypstRange};
+
+use super::TypstServer;
+
+/// Where a link in a source leads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    /// A file imported or included by a path
+    File(FileId),
+    /// An external URL
+    Url(String),
+}
+
+impl TypstServer {
+    /// Finds the imports, includes, and URLs in the file which the editor can open
+    #[tracing::instrument(skip(self))]
+    pub async fn get_document_links(&self, uri: &Url) -> anyhow::Result<Vec<DocumentLink>> {
+        let position_encoding = self.position_encoding();
+        let (project, _) = self.project_and_full_id(uri).await?;
+        let source = project.read_source_by_uri(uri)?;
+
+        let mut links = Vec::new();
+        for (range, target) in document_links(&source) {
+            let target = match target {
+                LinkTarget::File(id) => project.full_id_to_uri(project.fill_id(id)).await.ok(),
+                LinkTarget::Url(url) => Url::parse(&url).ok(),
+            };
+            let Some(target) = target else {
+                continue;
+            };
+
+            links.push(DocumentLink {
+                range: typst_to_lsp::range(range, &source, position_encoding).raw_range,
+                target: Some(target),
+                tooltip: None,
+                data: None,
+            });
+        }
+
+        Ok(links)
+    }
+}
+
+/// Finds the paths of imports and includes, and the URLs of links, in the source. Package imports
+/// are skipped, since opening them may mean downloading the package.
+pub fn document_links(source: &Source) -> Vec<(TypstRange, LinkTarget)> {
+    let mut links = Vec::new();
+    collect_links(source, &LinkedNode::new(source.root()), &mut links);
+    links
+}
+
+fn collect_links(source: &Source, node: &LinkedNode, links: &mut Vec<(TypstRange, LinkTarget)>) {
+    if let Some(link) = link_at(source, node) {
+        links.push(link);
+    }
+
+    for child in node.children() {
+        collect_links(source, &child, links);
+    }
+}
+
+fn link_at(source: &Source, node: &LinkedNode) -> Option<(TypstRange, LinkTarget)> {
+    if node.kind() == SyntaxKind::Link {
+        return Some((node.range(), LinkTarget::Url(node.text().to_string())));
+    }
+
+    let path = if let Some(import) = node.cast::<ast::ModuleImport>() {
+        import.source()
+    } else if let Some(include) = node.cast::<ast::ModuleInclude>() {
+        include.source()
+    } else if let Some(call) = node.cast::<ast::FuncCall>() {
+        return link_call(source, call);
+    } else {
+        return None;
+    };
+
+    let ast::Expr::Str(path) = path else {
+        return None;
+    };
+    let path_str = path.get();
+    if path_str.starts_with('@') {
+        return None;
+    }
+
+    let range = source.range(path.span())?;
+    Some((range, LinkTarget::File(source.id().join(&path_str))))
+}
+
+/// The URL of a call like `link("https://typst.app")`
+fn link_call(source: &Source, call: ast::FuncCall) -> Option<(TypstRange, LinkTarget)> {
+    let ast::Expr::Ident(callee) = call.callee() else {
+        return None;
+    };
+    if callee.as_str() != "link" {
+        return None;
+    }
+
+    let dest = call.args().items().find_map(|arg| match arg {
+        ast::Arg::Pos(ast::Expr::Str(dest)) => Some(dest),
+        _ => None,
+    })?;
+    let url = dest.get();
+    if !url.contains(':') {
+        // Not a URL, so probably a label or location
+        return None;
+    }
+
+    let range = source.range(dest.span())?;
+    Some((range, LinkTarget::Url(url.to_string())))
+}
+
+#[cfg(test)]
+mod test {
+    use typst::syntax::VirtualPath;
+
+    use super::*;
+
+    #[test]
+    fn import_and_urls() {
+        let id = FileId::new(None, VirtualPath::new("chapters/main.typ"));
+        let source = Source::new(
+            id,
+            "#import \"utils.typ\": *\n#import \"@preview/example:0.1.0\"\nSee https://typst.app or #link(\"https://typst.app/docs\")[the docs]."
+                .to_owned(),
+        );
+
+        let links = document_links(&source);
+
+        assert_eq!(
+            links,
+            [
+                (
+                    8..19,
+                    LinkTarget::File(FileId::new(None, VirtualPath::new("chapters/utils.typ")))
+                ),
+                (60..77, LinkTarget::Url("https://typst.app".to_owned())),
+                (
+                    87..111,
+                    LinkTarget::Url("https://typst.app/docs".to_owned())
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::notification::Progress;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+use tower_lsp::lsp_types::{
+    NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport,
+};
+use tower_lsp::Client;
+use tracing::warn;
+use typst::syntax::package::PackageSpec;
+
+use crate::workspace::package::external::DownloadProgress;
+
+/// Shows package downloads in the editor as work done progress.
+///
+/// Downloads may be reported from the Typst thread, which can't wait on the client, so events are
+/// queued and sent to the client in order by a separate task.
+#[derive(Debug)]
+pub struct ClientDownloadProgress {
+    events: mpsc::UnboundedSender<ProgressEvent>,
+}
+
+#[derive(Debug)]
+enum ProgressEvent {
+    Begin(PackageSpec),
+    Report(PackageSpec, String),
+    End(PackageSpec),
+}
+
+impl ClientDownloadProgress {
+    pub fn new(client: Client) -> Self {
+        let (events, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Self::send_events(client, receiver));
+        Self { events }
+    }
+
+    async fn send_events(client: Client, mut events: mpsc::UnboundedReceiver<ProgressEvent>) {
+        // Progress can only be sent for tokens the client accepted
+        let mut created = HashSet::new();
+
+        while let Some(event) = events.recv().await {
+            let (spec, progress) = match event {
+                ProgressEvent::Begin(spec) => {
+                    let params = WorkDoneProgressCreateParams {
+                        token: token(&spec),
+                    };
+                    if let Err(err) = client.send_request::<WorkDoneProgressCreate>(params).await {
+                        warn!(%err, %spec, "could not create download progress");
+                        continue;
+                    }
+                    created.insert(spec.clone());
+
+                    let begin = WorkDoneProgressBegin {
+                        title: format!("Downloading {spec}"),
+                        ..Default::default()
+                    };
+                    (spec, WorkDoneProgress::Begin(begin))
+                }
+                ProgressEvent::Report(spec, message) if created.contains(&spec) => {
+                    let report = WorkDoneProgressReport {
+                        message: Some(message),
+                        ..Default::default()
+                    };
+                    (spec, WorkDoneProgress::Report(report))
+                }
+                ProgressEvent::End(spec) if created.remove(&spec) => (
+                    spec,
+                    WorkDoneProgress::End(WorkDoneProgressEnd { message: None }),
+                ),
+                _ => continue,
+            };
+
+            client
+                .send_notification::<Progress>(ProgressParams {
+                    token: token(&spec),
+                    value: ProgressParamsValue::WorkDone(progress),
+                })
+                .await;
+        }
+    }
+
+    fn send(&self, event: ProgressEvent) {
+        // Only fails once the server is shutting down, when progress no longer matters
+        let _ = self.events.send(event);
+    }
+}
+
+impl DownloadProgress for ClientDownloadProgress {
+    fn begin(&self, spec: &PackageSpec) {
+        self.send(ProgressEvent::Begin(spec.clone()));
+    }
+
+    fn report(&self, spec: &PackageSpec, message: &str) {
+        self.send(ProgressEvent::Report(spec.clone(), message.to_owned()));
+    }
+
+    fn end(&self, spec: &PackageSpec) {
+        self.send(ProgressEvent::End(spec.clone()));
+    }
+}
+
+fn token(spec: &PackageSpec) -> NumberOrString {
+    NumberOrString::String(format!("typst-lsp/download/{spec}"))
+}
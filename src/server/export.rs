@@ -1,23 +1,136 @@
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use itertools::Itertools;
+use lopdf::{dictionary, Object, Stream};
+use serde::{Deserialize, Serialize};
+use tiny_skia::{Color as SkColor, Pixmap, PixmapPaint, Transform};
 use tower_lsp::lsp_types::Url;
-use tracing::info;
-use typst::foundations::Smart;
+use tracing::{info, warn};
+use typst::eval::Tracer;
+use typst::foundations::{Bytes, Smart};
 use typst::model::Document;
+use typst::syntax::FileId;
+use typst::visualize::Color;
+use typst::World;
 
 use crate::ext::UrlExt;
+use crate::workspace::fs::local::{FsPathToUriError, LocalFs, UriToFsPathError};
 
 use super::TypstServer;
 
+/// Space between thumbnails, and around the edge of the contact sheet, in pixels
+const THUMBNAIL_PADDING: u32 = 8;
+/// Most thumbnails in each row of a contact sheet, so a sheet can't be too large to allocate
+pub const MAX_THUMBNAIL_COLUMNS: u32 = 64;
+/// Largest width of a thumbnail, in pixels
+pub const MAX_THUMBNAIL_WIDTH: u32 = 4096;
+
+/// Layout of a contact sheet, i.e. a grid of page thumbnails
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailOptions {
+    /// Number of thumbnails in each row
+    pub columns: usize,
+    /// Width of each thumbnail, in pixels. The height is chosen to preserve the page's aspect ratio.
+    pub width: u32,
+}
+
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        Self {
+            columns: 4,
+            width: 200,
+        }
+    }
+}
+
+/// Returned when exporting images of a document without any pages
+#[derive(Debug, thiserror::Error)]
+#[error("the document has no pages to export")]
+pub struct EmptyDocument;
+
+/// Returned when asked to export a page the document doesn't have
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("page {page} does not exist, since the document has {page_count} pages")]
+pub struct PageOutOfRange {
+    pub page: usize,
+    pub page_count: usize,
+}
+
+/// Why an `exportOutputPath` template can't be used for a source
+#[derive(Debug, thiserror::Error)]
+pub enum OutputPathError {
+    #[error("unknown placeholder in `{0}`; only `{{name}}` and `{{dir}}` are supported")]
+    UnknownPlaceholder(String),
+    #[error("`{0}` does not name a file")]
+    NotAFile(String),
+    #[error("could not create output directory")]
+    CreateDir(#[from] std::io::Error),
+    #[error(transparent)]
+    SourcePath(#[from] UriToFsPathError),
+    #[error(transparent)]
+    OutputUri(#[from] FsPathToUriError),
+}
+
+/// Which source files to attach to a PDF exported with its source
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EmbeddedSources {
+    /// Only the file which was compiled
+    Main,
+    /// Every file from the project which the document depends on, including images and data.
+    /// Files from packages are left out, since the package spec is enough to get them again.
+    #[default]
+    All,
+}
+
+/// A file attached to an exported PDF
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedFile {
+    /// The attachment's name, which is the file's path relative to the project root
+    pub name: String,
+    pub size: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceExport {
+    pub pdf: Url,
+    pub embedded: Vec<EmbeddedFile>,
+}
+
 impl TypstServer {
+    /// Where to export the PDF of `source_uri`. This follows the `exportOutputPath` template if
+    /// one is set, falling back to next to the source if the template can't be used.
+    async fn pdf_uri(&self, source_uri: &Url) -> anyhow::Result<Url> {
+        let sibling = source_uri.clone().with_extension("pdf")?;
+        let Some(template) = self.config.read().await.export_output_path.clone() else {
+            return Ok(sibling);
+        };
+
+        let output_uri = output_path(source_uri, &template).and_then(|path| {
+            prepare_output_dir(&path)?;
+            Ok(LocalFs::path_to_uri(path)?)
+        });
+        match output_uri {
+            Ok(output_uri) => Ok(output_uri),
+            Err(err) => {
+                warn!(%err, %template, "cannot use export output path; exporting next to the source");
+                Ok(sibling)
+            }
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn export_pdf(
         &self,
         source_uri: &Url,
         document: Arc<Document>,
     ) -> anyhow::Result<()> {
-        let pdf_uri = source_uri.clone().with_extension("pdf")?;
+        let pdf_uri = self.pdf_uri(source_uri).await?;
         info!(%pdf_uri, "exporting PDF");
 
         self.thread_with_world(source_uri)
@@ -35,4 +148,453 @@ impl TypstServer {
 
         Ok(())
     }
+
+    /// Exports a PDF of only one page of the document, counting pages from 1. The PDF is written
+    /// next to where the full PDF would be, with the page number before the extension, like
+    /// `main.2.pdf`.
+    #[tracing::instrument(skip(self, document))]
+    pub async fn export_pdf_page(
+        &self,
+        source_uri: &Url,
+        document: Arc<Document>,
+        page: usize,
+    ) -> anyhow::Result<Url> {
+        let page_document = single_page(&document, page)?;
+        let width = document.pages.len().to_string().len();
+        let pdf_uri = self
+            .pdf_uri(source_uri)
+            .await?
+            .with_extension(&format!("{page:0width$}.pdf"))?;
+        info!(%pdf_uri, "exporting PDF page");
+
+        let target_uri = pdf_uri.clone();
+        self.thread_with_world(source_uri)
+            .await?
+            .run(move |world| {
+                let data = typst_pdf::pdf(&page_document, Smart::Auto, world.now());
+
+                world
+                    .write_raw(&target_uri, &data)
+                    .context("failed to export PDF page")
+            })
+            .await?;
+
+        info!("PDF page export complete");
+
+        Ok(pdf_uri)
+    }
+
+    /// Compiles and exports a PDF with the document's source files attached, so the PDF carries
+    /// everything needed to reproduce it. Compiling here, rather than reusing a document, lets us
+    /// see which files the compilation read.
+    #[tracing::instrument(skip(self))]
+    pub async fn export_pdf_with_source(
+        &self,
+        source_uri: &Url,
+        sources: EmbeddedSources,
+    ) -> anyhow::Result<SourceExport> {
+        let pdf_uri = self.pdf_uri(source_uri).await?;
+        info!(%pdf_uri, "exporting PDF with source");
+
+        let target_uri = pdf_uri.clone();
+        let embedded = self
+            .thread_with_world(source_uri)
+            .await?
+            .run(move |world| {
+                let mut tracer = Tracer::default();
+                let Ok(document) = typst::compile(&world, &mut tracer) else {
+                    bail!("failed to generate document after compilation");
+                };
+
+                let ids = match sources {
+                    EmbeddedSources::Main => vec![world.main().id()],
+                    EmbeddedSources::All => world.dependencies(),
+                };
+                let files: Vec<_> = ids
+                    .into_iter()
+                    .filter(|id| id.package().is_none())
+                    .filter_map(|id| Some((attachment_name(id), world.file(id).ok()?)))
+                    .collect();
+
+                let pdf = typst_pdf::pdf(&document, Smart::Auto, world.now());
+                let pdf = attach_files(pdf, &files).context("failed to attach source files")?;
+
+                world
+                    .write_raw(&target_uri, &pdf)
+                    .context("failed to export PDF")?;
+
+                let embedded = files
+                    .into_iter()
+                    .map(|(name, data)| EmbeddedFile {
+                        name,
+                        size: data.len(),
+                    })
+                    .collect();
+                anyhow::Ok(embedded)
+            })
+            .await?;
+
+        info!("PDF export with source complete");
+
+        Ok(SourceExport {
+            pdf: pdf_uri,
+            embedded,
+        })
+    }
+
+    /// Exports each page as a PNG image with the given resolution in pixels per inch, returning
+    /// the URIs written to
+    #[tracing::instrument(skip(self, document))]
+    pub async fn export_png(
+        &self,
+        source_uri: &Url,
+        document: Arc<Document>,
+        ppi: f32,
+    ) -> anyhow::Result<Vec<Url>> {
+        let png_uris = png_uris(source_uri, document.pages.len())?;
+        info!(?png_uris, "exporting PNG");
+
+        let target_uris = png_uris.clone();
+        self.thread_with_world(source_uri)
+            .await?
+            .run(move |world| {
+                for (uri, page) in target_uris.iter().zip(render_pages(&document, ppi)) {
+                    let data = page.encode_png().context("failed to encode page")?;
+                    world
+                        .write_raw(uri, &data)
+                        .context("failed to export PNG")?;
+                }
+                anyhow::Ok(())
+            })
+            .await?;
+
+        info!("PNG export complete");
+
+        Ok(png_uris)
+    }
+
+    #[tracing::instrument(skip(self, document))]
+    pub async fn export_thumbnails(
+        &self,
+        source_uri: &Url,
+        document: Arc<Document>,
+        options: ThumbnailOptions,
+    ) -> anyhow::Result<()> {
+        let png_uri = source_uri.clone().with_extension("thumbnails.png")?;
+        info!(%png_uri, "exporting thumbnails");
+
+        self.thread_with_world(source_uri)
+            .await?
+            .run(move |world| {
+                let thumbnails = render_thumbnails(&document, options.width);
+                let sheet = contact_sheet(&thumbnails, options.columns)
+                    .context("failed to allocate contact sheet")?;
+                let data = sheet
+                    .encode_png()
+                    .context("failed to encode contact sheet")?;
+
+                world
+                    .write_raw(&png_uri, &data)
+                    .context("failed to export thumbnails")
+            })
+            .await?;
+
+        info!("thumbnail export complete");
+
+        Ok(())
+    }
+}
+
+fn attachment_name(id: FileId) -> String {
+    id.vpath()
+        .as_rootless_path()
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .join("/")
+}
+
+/// Resolves an `exportOutputPath` template for a source. `{name}` is replaced by the source's name
+/// without its extension and `{dir}` by its directory. Relative paths are relative to the source's
+/// directory.
+fn output_path(source_uri: &Url, template: &str) -> Result<PathBuf, OutputPathError> {
+    let source_path = LocalFs::uri_to_path(source_uri)?;
+    let dir = source_path.parent().unwrap_or(Path::new("/"));
+    let name = source_path.file_stem().unwrap_or_default();
+
+    let resolved = template
+        .replace("{name}", &name.to_string_lossy())
+        .replace("{dir}", &dir.to_string_lossy());
+    if resolved.contains(['{', '}']) {
+        return Err(OutputPathError::UnknownPlaceholder(template.to_owned()));
+    }
+    if resolved.ends_with(['/', std::path::MAIN_SEPARATOR]) {
+        return Err(OutputPathError::NotAFile(template.to_owned()));
+    }
+
+    let path = dir.join(resolved);
+    if path.file_name().is_none() || path.is_dir() {
+        return Err(OutputPathError::NotAFile(template.to_owned()));
+    }
+
+    Ok(path)
+}
+
+/// Creates the directory an output file goes in, so build directories don't have to exist before
+/// the first export
+fn prepare_output_dir(path: &Path) -> Result<(), OutputPathError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Adds files to a PDF as embedded files, which PDF readers list as attachments. Typst can't attach
+/// files itself, so this rewrites the finished PDF.
+fn attach_files(pdf: Vec<u8>, files: &[(String, Bytes)]) -> anyhow::Result<Vec<u8>> {
+    let mut pdf = lopdf::Document::load_mem(&pdf)?;
+
+    let mut sorted: Vec<_> = files.iter().collect();
+    // The name tree must be sorted by name
+    sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut names = Vec::new();
+    let mut specs = Vec::new();
+    for (name, data) in sorted {
+        let stream = Stream::new(
+            dictionary! {
+                "Type" => "EmbeddedFile",
+                "Params" => dictionary! { "Size" => data.len() as i64 },
+            },
+            data.to_vec(),
+        );
+        let stream_id = pdf.add_object(stream);
+
+        let spec_id = pdf.add_object(dictionary! {
+            "Type" => "Filespec",
+            "F" => Object::string_literal(name.as_str()),
+            "UF" => Object::string_literal(name.as_str()),
+            "EF" => dictionary! { "F" => stream_id },
+            "AFRelationship" => "Source",
+        });
+
+        names.push(Object::string_literal(name.as_str()));
+        names.push(spec_id.into());
+        specs.push(spec_id.into());
+    }
+
+    let catalog_id = pdf.trailer.get(b"Root").and_then(Object::as_reference)?;
+    let catalog = pdf
+        .get_object_mut(catalog_id)
+        .and_then(Object::as_dict_mut)?;
+
+    let embedded_files = dictionary! { "Names" => names };
+    if let Ok(existing) = catalog.get_mut(b"Names").and_then(Object::as_dict_mut) {
+        existing.set("EmbeddedFiles", embedded_files);
+    } else {
+        catalog.set("Names", dictionary! { "EmbeddedFiles" => embedded_files });
+    }
+    catalog.set("AF", Object::Array(specs));
+
+    let mut out = Vec::new();
+    pdf.save_to(&mut out)?;
+    Ok(out)
+}
+
+/// Where to write each page's image. A single page goes next to the source, like `file.png`, while
+/// several are numbered from 1, like `file.01.png`, padded so they sort in page order.
+fn png_uris(source_uri: &Url, pages: usize) -> anyhow::Result<Vec<Url>> {
+    match pages {
+        0 => Err(EmptyDocument.into()),
+        1 => Ok(vec![source_uri.clone().with_extension("png")?]),
+        _ => {
+            let width = pages.to_string().len();
+            (1..=pages)
+                .map(|page| {
+                    let extension = format!("{page:0width$}.png");
+                    Ok(source_uri.clone().with_extension(&extension)?)
+                })
+                .collect()
+        }
+    }
+}
+
+/// A document with only the page with the given number, counting from 1. The document's metadata
+/// is kept.
+fn single_page(document: &Document, page: usize) -> Result<Document, PageOutOfRange> {
+    let out_of_range = PageOutOfRange {
+        page,
+        page_count: document.pages.len(),
+    };
+    let index = page.checked_sub(1).ok_or_else(|| out_of_range.clone())?;
+    let page = document.pages.get(index).ok_or(out_of_range)?;
+
+    Ok(Document {
+        pages: vec![page.clone()],
+        title: document.title.clone(),
+        author: document.author.clone(),
+        keywords: document.keywords.clone(),
+        ..Default::default()
+    })
+}
+
+/// Render each page of the document at `ppi` pixels per inch
+fn render_pages(document: &Document, ppi: f32) -> Vec<Pixmap> {
+    let pixel_per_pt = ppi / 72.0;
+    document
+        .pages
+        .iter()
+        .map(|page| typst_render::render(&page.frame, pixel_per_pt, Color::WHITE))
+        .collect()
+}
+
+/// Render each page of the document to an image `width` pixels wide
+fn render_thumbnails(document: &Document, width: u32) -> Vec<Pixmap> {
+    document
+        .pages
+        .iter()
+        .map(|page| {
+            let page_width = page.frame.width().to_pt() as f32;
+            let pixel_per_pt = if page_width > 0.0 {
+                width as f32 / page_width
+            } else {
+                1.0
+            };
+            typst_render::render(&page.frame, pixel_per_pt, Color::WHITE)
+        })
+        .collect()
+}
+
+/// Arrange the thumbnails in a grid with the given number of columns, left to right, then top to
+/// bottom. Each cell is as large as the largest thumbnail, so mixed page sizes still line up.
+fn contact_sheet(thumbnails: &[Pixmap], columns: usize) -> Option<Pixmap> {
+    let columns = columns.clamp(1, thumbnails.len().max(1));
+    let rows = thumbnails.len().div_ceil(columns).max(1);
+
+    let cell_width = thumbnails.iter().map(Pixmap::width).max().unwrap_or(0);
+    let cell_height = thumbnails.iter().map(Pixmap::height).max().unwrap_or(0);
+
+    let sheet_width = columns as u32 * (cell_width + THUMBNAIL_PADDING) + THUMBNAIL_PADDING;
+    let sheet_height = rows as u32 * (cell_height + THUMBNAIL_PADDING) + THUMBNAIL_PADDING;
+
+    let mut sheet = Pixmap::new(sheet_width, sheet_height)?;
+    sheet.fill(SkColor::from_rgba8(0xdd, 0xdd, 0xdd, 0xff));
+
+    for (index, thumbnail) in thumbnails.iter().enumerate() {
+        let column = (index % columns) as u32;
+        let row = (index / columns) as u32;
+        let x = THUMBNAIL_PADDING + column * (cell_width + THUMBNAIL_PADDING);
+        let y = THUMBNAIL_PADDING + row * (cell_height + THUMBNAIL_PADDING);
+
+        sheet.draw_pixmap(
+            x as i32,
+            y as i32,
+            thumbnail.as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+    }
+
+    Some(sheet)
+}
+
+#[cfg(test)]
+mod test {
+    use typst::layout::{Abs, Frame, Page, Size};
+
+    use super::*;
+
+    fn document(width: f64, height: f64) -> Document {
+        Document {
+            pages: vec![page(width, height)],
+            ..Default::default()
+        }
+    }
+
+    fn page(width: f64, height: f64) -> Page {
+        Page {
+            frame: Frame::hard(Size::new(Abs::pt(width), Abs::pt(height))),
+            numbering: None,
+            number: 1,
+        }
+    }
+
+    #[test]
+    fn export_second_of_three_pages() {
+        let document = Document {
+            pages: vec![page(10.0, 10.0), page(20.0, 20.0), page(30.0, 30.0)],
+            ..Default::default()
+        };
+
+        let second = single_page(&document, 2).unwrap();
+        assert_eq!(second.pages.len(), 1);
+        assert_eq!(second.pages[0].frame.width(), Abs::pt(20.0));
+
+        let pdf = typst_pdf::pdf(&second, Smart::Auto, None);
+        let pdf = lopdf::Document::load_mem(&pdf).unwrap();
+        assert_eq!(pdf.get_pages().len(), 1);
+
+        let err = single_page(&document, 4).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "page 4 does not exist, since the document has 3 pages"
+        );
+        assert!(single_page(&document, 0).is_err());
+    }
+
+    #[test]
+    fn png_size_scales_with_ppi() {
+        let document = document(72.0, 36.0);
+
+        let low = render_pages(&document, 72.0);
+        let high = render_pages(&document, 144.0);
+
+        assert_eq!((low[0].width(), low[0].height()), (72, 36));
+        assert_eq!((high[0].width(), high[0].height()), (144, 72));
+    }
+
+    #[test]
+    fn output_path_in_build_directory() {
+        let source_uri = Url::parse("file:///project/chapters/main.typ").unwrap();
+
+        let path = output_path(&source_uri, "build/{name}.pdf").unwrap();
+
+        assert_eq!(path, PathBuf::from("/project/chapters/build/main.pdf"));
+    }
+
+    #[test]
+    fn output_path_with_dir_placeholder() {
+        let source_uri = Url::parse("file:///project/chapters/main.typ").unwrap();
+
+        let path = output_path(&source_uri, "{dir}/../build/{name}.pdf").unwrap();
+
+        assert_eq!(path, PathBuf::from("/project/chapters/../build/main.pdf"));
+    }
+
+    #[test]
+    fn output_path_rejects_invalid_templates() {
+        let source_uri = Url::parse("file:///project/main.typ").unwrap();
+
+        assert!(matches!(
+            output_path(&source_uri, "build/{stem}.pdf"),
+            Err(OutputPathError::UnknownPlaceholder(_))
+        ));
+        assert!(matches!(
+            output_path(&source_uri, "build/"),
+            Err(OutputPathError::NotAFile(_))
+        ));
+    }
+
+    #[test]
+    fn png_uris_numbered_by_page() {
+        let source_uri = Url::parse("file:///path/to/file.typ").unwrap();
+
+        let single = png_uris(&source_uri, 1).unwrap();
+        let several = png_uris(&source_uri, 10).unwrap();
+
+        assert_eq!(single[0].as_str(), "file:///path/to/file.png");
+        assert_eq!(several[0].as_str(), "file:///path/to/file.01.png");
+        assert_eq!(several[9].as_str(), "file:///path/to/file.10.png");
+        assert!(png_uris(&source_uri, 0).unwrap_err().is::<EmptyDocument>());
+    }
 }
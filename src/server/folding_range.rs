@@ -0,0 +1,172 @@
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+use typst::syntax::{ast, LinkedNode, Source, SyntaxKind};
+
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::{typst_to_lsp, TypstRange};
+
+use super::TypstServer;
+
+impl TypstServer {
+    pub fn get_folding_ranges(&self, source: &Source) -> Vec<FoldingRange> {
+        folding_ranges(source, self.position_encoding())
+    }
+}
+
+/// Finds the foldable regions of a source: block comments, code and content blocks, argument and
+/// parameter lists, and the section under each heading
+pub fn folding_ranges(source: &Source, position_encoding: PositionEncoding) -> Vec<FoldingRange> {
+    let mut ranges = Vec::new();
+    collect_folding_ranges(
+        source,
+        position_encoding,
+        &LinkedNode::new(source.root()),
+        &mut ranges,
+    );
+    ranges
+}
+
+fn collect_folding_ranges(
+    source: &Source,
+    position_encoding: PositionEncoding,
+    node: &LinkedNode,
+    ranges: &mut Vec<FoldingRange>,
+) {
+    match node.kind() {
+        SyntaxKind::BlockComment => {
+            let (start_line, end_line) = lines(source, position_encoding, node.range());
+            push_range(
+                ranges,
+                start_line,
+                end_line,
+                Some(FoldingRangeKind::Comment),
+            );
+        }
+        SyntaxKind::CodeBlock | SyntaxKind::ContentBlock => {
+            // Leave the line with the closing bracket visible
+            let (start_line, end_line) = lines(source, position_encoding, node.range());
+            push_range(ranges, start_line, end_line.saturating_sub(1), None);
+        }
+        SyntaxKind::Args | SyntaxKind::Params => {
+            if let Some(parens) = paren_range(node) {
+                // Like blocks, leave the line with the closing parenthesis visible
+                let (start_line, end_line) = lines(source, position_encoding, parens);
+                push_range(ranges, start_line, end_line.saturating_sub(1), None);
+            }
+        }
+        SyntaxKind::Markup => collect_sections(source, position_encoding, node, ranges),
+        _ => {}
+    }
+
+    for child in node.children() {
+        collect_folding_ranges(source, position_encoding, &child, ranges);
+    }
+}
+
+/// Adds a range for each heading in the markup, spanning until the next heading of the same or
+/// a higher level, or the end of the markup
+fn collect_sections(
+    source: &Source,
+    position_encoding: PositionEncoding,
+    markup: &LinkedNode,
+    ranges: &mut Vec<FoldingRange>,
+) {
+    let headings: Vec<_> = markup
+        .children()
+        .filter_map(|child| {
+            let heading = child.cast::<ast::Heading>()?;
+            Some((heading.depth().get(), child.offset()))
+        })
+        .collect();
+
+    for (index, &(depth, start)) in headings.iter().enumerate() {
+        let end = headings[index + 1..]
+            .iter()
+            .find(|(next_depth, _)| *next_depth <= depth)
+            .map_or(markup.range().end, |&(_, next_start)| next_start);
+        // Blank lines before the next heading don't belong to the section
+        let end = start + source.text()[start..end].trim_end().len();
+
+        let (start_line, end_line) = lines(source, position_encoding, start..end);
+        push_range(ranges, start_line, end_line, Some(FoldingRangeKind::Region));
+    }
+}
+
+/// The range from the opening to the closing parenthesis of arguments or parameters. Arguments
+/// may have no parentheses, like `#list[a][b]`, or trailing content blocks after them.
+fn paren_range(node: &LinkedNode) -> Option<TypstRange> {
+    let left = node
+        .children()
+        .find(|child| child.kind() == SyntaxKind::LeftParen)?;
+    let right = node
+        .children()
+        .find(|child| child.kind() == SyntaxKind::RightParen)?;
+    Some(left.offset()..right.range().end)
+}
+
+fn lines(source: &Source, position_encoding: PositionEncoding, range: TypstRange) -> (u32, u32) {
+    let range = typst_to_lsp::range(range, source, position_encoding).raw_range;
+    (range.start.line, range.end.line)
+}
+
+/// Adds a range if it spans more than one line, since there is nothing to fold otherwise
+fn push_range(
+    ranges: &mut Vec<FoldingRange>,
+    start_line: u32,
+    end_line: u32,
+    kind: Option<FoldingRangeKind>,
+) {
+    if end_line <= start_line {
+        return;
+    }
+
+    ranges.push(FoldingRange {
+        start_line,
+        end_line,
+        kind,
+        ..Default::default()
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line_ranges(text: &str, kind: Option<FoldingRangeKind>) -> Vec<(u32, u32)> {
+        let source = Source::detached(text);
+        folding_ranges(&source, PositionEncoding::Utf16)
+            .into_iter()
+            .filter(|range| range.kind == kind)
+            .map(|range| (range.start_line, range.end_line))
+            .collect()
+    }
+
+    #[test]
+    fn nested_heading_sections() {
+        let text = "= One\ntext\n== Two\n=== Three\nmore\n\n== Four\nlast\n";
+
+        let sections = line_ranges(text, Some(FoldingRangeKind::Region));
+
+        assert_eq!(sections, vec![(0, 7), (2, 4), (3, 4), (6, 7)]);
+    }
+
+    #[test]
+    fn comments_and_blocks() {
+        let text = "/* a\nb */\n#{\n  let x = 1\n}\n#[\n  text\n]";
+
+        let comments = line_ranges(text, Some(FoldingRangeKind::Comment));
+        let blocks = line_ranges(text, None);
+
+        assert_eq!(comments, vec![(0, 1)]);
+        assert_eq!(blocks, vec![(2, 3), (5, 6)]);
+    }
+
+    #[test]
+    fn multiline_arguments() {
+        let text =
+            "#table(\n  columns: 2,\n  [a], [b],\n)\n#grid(columns: 2)[a][b]\n#let f(\n  x,\n) = x";
+
+        let ranges = line_ranges(text, None);
+
+        assert_eq!(ranges, vec![(0, 2), (5, 6)]);
+    }
+}
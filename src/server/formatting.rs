@@ -1,33 +1,52 @@
 use anyhow::anyhow;
 use futures::future::TryFutureExt;
-use tower_lsp::lsp_types::{Position, Range, Registration, TextEdit, Unregistration};
+use tower_lsp::lsp_types::{
+    FormattingOptions, Position, Range, Registration, TextEdit, Unregistration,
+};
 use typst::{
     foundations::Bytes,
-    syntax::{FileId, Source, VirtualPath},
+    syntax::{FileId, LinkedNode, Source, SyntaxKind, VirtualPath},
 };
 use typstfmt_lib::Config;
 
+use crate::config::{FormatterIndent, FormatterOptions};
+use crate::lsp_typst_boundary::{typst_to_lsp, LspRange, LspRawRange, TypstRange};
 use crate::workspace::{fs::FsResult, project::Project};
 
 use super::TypstServer;
 
 const FORMATTING_REGISTRATION_ID: &str = "formatting";
 const DOCUMENT_FORMATTING_METHOD_ID: &str = "textDocument/formatting";
+const RANGE_FORMATTING_REGISTRATION_ID: &str = "range_formatting";
+const RANGE_FORMATTING_METHOD_ID: &str = "textDocument/rangeFormatting";
 const CONFIG_PATH: &str = "typstfmt.toml";
 
-pub fn get_formatting_registration() -> Registration {
-    Registration {
-        id: FORMATTING_REGISTRATION_ID.to_owned(),
-        method: DOCUMENT_FORMATTING_METHOD_ID.to_owned(),
-        register_options: None,
-    }
+pub fn get_formatting_registrations() -> Vec<Registration> {
+    vec![
+        Registration {
+            id: FORMATTING_REGISTRATION_ID.to_owned(),
+            method: DOCUMENT_FORMATTING_METHOD_ID.to_owned(),
+            register_options: None,
+        },
+        Registration {
+            id: RANGE_FORMATTING_REGISTRATION_ID.to_owned(),
+            method: RANGE_FORMATTING_METHOD_ID.to_owned(),
+            register_options: None,
+        },
+    ]
 }
 
-pub fn get_formatting_unregistration() -> Unregistration {
-    Unregistration {
-        id: FORMATTING_REGISTRATION_ID.to_owned(),
-        method: DOCUMENT_FORMATTING_METHOD_ID.to_owned(),
-    }
+pub fn get_formatting_unregistrations() -> Vec<Unregistration> {
+    vec![
+        Unregistration {
+            id: FORMATTING_REGISTRATION_ID.to_owned(),
+            method: DOCUMENT_FORMATTING_METHOD_ID.to_owned(),
+        },
+        Unregistration {
+            id: RANGE_FORMATTING_REGISTRATION_ID.to_owned(),
+            method: RANGE_FORMATTING_METHOD_ID.to_owned(),
+        },
+    ]
 }
 
 impl TypstServer {
@@ -35,10 +54,9 @@ impl TypstServer {
         &self,
         project: Project,
         source: Source,
+        options: &FormattingOptions,
     ) -> anyhow::Result<Vec<TextEdit>> {
-        let config = get_config(&project).await?;
-        let original_text = source.text();
-        let res = typstfmt_lib::format(original_text, config);
+        let res = self.format_text(&project, source.text(), options).await?;
 
         Ok(vec![TextEdit {
             new_text: res,
@@ -54,12 +72,151 @@ impl TypstServer {
             ),
         }])
     }
+
+    /// Formats the top-level nodes intersecting the range, leaving the rest of the document as it
+    /// is
+    pub async fn format_range(
+        &self,
+        project: Project,
+        source: Source,
+        range: LspRawRange,
+        options: &FormattingOptions,
+    ) -> anyhow::Result<Vec<TextEdit>> {
+        let position_encoding = self.position_encoding();
+        let range = LspRange::new(range, position_encoding).into_range_on(&source);
+        let Some(range) = top_level_range(&source, range) else {
+            return Ok(vec![]);
+        };
+
+        let original_text = &source.text()[range.clone()];
+        let res = self.format_text(&project, original_text, options).await?;
+        let Some(new_text) = fit_formatted_range(original_text, res) else {
+            return Ok(vec![]);
+        };
+
+        Ok(vec![TextEdit {
+            new_text,
+            range: typst_to_lsp::range(range, &source, position_encoding).raw_range,
+        }])
+    }
+
+    async fn format_text(
+        &self,
+        project: &Project,
+        text: &str,
+        options: &FormattingOptions,
+    ) -> anyhow::Result<String> {
+        let file_config = config_from_file(project).await.transpose()?;
+        let settings = self.config.read().await.formatter_options;
+        let (config, use_tabs) = formatter_config(file_config, settings, options);
+        let indent_space = config.indent_space;
+
+        let mut res = typstfmt_lib::format(text, config);
+        if use_tabs {
+            res = indent_with_tabs(&res, indent_space);
+        }
+        Ok(res)
+    }
 }
 
-async fn get_config(project: &Project) -> anyhow::Result<Config> {
-    config_from_file(project)
-        .await
-        .unwrap_or_else(|| Ok(Config::default()))
+/// The range covered by the top-level nodes intersecting the range, ignoring whitespace between
+/// them. Formatting whole nodes keeps the formatter from seeing half an expression.
+fn top_level_range(source: &Source, range: TypstRange) -> Option<TypstRange> {
+    let root = LinkedNode::new(source.root());
+    let mut intersecting = root.children().filter(|child| {
+        let child_range = child.range();
+        let intersects = child_range.start <= range.end && range.start <= child_range.end;
+        intersects && !matches!(child.kind(), SyntaxKind::Space | SyntaxKind::Parbreak)
+    });
+
+    let first = intersecting.next()?;
+    let last = intersecting.last().unwrap_or_else(|| first.clone());
+    Some(first.offset()..last.range().end)
+}
+
+/// Makes the formatted text a drop-in replacement for the original range. The formatter treats
+/// its input as a whole file, so it may add or remove a trailing newline. Returns `None` if
+/// nothing changed.
+fn fit_formatted_range(original_text: &str, formatted: String) -> Option<String> {
+    let trimmed = formatted.trim_end_matches('\n');
+    let trailing_newlines = original_text.len() - original_text.trim_end_matches('\n').len();
+    let fitted = format!("{trimmed}{}", "\n".repeat(trailing_newlines));
+    (fitted != original_text).then_some(fitted)
+}
+
+/// Combines the formatter settings, the project's `typstfmt.toml`, and the editor's formatting
+/// options, in that order of precedence. Also returns whether the output should be indented with
+/// tabs, which the formatter can't do itself.
+fn formatter_config(
+    file_config: Option<Config>,
+    settings: FormatterOptions,
+    options: &FormattingOptions,
+) -> (Config, bool) {
+    let has_file_config = file_config.is_some();
+    let mut config = file_config.unwrap_or_default();
+
+    if let Some(line_width) = settings.line_width {
+        config.max_line_length = line_width;
+    }
+
+    let use_tabs = match settings.indent {
+        Some(FormatterIndent::Spaces(spaces)) => {
+            config.indent_space = spaces;
+            false
+        }
+        Some(FormatterIndent::Tabs) => true,
+        None if has_file_config => false,
+        None => {
+            config.indent_space = options.tab_size as usize;
+            !options.insert_spaces
+        }
+    };
+
+    (config, use_tabs)
+}
+
+/// Replaces each full indentation level of spaces at the start of a line with a tab. Lines which
+/// start inside a raw block or a string are left alone, since their spaces are part of the content.
+fn indent_with_tabs(text: &str, indent_space: usize) -> String {
+    if indent_space == 0 {
+        return text.to_owned();
+    }
+
+    let mut verbatim = Vec::new();
+    collect_verbatim_ranges(&LinkedNode::new(&typst::syntax::parse(text)), &mut verbatim);
+    let is_verbatim = |offset: usize| {
+        verbatim
+            .iter()
+            .any(|range| range.start < offset && offset < range.end)
+    };
+
+    let mut offset = 0;
+    text.split_inclusive('\n')
+        .map(|line| {
+            let start = offset;
+            offset += line.len();
+            if is_verbatim(start) {
+                return line.to_owned();
+            }
+
+            let spaces = line.len() - line.trim_start_matches(' ').len();
+            let levels = spaces / indent_space;
+            let rest = &line[levels * indent_space..];
+            "\t".repeat(levels) + rest
+        })
+        .collect()
+}
+
+/// Collects the ranges of raw blocks and strings, whose text must be kept as it is
+fn collect_verbatim_ranges(node: &LinkedNode, ranges: &mut Vec<TypstRange>) {
+    if matches!(node.kind(), SyntaxKind::Raw | SyntaxKind::Str) {
+        ranges.push(node.range());
+        return;
+    }
+
+    for child in node.children() {
+        collect_verbatim_ranges(&child, ranges);
+    }
 }
 
 async fn config_from_file(project: &Project) -> Option<anyhow::Result<Config>> {
@@ -81,3 +238,82 @@ fn config_from_bytes(bytes: &[u8]) -> anyhow::Result<Config> {
     let config = Config::from_toml(string).map_err(|err| anyhow!("{err}"))?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn editor_options() -> FormattingOptions {
+        FormattingOptions {
+            tab_size: 4,
+            insert_spaces: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn settings_override_file_and_editor() {
+        let settings = FormatterOptions {
+            line_width: Some(10),
+            indent: Some(FormatterIndent::Spaces(3)),
+        };
+        let (config, use_tabs) = formatter_config(None, settings, &editor_options());
+        assert_eq!(config.max_line_length, 10);
+        assert_eq!(config.indent_space, 3);
+        assert!(!use_tabs);
+
+        let (config, use_tabs) =
+            formatter_config(None, FormatterOptions::default(), &editor_options());
+        assert_eq!(config.indent_space, 4);
+        assert!(!use_tabs);
+    }
+
+    #[test]
+    fn line_width_changes_output() {
+        let text = "#f(aaaa, bbbb, cccc, dddd, eeee, ffff)";
+        let format = |line_width| {
+            let settings = FormatterOptions {
+                line_width: Some(line_width),
+                indent: None,
+            };
+            let (config, _) = formatter_config(None, settings, &editor_options());
+            typstfmt_lib::format(text, config)
+        };
+
+        assert_ne!(format(10), format(80));
+    }
+
+    #[test]
+    fn range_covers_only_intersecting_nodes() {
+        let text = "= Title\n\n#let   x  =   1\n\nSome text.\n";
+        let source = Source::detached(text);
+        let line = text.find("#let").unwrap();
+
+        let range = top_level_range(&source, line + 2..line + 3).unwrap();
+        assert_eq!(&text[range.clone()], "#let   x  =   1");
+
+        let (config, _) = formatter_config(None, FormatterOptions::default(), &editor_options());
+        let formatted = typstfmt_lib::format(&text[range.clone()], config);
+        let new_text = fit_formatted_range(&text[range], formatted).unwrap();
+        assert_eq!(new_text, "#let x = 1");
+    }
+
+    #[test]
+    fn tabs() {
+        assert_eq!(
+            indent_with_tabs("a\n    b\n      c", 2),
+            "a\n\t\tb\n\t\t\tc"
+        );
+        assert_eq!(indent_with_tabs("  a", 4), "  a");
+    }
+
+    #[test]
+    fn tabs_keep_raw_and_string_contents() {
+        let text = "#f(\n    ```py\n    def f():\n        pass\n    ```,\n    \"a\n    b\",\n)";
+
+        assert_eq!(
+            indent_with_tabs(text, 4),
+            "#f(\n\t```py\n    def f():\n        pass\n    ```,\n\t\"a\n    b\",\n)"
+        );
+    }
+}
@@ -1,10 +1,17 @@
 use anyhow::Context;
-use tower_lsp::lsp_types::{Hover, Url};
-use typst::syntax::LinkedNode;
+use itertools::Itertools;
+use tower_lsp::lsp_types::{Hover, HoverContents, MarkedString, MarkupContent, MarkupKind, Url};
+use typst::diag::EcoString;
+use typst::foundations::{Repr, Value};
+use typst::syntax::package::{PackageManifest, PackageSpec};
+use typst::syntax::{ast, FileId, LinkedNode, Source, SyntaxKind, VirtualPath};
 use typst::World;
+use typst_ide::Tooltip;
 
-use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition};
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
 
+use super::definition::SourceLoader;
+use super::signature::ParamInFunction;
 use super::TypstServer;
 
 impl TypstServer {
@@ -13,13 +20,22 @@ impl TypstServer {
         uri: &Url,
         position: LspPosition,
     ) -> anyhow::Result<Option<Hover>> {
-        let position_encoding = self.const_config().position_encoding;
+        let position_encoding = self.position_encoding();
 
-        let doc = self.document.lock().await.clone();
+        if let Some(hover) = self.get_package_hover(uri, position).await? {
+            return Ok(Some(hover));
+        }
 
-        let fid = self.workspace().read().await.full_id(uri)?;
+        if let Some(hover) = self.get_binding_hover(uri, position).await? {
+            return Ok(Some(hover));
+        }
+
+        let main_uri = self.main_url().await.unwrap_or_else(|| uri.clone());
+        let doc = self.documents.get(&main_uri);
+
+        let fid = self.read_workspace().await?.full_id(uri)?;
         let result = self
-            .thread_with_world(self.main_url().await.as_ref().unwrap_or(uri))
+            .thread_with_world(&main_uri)
             .await?
             .run(move |world| {
                 let source = world.source(fid.into()).ok()?;
@@ -27,16 +43,33 @@ impl TypstServer {
                 let typst_offset =
                     lsp_to_typst::position_to_offset(position, position_encoding, &source);
 
-                let typst_tooltip = typst_ide::tooltip(&world, Some(&doc), &source, typst_offset)?;
+                let typst_tooltip =
+                    typst_ide::tooltip(&world, doc.as_deref(), &source, typst_offset);
+                let conversions = numeric_conversions(&source, typst_offset);
+                if typst_tooltip.is_none() && conversions.is_none() {
+                    return None;
+                }
 
-                Some((typst_offset, typst_tooltip))
+                Some((typst_offset, typst_tooltip, conversions))
             })
             .await;
-        let Some((typst_offset, typst_tooltip)) = result else {
+        let Some((typst_offset, typst_tooltip, conversions)) = result else {
             return Ok(None);
         };
 
-        let lsp_tooltip = typst_to_lsp::tooltip(&typst_tooltip);
+        let lsp_tooltip = match (typst_tooltip, conversions) {
+            (Some(typst_tooltip), Some(conversions)) => {
+                match typst_to_lsp::tooltip(&typst_tooltip) {
+                    HoverContents::Scalar(tooltip) => {
+                        HoverContents::Array(vec![tooltip, MarkedString::String(conversions)])
+                    }
+                    tooltip => tooltip,
+                }
+            }
+            (Some(typst_tooltip), None) => typst_to_lsp::tooltip(&typst_tooltip),
+            (None, Some(conversions)) => HoverContents::Scalar(MarkedString::String(conversions)),
+            (None, None) => return Ok(None),
+        };
 
         let lsp_hovered_range = self.scope_with_source(uri).await?.run(|source, _| {
             let typst_hovered_node = LinkedNode::new(source.root())
@@ -45,7 +78,7 @@ impl TypstServer {
             anyhow::Ok(typst_to_lsp::range(
                 typst_hovered_node.range(),
                 source,
-                self.const_config().position_encoding,
+                self.position_encoding(),
             ))
         })?;
 
@@ -54,4 +87,328 @@ impl TypstServer {
             range: Some(lsp_hovered_range.raw_range),
         }))
     }
+
+    /// Shows the metadata of the package imported by the hovered package spec, like
+    /// `"@preview/example:0.1.0"`. The package is downloaded if it isn't already.
+    async fn get_package_hover(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Hover>> {
+        let position_encoding = self.position_encoding();
+
+        let source = self
+            .scope_with_source(uri)
+            .await?
+            .run(|source, _| source.clone());
+        let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+        let Some((spec, range)) = hovered_package(&source, offset) else {
+            return Ok(None);
+        };
+
+        let markdown = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| package_markdown(&world, spec))
+            .await;
+
+        Ok(markdown.map(|value| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(typst_to_lsp::range(range, &source, position_encoding).raw_range),
+        }))
+    }
+
+    /// Shows the value of a top-level `let` binding with a constant value, or the signature of a
+    /// function binding, by evaluating the source. Gives `None` if the cursor isn't on such a
+    /// binding or evaluation fails, so the regular tooltip can be used instead.
+    async fn get_binding_hover(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Hover>> {
+        let position_encoding = self.position_encoding();
+
+        let source = self
+            .scope_with_source(uri)
+            .await?
+            .run(|source, _| source.clone());
+        let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+        let Some(binding) = hovered_binding(&source, offset) else {
+            return Ok(None);
+        };
+
+        let Some(module) = self.eval_source(uri).await?.0 else {
+            return Ok(None);
+        };
+        let Some(value) = module.scope().get(&binding.name) else {
+            return Ok(None);
+        };
+
+        let tooltip = Tooltip::Code(binding_tooltip(value).into());
+        Ok(Some(Hover {
+            contents: typst_to_lsp::tooltip(&tooltip),
+            range: Some(typst_to_lsp::range(binding.range, &source, position_encoding).raw_range),
+        }))
+    }
+}
+
+/// The package spec in the hovered import or include string, along with the range of the string
+fn hovered_package(source: &Source, offset: usize) -> Option<(PackageSpec, TypstRange)> {
+    let leaf = LinkedNode::new(source.root()).leaf_at(offset)?;
+    if leaf.kind() != SyntaxKind::Str {
+        return None;
+    }
+
+    let parent = leaf.parent()?;
+    let is_import_source = parent
+        .cast::<ast::ModuleImport>()
+        .map(|import| import.source().span() == leaf.span())
+        .or_else(|| {
+            let include = parent.cast::<ast::ModuleInclude>()?;
+            Some(include.source().span() == leaf.span())
+        })
+        .unwrap_or(false);
+    if !is_import_source {
+        return None;
+    }
+
+    let spec = leaf.cast::<ast::Str>()?.get().parse().ok()?;
+    Some((spec, leaf.range()))
+}
+
+/// Pixels per inch assumed when converting lengths to pixels, as in CSS
+const PIXELS_PER_INCH: f64 = 96.0;
+
+/// The hovered length, angle or ratio literal in other units, like `12pt = 16px at 96dpi`, which
+/// helps when debugging layouts. Gives `None` for units which depend on context, like `em` and
+/// `fr`.
+fn numeric_conversions(source: &Source, offset: usize) -> Option<String> {
+    let leaf = LinkedNode::new(source.root()).leaf_at(offset)?;
+    let (value, unit) = leaf.cast::<ast::Numeric>()?.get();
+    let literal = leaf.text();
+
+    let conversions = match unit {
+        ast::Unit::Pt | ast::Unit::Mm | ast::Unit::Cm | ast::Unit::In => {
+            let points = value * points_per(unit)?;
+            let pixels = points / 72.0 * PIXELS_PER_INCH;
+            let mut conversions = vec![format!("{}px at {PIXELS_PER_INCH}dpi", round(pixels))];
+            for other in [ast::Unit::Pt, ast::Unit::Mm, ast::Unit::Cm, ast::Unit::In] {
+                if other != unit {
+                    let converted = points / points_per(other)?;
+                    conversions.push(format!("{}{}", round(converted), unit_name(other)?));
+                }
+            }
+            conversions
+        }
+        ast::Unit::Deg => vec![format!("{}rad", round(value.to_radians()))],
+        ast::Unit::Rad => vec![format!("{}deg", round(value.to_degrees()))],
+        ast::Unit::Percent => vec![round(value / 100.0)],
+        _ => return None,
+    };
+
+    Some(format!("{literal} = {}", conversions.join(" = ")))
+}
+
+fn points_per(unit: ast::Unit) -> Option<f64> {
+    match unit {
+        ast::Unit::Pt => Some(1.0),
+        ast::Unit::Mm => Some(72.0 / 25.4),
+        ast::Unit::Cm => Some(72.0 / 2.54),
+        ast::Unit::In => Some(72.0),
+        _ => None,
+    }
+}
+
+fn unit_name(unit: ast::Unit) -> Option<&'static str> {
+    match unit {
+        ast::Unit::Pt => Some("pt"),
+        ast::Unit::Mm => Some("mm"),
+        ast::Unit::Cm => Some("cm"),
+        ast::Unit::In => Some("in"),
+        _ => None,
+    }
+}
+
+/// Formats with at most three decimal places, without trailing zeros
+fn round(value: f64) -> String {
+    let formatted = format!("{value:.3}");
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_owned()
+}
+
+/// Describes a package using its manifest, or `None` if the package can't be loaded
+fn package_markdown(loader: &dyn SourceLoader, spec: PackageSpec) -> Option<String> {
+    let manifest_id = FileId::new(Some(spec), VirtualPath::new("typst.toml"));
+    let manifest = loader.load_bytes(manifest_id)?;
+    let manifest: PackageManifest = toml::from_str(std::str::from_utf8(&manifest).ok()?).ok()?;
+    let package = manifest.package;
+
+    let mut markdown = format!("**{}** `{}`", package.name, package.version);
+    if let Some(description) = package.description {
+        markdown.push_str(&format!("\n\n{description}"));
+    }
+    if !package.authors.is_empty() {
+        markdown.push_str(&format!("\n\nBy {}", package.authors.iter().join(", ")));
+    }
+    Some(markdown)
+}
+
+/// The name of a `let` binding being hovered
+#[derive(Debug, PartialEq)]
+struct HoveredBinding {
+    name: EcoString,
+    range: TypstRange,
+}
+
+/// Finds the `let` binding whose name is at `offset`, if its value is a constant or it defines a
+/// function
+fn hovered_binding(source: &Source, offset: usize) -> Option<HoveredBinding> {
+    let root = LinkedNode::new(source.root());
+    let ident_node = root.leaf_at(offset)?;
+    let ident = ident_node.cast::<ast::Ident>()?;
+    let binding = ident_node.parent()?.cast::<ast::LetBinding>().or_else(|| {
+        // The name of a function binding is nested in the closure
+        ident_node.parent()?.parent()?.cast::<ast::LetBinding>()
+    })?;
+
+    let bound = match binding.kind() {
+        ast::LetBindingKind::Closure(name) => name,
+        ast::LetBindingKind::Normal(ast::Pattern::Normal(ast::Expr::Ident(name)))
+            if binding.init().is_some_and(is_constant) =>
+        {
+            name
+        }
+        _ => return None,
+    };
+    if bound.span() != ident.span() {
+        return None;
+    }
+
+    Some(HoveredBinding {
+        name: ident.get().clone(),
+        range: ident_node.range(),
+    })
+}
+
+/// Whether an expression always evaluates to the same value, independent of any variables
+fn is_constant(expr: ast::Expr) -> bool {
+    match expr {
+        ast::Expr::None(_)
+        | ast::Expr::Auto(_)
+        | ast::Expr::Bool(_)
+        | ast::Expr::Int(_)
+        | ast::Expr::Float(_)
+        | ast::Expr::Numeric(_)
+        | ast::Expr::Str(_) => true,
+        ast::Expr::Parenthesized(parenthesized) => is_constant(parenthesized.expr()),
+        ast::Expr::Unary(unary) => is_constant(unary.expr()),
+        ast::Expr::Binary(binary) => is_constant(binary.lhs()) && is_constant(binary.rhs()),
+        ast::Expr::Array(array) => array.items().all(|item| match item {
+            ast::ArrayItem::Pos(expr) => is_constant(expr),
+            ast::ArrayItem::Spread(_) => false,
+        }),
+        ast::Expr::Dict(dict) => dict.items().all(|item| match item {
+            ast::DictItem::Named(named) => is_constant(named.expr()),
+            ast::DictItem::Keyed(keyed) => is_constant(keyed.key()) && is_constant(keyed.expr()),
+            ast::DictItem::Spread(_) => false,
+        }),
+        _ => false,
+    }
+}
+
+fn binding_tooltip(value: &Value) -> String {
+    match value {
+        Value::Func(function) => ParamInFunction::of_function(function).label(),
+        value => value.repr().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use typst::foundations::Scope;
+
+    use crate::server::definition::test::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn constant_binding_shows_value() {
+        let source = Source::detached("#let pi = 3.14");
+        let mut scope = Scope::new();
+        scope.define("pi", 3.14);
+
+        let binding = hovered_binding(&source, 6).unwrap();
+        let value = scope.get(&binding.name).unwrap();
+
+        assert_eq!(binding.range, 5..7);
+        assert_eq!(binding_tooltip(value), "3.14");
+    }
+
+    #[test]
+    fn package_import_shows_manifest() {
+        let spec: PackageSpec = "@preview/example:0.1.0".parse().unwrap();
+        let manifest = r#"
+            [package]
+            name = "example"
+            version = "0.1.0"
+            entrypoint = "lib.typ"
+            authors = ["Jane Doe"]
+            description = "An example package."
+        "#;
+        let fixture = Fixture::default().with_id(
+            FileId::new(Some(spec.clone()), VirtualPath::new("typst.toml")),
+            manifest,
+        );
+
+        let source = Source::detached(r#"#import "@preview/example:0.1.0": *"#);
+        let (hovered, range) = hovered_package(&source, 12).unwrap();
+        assert_eq!(hovered, spec);
+        assert_eq!(range, 8..32);
+
+        let markdown = package_markdown(&fixture, hovered).unwrap();
+        assert_eq!(
+            markdown,
+            "**example** `0.1.0`\n\nAn example package.\n\nBy Jane Doe"
+        );
+
+        let missing = "@preview/missing:0.1.0".parse().unwrap();
+        assert!(package_markdown(&fixture, missing).is_none());
+        assert!(hovered_package(&Source::detached(r#"#"@preview/example:0.1.0""#), 5).is_none());
+    }
+
+    #[test]
+    fn non_constant_binding_is_skipped() {
+        let source = Source::detached("#let x = calc.pi\n#let y = x + 1");
+
+        assert_eq!(hovered_binding(&source, 6), None);
+        assert_eq!(hovered_binding(&source, 23), None);
+    }
+
+    #[test]
+    fn length_literal_shows_conversions() {
+        let source = Source::detached("#h(12pt)");
+
+        let conversions = numeric_conversions(&source, 5).unwrap();
+
+        assert_eq!(
+            conversions,
+            "12pt = 16px at 96dpi = 4.233mm = 0.423cm = 0.167in"
+        );
+    }
+
+    #[test]
+    fn angle_literal_shows_conversions() {
+        let source = Source::detached("#rotate(45deg)[x]");
+
+        assert_eq!(
+            numeric_conversions(&source, 10).as_deref(),
+            Some("45deg = 0.785rad")
+        );
+        assert_eq!(numeric_conversions(&Source::detached("#h(1fr)"), 4), None);
+    }
 }
@@ -0,0 +1,95 @@
+use tower_lsp::lsp_types::LinkedEditingRanges;
+use typst::syntax::{LinkedNode, Source};
+
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+
+use super::references::{label_name, label_uses};
+use super::TypstServer;
+
+/// Characters which may appear in a label's name, as a JavaScript regular expression
+const LABEL_NAME_PATTERN: &str = r"[\p{L}\p{N}_\-.:]+";
+
+impl TypstServer {
+    pub fn get_linked_editing_ranges(
+        &self,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<LinkedEditingRanges> {
+        let position_encoding = self.position_encoding();
+        let offset = lsp_to_typst::position_to_offset(position, position_encoding, source);
+        let ranges = linked_label_ranges(source, offset)?;
+        Some(linked_editing_ranges(source, position_encoding, ranges))
+    }
+}
+
+fn linked_editing_ranges(
+    source: &Source,
+    position_encoding: PositionEncoding,
+    ranges: Vec<TypstRange>,
+) -> LinkedEditingRanges {
+    LinkedEditingRanges {
+        ranges: ranges
+            .into_iter()
+            .map(|range| typst_to_lsp::range(range, source, position_encoding).raw_range)
+            .collect(),
+        word_pattern: Some(LABEL_NAME_PATTERN.to_owned()),
+    }
+}
+
+/// If the cursor is on the name of a label or a reference to it, gets the ranges of the names of
+/// the label and its references, so renaming one renames them all. Only labels attached once in
+/// the file and referenced in it are linked, since otherwise it isn't clear what belongs together.
+fn linked_label_ranges(source: &Source, offset: usize) -> Option<Vec<TypstRange>> {
+    let root = LinkedNode::new(source.root());
+    let name = [offset, offset.saturating_sub(1)]
+        .into_iter()
+        .filter_map(|offset| root.leaf_at(offset))
+        .find_map(|leaf| label_name(&leaf).map(|(name, _)| name.to_owned()))?;
+
+    let (labels, references): (Vec<_>, Vec<_>) = label_uses(source)
+        .into_iter()
+        .filter(|label| label.name.as_str() == name)
+        .partition(|label| label.is_label);
+
+    if labels.len() != 1 || references.is_empty() {
+        return None;
+    }
+
+    Some(
+        labels
+            .into_iter()
+            .chain(references)
+            .map(|label| label.range)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn label_with_one_reference() {
+        let source = Source::detached("= Intro <intro>\nSee @intro.");
+        let text = source.text();
+        let label = text.find("intro>").unwrap();
+        let reference = text.find("@intro").unwrap() + 1;
+
+        let expected = vec![label..label + 5, reference..reference + 5];
+        assert_eq!(
+            linked_label_ranges(&source, label + 2),
+            Some(expected.clone())
+        );
+        assert_eq!(linked_label_ranges(&source, reference + 5), Some(expected));
+    }
+
+    #[test]
+    fn unreferenced_or_repeated_labels_are_not_linked() {
+        let source = Source::detached("= Intro <intro>");
+        assert_eq!(linked_label_ranges(&source, 10), None);
+
+        let source = Source::detached("= A <a>\n= B <a>\n@a");
+        assert_eq!(linked_label_ranges(&source, 5), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, DiagnosticTag};
+use typst::diag::EcoString;
+use typst::syntax::{ast, LinkedNode, Source, Span};
+
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::{typst_to_lsp, TypstRange};
+
+use super::definition::{collect_used_names, import_bindings, let_bindings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnusedKind {
+    Binding,
+    Import,
+}
+
+/// A top-level name which is never used in its file
+#[derive(Debug, PartialEq, Eq)]
+struct Unused {
+    name: EcoString,
+    range: TypstRange,
+    kind: UnusedKind,
+}
+
+/// Warns about top-level `let` bindings and imported names which are never used in the file. Only
+/// meant for files which aren't imported, since otherwise their bindings may be used elsewhere.
+pub fn unused_diagnostics(source: &Source, position_encoding: PositionEncoding) -> Vec<Diagnostic> {
+    unused_names(source)
+        .into_iter()
+        .map(|unused| {
+            let what = match unused.kind {
+                UnusedKind::Binding => "binding",
+                UnusedKind::Import => "import",
+            };
+            Diagnostic {
+                range: typst_to_lsp::range(unused.range, source, position_encoding).raw_range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                message: format!("unused {what} `{}`", unused.name),
+                source: Some("typst-lsp".to_owned()),
+                tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn unused_names(source: &Source) -> Vec<Unused> {
+    let root = LinkedNode::new(source.root());
+
+    let mut declared = Vec::new();
+    for child in root.children() {
+        if let Some(binding) = child.cast::<ast::LetBinding>() {
+            declared.extend(
+                let_bindings(binding)
+                    .into_iter()
+                    .map(|ident| (ident, UnusedKind::Binding)),
+            );
+        } else if let Some(import) = child.cast::<ast::ModuleImport>() {
+            declared.extend(
+                import_bindings(import)
+                    .into_iter()
+                    .map(|ident| (ident, UnusedKind::Import)),
+            );
+        }
+    }
+
+    let declaration_spans: HashSet<Span> = declared.iter().map(|(ident, _)| ident.span()).collect();
+    let mut used = HashSet::new();
+    collect_used_names(&root, &declaration_spans, &mut used);
+
+    declared
+        .into_iter()
+        .filter(|(ident, _)| !used.contains(ident.as_str()))
+        .filter_map(|(ident, kind)| {
+            Some(Unused {
+                name: ident.get().clone(),
+                range: source.range(ident.span())?,
+                kind,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unused(text: &str) -> Vec<EcoString> {
+        unused_names(&Source::detached(text))
+            .into_iter()
+            .map(|unused| unused.name)
+            .collect()
+    }
+
+    #[test]
+    fn unused_binding_is_reported() {
+        assert_eq!(unused("#let x = 1\n#let y = 2\n#y"), ["x"]);
+        assert_eq!(unused("#let x = 1\n\"x\" <x>"), ["x"]);
+        assert_eq!(unused("#let x = 1\n#let y = 2\n#y.x"), ["x"]);
+    }
+
+    #[test]
+    fn used_names_are_not_reported() {
+        assert!(unused("#let x = 1\n#let f(a) = a + x\n#f(2)").is_empty());
+        assert!(unused("#import \"a.typ\": *\n#import \"b.typ\": c\n#c").is_empty());
+        assert_eq!(unused("#import \"b.typ\": c, d as e\n#c"), ["e"]);
+    }
+
+    #[test]
+    fn unused_diagnostic_is_unnecessary() {
+        let source = Source::detached("#let x = 1");
+        let diagnostics = unused_diagnostics(&source, PositionEncoding::Utf16);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(diagnostics[0].tags, Some(vec![DiagnosticTag::UNNECESSARY]));
+        assert_eq!(diagnostics[0].range.start.character, 5);
+    }
+}
@@ -4,22 +4,79 @@ use tokio::runtime::Handle;
 use tower_lsp::lsp_types::MessageType;
 use tower_lsp::Client;
 use tracing::field::{Field, Visit};
-use tracing::{Event, Level, Metadata, Subscriber};
+use tracing::{error, Event, Level, Metadata, Subscriber};
 use tracing_subscriber::layer::Context;
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::Layer;
+use tracing_subscriber::{reload, Registry};
 
+use crate::config::LogFileOptions;
+
+use super::log_file::FileLogLayer;
 use super::TypstServer;
 
 impl TypstServer {
     pub fn tracing_init(&self) {
         let lsp_layer = LspLayer::new(self.client.clone());
         self.lsp_tracing_layer_handle
-            .reload(Some(lsp_layer))
+            .modify(|layers| layers.lsp = Some(lsp_layer))
             .expect("should be able to replace layer, since it should only fail when there is a larger issue with the `Subscriber`");
     }
 }
 
+/// Starts, stops, or adjusts logging to a file. Failing to open the log file is logged rather than
+/// returned, since the server works fine without it.
+pub fn apply_log_file_options(
+    handle: &reload::Handle<LogLayers, Registry>,
+    options: &LogFileOptions,
+) {
+    let same_path = handle
+        .with_current(|layers| {
+            let current = layers.file.as_ref().map(FileLogLayer::configured_path);
+            current == options.path.as_deref()
+        })
+        .unwrap_or_default();
+    if same_path {
+        let level = options.level;
+        let result = handle.modify(|layers| {
+            if let Some(file) = &mut layers.file {
+                file.set_level(level);
+            }
+        });
+        if let Err(err) = result {
+            error!(%err, "could not change log file level");
+        }
+        return;
+    }
+
+    let file = FileLogLayer::open(options).unwrap_or_else(|err| {
+        error!(%err, "could not open log file");
+        None
+    });
+    if let Err(err) = handle.modify(|layers| layers.file = file) {
+        error!(%err, "could not replace log file");
+    }
+}
+
+/// The layers which can be replaced while the server runs, since they depend on the client and
+/// the configuration
+#[derive(Default)]
+pub struct LogLayers {
+    pub lsp: Option<LspLayer>,
+    pub file: Option<FileLogLayer>,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for LogLayers {
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        if let Some(lsp) = &self.lsp {
+            lsp.on_event(event, ctx.clone());
+        }
+        if let Some(file) = &self.file {
+            file.on_event(event, ctx);
+        }
+    }
+}
+
 pub struct LspLayer {
     client: Client,
 }
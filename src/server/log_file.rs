@@ -0,0 +1,211 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use serde_json::{Map, Value as JsonValue};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::config::{LogFileOptions, LogLevel};
+
+/// How many rotated log files to keep, one per day
+const MAX_LOG_FILES: usize = 7;
+
+/// Writes events to a log file as JSON lines, rotating to a new file each day
+pub struct FileLogLayer {
+    /// The path as configured, before resolving it
+    configured_path: PathBuf,
+    writer: parking_lot::Mutex<Box<dyn Write + Send>>,
+    level: LevelFilter,
+    /// Flushes the log file when dropped
+    _guard: Option<WorkerGuard>,
+}
+
+impl FileLogLayer {
+    /// Opens the log file configured by the options, if any. A relative path is resolved against
+    /// the server's cache directory.
+    pub fn open(options: &LogFileOptions) -> anyhow::Result<Option<Self>> {
+        let Some(path) = &options.path else {
+            return Ok(None);
+        };
+        let path = resolve_log_path(path)?;
+
+        let directory = path.parent().context("log file has no parent directory")?;
+        let file_name = path.file_name().context("log file has no file name")?;
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("could not create log directory {}", directory.display()))?;
+
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(file_name.to_string_lossy())
+            .max_log_files(MAX_LOG_FILES)
+            .build(directory)?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+
+        Ok(Some(Self {
+            configured_path: options.path.clone().unwrap_or_default(),
+            writer: parking_lot::Mutex::new(Box::new(writer)),
+            level: options.level.into(),
+            _guard: Some(guard),
+        }))
+    }
+
+    pub fn configured_path(&self) -> &Path {
+        &self.configured_path
+    }
+
+    pub fn set_level(&mut self, level: LogLevel) {
+        self.level = level.into();
+    }
+
+    fn format_event(event: &Event) -> JsonValue {
+        let metadata = event.metadata();
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+
+        let mut fields = Map::new();
+        event.record(&mut JsonVisit(&mut fields));
+
+        serde_json::json!({
+            "timestampMs": timestamp_ms,
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "file": metadata.file(),
+            "line": metadata.line(),
+            "fields": fields,
+        })
+    }
+}
+
+fn resolve_log_path(path: &Path) -> anyhow::Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_owned());
+    }
+
+    let cache = dirs::cache_dir().context("no cache directory for relative log file")?;
+    Ok(cache.join("typst-lsp").join(path))
+}
+
+impl<S: Subscriber> Layer<S> for FileLogLayer {
+    fn on_event(&self, event: &Event, _ctx: Context<S>) {
+        if *event.metadata().level() > self.level {
+            return;
+        }
+
+        let mut line = Self::format_event(event).to_string();
+        line.push('\n');
+        // Logging must never take the server down, so a failed write is dropped
+        let _ = self.writer.lock().write_all(line.as_bytes());
+    }
+}
+
+struct JsonVisit<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'a> Visit for JsonVisit<'a> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{value:?}").into());
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        let level = match level {
+            LogLevel::Error => Level::ERROR,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Trace => Level::TRACE,
+        };
+        LevelFilter::from_level(level)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::sync::Arc;
+
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::reload;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<JsonValue> {
+            let buffer = self.0.lock();
+            std::str::from_utf8(&buffer)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn level_filters_events() {
+        let buffer = SharedBuffer::default();
+        let layer = FileLogLayer {
+            configured_path: PathBuf::from("typst-lsp.log"),
+            writer: parking_lot::Mutex::new(Box::new(buffer.clone())),
+            level: LogLevel::Info.into(),
+            _guard: None,
+        };
+        let (layer, handle) = reload::Layer::new(layer);
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("hidden");
+            tracing::info!(count = 1, "shown");
+
+            handle
+                .modify(|layer| layer.set_level(LogLevel::Debug))
+                .unwrap();
+            tracing::debug!("shown after level change");
+        });
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["count"], 1);
+        assert_eq!(lines[0]["fields"]["message"], "shown");
+        assert_eq!(lines[1]["level"], "DEBUG");
+    }
+}
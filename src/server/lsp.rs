@@ -2,10 +2,11 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use async_trait::async_trait;
-use futures::FutureExt;
+use futures::{future, FutureExt};
 use itertools::Itertools;
 use serde_json::Value as JsonValue;
 use tokio::sync::RwLock;
+use tower_lsp::lsp_types::request::{GotoTypeDefinitionParams, GotoTypeDefinitionResponse};
 use tower_lsp::lsp_types::*;
 use tower_lsp::{jsonrpc, LanguageServer};
 use tracing::{error, info, trace, warn};
@@ -18,14 +19,22 @@ use crate::config::{
 use crate::ext::InitializeParamsExt;
 use crate::lsp_typst_boundary::typst_to_lsp::offset_to_position;
 use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspRawRange};
-use crate::server::formatting::{get_formatting_registration, get_formatting_unregistration};
+use crate::server::formatting::{get_formatting_registrations, get_formatting_unregistrations};
+use crate::workspace::fs::lsp::SourceEdit;
+use crate::workspace::package::external::DownloadProgress;
 use crate::workspace::Workspace;
 
 use super::command::LspCommand;
+use super::completion;
+use super::document::CompileTarget;
+use super::download_progress::ClientDownloadProgress;
+use super::log::apply_log_file_options;
+use super::rename::RenameError;
 use super::semantic_tokens::{
     get_semantic_tokens_options, get_semantic_tokens_registration,
     get_semantic_tokens_unregistration,
 };
+use super::symbols::get_nested_symbols;
 use super::TypstServer;
 
 #[async_trait]
@@ -34,15 +43,15 @@ impl LanguageServer for TypstServer {
     async fn initialize(&self, params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
         self.tracing_init();
 
-        self.workspace
-            .set(Arc::new(RwLock::new(Workspace::new(&params))))
-            .map_err(|_| ())
-            .expect("workspace should not yet be initialized");
-
         self.const_config
             .set(ConstConfig::from(&params))
             .expect("const config should not yet be initialized");
 
+        self.config
+            .write()
+            .await
+            .set_workspace_roots(params.root_uris());
+
         if let Some(init) = &params.initialization_options {
             let mut config = self.config.write().await;
             config
@@ -53,6 +62,26 @@ impl LanguageServer for TypstServer {
                 .map_err(jsonrpc::Error::invalid_params)?;
         }
 
+        // The package manager is configured from the initialization options, so set up the
+        // workspace after reading them. Progress is only reported to clients which accept
+        // server-initiated progress.
+        let download_progress: Arc<dyn DownloadProgress> = if params.supports_work_done_progress() {
+            Arc::new(ClientDownloadProgress::new(self.client.clone()))
+        } else {
+            Arc::new(())
+        };
+        let workspace = Workspace::new(&params, &*self.config.read().await, download_progress);
+        if let Some(err) = workspace.package_manager().download_error() {
+            let message = format!("Package downloads are disabled: {err:#}");
+            self.client
+                .show_message(MessageType::WARNING, message)
+                .await;
+        }
+        self.workspace
+            .set(Arc::new(RwLock::new(workspace)))
+            .map_err(|_| ())
+            .expect("workspace should not yet be initialized");
+
         if let Err(err) = self.register_workspace_files().await {
             error!(%err, "could not register workspace files on init");
             return Err(jsonrpc::Error::internal_error());
@@ -69,17 +98,17 @@ impl LanguageServer for TypstServer {
             _ => None,
         };
 
-        let document_formatting_provider = match config.formatter {
-            ExperimentalFormatterMode::On
-                if !params.supports_document_formatting_dynamic_registration() =>
-            {
-                Some(OneOf::Left(true))
-            }
-            _ => None,
-        };
+        // Without dynamic registration, formatting must be advertised up front
+        let static_formatting = config.formatter == ExperimentalFormatterMode::On
+            && !params.supports_document_formatting_dynamic_registration();
+        let document_formatting_provider = static_formatting.then_some(OneOf::Left(true));
+        let document_range_formatting_provider = static_formatting.then_some(OneOf::Left(true));
 
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
+                // Tell the client which of its offered encodings was chosen, since it must convert
+                // positions accordingly
+                position_encoding: Some(self.position_encoding().into()),
                 signature_help_provider: Some(SignatureHelpOptions {
                     trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
                     retrigger_characters: None,
@@ -88,12 +117,43 @@ impl LanguageServer for TypstServer {
                     },
                 }),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
+                references_provider: Some(OneOf::Left(true)),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                type_hierarchy_provider: Some(TypeHierarchyServerCapability::Simple(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: None,
+                    },
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::REFACTOR_REWRITE,
+                        ]),
+                        ..Default::default()
+                    },
+                )),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: None,
+                    },
+                })),
                 completion_provider: Some(CompletionOptions {
                     trigger_characters: Some(vec![
                         String::from("#"),
                         String::from("."),
                         String::from("@"),
+                        String::from("/"),
+                        String::from("<"),
+                        String::from(":"),
                     ]),
+                    resolve_provider: Some(true),
                     ..Default::default()
                 }),
                 text_document_sync: Some(TextDocumentSyncCapability::Options(
@@ -114,6 +174,14 @@ impl LanguageServer for TypstServer {
                 document_symbol_provider: Some(OneOf::Left(true)),
                 workspace_symbol_provider: Some(OneOf::Left(true)),
                 selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                linked_editing_range_provider: Some(LinkedEditingRangeServerCapabilities::Simple(
+                    true,
+                )),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: String::from("}"),
+                    more_trigger_character: Some(vec![String::from("$"), String::from("\n")]),
+                }),
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                         supported: Some(true),
@@ -122,6 +190,7 @@ impl LanguageServer for TypstServer {
                     ..Default::default()
                 }),
                 document_formatting_provider,
+                document_range_formatting_provider,
                 ..Default::default()
             },
             ..Default::default()
@@ -130,7 +199,10 @@ impl LanguageServer for TypstServer {
 
     #[tracing::instrument(skip_all)]
     async fn initialized(&self, _: InitializedParams) {
-        let const_config = self.const_config();
+        let Some(const_config) = self.const_config() else {
+            warn!("ignoring `initialized` received before `initialize`");
+            return;
+        };
         let mut config = self.config.write().await;
 
         if const_config.supports_semantic_tokens_dynamic_registration {
@@ -182,7 +254,7 @@ impl LanguageServer for TypstServer {
                 let client = client.clone();
                 async move {
                     client
-                        .register_capability(vec![get_formatting_registration()])
+                        .register_capability(get_formatting_registrations())
                         .await
                         .context("could not register document formatting")
                 }
@@ -194,7 +266,7 @@ impl LanguageServer for TypstServer {
                 let client = client.clone();
                 async move {
                     client
-                        .unregister_capability(vec![get_formatting_unregistration()])
+                        .unregister_capability(get_formatting_unregistrations())
                         .await
                         .context("could not unregister document formatting")
                 }
@@ -212,6 +284,40 @@ impl LanguageServer for TypstServer {
             }));
         }
 
+        self.thread_stats.set_enabled(config.profile_typst_thread);
+        let thread_stats = Arc::clone(&self.thread_stats);
+        config.listen_profile_typst_thread(Box::new(move |enabled| {
+            thread_stats.set_enabled(*enabled);
+            future::ready(Ok(())).boxed()
+        }));
+
+        apply_log_file_options(&self.lsp_tracing_layer_handle, &config.log_file);
+        let log_handle = self.lsp_tracing_layer_handle.clone();
+        config.listen_log_file(Box::new(move |options| {
+            apply_log_file_options(&log_handle, options);
+            future::ready(Ok(())).boxed()
+        }));
+
+        if config.preview {
+            if let Err(err) = self.preview.set_enabled(&self.client, true).await {
+                error!(%err, "could not start preview server");
+            }
+        }
+        let preview = self.preview.clone();
+        let client = self.client.clone();
+        config.listen_preview(Box::new(move |enabled| {
+            let preview = preview.clone();
+            let client = client.clone();
+            let enabled = *enabled;
+            async move {
+                preview
+                    .set_enabled(&client, enabled)
+                    .await
+                    .context("could not start preview server")
+            }
+            .boxed()
+        }));
+
         if const_config.supports_config_change_registration {
             trace!("setting up to request config change notifications");
 
@@ -225,21 +331,26 @@ impl LanguageServer for TypstServer {
             }
         }
 
-        trace!("setting up to watch Typst files");
+        trace!("setting up to watch files");
         let watch_files_error = self
             .client
             .register_capability(vec![self.get_watcher_registration()])
             .await
             .err();
         if let Some(err) = watch_files_error {
-            error!(%err, "could not register to watch Typst files");
+            error!(%err, "could not register to watch files");
         }
 
+        // A main file may have been pinned by the initialization options
+        drop(config);
+        self.notify_pinned_main(None).await;
+
         info!("server initialized");
     }
 
     #[tracing::instrument(skip_all)]
     async fn shutdown(&self) -> jsonrpc::Result<()> {
+        self.preview.stop();
         Ok(())
     }
 
@@ -248,7 +359,10 @@ impl LanguageServer for TypstServer {
         let uri = params.text_document.uri;
         let text = params.text_document.text;
 
-        let mut workspace = self.workspace().write().await;
+        let Some(workspace) = self.notified_workspace() else {
+            return;
+        };
+        let mut workspace = workspace.write().await;
 
         if let Err(err) = workspace.open_lsp(uri.clone(), text) {
             error!(%err, %uri, "could not open file from LSP client");
@@ -257,6 +371,10 @@ impl LanguageServer for TypstServer {
 
         drop(workspace);
 
+        self.semantic_tokens_delta_cache
+            .write()
+            .mark_edited(&uri, None, [SourceEdit::All]);
+
         if let Err(err) = self.on_source_changed(&uri).await {
             error!(%err, %uri, "could not handle source change");
         };
@@ -265,10 +383,24 @@ impl LanguageServer for TypstServer {
     #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         let uri = params.text_document.uri;
+        let Some(workspace) = self.notified_workspace() else {
+            return;
+        };
 
-        let mut workspace = self.workspace().write().await;
+        // Only documents which were compiled while open are exported, so opening and closing a
+        // file doesn't write a PDF next to it
+        let export_pdf = self.config.read().await.export_pdf;
+        if export_pdf == ExportPdfMode::OnClose && self.documents.get(&uri).is_some() {
+            if let Err(err) = self.run_export(&uri).await {
+                error!(%err, %uri, "could not export closed document");
+            }
+        }
+
+        let mut workspace = workspace.write().await;
 
         workspace.close_lsp(&uri);
+        self.documents.remove(&uri);
+        self.dependencies.write().remove(&uri);
         self.client.publish_diagnostics(uri, Vec::new(), None).await;
     }
 
@@ -277,29 +409,40 @@ impl LanguageServer for TypstServer {
         let uri = params.text_document.uri;
         let changes = params.content_changes;
 
-        let mut workspace = self.workspace().write().await;
+        let Some(workspace) = self.notified_workspace() else {
+            return;
+        };
+        let mut workspace = workspace.write().await;
 
-        workspace.edit_lsp(&uri, changes, self.const_config().position_encoding);
+        let before = workspace.read_source(&uri).ok();
+        let edits = workspace.edit_lsp(&uri, changes, self.position_encoding());
 
         drop(workspace);
 
+        self.semantic_tokens_delta_cache.write().mark_edited(
+            &uri,
+            before.as_ref().map(|source| source.text()),
+            edits,
+        );
+
         if let Err(err) = self.on_source_changed(&uri).await {
             error!(%err, %uri, "could not handle source change");
         };
+        self.recompile_edit_dependents(&uri).await;
     }
 
     #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
         let uri = params.text_document.uri;
+        if self.notified_workspace().is_none() {
+            return;
+        }
 
-        let config = self.config.read().await;
-
-        let uri = match config.export_pdf {
-            ExportPdfMode::OnPinnedMainSave => Some(self.main_url().await.unwrap_or(uri)),
-            ExportPdfMode::OnSave => Some(uri),
-            _ => None,
+        let target = {
+            let config = self.config.read().await;
+            CompileTarget::on_save(config.export_pdf, config.main_file.as_ref(), &uri)
         };
-        let Some(uri) = uri else {
+        let Some(CompileTarget { uri, .. }) = target else {
             return;
         };
 
@@ -310,24 +453,49 @@ impl LanguageServer for TypstServer {
 
     #[tracing::instrument(skip(self))]
     async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
-        let changes = params.changes;
+        let changes = Self::coalesce_file_events(params.changes);
 
-        let mut workspace = self.workspace().write().await;
+        let Some(workspace) = self.notified_workspace() else {
+            return;
+        };
+        let mut workspace = workspace.write().await;
 
+        let changed = changes
+            .iter()
+            .map(|change| change.uri.clone())
+            .collect_vec();
         for change in changes {
             self.handle_file_change_event(&mut workspace, change);
         }
+
+        drop(workspace);
+
+        self.recompile_dependents(&changed).await;
     }
 
     #[tracing::instrument(skip(self))]
     async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
         let event = params.event;
 
-        let mut workspace = self.workspace().write().await;
+        let Some(workspace) = self.notified_workspace() else {
+            return;
+        };
+        let mut workspace = workspace.write().await;
 
         if let Err(err) = workspace.handle_workspace_folders_change_event(&event) {
             error!(%err, "error when changing workspace folders");
         }
+        drop(workspace);
+
+        // Documents may now belong to different projects
+        self.documents.clear();
+
+        let previous_main = self.main_url().await;
+        self.config
+            .write()
+            .await
+            .handle_workspace_folders_change_event(&event);
+        self.notify_pinned_main(previous_main).await;
     }
 
     #[tracing::instrument(
@@ -338,27 +506,64 @@ impl LanguageServer for TypstServer {
         &self,
         params: ExecuteCommandParams,
     ) -> jsonrpc::Result<Option<JsonValue>> {
+        self.ensure_initialized()?;
+
         let ExecuteCommandParams {
             command,
             arguments,
             work_done_progress_params: _,
         } = params;
-        match LspCommand::parse(&command) {
+        let result = match LspCommand::parse(&command) {
             Some(LspCommand::ExportPdf) => {
                 self.command_export_pdf(arguments).await?;
+                None
             }
             Some(LspCommand::ClearCache) => {
                 self.command_clear_cache(arguments).await?;
+                None
             }
             Some(LspCommand::PinMain) => {
                 self.command_pin_main(arguments).await?;
+                None
+            }
+            Some(LspCommand::ExportThumbnails) => {
+                self.command_export_thumbnails(arguments).await?;
+                None
+            }
+            Some(LspCommand::ListStyleRules) => {
+                Some(self.command_list_style_rules(arguments).await?)
+            }
+            Some(LspCommand::ThreadStats) => Some(self.command_thread_stats(arguments).await?),
+            Some(LspCommand::RenderedOutline) => {
+                Some(self.command_rendered_outline(arguments).await?)
+            }
+            Some(LspCommand::InstantiateTemplate) => {
+                Some(self.command_instantiate_template(arguments).await?)
+            }
+            Some(LspCommand::ExportWithSource) => {
+                Some(self.command_export_with_source(arguments).await?)
+            }
+            Some(LspCommand::CheckPackageUpdates) => {
+                Some(self.command_check_package_updates().await?)
+            }
+            Some(LspCommand::ExportPng) => Some(self.command_export_png(arguments).await?),
+            Some(LspCommand::ListFonts) => Some(self.command_list_fonts().await?),
+            Some(LspCommand::Compile) => Some(self.command_compile(arguments).await?),
+            Some(LspCommand::ExportPdfPage) => Some(self.command_export_pdf_page(arguments).await?),
+            Some(LspCommand::Query) => Some(self.command_query(arguments).await?),
+            Some(LspCommand::UnpinMain) => {
+                self.command_unpin_main().await?;
+                None
+            }
+            Some(LspCommand::DocumentOutline) => {
+                Some(self.command_document_outline(arguments).await?)
             }
             None => {
                 error!("asked to execute unknown command");
                 return Err(jsonrpc::Error::method_not_found());
             }
         };
-        Ok(None)
+        Ok(result)
     }
 
     #[tracing::instrument(
@@ -369,6 +574,8 @@ impl LanguageServer for TypstServer {
         )
     )]
     async fn hover(&self, params: HoverParams) -> jsonrpc::Result<Option<Hover>> {
+        self.ensure_initialized()?;
+
         let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
 
@@ -381,61 +588,77 @@ impl LanguageServer for TypstServer {
     #[tracing::instrument(
         skip_all,
         fields(
-            uri = %params.text_document_position.text_document.uri,
-            position = ?params.text_document_position.position,
+            uri = %params.text_document_position_params.text_document.uri,
+            position = ?params.text_document_position_params.position,
         )
     )]
-    async fn completion(
+    async fn goto_definition(
         &self,
-        params: CompletionParams,
-    ) -> jsonrpc::Result<Option<CompletionResponse>> {
-        let uri = params.text_document_position.text_document.uri;
-        let position = params.text_document_position.position;
+        params: GotoDefinitionParams,
+    ) -> jsonrpc::Result<Option<GotoDefinitionResponse>> {
+        self.ensure_initialized()?;
 
-        // FIXME: correctly identify a completion which is triggered
-        // by explicit action, such as by pressing control and space
-        // or something similar.
-        //
-        // See <https://github.com/microsoft/language-server-protocol/issues/1101>
-        // > As of LSP 3.16, CompletionTriggerKind takes the value Invoked for
-        // > both manually invoked (for ex: ctrl + space in VSCode) completions
-        // > and always on (what the spec refers to as 24/7 completions).
-        //
-        // Hence, we cannot distinguish between the two cases. Conservatively, we
-        // assume that the completion is not explicit.
-        let explicit = false;
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
 
-        let position_encoding = self.const_config().position_encoding;
-        let doc = { self.document.lock().await.clone() };
-        let fid = self.workspace().read().await.full_id(&uri).map_err(|err| {
-            error!(%err, %uri, "error getting completion");
+        let location = self.goto_definition(&uri, position).await.map_err(|err| {
+            error!(%err, %uri, "error getting definition");
             jsonrpc::Error::internal_error()
         })?;
-        let completions = self
-            .thread_with_world(self.main_url().await.as_ref().unwrap_or(&uri))
+
+        Ok(location.map(GotoDefinitionResponse::Scalar))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position_params.text_document.uri,
+            position = ?params.text_document_position_params.position,
+        )
+    )]
+    async fn goto_type_definition(
+        &self,
+        params: GotoTypeDefinitionParams,
+    ) -> jsonrpc::Result<Option<GotoTypeDefinitionResponse>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let location = self
+            .get_type_definition(&uri, position)
             .await
             .map_err(|err| {
-                error!(%err, %uri, "error getting completion");
+                error!(%err, %uri, "error getting type definition");
                 jsonrpc::Error::internal_error()
-            })?
-            .run(move |world| {
-                let source = world.source(fid.into()).ok()?;
+            })?;
 
-                let typst_offset =
-                    lsp_to_typst::position_to_offset(position, position_encoding, &source);
-                let (typst_start_offset, completions) =
-                    typst_ide::autocomplete(&world, Some(&doc), &source, typst_offset, explicit)?;
-                let lsp_start_position =
-                    offset_to_position(typst_start_offset, position_encoding, &source);
+        Ok(location.map(GotoTypeDefinitionResponse::Scalar))
+    }
 
-                Some((lsp_start_position, completions))
-            })
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position.text_document.uri,
+            position = ?params.text_document_position.position,
+        )
+    )]
+    async fn references(&self, params: ReferenceParams) -> jsonrpc::Result<Option<Vec<Location>>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let include_declaration = params.context.include_declaration;
+
+        let references = self
+            .find_references(&uri, position, include_declaration)
             .await
-            .map(|(start_position, completions)| {
-                let replace_range = LspRawRange::new(start_position, position);
-                typst_to_lsp::completions(&completions, replace_range).into()
-            });
-        Ok(completions)
+            .map_err(|err| {
+                error!(%err, %uri, "error finding references");
+                jsonrpc::Error::internal_error()
+            })?;
+
+        Ok(Some(references))
     }
 
     #[tracing::instrument(
@@ -445,135 +668,505 @@ impl LanguageServer for TypstServer {
             position = ?params.text_document_position_params.position,
         )
     )]
-    async fn signature_help(
+    async fn prepare_call_hierarchy(
         &self,
-        params: SignatureHelpParams,
-    ) -> jsonrpc::Result<Option<SignatureHelp>> {
+        params: CallHierarchyPrepareParams,
+    ) -> jsonrpc::Result<Option<Vec<CallHierarchyItem>>> {
+        self.ensure_initialized()?;
+
         let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
 
-        self.get_signature_at_position(&uri, position)
+        self.get_call_hierarchy_items(&uri, position)
             .await
             .map_err(|err| {
-                error!(%err, %uri, "error getting signature");
+                error!(%err, %uri, "error preparing call hierarchy");
                 jsonrpc::Error::internal_error()
             })
     }
 
-    #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
-    async fn document_symbol(
+    #[tracing::instrument(skip_all, fields(uri = %params.item.uri, name = %params.item.name))]
+    async fn incoming_calls(
         &self,
-        params: DocumentSymbolParams,
-    ) -> jsonrpc::Result<Option<DocumentSymbolResponse>> {
-        let uri = params.text_document.uri;
+        params: CallHierarchyIncomingCallsParams,
+    ) -> jsonrpc::Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        self.ensure_initialized()?;
 
-        let symbols: Vec<_> = self
-            .scope_with_source(&uri)
-            .await
-            .map_err(|err| {
-                error!(%err, %uri, "error getting document symbols");
-                jsonrpc::Error::internal_error()
-            })?
-            .run(|source, _| self.document_symbols(source, &uri, None).try_collect())
-            .map_err(|err| {
-                error!(%err, %uri, "failed to get document symbols");
-                jsonrpc::Error::internal_error()
-            })?;
+        let uri = params.item.uri.clone();
 
-        Ok(Some(symbols.into()))
+        let calls = self.get_incoming_calls(params.item).await.map_err(|err| {
+            error!(%err, %uri, "error finding incoming calls");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        Ok(Some(calls))
     }
 
-    #[tracing::instrument(skip_all, fields(query = params.query))]
-    async fn symbol(
+    #[tracing::instrument(skip_all, fields(uri = %params.item.uri, name = %params.item.name))]
+    async fn outgoing_calls(
         &self,
-        params: WorkspaceSymbolParams,
-    ) -> jsonrpc::Result<Option<Vec<SymbolInformation>>> {
-        let handle_read_err = |err| warn!(%err, "could not read source");
-        let handle_symbol_err = |err| {
-            error!(%err, "failed to get document symbols");
-            jsonrpc::Error::internal_error()
-        };
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> jsonrpc::Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        self.ensure_initialized()?;
 
-        let query = (!params.query.is_empty()).then_some(params.query.as_str());
+        let uri = params.item.uri.clone();
 
-        let workspace = self.read_workspace().await;
+        let calls = self.get_outgoing_calls(params.item).await.map_err(|err| {
+            error!(%err, %uri, "error finding outgoing calls");
+            jsonrpc::Error::internal_error()
+        })?;
 
-        let uris = workspace.known_uris();
+        Ok(Some(calls))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position_params.text_document.uri,
+            position = ?params.text_document_position_params.position,
+        )
+    )]
+    async fn prepare_type_hierarchy(
+        &self,
+        params: TypeHierarchyPrepareParams,
+    ) -> jsonrpc::Result<Option<Vec<TypeHierarchyItem>>> {
+        self.ensure_initialized()?;
 
-        trace!(?uris, "getting sources for these URIs");
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
 
-        let uris_sources = uris
-            .into_iter()
-            .map(|uri| workspace.read_source(&uri).map(|source| (uri, source)))
-            .map(|result| result.map_err(handle_read_err))
-            .filter_map(Result::ok)
-            .collect_vec();
+        self.prepare_element_hierarchy(&uri, position)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error preparing type hierarchy");
+                jsonrpc::Error::internal_error()
+            })
+    }
 
-        trace!(?uris_sources, "getting symbols for these sources");
+    #[tracing::instrument(skip_all, fields(uri = %params.item.uri, name = %params.item.name))]
+    async fn supertypes(
+        &self,
+        params: TypeHierarchySupertypesParams,
+    ) -> jsonrpc::Result<Option<Vec<TypeHierarchyItem>>> {
+        self.ensure_initialized()?;
 
-        let symbols = uris_sources
-            .iter()
-            .flat_map(|(uri, source)| self.document_symbols(source, uri, query))
-            .try_collect()
-            .map_err(handle_symbol_err);
+        Ok(Some(self.get_element_supertypes(params.item)))
+    }
 
-        trace!(?symbols, "got symbols");
+    #[tracing::instrument(skip_all, fields(uri = %params.item.uri, name = %params.item.name))]
+    async fn subtypes(
+        &self,
+        params: TypeHierarchySubtypesParams,
+    ) -> jsonrpc::Result<Option<Vec<TypeHierarchyItem>>> {
+        self.ensure_initialized()?;
 
-        Some(symbols).transpose()
+        Ok(Some(self.get_element_subtypes(params.item).await))
     }
 
     #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
-    async fn semantic_tokens_full(
+    async fn code_action(
         &self,
-        params: SemanticTokensParams,
-    ) -> jsonrpc::Result<Option<SemanticTokensResult>> {
+        params: CodeActionParams,
+    ) -> jsonrpc::Result<Option<CodeActionResponse>> {
+        self.ensure_initialized()?;
+
         let uri = params.text_document.uri;
 
-        let (tokens, result_id) = self
-            .scope_with_source(&uri)
+        let actions = self
+            .get_code_actions(&uri, params.range, &params.context.diagnostics)
             .await
             .map_err(|err| {
-                error!(%err, %uri, "error getting full semantic tokens");
+                error!(%err, %uri, "error getting code actions");
                 jsonrpc::Error::internal_error()
-            })?
-            .run(|source, _| self.get_semantic_tokens_full(source));
+            })?;
 
-        Ok(Some(
-            SemanticTokens {
-                result_id: Some(result_id),
-                data: tokens,
-            }
-            .into(),
-        ))
+        Ok(Some(actions))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position_params.text_document.uri,
+            position = ?params.text_document_position_params.position,
+        )
+    )]
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> jsonrpc::Result<Option<Vec<DocumentHighlight>>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let highlights = self
+            .get_document_highlights(&uri, position)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting document highlights");
+                jsonrpc::Error::internal_error()
+            })?;
+
+        Ok(Some(highlights))
     }
 
     #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
-    async fn semantic_tokens_full_delta(
+    async fn document_link(
         &self,
-        params: SemanticTokensDeltaParams,
-    ) -> jsonrpc::Result<Option<SemanticTokensFullDeltaResult>> {
+        params: DocumentLinkParams,
+    ) -> jsonrpc::Result<Option<Vec<DocumentLink>>> {
+        self.ensure_initialized()?;
+
         let uri = params.text_document.uri;
-        let previous_result_id = params.previous_result_id;
 
-        let scope = self.scope_with_source(&uri).await.map_err(|err| {
-            error!(%err, %uri, "error getting semantic token delta");
+        let links = self.get_document_links(&uri).await.map_err(|err| {
+            error!(%err, %uri, "error getting document links");
             jsonrpc::Error::internal_error()
         })?;
-        scope.run(|source, _| {
-            let (tokens, result_id) =
-                self.try_semantic_tokens_delta_from_result_id(source, &previous_result_id);
-            match tokens {
-                Ok(edits) => Ok(Some(
-                    SemanticTokensDelta {
-                        result_id: Some(result_id),
-                        edits,
-                    }
-                    .into(),
-                )),
-                Err(tokens) => Ok(Some(
-                    SemanticTokens {
-                        result_id: Some(result_id),
-                        data: tokens,
+
+        Ok(Some(links))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document.uri,
+            position = ?params.position,
+        )
+    )]
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> jsonrpc::Result<Option<PrepareRenameResponse>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document.uri;
+
+        let response = self
+            .prepare_rename(&uri, params.position)
+            .await
+            .map_err(|err| rename_error_to_jsonrpc(err, &uri))?;
+
+        Ok(Some(response))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position.text_document.uri,
+            position = ?params.text_document_position.position,
+        )
+    )]
+    async fn rename(&self, params: RenameParams) -> jsonrpc::Result<Option<WorkspaceEdit>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        let edit = self
+            .rename(&uri, position, params.new_name)
+            .await
+            .map_err(|err| rename_error_to_jsonrpc(err, &uri))?;
+
+        Ok(Some(edit))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position.text_document.uri,
+            position = ?params.text_document_position.position,
+        )
+    )]
+    async fn completion(
+        &self,
+        params: CompletionParams,
+    ) -> jsonrpc::Result<Option<CompletionResponse>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        // FIXME: correctly identify a completion which is triggered
+        // by explicit action, such as by pressing control and space
+        // or something similar.
+        //
+        // See <https://github.com/microsoft/language-server-protocol/issues/1101>
+        // > As of LSP 3.16, CompletionTriggerKind takes the value Invoked for
+        // > both manually invoked (for ex: ctrl + space in VSCode) completions
+        // > and always on (what the spec refers to as 24/7 completions).
+        //
+        // Hence, we cannot distinguish between the two cases. Conservatively, we
+        // assume that the completion is not explicit.
+        let explicit = false;
+
+        let trigger = params
+            .context
+            .as_ref()
+            .and_then(|context| context.trigger_character.as_deref());
+        if trigger == Some(":") {
+            let completes = self
+                .colon_completes(&uri, position)
+                .await
+                .unwrap_or_else(|err| {
+                    error!(%err, %uri, "error checking completion trigger");
+                    false
+                });
+            if !completes {
+                return Ok(None);
+            }
+        }
+
+        match self.get_path_completions(&uri, position).await {
+            Ok(Some(completions)) => return Ok(Some(completions.into())),
+            Ok(None) => {}
+            Err(err) => error!(%err, %uri, "error getting path completion"),
+        }
+
+        let position_encoding = self.position_encoding();
+        let priorities = self.config.read().await.completion_priorities.clone();
+        let main_uri = self.main_url().await.unwrap_or_else(|| uri.clone());
+        let doc = self.documents.get(&main_uri);
+        let workspace = self.read_workspace().await;
+        let fid = workspace
+            .and_then(|workspace| workspace.full_id(&uri))
+            .map_err(|err| {
+                error!(%err, %uri, "error getting completion");
+                jsonrpc::Error::internal_error()
+            })?;
+        let completions = self
+            .thread_with_world(&main_uri)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting completion");
+                jsonrpc::Error::internal_error()
+            })?
+            .run(move |world| {
+                let source = world.source(fid.into()).ok()?;
+
+                let typst_offset =
+                    lsp_to_typst::position_to_offset(position, position_encoding, &source);
+                // Labels added since the last compile are only found syntactically
+                let (typst_start_offset, completions) = typst_ide::autocomplete(
+                    &world,
+                    doc.as_deref(),
+                    &source,
+                    typst_offset,
+                    explicit,
+                )
+                .filter(|(_, completions)| !completions.is_empty())
+                .or_else(|| completion::label_completions(&source, typst_offset))?;
+                let lsp_start_position =
+                    offset_to_position(typst_start_offset, position_encoding, &source);
+                let typst_end_offset = completion::replace_end(&source, typst_offset);
+                let lsp_end_position =
+                    offset_to_position(typst_end_offset, position_encoding, &source);
+
+                Some((lsp_start_position, lsp_end_position, completions))
+            })
+            .await
+            .map(|(start_position, end_position, completions)| {
+                let replace_range = LspRawRange::new(start_position, end_position);
+                let mut completions =
+                    typst_to_lsp::completions(&completions, replace_range, &priorities);
+                completion::attach_resolve_data(&mut completions);
+                completions
+            });
+
+        let bibliography = self
+            .get_bibliography_completions(&uri, position)
+            .await
+            .unwrap_or_else(|err| {
+                error!(%err, %uri, "error getting bibliography completions");
+                None
+            });
+        let completions = match (completions, bibliography) {
+            (Some(mut completions), Some(bibliography)) => {
+                let missing: Vec<_> = bibliography
+                    .into_iter()
+                    .filter(|item| !completions.iter().any(|known| known.label == item.label))
+                    .collect();
+                completions.extend(missing);
+                Some(completions)
+            }
+            (completions, bibliography) => completions.or(bibliography),
+        };
+
+        Ok(completions.map(Into::into))
+    }
+
+    #[tracing::instrument(skip_all, fields(label = %params.label))]
+    async fn completion_resolve(&self, params: CompletionItem) -> jsonrpc::Result<CompletionItem> {
+        self.ensure_initialized()?;
+
+        Ok(completion::resolve_completion(params))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position_params.text_document.uri,
+            position = ?params.text_document_position_params.position,
+        )
+    )]
+    async fn signature_help(
+        &self,
+        params: SignatureHelpParams,
+    ) -> jsonrpc::Result<Option<SignatureHelp>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        self.get_signature_at_position(&uri, position)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting signature");
+                jsonrpc::Error::internal_error()
+            })
+    }
+
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> jsonrpc::Result<Option<DocumentSymbolResponse>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document.uri;
+
+        let scope = self.scope_with_source(&uri).await.map_err(|err| {
+            error!(%err, %uri, "error getting document symbols");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        let hierarchical = self
+            .const_config()
+            .is_some_and(|const_config| const_config.supports_hierarchical_document_symbols);
+        if hierarchical {
+            let position_encoding = self.position_encoding();
+            let symbols = scope
+                .run(|source, _| get_nested_symbols(source, &uri, position_encoding))
+                .map_err(|err| {
+                    error!(%err, %uri, "failed to get document symbols");
+                    jsonrpc::Error::internal_error()
+                })?;
+            return Ok(Some(DocumentSymbolResponse::Nested(symbols)));
+        }
+
+        let symbols: Vec<_> = scope
+            .run(|source, _| self.document_symbols(source, &uri, None).try_collect())
+            .map_err(|err| {
+                error!(%err, %uri, "failed to get document symbols");
+                jsonrpc::Error::internal_error()
+            })?;
+
+        Ok(Some(symbols.into()))
+    }
+
+    #[tracing::instrument(skip_all, fields(query = params.query))]
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> jsonrpc::Result<Option<Vec<SymbolInformation>>> {
+        self.ensure_initialized()?;
+
+        let query = (!params.query.is_empty()).then_some(params.query.as_str());
+
+        let symbols = self.workspace_symbols(query).await.map_err(|err| {
+            error!(%err, "failed to get document symbols");
+            jsonrpc::Error::internal_error()
+        })?;
+
+        trace!(?symbols, "got symbols");
+
+        Ok(Some(symbols))
+    }
+
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensResult>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document.uri;
+
+        let (tokens, result_id) = self
+            .scope_with_source(&uri)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting full semantic tokens");
+                jsonrpc::Error::internal_error()
+            })?
+            .run(|source, _| self.get_semantic_tokens_full(source, &uri));
+
+        Ok(Some(
+            SemanticTokens {
+                result_id: Some(result_id),
+                data: tokens,
+            }
+            .into(),
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensRangeResult>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document.uri;
+        let range = params.range;
+
+        let tokens = self
+            .scope_with_source(&uri)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting semantic tokens for range");
+                jsonrpc::Error::internal_error()
+            })?
+            .run(|source, _| self.get_semantic_tokens_range(source, range));
+
+        Ok(Some(
+            SemanticTokens {
+                result_id: None,
+                data: tokens,
+            }
+            .into(),
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensFullDeltaResult>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document.uri;
+        let previous_result_id = params.previous_result_id;
+
+        let scope = self.scope_with_source(&uri).await.map_err(|err| {
+            error!(%err, %uri, "error getting semantic token delta");
+            jsonrpc::Error::internal_error()
+        })?;
+        scope.run(|source, _| {
+            let (tokens, result_id) =
+                self.try_semantic_tokens_delta_from_result_id(source, &uri, &previous_result_id);
+            match tokens {
+                Ok(edits) => Ok(Some(
+                    SemanticTokensDelta {
+                        result_id: Some(result_id),
+                        edits,
+                    }
+                    .into(),
+                )),
+                Err(tokens) => Ok(Some(
+                    SemanticTokens {
+                        result_id: Some(result_id),
+                        data: tokens,
                     }
                     .into(),
                 )),
@@ -594,6 +1187,8 @@ impl LanguageServer for TypstServer {
                 .map(Config::values_to_map),
         };
 
+        let previous_main = self.main_url().await;
+        let previous_fonts = self.config.read().await.fonts;
         let result = match values {
             Ok(values) => {
                 let mut config = self.config.write().await;
@@ -605,6 +1200,12 @@ impl LanguageServer for TypstServer {
         match result {
             Ok(()) => {
                 info!("new settings applied");
+                self.notify_pinned_main(previous_main).await;
+
+                let fonts = self.config.read().await.fonts;
+                if fonts != previous_fonts {
+                    self.reload_fonts(fonts).await;
+                }
             }
             Err(err) => {
                 error!(%err, "error applying new settings");
@@ -617,6 +1218,8 @@ impl LanguageServer for TypstServer {
         &self,
         params: SelectionRangeParams,
     ) -> jsonrpc::Result<Option<Vec<SelectionRange>>> {
+        self.ensure_initialized()?;
+
         let uri = params.text_document.uri;
         let positions = params.positions;
 
@@ -632,10 +1235,96 @@ impl LanguageServer for TypstServer {
         Ok(selection_range)
     }
 
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position_params.text_document.uri,
+            position = ?params.text_document_position_params.position,
+        )
+    )]
+    async fn linked_editing_range(
+        &self,
+        params: LinkedEditingRangeParams,
+    ) -> jsonrpc::Result<Option<LinkedEditingRanges>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let ranges = self
+            .scope_with_source(&uri)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting linked editing ranges");
+                jsonrpc::Error::internal_error()
+            })?
+            .run(|source, _| self.get_linked_editing_ranges(source, position));
+
+        Ok(ranges)
+    }
+
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
+    async fn folding_range(
+        &self,
+        params: FoldingRangeParams,
+    ) -> jsonrpc::Result<Option<Vec<FoldingRange>>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document.uri;
+
+        let folding_ranges = self
+            .scope_with_source(&uri)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting folding ranges");
+                jsonrpc::Error::internal_error()
+            })?
+            .run(|source, _| self.get_folding_ranges(source));
+
+        Ok(Some(folding_ranges))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            uri = %params.text_document_position.text_document.uri,
+            position = ?params.text_document_position.position,
+            ch = params.ch,
+        )
+    )]
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        self.ensure_initialized()?;
+
+        if !self.config.read().await.on_type_formatting {
+            return Ok(None);
+        }
+
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        let edits = self
+            .scope_with_source(&uri)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting document to format on type");
+                jsonrpc::Error::internal_error()
+            })?
+            .run(|source, _| {
+                self.get_on_type_formatting(source, position, &params.ch, &params.options)
+            });
+
+        Ok(Some(edits))
+    }
+
     async fn formatting(
         &self,
         params: DocumentFormattingParams,
     ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        self.ensure_initialized()?;
+
         let uri = params.text_document.uri;
 
         let edits = self
@@ -645,7 +1334,7 @@ impl LanguageServer for TypstServer {
                 error!(%err, %uri, "error getting document to format");
                 jsonrpc::Error::internal_error()
             })?
-            .run2(|source, project| self.format_document(project, source))
+            .run2(|source, project| self.format_document(project, source, &params.options))
             .await
             .map_err(|err| {
                 error!(%err, %uri, "error formatting document");
@@ -654,4 +1343,871 @@ impl LanguageServer for TypstServer {
 
         Ok(Some(edits))
     }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        self.ensure_initialized()?;
+
+        let uri = params.text_document.uri;
+
+        let edits = self
+            .scope_with_source(&uri)
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error getting document to format");
+                jsonrpc::Error::internal_error()
+            })?
+            .run2(|source, project| {
+                self.format_range(project, source, params.range, &params.options)
+            })
+            .await
+            .map_err(|err| {
+                error!(%err, %uri, "error formatting range");
+                jsonrpc::Error::internal_error()
+            })?;
+
+        Ok(Some(edits))
+    }
+}
+
+fn rename_error_to_jsonrpc(err: RenameError, uri: &Url) -> jsonrpc::Error {
+    if err.is_invalid_request() {
+        jsonrpc::Error::invalid_params(err.to_string())
+    } else {
+        error!(%err, %uri, "error renaming");
+        jsonrpc::Error::internal_error()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use futures::StreamExt;
+    use serde_json::json;
+    use temp_dir::TempDir;
+    use tokio::task::JoinHandle;
+    use tower::Service;
+    use tower_lsp::lsp_types::notification::{Notification, PublishDiagnostics};
+    use tower_lsp::{ClientSocket, LspService};
+
+    use crate::server::compile_status::CompileStatusNotification;
+    use crate::server::pinned_main::{PinnedMain, PinnedMainNotification};
+    use crate::workspace::font_manager::FontManager;
+    use tracing_subscriber::{reload, Registry};
+
+    use crate::server::log::LogLayers;
+
+    use super::*;
+
+    /// Also returns the layer the server logs to, which must outlive the server
+    fn service() -> (LspService<TypstServer>, reload::Layer<LogLayers, Registry>) {
+        let (service, _, layer) = service_with_socket();
+        (service, layer)
+    }
+
+    /// Like [`service`], but also returns the socket receiving messages sent to the client
+    fn service_with_socket() -> (
+        LspService<TypstServer>,
+        ClientSocket,
+        reload::Layer<LogLayers, Registry>,
+    ) {
+        let (layer, lsp_tracing_layer_handle) = reload::Layer::new(LogLayers::default());
+        let (service, socket) = LspService::new(move |client| {
+            TypstServer::new(client, lsp_tracing_layer_handle, Arc::default())
+        });
+        (service, socket, layer)
+    }
+
+    /// Starts a server with the directory as its only workspace folder. It is initialized through
+    /// the service, since messages to the client are only sent on the socket after that.
+    async fn initialized_server(
+        root: &Url,
+        init_options: JsonValue,
+    ) -> (
+        LspService<TypstServer>,
+        ClientSocket,
+        reload::Layer<LogLayers, Registry>,
+    ) {
+        let (mut service, socket, layer) = service_with_socket();
+        let initialize = jsonrpc::Request::build("initialize")
+            .params(json!({
+                "capabilities": {},
+                "workspaceFolders": [{ "uri": root, "name": "project" }],
+                "initializationOptions": init_options,
+            }))
+            .id(1)
+            .finish();
+        std::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        service.call(initialize).await.unwrap();
+        (service, socket, layer)
+    }
+
+    /// Waits for non-empty diagnostics to be published for the URI. Takes the socket, since the
+    /// server blocks on sending to the client unless the socket is read or dropped.
+    fn next_published_diagnostics(
+        socket: ClientSocket,
+        uri: Url,
+    ) -> JoinHandle<PublishDiagnosticsParams> {
+        tokio::spawn(async move {
+            let published = socket.filter_map(|message| {
+                let uri = uri.clone();
+                async move {
+                    if message.method() != PublishDiagnostics::METHOD {
+                        return None;
+                    }
+                    let params: PublishDiagnosticsParams =
+                        serde_json::from_value(message.params()?.clone()).ok()?;
+                    (params.uri == uri && !params.diagnostics.is_empty()).then_some(params)
+                }
+            });
+            let mut published = std::pin::pin!(published);
+            published
+                .next()
+                .await
+                .expect("diagnostics should be published")
+        })
+    }
+
+    #[tokio::test]
+    async fn hover_before_initialize_is_an_error() {
+        let (service, _layer) = service();
+        let server: &TypstServer = service.inner();
+
+        let params = HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::parse("file:///project/main.typ").unwrap(),
+                },
+                position: Position::new(0, 0),
+            },
+            work_done_progress_params: Default::default(),
+        };
+        let err = server.hover(params).await.unwrap_err();
+
+        assert_eq!(err.code, jsonrpc::ErrorCode::ServerError(-32002));
+    }
+
+    #[tokio::test]
+    async fn did_open_before_initialize_is_ignored() {
+        let (service, _layer) = service();
+        let server: &TypstServer = service.inner();
+
+        let uri = Url::parse("file:///project/main.typ").unwrap();
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "typst".to_owned(),
+                version: 0,
+                text: "= Title".to_owned(),
+            },
+        };
+        server.did_open(params).await;
+
+        assert!(server.workspace().is_none());
+        assert!(server.documents.get(&uri).is_none());
+    }
+
+    #[tokio::test]
+    async fn negotiated_position_encoding_is_advertised() {
+        let (service, _layer) = service();
+        let server: &TypstServer = service.inner();
+
+        let params = InitializeParams {
+            capabilities: ClientCapabilities {
+                general: Some(GeneralClientCapabilities {
+                    position_encodings: Some(vec![
+                        PositionEncodingKind::UTF8,
+                        PositionEncodingKind::UTF16,
+                    ]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = server.initialize(params).await.unwrap();
+
+        assert_eq!(
+            result.capabilities.position_encoding,
+            Some(PositionEncodingKind::UTF8)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn edited_included_file_diagnostics_use_pinned_main_project() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.child("chapters")).unwrap();
+        fs::write(
+            temp_dir.child("main.typ"),
+            "= Title\n#include \"chapters/one.typ\"",
+        )
+        .unwrap();
+        fs::write(temp_dir.child("chapters/one.typ"), "Text\n#undefined").unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let chapter = root.join("chapters/one.typ").unwrap();
+
+        let options = json!({ "mainFile": main.as_str() });
+        let (service, socket, _layer) = initialized_server(&root, options).await;
+        let server: &TypstServer = service.inner();
+        // The chapter is also in its own workspace folder, so on its own it has a different root
+        server
+            .did_change_workspace_folders(DidChangeWorkspaceFoldersParams {
+                event: WorkspaceFoldersChangeEvent {
+                    added: vec![WorkspaceFolder {
+                        uri: root.join("chapters/").unwrap(),
+                        name: "chapters".to_owned(),
+                    }],
+                    removed: Vec::new(),
+                },
+            })
+            .await;
+
+        let published = next_published_diagnostics(socket, chapter.clone());
+        server.on_source_changed(&chapter).await.unwrap();
+
+        let published = published.await.unwrap();
+        assert_eq!(published.diagnostics.len(), 1);
+        assert_eq!(published.diagnostics[0].range.start, Position::new(1, 1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn syntax_errors_are_published_before_compiling() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "Text $x").unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+
+        // The debounce is long enough that the compile never starts during the test
+        let options = json!({ "exportPdf": "onType", "onTypeDebounceMs": 60000 });
+        let (service, socket, _layer) = initialized_server(&root, options).await;
+        let server: &TypstServer = service.inner();
+
+        let published = next_published_diagnostics(socket, main.clone());
+        let compile = server.on_source_changed(&main);
+        let waited = tokio::time::timeout(std::time::Duration::from_secs(1), compile).await;
+        assert!(waited.is_err(), "the compile should still be debounced");
+
+        let published = published.await.unwrap();
+        assert_eq!(published.diagnostics.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn importer_diagnostics_update_when_imported_file_changes_on_disk() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "#import \"lib.typ\": x\n#x").unwrap();
+        fs::write(temp_dir.child("lib.typ"), "#let x = 1").unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let lib = root.join("lib.typ").unwrap();
+
+        let (service, socket, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let published = next_published_diagnostics(socket, main.clone());
+        server.on_source_changed(&main).await.unwrap();
+
+        // `lib.typ` isn't open, so only the file watcher reports the change
+        fs::write(temp_dir.child("lib.typ"), "#let y = 1").unwrap();
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent::new(lib, FileChangeType::CHANGED)],
+            })
+            .await;
+
+        published.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn importer_diagnostics_update_when_imported_file_is_edited() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "#import \"lib.typ\": x\n#x").unwrap();
+        fs::write(temp_dir.child("lib.typ"), "#let x = 1").unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let lib = root.join("lib.typ").unwrap();
+
+        let (service, socket, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let published = next_published_diagnostics(socket, main.clone());
+        server.on_source_changed(&main).await.unwrap();
+        server
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    lib.clone(),
+                    "typst".to_owned(),
+                    0,
+                    "#let x = 1".to_owned(),
+                ),
+            })
+            .await;
+        server
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(lib, 1),
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "#let y = 1".to_owned(),
+                }],
+            })
+            .await;
+
+        published.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn outline_headings_are_located_in_included_files() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.child("chapters")).unwrap();
+        fs::write(
+            temp_dir.child("main.typ"),
+            "= Intro\n#include \"chapters/one.typ\"",
+        )
+        .unwrap();
+        fs::write(temp_dir.child("chapters/one.typ"), "Text\n= Chapter").unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let main = root.join("main.typ").unwrap();
+        let chapter = root.join("chapters/one.typ").unwrap();
+        let outline = server.document_outline(&main).await.unwrap();
+
+        let locations: Vec<_> = outline
+            .into_iter()
+            .map(|entry| {
+                let location = entry.location.unwrap();
+                (location.uri, location.range.start)
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            [(main, Position::new(0, 0)), (chapter, Position::new(1, 0))]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rendered_outline_is_nested_with_numbers_and_pages() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.child("main.typ"),
+            "#set heading(numbering: \"1.1\")\n= Intro\n#for name in (\"A\", \"B\") [== #name]",
+        )
+        .unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let main = root.join("main.typ").unwrap();
+        let outline = server.rendered_outline(&main).await.unwrap();
+
+        let DocumentSymbolResponse::Nested(symbols) = outline else {
+            panic!("expected nested symbols");
+        };
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].name, "Intro");
+        assert_eq!(symbols[0].detail.as_deref(), Some("1, page 1"));
+        let details: Vec<_> = symbols[0]
+            .children
+            .iter()
+            .flatten()
+            .map(|symbol| symbol.detail.as_deref().unwrap())
+            .collect();
+        assert_eq!(details, ["1.1, page 1", "1.2, page 1"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn changed_data_file_is_reread_on_recompile() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.child("main.typ"),
+            "#assert.eq(json(\"data.json\").value, 1)",
+        )
+        .unwrap();
+        fs::write(temp_dir.child("data.json"), r#"{ "value": 1 }"#).unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let main = root.join("main.typ").unwrap();
+        let data = root.join("data.json").unwrap();
+        let (_, diagnostics) = server.compile_source(&main).await.unwrap();
+        assert!(diagnostics.values().all(Vec::is_empty));
+
+        fs::write(temp_dir.child("data.json"), r#"{ "value": 2 }"#).unwrap();
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent::new(data, FileChangeType::CHANGED)],
+            })
+            .await;
+
+        let (_, diagnostics) = server.compile_source(&main).await.unwrap();
+        assert_eq!(diagnostics[&main].len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn created_data_file_is_read_on_recompile() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "#csv(\"data.csv\")").unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let main = root.join("main.typ").unwrap();
+        let data = root.join("data.csv").unwrap();
+        let (_, diagnostics) = server.compile_source(&main).await.unwrap();
+        assert_eq!(diagnostics[&main].len(), 1);
+
+        fs::write(temp_dir.child("data.csv"), "a,b").unwrap();
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent::new(data, FileChangeType::CREATED)],
+            })
+            .await;
+
+        let (_, diagnostics) = server.compile_source(&main).await.unwrap();
+        assert!(diagnostics.values().all(Vec::is_empty));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bibliography_of_pinned_main_completes_in_included_file() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.child("main.typ"),
+            "#include \"chapter.typ\"\n#bibliography(\"refs.bib\")",
+        )
+        .unwrap();
+        fs::write(temp_dir.child("chapter.typ"), "See @").unwrap();
+        fs::write(
+            temp_dir.child("refs.bib"),
+            "@book{kopka, title = {A Guide to LaTeX}, author = {Kopka, Helmut}, year = {2004}}",
+        )
+        .unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let chapter = root.join("chapter.typ").unwrap();
+        let (service, _, _layer) =
+            initialized_server(&root, json!({ "mainFile": main.as_str() })).await;
+        let server: &TypstServer = service.inner();
+
+        let completions = server
+            .get_bibliography_completions(&chapter, Position::new(0, 5))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let labels: Vec<_> = completions.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, ["kopka"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn superseded_compile_leaves_document_cache_untouched() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "= Title").unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let superseded = server.compile_generations.next(&main);
+        server.compile_generations.next(&main);
+        let compiled = server
+            .compile_source_if_current(&main, superseded)
+            .await
+            .unwrap();
+
+        assert!(compiled.is_none());
+        assert!(server.documents.get(&main).is_none());
+        assert!(server.dependencies.read().mains().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compile_status_is_only_reported_when_published() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "= Title").unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+
+        let (service, mut socket, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        // Commands compile without publishing
+        server.compile_source(&main).await.unwrap();
+        assert!(socket.next().now_or_never().is_none());
+
+        let statuses = tokio::spawn(
+            socket
+                .filter(|message| {
+                    let method = message.method().to_owned();
+                    async move { method == CompileStatusNotification::METHOD }
+                })
+                .take(1)
+                .collect::<Vec<_>>(),
+        );
+        server.run_diagnostics_and_export(&main).await.unwrap();
+
+        assert_eq!(statuses.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn script_bindings_can_be_imported() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.child("main.typ"),
+            "#import \"lib.typc\": x\n#assert.eq(x, 2)",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.child("lib.typc"),
+            "let double(n) = n * 2\nlet x = double(1)",
+        )
+        .unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let main = root.join("main.typ").unwrap();
+        let (document, diagnostics) = server.compile_source(&main).await.unwrap();
+
+        assert!(document.is_some());
+        assert!(diagnostics.values().all(Vec::is_empty));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn closing_compiled_document_exports_once() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "= Title").unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let pdf = temp_dir.child("main.pdf");
+
+        let (service, _, _layer) =
+            initialized_server(&root, json!({ "exportPdf": "onClose" })).await;
+        let server: &TypstServer = service.inner();
+
+        server
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    main.clone(),
+                    "typst".to_owned(),
+                    1,
+                    "= Title".to_owned(),
+                ),
+            })
+            .await;
+        assert!(!pdf.exists(), "opening should not export");
+
+        let close = || DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri: main.clone() },
+        };
+        server.did_close(close()).await;
+        assert!(pdf.exists(), "closing should export");
+
+        // The document is no longer open, so closing it again must not export it again
+        fs::remove_file(&pdf).unwrap();
+        server.did_close(close()).await;
+        assert!(!pdf.exists(), "only the first close should export");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compile_command_returns_diagnostics() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "= Title\n#undefined").unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let result = server
+            .command_compile(vec![json!(main.as_str())])
+            .await
+            .unwrap();
+
+        assert_eq!(result["pageCount"], JsonValue::Null);
+        let diagnostics = result["diagnostics"][main.as_str()].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["message"], "unknown variable: undefined");
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({ "line": 1, "character": 1 })
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clear_cache_command_rebuilds_font_book() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+
+        let (service, _, _layer) =
+            initialized_server(&root, json!({ "ignoreSystemFonts": true })).await;
+        let server: &TypstServer = service.inner();
+        let workspace = server.workspace().unwrap();
+        workspace
+            .write()
+            .await
+            .set_fonts(FontManager::builder().build());
+
+        server
+            .command_clear_cache(vec![json!({ "fonts": true })])
+            .await
+            .unwrap();
+
+        let workspace = workspace.read().await;
+        let mut libertine = workspace
+            .font_manager()
+            .book()
+            .select_family("linux libertine");
+        assert!(libertine.next().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn local_packages_complete_in_import() {
+        let temp_dir = TempDir::new().unwrap();
+        let packages = temp_dir.child("packages");
+        let example = packages.join("preview/example/0.1.0");
+        fs::create_dir_all(&example).unwrap();
+        fs::write(
+            example.join("typst.toml"),
+            "[package]\nname = \"example\"\nversion = \"0.1.0\"\nentrypoint = \"lib.typ\"",
+        )
+        .unwrap();
+        let project = temp_dir.child("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("main.typ"), "#import \"@preview/\"").unwrap();
+
+        let root = Url::from_directory_path(&project).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let (service, _, _layer) = initialized_server(
+            &root,
+            json!({
+                "offline": true,
+                "localPackageRoots": [packages],
+            }),
+        )
+        .await;
+        let server: &TypstServer = service.inner();
+
+        let completions = server
+            .completion(CompletionParams {
+                text_document_position: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier { uri: main },
+                    position: Position::new(0, 18),
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            })
+            .await
+            .unwrap();
+
+        let Some(CompletionResponse::Array(completions)) = completions else {
+            panic!("expected completions, got {completions:?}");
+        };
+        let labels: Vec<_> = completions.iter().map(|item| item.label.as_str()).collect();
+        assert!(
+            labels
+                .iter()
+                .any(|label| label.contains("@preview/example")),
+            "got completions {labels:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn colon_completes_named_arguments_but_not_prose() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "Note: x\n#text(fill:)").unwrap();
+
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let complete_colon = |position| {
+            server.completion(CompletionParams {
+                text_document_position: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier { uri: main.clone() },
+                    position,
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: Some(CompletionContext {
+                    trigger_kind: CompletionTriggerKind::TRIGGER_CHARACTER,
+                    trigger_character: Some(":".to_owned()),
+                }),
+            })
+        };
+
+        let prose = complete_colon(Position::new(0, 5)).await.unwrap();
+        let named = complete_colon(Position::new(1, 11)).await.unwrap();
+
+        assert!(prose.is_none(), "got completions {prose:?}");
+        assert!(named.is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unknown_fonts_in_included_files_are_warned() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "#include \"chapter.typ\"").unwrap();
+        fs::write(
+            temp_dir.child("chapter.typ"),
+            "#set text(font: \"Nonexistent Sans\")\nText",
+        )
+        .unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let chapter = root.join("chapter.typ").unwrap();
+
+        let warnings = |ignore_unknown_fonts: bool| {
+            let (root, main, chapter) = (root.clone(), main.clone(), chapter.clone());
+            async move {
+                let options = json!({ "ignoreUnknownFonts": ignore_unknown_fonts });
+                let (service, _, _layer) = initialized_server(&root, options).await;
+                let server: &TypstServer = service.inner();
+
+                let (_, diagnostics) = server.compile_source(&main).await.unwrap();
+                diagnostics
+                    .get(&chapter)
+                    .into_iter()
+                    .flatten()
+                    .map(|diagnostic| diagnostic.message.clone())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            warnings(false).await,
+            ["unknown font family: Nonexistent Sans"]
+        );
+        assert!(warnings(true).await.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn duplicate_label_points_at_definitions_in_included_files() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.child("main.typ"),
+            "= Intro <intro>\n#include \"chapter.typ\"\nSee @intro",
+        )
+        .unwrap();
+        fs::write(temp_dir.child("chapter.typ"), "= Again <intro>").unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+        let chapter = root.join("chapter.typ").unwrap();
+
+        let (service, _, _layer) = initialized_server(&root, json!({})).await;
+        let server: &TypstServer = service.inner();
+
+        let (_, diagnostics) = server.compile_source(&main).await.unwrap();
+        let duplicate = diagnostics
+            .values()
+            .flatten()
+            .find(|diagnostic| diagnostic.message.contains("occurs multiple times"))
+            .unwrap();
+
+        let definitions: Vec<_> = duplicate
+            .related_information
+            .iter()
+            .flatten()
+            .filter(|info| info.message == "label defined here")
+            .map(|info| (info.location.uri.clone(), info.location.range.start))
+            .collect();
+        assert_eq!(
+            definitions,
+            [(main, Position::new(0, 8)), (chapter, Position::new(0, 8))]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn untitled_document_has_symbols() {
+        let (service, _layer) = service();
+        let server: &TypstServer = service.inner();
+        server
+            .initialize(InitializeParams::default())
+            .await
+            .unwrap();
+
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        server
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "typst".to_owned(),
+                    1,
+                    "= Introduction\n#let x = 1".to_owned(),
+                ),
+            })
+            .await;
+
+        let symbols = server
+            .document_symbol(DocumentSymbolParams {
+                text_document: TextDocumentIdentifier { uri },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        let Some(DocumentSymbolResponse::Flat(symbols)) = symbols else {
+            panic!("expected flat symbols, since the client doesn't support nested ones");
+        };
+        let names: Vec<_> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert!(names.contains(&"Introduction"), "got symbols {names:?}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pinning_and_unpinning_notifies_client() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "= Title").unwrap();
+        let root = Url::from_directory_path(temp_dir.path()).unwrap();
+        let main = root.join("main.typ").unwrap();
+
+        let (service, socket, _layer) = initialized_server(&root, json!({})).await;
+        let pin_changes = tokio::spawn(
+            socket
+                .filter(|message| {
+                    let method = message.method().to_owned();
+                    async move { method == PinnedMainNotification::METHOD }
+                })
+                .map(|message| {
+                    serde_json::from_value::<PinnedMain>(message.params().unwrap().clone()).unwrap()
+                })
+                .take(2)
+                .collect::<Vec<_>>(),
+        );
+
+        let server: &TypstServer = service.inner();
+        server
+            .command_pin_main(vec![json!(main.as_str())])
+            .await
+            .unwrap();
+        server.command_unpin_main().await.unwrap();
+
+        let pin_changes = pin_changes.await.unwrap();
+        assert_eq!(
+            pin_changes,
+            [
+                PinnedMain {
+                    uri: Some(main.clone())
+                },
+                PinnedMain { uri: None }
+            ]
+        );
+    }
 }
@@ -1,57 +1,103 @@
 use std::sync::Arc;
 
+use anyhow::anyhow;
 use once_cell::sync::OnceCell;
 use tokio::runtime;
 use tokio::sync::{Mutex, OwnedRwLockReadGuard, RwLock, RwLockReadGuard};
 use tower_lsp::lsp_types::Url;
-use tower_lsp::Client;
+use tower_lsp::{jsonrpc, Client};
+use tracing::warn;
 use tracing_subscriber::{reload, Registry};
-use typst::model::Document;
 use typst::syntax::Source;
 
-use crate::config::{Config, ConstConfig};
+use crate::config::{Config, ConstConfig, PositionEncoding};
 use crate::server::semantic_tokens::SemanticTokenCache;
-use crate::workspace::fs::FsResult;
+use crate::workspace::deps::DependencyGraph;
+use crate::workspace::fs::{FsError, FsResult};
 use crate::workspace::package::FullFileId;
 use crate::workspace::project::Project;
 use crate::workspace::world::typst_thread::TypstThread;
 use crate::workspace::world::ProjectWorld;
 use crate::workspace::{Workspace, TYPST_STDLIB};
 
+use self::bibliography::BibliographyCache;
+use self::debounce::Debouncer;
 use self::diagnostics::DiagnosticsManager;
-use self::log::LspLayer;
+use self::document_cache::DocumentCache;
+use self::log::LogLayers;
+use self::preview::Preview;
+use self::profiling::ThreadStats;
+use self::typst_compiler::CompileGenerations;
 
+pub mod bibliography;
+pub mod call_hierarchy;
+pub mod code_action;
 pub mod command;
+pub mod compile_status;
+pub mod completion;
+pub mod debounce;
+pub mod definition;
 pub mod diagnostics;
 pub mod document;
+pub mod document_cache;
+pub mod document_highlight;
+pub mod document_link;
+pub mod download_progress;
 pub mod export;
+pub mod folding_range;
 pub mod formatting;
 pub mod hover;
+pub mod linked_editing;
+pub mod lints;
 pub mod log;
+pub mod log_file;
 pub mod lsp;
+pub mod on_type_formatting;
+pub mod outline;
+pub mod package_updates;
+pub mod pinned_main;
+pub mod preview;
+pub mod profiling;
+pub mod query;
+pub mod references;
+pub mod rename;
 pub mod selection_range;
 pub mod semantic_tokens;
 pub mod signature;
+pub mod style_rules;
 pub mod symbols;
+pub mod template;
+pub mod type_definition;
+pub mod type_hierarchy;
 pub mod typst_compiler;
 pub mod watch;
 
+/// The LSP error code for requests sent before the server was initialized
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
 pub struct TypstServer {
     client: Client,
-    document: Mutex<Arc<Document>>,
+    documents: DocumentCache,
     typst_thread: TypstThread,
     workspace: OnceCell<Arc<RwLock<Workspace>>>,
     config: Arc<RwLock<Config>>,
     const_config: OnceCell<ConstConfig>,
     semantic_tokens_delta_cache: Arc<parking_lot::RwLock<SemanticTokenCache>>,
-    diagnostics: Mutex<DiagnosticsManager>,
-    lsp_tracing_layer_handle: reload::Handle<Option<LspLayer>, Registry>,
+    diagnostics: Arc<Mutex<DiagnosticsManager>>,
+    lsp_tracing_layer_handle: reload::Handle<LogLayers, Registry>,
+    thread_stats: Arc<ThreadStats>,
+    on_type_compiles: Debouncer<Url>,
+    compile_generations: CompileGenerations,
+    dependencies: parking_lot::RwLock<DependencyGraph>,
+    bibliographies: BibliographyCache,
+    preview: Preview,
 }
 
 impl TypstServer {
     pub fn new(
         client: Client,
-        lsp_tracing_layer_handle: reload::Handle<Option<LspLayer>, Registry>,
+        lsp_tracing_layer_handle: reload::Handle<LogLayers, Registry>,
+        thread_stats: Arc<ThreadStats>,
     ) -> Self {
         Self {
             typst_thread: Default::default(),
@@ -59,23 +105,67 @@ impl TypstServer {
             config: Default::default(),
             const_config: Default::default(),
             semantic_tokens_delta_cache: Default::default(),
-            diagnostics: Mutex::new(DiagnosticsManager::new(client.clone())),
+            diagnostics: Arc::new(Mutex::new(DiagnosticsManager::new(client.clone()))),
             lsp_tracing_layer_handle,
+            thread_stats,
             client,
-            document: Default::default(),
+            documents: Default::default(),
+            on_type_compiles: Default::default(),
+            compile_generations: Default::default(),
+            dependencies: Default::default(),
+            bibliographies: Default::default(),
+            preview: Default::default(),
         }
     }
 
-    pub fn const_config(&self) -> &ConstConfig {
-        self.const_config
-            .get()
-            .expect("const config should be initialized")
+    /// `None` until the client has sent `initialize`
+    pub fn const_config(&self) -> Option<&ConstConfig> {
+        self.const_config.get()
+    }
+
+    /// The position encoding chosen in `initialize`. Before that, it is UTF-16, which LSP
+    /// specifies as the default.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.const_config()
+            .map_or(PositionEncoding::Utf16, |const_config| {
+                const_config.position_encoding
+            })
     }
 
-    pub fn workspace(&self) -> &Arc<RwLock<Workspace>> {
-        self.workspace
-            .get()
-            .expect("workspace should be initialized")
+    /// `None` until the server has handled `initialize`
+    pub fn workspace(&self) -> Option<&Arc<RwLock<Workspace>>> {
+        self.workspace.get()
+    }
+
+    /// The workspace, for work which can't be done before `initialize`
+    fn initialized_workspace(&self) -> FsResult<&Arc<RwLock<Workspace>>> {
+        self.workspace()
+            .ok_or_else(|| FsError::Other(anyhow!("server not initialized")))
+    }
+
+    /// The workspace, or `None` with a warning if the server hasn't handled `initialize`.
+    /// Notification handlers use this, since they can't reply with an error.
+    fn notified_workspace(&self) -> Option<&Arc<RwLock<Workspace>>> {
+        let workspace = self.workspace();
+        if workspace.is_none() {
+            warn!("ignoring notification received before `initialize`");
+        }
+        workspace
+    }
+
+    /// Fails with the error LSP specifies for requests received before `initialize` was handled.
+    /// Request handlers check this first, so a client racing `initialize` gets an error instead
+    /// of crashing the server.
+    pub fn ensure_initialized(&self) -> jsonrpc::Result<()> {
+        if self.const_config().is_some() && self.workspace().is_some() {
+            Ok(())
+        } else {
+            Err(jsonrpc::Error {
+                code: jsonrpc::ErrorCode::ServerError(SERVER_NOT_INITIALIZED),
+                message: "server not initialized".into(),
+                data: None,
+            })
+        }
     }
 
     pub async fn main_url(&self) -> Option<Url> {
@@ -88,20 +178,20 @@ impl TypstServer {
 
     #[tracing::instrument(skip(self))]
     pub async fn register_workspace_files(&self) -> FsResult<()> {
-        let mut workspace = self.workspace().write().await;
+        let mut workspace = self.initialized_workspace()?.write().await;
         workspace.register_files()
     }
 
-    async fn read_workspace(&self) -> RwLockReadGuard<Workspace> {
-        self.workspace().read().await
+    async fn read_workspace(&self) -> FsResult<RwLockReadGuard<Workspace>> {
+        Ok(self.initialized_workspace()?.read().await)
     }
 
-    async fn read_workspace_owned(&self) -> OwnedRwLockReadGuard<Workspace> {
-        Arc::clone(self.workspace()).read_owned().await
+    async fn read_workspace_owned(&self) -> FsResult<OwnedRwLockReadGuard<Workspace>> {
+        Ok(Arc::clone(self.initialized_workspace()?).read_owned().await)
     }
 
     pub async fn project_and_full_id(&self, uri: &Url) -> FsResult<(Project, FullFileId)> {
-        let workspace = self.read_workspace_owned().await;
+        let workspace = self.read_workspace_owned().await?;
         let full_id = workspace.full_id(uri)?;
         let project = Project::new(full_id.package(), workspace);
         Ok((project, full_id))
@@ -117,7 +207,10 @@ impl TypstServer {
         &self,
         builder: impl Into<WorldBuilder<'_>>,
     ) -> FsResult<WorldThread> {
-        let (main, project) = builder.into().main_project(self.workspace()).await?;
+        let (main, project) = builder
+            .into()
+            .main_project(self.initialized_workspace()?)
+            .await?;
 
         Ok(WorldThread {
             main,
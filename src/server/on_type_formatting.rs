@@ -0,0 +1,166 @@
+use tower_lsp::lsp_types::{FormattingOptions, TextEdit};
+use typst::syntax::{LinkedNode, Source, SyntaxKind};
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+
+use super::TypstServer;
+
+impl TypstServer {
+    pub fn get_on_type_formatting(
+        &self,
+        source: &Source,
+        position: LspPosition,
+        ch: &str,
+        options: &FormattingOptions,
+    ) -> Vec<TextEdit> {
+        let position_encoding = self.position_encoding();
+        let offset = lsp_to_typst::position_to_offset(position, position_encoding, source);
+
+        let indent_unit = if options.insert_spaces {
+            " ".repeat(options.tab_size as usize)
+        } else {
+            "\t".to_owned()
+        };
+
+        on_type_edits(source, offset, ch, &indent_unit)
+            .into_iter()
+            .map(|(range, new_text)| TextEdit {
+                range: typst_to_lsp::range(range, source, position_encoding).raw_range,
+                new_text,
+            })
+            .collect()
+    }
+}
+
+/// Finds edits to make after `ch` was typed, leaving the cursor at `offset`
+pub fn on_type_edits(
+    source: &Source,
+    offset: usize,
+    ch: &str,
+    indent_unit: &str,
+) -> Vec<(TypstRange, String)> {
+    let edit = match ch {
+        "}" => dedent_closing_brace(source, offset),
+        "\n" => indent_new_line(source, offset, indent_unit),
+        "$" => close_equation(source, offset),
+        _ => None,
+    };
+
+    edit.into_iter().collect()
+}
+
+/// Aligns a `}` on its own line with the line which opened the block
+fn dedent_closing_brace(source: &Source, offset: usize) -> Option<(TypstRange, String)> {
+    let root = LinkedNode::new(source.root());
+    let brace = root.leaf_at(offset)?;
+    if brace.kind() != SyntaxKind::RightBrace {
+        return None;
+    }
+    let block = brace.parent()?;
+
+    let line_start = line_start(source, brace.offset())?;
+    let before_brace = &source.text()[line_start..brace.offset()];
+    if line_start <= block.offset() || !before_brace.trim().is_empty() {
+        return None;
+    }
+
+    let indent = indentation(source, block.offset())?;
+    (before_brace != indent).then(|| (line_start..brace.offset(), indent.to_owned()))
+}
+
+/// Indents a new line one level deeper than the line which opened the enclosing block, or to the
+/// same level if the line closes the block
+fn indent_new_line(
+    source: &Source,
+    offset: usize,
+    indent_unit: &str,
+) -> Option<(TypstRange, String)> {
+    let root = LinkedNode::new(source.root());
+    let leaf = root.leaf_at(offset)?;
+    let block = std::iter::successors(Some(leaf), |node| node.parent().cloned()).find(|node| {
+        matches!(
+            node.kind(),
+            SyntaxKind::CodeBlock | SyntaxKind::ContentBlock
+        ) && node.offset() < offset
+    })?;
+
+    let line_start = line_start(source, offset)?;
+    let line = source.text()[line_start..].lines().next().unwrap_or("");
+    let current = &line[..line.len() - line.trim_start().len()];
+    let closes_block = line.trim_start().starts_with(['}', ']']);
+
+    let block_indent = indentation(source, block.offset())?;
+    let indent = if closes_block {
+        block_indent.to_owned()
+    } else {
+        format!("{block_indent}{indent_unit}")
+    };
+
+    (current != indent).then(|| (line_start..line_start + current.len(), indent))
+}
+
+/// Inserts the closing `$` after an opening one
+fn close_equation(source: &Source, offset: usize) -> Option<(TypstRange, String)> {
+    let root = LinkedNode::new(source.root());
+    let dollar = root.leaf_at(offset)?;
+    if dollar.kind() != SyntaxKind::Dollar {
+        return None;
+    }
+
+    let equation = dollar.parent()?;
+    let dollars = equation
+        .children()
+        .filter(|child| child.kind() == SyntaxKind::Dollar)
+        .count();
+    let is_opening = dollar.index() == 0;
+
+    (dollars == 1 && is_opening).then(|| (offset..offset, "$".to_owned()))
+}
+
+fn line_start(source: &Source, offset: usize) -> Option<usize> {
+    source.line_to_byte(source.byte_to_line(offset)?)
+}
+
+/// The whitespace at the start of the line containing `offset`
+fn indentation(source: &Source, offset: usize) -> Option<&str> {
+    let line = &source.text()[line_start(source, offset)?..];
+    let content = line.trim_start_matches([' ', '\t']);
+    Some(&line[..line.len() - content.len()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edits(text: &str, offset: usize, ch: &str) -> Vec<(TypstRange, String)> {
+        on_type_edits(&Source::detached(text), offset, ch, "  ")
+    }
+
+    #[test]
+    fn closing_brace_is_dedented() {
+        let text = "#{\n  let x = 1\n  }";
+
+        assert_eq!(edits(text, text.len(), "}"), vec![(15..17, String::new())]);
+    }
+
+    #[test]
+    fn nested_closing_brace_matches_opening_line() {
+        let text = "#{\n  if true {\n    x\n    }\n}";
+        let offset = text.find("    }").unwrap() + 5;
+
+        assert_eq!(edits(text, offset, "}"), vec![(21..25, "  ".to_owned())]);
+    }
+
+    #[test]
+    fn new_line_in_block_is_indented() {
+        let text = "#{\n\n}";
+
+        assert_eq!(edits(text, 3, "\n"), vec![(3..3, "  ".to_owned())]);
+    }
+
+    #[test]
+    fn opening_dollar_is_closed() {
+        assert_eq!(edits("a $", 3, "$"), vec![(3..3, "$".to_owned())]);
+        assert_eq!(edits("a $x$", 5, "$"), vec![]);
+    }
+}
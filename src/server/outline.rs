@@ -0,0 +1,323 @@
+use std::num::NonZeroUsize;
+
+use anyhow::bail;
+use comemo::Track;
+use serde::Serialize;
+use tower_lsp::lsp_types::{
+    DocumentSymbol, DocumentSymbolResponse, Location, Range, SymbolKind, Url,
+};
+use typst::diag::EcoString;
+use typst::engine::{Engine, Route};
+use typst::eval::Tracer;
+use typst::foundations::{NativeElement, Selector, StyleChain};
+use typst::introspection::{Counter, Locator};
+use typst::model::{Document, HeadingElem};
+use typst::syntax::Span;
+use typst::World;
+
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::typst_to_lsp;
+use crate::workspace::project::Project;
+
+use super::symbols::get_nested_symbols;
+use super::TypstServer;
+
+/// A heading as it appears in the compiled document's outline
+#[derive(Debug, Clone)]
+pub struct OutlineHeading {
+    pub level: NonZeroUsize,
+    /// The heading's number as displayed, like `1.2`, if the heading is numbered
+    pub number: Option<EcoString>,
+    pub title: EcoString,
+    pub page: NonZeroUsize,
+    pub span: Span,
+}
+
+/// A heading in the compiled document, as listed by the document outline command
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineEntry {
+    pub text: EcoString,
+    pub level: NonZeroUsize,
+    pub number: Option<EcoString>,
+    pub page: NonZeroUsize,
+    /// Where the heading is written, which may be in an included file. `None` if the heading has
+    /// no source, like one generated by a package.
+    pub location: Option<Location>,
+}
+
+impl TypstServer {
+    /// Gets the outline of the compiled document. Unlike the syntactic document symbols, this
+    /// includes headings generated by code, with their resolved numbers and pages in the symbols'
+    /// details. Symbols can only point into the document itself, so headings written elsewhere,
+    /// like in included files, are placed at its start. If compilation fails, falls back to the
+    /// syntactic symbols.
+    #[tracing::instrument(skip(self))]
+    pub async fn rendered_outline(&self, uri: &Url) -> anyhow::Result<DocumentSymbolResponse> {
+        let Some(headings) = self.located_headings(uri).await? else {
+            let position_encoding = self.position_encoding();
+            let symbols = self
+                .scope_with_source(uri)
+                .await?
+                .run(|source, _| get_nested_symbols(source, uri, position_encoding))?;
+            return Ok(DocumentSymbolResponse::Nested(symbols));
+        };
+
+        let symbols = headings.into_iter().map(|(heading, location)| {
+            let range = location
+                .filter(|location| location.uri == *uri)
+                .map(|location| location.range)
+                .unwrap_or_default();
+            (heading.level, heading_symbol(&heading, range))
+        });
+
+        Ok(DocumentSymbolResponse::Nested(nest_symbols(symbols)))
+    }
+
+    /// Lists the headings of the compiled document in order, for panels to jump to sections
+    #[tracing::instrument(skip(self))]
+    pub async fn document_outline(&self, uri: &Url) -> anyhow::Result<Vec<OutlineEntry>> {
+        let Some(headings) = self.located_headings(uri).await? else {
+            bail!("failed to generate document after compilation");
+        };
+
+        let entries = headings
+            .into_iter()
+            .map(|(heading, location)| OutlineEntry {
+                text: heading.title,
+                level: heading.level,
+                number: heading.number,
+                page: heading.page,
+                location,
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Compiles the document and collects its outline headings, each with where it is written.
+    /// `None` if compilation fails.
+    async fn located_headings(
+        &self,
+        uri: &Url,
+    ) -> anyhow::Result<Option<Vec<(OutlineHeading, Option<Location>)>>> {
+        let (document, _) = self.compile_source(uri).await?;
+        let Some(document) = document else {
+            return Ok(None);
+        };
+
+        let position_encoding = self.position_encoding();
+        let (project, _) = self.project_and_full_id(uri).await?;
+        let headings = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| outline_headings(&world, &document))
+            .await;
+
+        let mut located = Vec::with_capacity(headings.len());
+        for heading in headings {
+            let location = span_location(&project, heading.span, position_encoding).await;
+            located.push((heading, location));
+        }
+
+        Ok(Some(located))
+    }
+}
+
+/// Where the span is written, in whichever file of the project it is in
+async fn span_location(
+    project: &Project,
+    span: Span,
+    position_encoding: PositionEncoding,
+) -> Option<Location> {
+    let uri = project
+        .full_id_to_uri(project.fill_id(span.id()?))
+        .await
+        .ok()?;
+    let source = project.read_source_by_uri(&uri).ok()?;
+    let range = source.range(span)?;
+
+    Some(Location {
+        range: typst_to_lsp::range(range, &source, position_encoding).raw_range,
+        uri,
+    })
+}
+
+/// Collects the headings which appear in the document's outline, in document order. Numbers are
+/// displayed from the heading counter's state in the document, so they account for counter
+/// updates and numbering functions, like Typst does. This needs the world the document was
+/// compiled in to evaluate numbering functions.
+pub fn outline_headings(world: &dyn World, document: &Document) -> Vec<OutlineHeading> {
+    let styles = StyleChain::default();
+    let mut locator = Locator::new();
+    let mut tracer = Tracer::default();
+    let mut engine = Engine {
+        world: world.track(),
+        introspector: document.introspector.track(),
+        route: Route::default(),
+        locator: &mut locator,
+        tracer: tracer.track_mut(),
+    };
+    let counter = Counter::of(HeadingElem::elem());
+
+    document
+        .introspector
+        .query(&Selector::Elem(HeadingElem::elem(), None))
+        .iter()
+        .filter_map(|content| {
+            let heading = content.to_packed::<HeadingElem>()?;
+            if !heading.outlined(styles) {
+                return None;
+            }
+
+            let location = content.location()?;
+            let number = heading.numbering(styles).clone().and_then(|numbering| {
+                counter
+                    .display_at_loc(&mut engine, location, styles, &numbering)
+                    .ok()
+                    .map(|number| number.plain_text())
+            });
+
+            Some(OutlineHeading {
+                level: heading.resolve_level(styles),
+                number,
+                title: heading.body().plain_text(),
+                page: document.introspector.page(location),
+                span: content.span(),
+            })
+        })
+        .collect()
+}
+
+#[allow(deprecated)]
+fn heading_symbol(heading: &OutlineHeading, range: Range) -> DocumentSymbol {
+    let detail = match &heading.number {
+        Some(number) => format!("{number}, page {}", heading.page),
+        None => format!("page {}", heading.page),
+    };
+
+    DocumentSymbol {
+        name: heading.title.to_string(),
+        detail: Some(detail),
+        kind: SymbolKind::NAMESPACE,
+        tags: None,
+        deprecated: None, // do not use, deprecated, use `tags` instead
+        range,
+        selection_range: range,
+        children: None,
+    }
+}
+
+/// Nests each symbol under the closest preceding one of a lower level, like subsections under
+/// their section
+fn nest_symbols(
+    symbols: impl IntoIterator<Item = (NonZeroUsize, DocumentSymbol)>,
+) -> Vec<DocumentSymbol> {
+    let mut roots = Vec::new();
+    let mut open: Vec<(NonZeroUsize, DocumentSymbol)> = Vec::new();
+
+    for (level, symbol) in symbols {
+        while open
+            .last()
+            .is_some_and(|(open_level, _)| *open_level >= level)
+        {
+            close_last(&mut open, &mut roots);
+        }
+        open.push((level, symbol));
+    }
+    while !open.is_empty() {
+        close_last(&mut open, &mut roots);
+    }
+
+    roots
+}
+
+fn close_last(open: &mut Vec<(NonZeroUsize, DocumentSymbol)>, roots: &mut Vec<DocumentSymbol>) {
+    let Some((_, symbol)) = open.pop() else {
+        return;
+    };
+    match open.last_mut() {
+        Some((_, parent)) => parent.children.get_or_insert_with(Vec::new).push(symbol),
+        None => roots.push(symbol),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use typst::syntax::Source;
+
+    use crate::server::query::test::SingleFileWorld;
+    use crate::workspace::font_manager::FontManager;
+
+    use super::*;
+
+    fn outline(text: &str) -> Vec<OutlineHeading> {
+        let world = SingleFileWorld {
+            main: Source::detached(text),
+            fonts: FontManager::builder().with_embedded().build(),
+        };
+        let document = typst::compile(&world, &mut Tracer::default()).unwrap();
+        outline_headings(&world, &document)
+    }
+
+    #[test]
+    fn headings_have_levels() {
+        let headings = outline("= A\n== B\nText");
+
+        let levels: Vec<_> = headings.iter().map(|heading| heading.level.get()).collect();
+        let titles: Vec<_> = headings
+            .iter()
+            .map(|heading| heading.title.as_str())
+            .collect();
+        assert_eq!(levels, [1, 2]);
+        assert_eq!(titles, ["A", "B"]);
+        assert!(headings
+            .iter()
+            .all(|heading| heading.page == NonZeroUsize::MIN));
+    }
+
+    #[test]
+    fn numbers_follow_counter_updates() {
+        let headings = outline(
+            "#set heading(numbering: \"1.a\")\n= A\n#counter(heading).update(4)\n= B\n== C",
+        );
+
+        let numbers: Vec<_> = headings
+            .iter()
+            .map(|heading| heading.number.as_deref())
+            .collect();
+        assert_eq!(numbers, [Some("1"), Some("5"), Some("5.a")]);
+    }
+
+    #[test]
+    fn numbering_functions_are_evaluated() {
+        let headings = outline("#set heading(numbering: (..n) => [Part #n.pos().first()])\n= A");
+
+        assert_eq!(headings[0].number.as_deref(), Some("Part 1"));
+    }
+
+    #[test]
+    fn symbols_nest_by_level() {
+        let headings = outline("#set heading(numbering: \"1.1\")\n= A\n== B\n=== C\n== D\n= E");
+
+        let symbols = nest_symbols(
+            headings
+                .iter()
+                .map(|heading| (heading.level, heading_symbol(heading, Range::default()))),
+        );
+
+        let names = |symbols: &[DocumentSymbol]| -> Vec<String> {
+            symbols.iter().map(|symbol| symbol.name.clone()).collect()
+        };
+        assert_eq!(names(&symbols), ["A", "E"]);
+        let a_children = symbols[0].children.as_deref().unwrap();
+        assert_eq!(names(a_children), ["B", "D"]);
+        assert_eq!(names(a_children[0].children.as_deref().unwrap()), ["C"]);
+        assert_eq!(a_children[0].detail.as_deref(), Some("1.1, page 1"));
+    }
+
+    #[test]
+    fn document_without_headings_has_empty_outline() {
+        assert!(outline("Just text").is_empty());
+    }
+}
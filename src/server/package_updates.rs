@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use tower_lsp::lsp_types::{Range, Url};
+use typst::syntax::package::{PackageSpec, PackageVersion};
+use typst::syntax::{ast, LinkedNode, Source};
+
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::typst_to_lsp;
+
+use super::TypstServer;
+
+/// An import of a package which has a newer version available
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageUpdate {
+    /// The package as imported, like `@preview/example:0.1.0`
+    pub package: String,
+    pub latest: String,
+    pub uri: Url,
+    /// Range of the import's string literal, which a client can replace to bump the version
+    pub range: Range,
+}
+
+impl TypstServer {
+    /// Finds `@preview` package imports across the workspace whose version is older than the
+    /// newest in the package index. The index is only downloaded once per session.
+    #[tracing::instrument(skip(self))]
+    pub async fn check_package_updates(&self) -> anyhow::Result<Vec<PackageUpdate>> {
+        let position_encoding = self.position_encoding();
+        let workspace = self.read_workspace().await?;
+
+        let mut latest: HashMap<&str, PackageVersion> = HashMap::new();
+        for (spec, _) in workspace.package_manager().packages().await {
+            if spec.namespace != "preview" {
+                continue;
+            }
+            let version = latest.entry(spec.name.as_str()).or_insert(spec.version);
+            *version = (*version).max(spec.version);
+        }
+
+        let mut uris: Vec<_> = workspace
+            .known_uris()
+            .into_iter()
+            .filter(|uri| uri.path().ends_with(".typ"))
+            .collect();
+        uris.sort();
+
+        let mut updates = Vec::new();
+        for uri in uris {
+            let Ok(source) = workspace.read_source(&uri) else {
+                continue;
+            };
+
+            let mut imports = Vec::new();
+            collect_package_imports(&LinkedNode::new(source.root()), &mut imports);
+
+            for (spec, node) in imports {
+                let Some(&newest) = latest.get(spec.name.as_str()) else {
+                    continue;
+                };
+                if spec.namespace != "preview" || newest <= spec.version {
+                    continue;
+                }
+
+                updates.push(PackageUpdate {
+                    package: spec.to_string(),
+                    latest: newest.to_string(),
+                    uri: uri.clone(),
+                    range: import_range(&node, &source, position_encoding),
+                });
+            }
+        }
+
+        Ok(updates)
+    }
+}
+
+/// Walks the tree, recording the package specs of imports and includes along with their string
+/// literals
+fn collect_package_imports<'a>(
+    node: &LinkedNode<'a>,
+    imports: &mut Vec<(PackageSpec, LinkedNode<'a>)>,
+) {
+    let source = if let Some(import) = node.cast::<ast::ModuleImport>() {
+        Some(import.source())
+    } else {
+        node.cast::<ast::ModuleInclude>()
+            .map(|include| include.source())
+    };
+
+    if let Some(ast::Expr::Str(path)) = source {
+        let spec = path.get().parse::<PackageSpec>().ok();
+        let literal = node
+            .children()
+            .find(|child| child.get() == path.to_untyped());
+        if let (Some(spec), Some(literal)) = (spec, literal) {
+            imports.push((spec, literal));
+        }
+    }
+
+    for child in node.children() {
+        collect_package_imports(&child, imports);
+    }
+}
+
+fn import_range(node: &LinkedNode, source: &Source, position_encoding: PositionEncoding) -> Range {
+    typst_to_lsp::range(node.range(), source, position_encoding).raw_range
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::Url;
+
+use super::TypstServer;
+
+/// Sent when the pinned main file changes, so editors can show it, like in a status bar
+#[derive(Debug)]
+pub enum PinnedMainNotification {}
+
+impl Notification for PinnedMainNotification {
+    type Params = PinnedMain;
+    const METHOD: &'static str = "$/typstMainPinChanged";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinnedMain {
+    /// `None` if no main file is pinned
+    pub uri: Option<Url>,
+}
+
+impl TypstServer {
+    /// Tells the client about the pinned main file if it is no longer `previous`
+    pub async fn notify_pinned_main(&self, previous: Option<Url>) {
+        let uri = self.main_url().await;
+        if uri != previous {
+            self.client
+                .send_notification::<PinnedMainNotification>(PinnedMain { uri })
+                .await;
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use futures::SinkExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::Client;
+use tracing::{error, info, warn};
+use typst::model::Document;
+
+/// Sent when the preview server starts or stops, so the editor can open the preview in a browser
+/// panel
+#[derive(Debug)]
+pub enum PreviewNotification {}
+
+impl Notification for PreviewNotification {
+    type Params = PreviewStatus;
+    const METHOD: &'static str = "$/typstPreview";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreviewStatus {
+    /// The address of the preview page on `127.0.0.1`, or `None` if the preview is stopped. It
+    /// contains a secret token, since any web page could otherwise connect to the port and read
+    /// the document.
+    pub url: Option<String>,
+}
+
+/// The pages of a compiled document as SVG, as sent to the browser
+#[derive(Debug, Serialize)]
+struct PreviewFrame {
+    pages: Vec<String>,
+}
+
+/// The page the browser opens. It connects back to the same port with a WebSocket, and shows each
+/// frame it receives.
+const PREVIEW_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Typst Preview</title>
+<style>
+body { margin: 0; padding: 16px; background: #888; }
+.page { margin: 0 auto 16px; max-width: 100%; background: white; box-shadow: 0 2px 6px #0006; }
+.page svg { display: block; width: 100%; height: auto; }
+</style>
+</head>
+<body>
+<script>
+const socket = new WebSocket("ws://" + location.host + location.pathname + "/ws");
+socket.onmessage = (event) => {
+    const frame = JSON.parse(event.data);
+    document.body.replaceChildren(...frame.pages.map((svg) => {
+        const page = document.createElement("div");
+        page.className = "page";
+        page.innerHTML = svg;
+        return page;
+    }));
+};
+</script>
+</body>
+</html>
+"#;
+
+/// A local HTTP and WebSocket server showing the last compiled document, updated on every
+/// successful compile. Started and stopped by the `preview` setting.
+#[derive(Debug, Default, Clone)]
+pub struct Preview {
+    server: Arc<parking_lot::Mutex<Option<PreviewServer>>>,
+}
+
+impl Preview {
+    /// Starts or stops the preview server, and tells the client where to find the preview
+    pub async fn set_enabled(&self, client: &Client, enabled: bool) -> io::Result<()> {
+        let url = if enabled {
+            Some(self.start().await?)
+        } else {
+            self.stop();
+            None
+        };
+
+        client
+            .send_notification::<PreviewNotification>(PreviewStatus { url })
+            .await;
+        Ok(())
+    }
+
+    /// Starts the server if it isn't running, giving the address of the preview page
+    async fn start(&self) -> io::Result<String> {
+        if let Some(server) = &*self.server.lock() {
+            return Ok(server.url());
+        }
+
+        let server = PreviewServer::start().await?;
+        let url = server.url();
+        *self.server.lock() = Some(server);
+        Ok(url)
+    }
+
+    pub fn stop(&self) {
+        if self.server.lock().take().is_some() {
+            info!("stopped preview server");
+        }
+    }
+
+    /// Sends the document to connected browsers, if the preview is running. Rendering happens in
+    /// the background, so this doesn't hold up publishing diagnostics. Documents pushed while a
+    /// previous one renders replace each other, so only the latest is rendered next.
+    pub fn push(&self, document: Arc<Document>) {
+        if let Some(server) = &*self.server.lock() {
+            server.documents.send_replace(Some(document));
+        }
+    }
+}
+
+struct PreviewServer {
+    port: u16,
+    /// Secret part of the page and WebSocket paths, so only the editor's client finds them
+    token: String,
+    /// The latest document to render. A single task renders them in order.
+    documents: watch::Sender<Option<Arc<Document>>>,
+    accept_task: JoinHandle<()>,
+    render_task: JoinHandle<()>,
+}
+
+impl PreviewServer {
+    async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let port = listener.local_addr()?.port();
+        let token = random_token();
+
+        let frames = Arc::new(watch::channel(None).0);
+        let (documents, rendered) = watch::channel(None);
+        let accept_task = tokio::spawn(accept(listener, token.clone(), Arc::clone(&frames)));
+        let render_task = tokio::spawn(render(rendered, frames));
+
+        info!(port, "started preview server");
+        Ok(Self {
+            port,
+            token,
+            documents,
+            accept_task,
+            render_task,
+        })
+    }
+
+    fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/{}", self.port, self.token)
+    }
+}
+
+/// A hard to guess token from the standard library's randomly keyed hasher, to avoid depending on
+/// a random number generator just for this
+fn random_token() -> String {
+    let half = || RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", half(), half())
+}
+
+/// Stopping the server stops accepting connections. Open connections close once the last frame
+/// sender is dropped.
+impl Drop for PreviewServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        self.render_task.abort();
+    }
+}
+
+impl std::fmt::Debug for PreviewServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreviewServer")
+            .field("port", &self.port)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Renders each document in turn, so a frame is never replaced by one of an older document
+async fn render(
+    mut documents: watch::Receiver<Option<Arc<Document>>>,
+    frames: Arc<watch::Sender<Option<Arc<str>>>>,
+) {
+    while documents.changed().await.is_ok() {
+        let Some(document) = documents.borrow_and_update().clone() else {
+            continue;
+        };
+
+        let frame = tokio::task::spawn_blocking(move || {
+            serde_json::to_string(&PreviewFrame {
+                pages: document
+                    .pages
+                    .iter()
+                    .map(|page| typst_svg::svg(&page.frame))
+                    .collect(),
+            })
+        })
+        .await;
+
+        match frame {
+            Ok(Ok(frame)) => {
+                frames.send_replace(Some(frame.into()));
+            }
+            Ok(Err(err)) => error!(%err, "could not serialize preview frame"),
+            Err(err) => error!(%err, "could not render preview frame"),
+        }
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    token: String,
+    frames: Arc<watch::Sender<Option<Arc<str>>>>,
+) {
+    let token: Arc<str> = token.into();
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let frames = frames.subscribe();
+                let token = Arc::clone(&token);
+                tokio::spawn(async move {
+                    if let Err(err) = serve(stream, &token, frames).await {
+                        warn!(%err, "preview connection failed");
+                    }
+                });
+            }
+            Err(err) => {
+                error!(%err, "could not accept preview connection");
+                return;
+            }
+        }
+    }
+}
+
+/// Serves the preview page at `/<token>`, or streams frames if the request is a WebSocket upgrade
+/// at `/<token>/ws`. Other paths are not found.
+async fn serve(
+    mut stream: TcpStream,
+    token: &str,
+    mut frames: watch::Receiver<Option<Arc<str>>>,
+) -> anyhow::Result<()> {
+    let mut request = [0; 2048];
+    let read = stream.peek(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let upgrade = request.to_ascii_lowercase().contains("upgrade: websocket");
+
+    let page_path = format!("/{token}");
+    let socket_path = format!("{page_path}/ws");
+    if !upgrade || path != socket_path {
+        let response = if !upgrade && path == page_path {
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{PREVIEW_PAGE}",
+                PREVIEW_PAGE.len()
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        };
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    let mut socket = tokio_tungstenite::accept_async(stream).await?;
+    loop {
+        let frame = frames.borrow_and_update().clone();
+        if let Some(frame) = frame {
+            socket.send(Message::Text(frame.to_string())).await?;
+        }
+        if frames.changed().await.is_err() {
+            // The preview was stopped
+            socket.close(None).await?;
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use typst::eval::Tracer;
+    use typst::syntax::Source;
+
+    use crate::server::query::test::SingleFileWorld;
+    use crate::workspace::font_manager::FontManager;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compile_pushes_frame() {
+        let world = SingleFileWorld {
+            main: Source::detached("= Hello"),
+            fonts: FontManager::builder().with_embedded().build(),
+        };
+        let document = Arc::new(typst::compile(&world, &mut Tracer::default()).unwrap());
+
+        let preview = Preview::default();
+        let url = preview.start().await.unwrap();
+        let socket_url = format!("{}/ws", url.replacen("http", "ws", 1));
+        let (mut socket, _) = tokio_tungstenite::connect_async(socket_url).await.unwrap();
+
+        preview.push(document);
+        let message = tokio::time::timeout(Duration::from_secs(10), socket.next())
+            .await
+            .expect("a frame should be pushed")
+            .unwrap()
+            .unwrap();
+
+        let Message::Text(frame) = message else {
+            panic!("expected a text frame, got {message:?}");
+        };
+        let frame: serde_json::Value = serde_json::from_str(&frame).unwrap();
+        let pages = frame["pages"].as_array().unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].as_str().unwrap().contains("<svg"));
+
+        preview.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connections_without_token_are_rejected() {
+        let preview = Preview::default();
+        let url = preview.start().await.unwrap();
+        let port = url.split(':').nth(2).unwrap().split('/').next().unwrap();
+
+        let without_token =
+            tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws")).await;
+
+        assert!(without_token.is_err());
+        preview.stop();
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::workspace::world::typst_thread::TASK_SPAN;
+
+/// Spans, as named by `#[tracing::instrument]`, for operations which do their work on the Typst
+/// thread. Only the time their work spends on the thread is counted, not the time spent waiting
+/// for locks or for the thread to be free.
+const TRACKED_SPANS: &[&str] = &[
+    "compile_source",
+    "eval_source",
+    "completion",
+    "hover",
+    "signature_help",
+    "export_pdf",
+    "export_thumbnails",
+];
+
+/// How many of the most recent durations to keep for each span when computing percentiles
+const WINDOW_SIZE: usize = 256;
+
+/// Rolling timings of operations dispatched to the Typst thread. Nothing is recorded unless
+/// enabled, so this costs almost nothing by default.
+#[derive(Debug, Default)]
+pub struct ThreadStats {
+    enabled: AtomicBool,
+    timings: parking_lot::Mutex<HashMap<&'static str, SpanTimings>>,
+}
+
+impl ThreadStats {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    fn record(&self, name: &'static str, duration: Duration) {
+        self.timings
+            .lock()
+            .entry(name)
+            .or_default()
+            .record(duration);
+    }
+
+    pub fn summary(&self) -> HashMap<&'static str, SpanSummary> {
+        self.timings
+            .lock()
+            .iter()
+            .map(|(name, timings)| (*name, timings.summary()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct SpanTimings {
+    count: u64,
+    recent: VecDeque<Duration>,
+}
+
+impl SpanTimings {
+    fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        if self.recent.len() == WINDOW_SIZE {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(duration);
+    }
+
+    fn summary(&self) -> SpanSummary {
+        let mut sorted: Vec<_> = self.recent.iter().copied().collect();
+        sorted.sort_unstable();
+
+        let percentile = |p: f64| {
+            let index = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+            sorted.get(index).copied().map(as_millis).unwrap_or(0.0)
+        };
+        let total: Duration = sorted.iter().sum();
+        let average = if sorted.is_empty() {
+            0.0
+        } else {
+            as_millis(total) / sorted.len() as f64
+        };
+
+        SpanSummary {
+            count: self.count,
+            average_ms: average,
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: sorted.last().copied().map(as_millis).unwrap_or(0.0),
+        }
+    }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Summary of a span's timings. The count is over the whole session, while the durations are over
+/// the most recent operations only.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanSummary {
+    pub count: u64,
+    pub average_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Aggregates the durations of work on the Typst thread into [`ThreadStats`], by the tracked span
+/// the work was sent from
+pub struct ThreadStatsLayer {
+    stats: Arc<ThreadStats>,
+}
+
+impl ThreadStatsLayer {
+    pub fn new(stats: Arc<ThreadStats>) -> Self {
+        Self { stats }
+    }
+}
+
+/// When a task started on the Typst thread, and the tracked operation it was sent from
+struct TaskStart {
+    start: Instant,
+    operation: &'static str,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ThreadStatsLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !self.stats.is_enabled() || attrs.metadata().name() != TASK_SPAN {
+            return;
+        }
+
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let operation = span
+            .scope()
+            .skip(1)
+            .map(|ancestor| ancestor.name())
+            .find(|name| TRACKED_SPANS.contains(name));
+        if let Some(operation) = operation {
+            span.extensions_mut().insert(TaskStart {
+                start: Instant::now(),
+                operation,
+            });
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let task = span
+            .extensions()
+            .get::<TaskStart>()
+            .map(|task| (task.operation, task.start.elapsed()));
+        if let Some((operation, duration)) = task {
+            self.stats.record(operation, duration);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+
+    use super::*;
+
+    /// Runs `f` with a subscriber recording into the returned stats
+    fn profile(enabled: bool, f: impl FnOnce()) -> Arc<ThreadStats> {
+        let stats = Arc::new(ThreadStats::default());
+        stats.set_enabled(enabled);
+        let subscriber =
+            tracing_subscriber::registry().with(ThreadStatsLayer::new(Arc::clone(&stats)));
+        tracing::subscriber::with_default(subscriber, f);
+        stats
+    }
+
+    #[test]
+    fn only_time_on_typst_thread_is_counted() {
+        let stats = profile(true, || {
+            let _operation = info_span!("compile_source").entered();
+            // Like waiting for a lock before sending work to the thread
+            thread::sleep(Duration::from_millis(100));
+            info_span!(TASK_SPAN).in_scope(|| thread::sleep(Duration::from_millis(10)));
+        });
+
+        let summary = stats.summary();
+        let compile = &summary["compile_source"];
+        assert_eq!(compile.count, 1);
+        assert!(compile.max_ms >= 10.0, "got {}ms", compile.max_ms);
+        assert!(compile.max_ms < 100.0, "got {}ms", compile.max_ms);
+    }
+
+    #[test]
+    fn untracked_and_disabled_work_is_not_counted() {
+        let untracked = profile(true, || {
+            let _operation = info_span!("formatting").entered();
+            info_span!(TASK_SPAN).in_scope(|| {});
+        });
+        let disabled = profile(false, || {
+            let _operation = info_span!("compile_source").entered();
+            info_span!(TASK_SPAN).in_scope(|| {});
+        });
+
+        assert!(untracked.summary().is_empty());
+        assert!(disabled.summary().is_empty());
+    }
+
+    #[test]
+    fn percentiles_are_of_recent_durations() {
+        let mut timings = SpanTimings::default();
+        for millis in 1..=(WINDOW_SIZE as u64 + 100) {
+            timings.record(Duration::from_millis(millis));
+        }
+
+        let summary = timings.summary();
+        assert_eq!(summary.count, WINDOW_SIZE as u64 + 100);
+        let close = |ms: f64, expected: usize| (ms - expected as f64).abs() < 1e-6;
+        assert!(close(summary.max_ms, WINDOW_SIZE + 100));
+        // The first 100 durations fell out of the window
+        assert!(close(summary.p50_ms, 100 + WINDOW_SIZE / 2 + 1));
+    }
+}
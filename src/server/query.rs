@@ -0,0 +1,149 @@
+use comemo::Track;
+use serde_json::Value as JsonValue;
+use tower_lsp::lsp_types::Url;
+use typst::diag::EcoString;
+use typst::eval::{eval_string, EvalMode};
+use typst::foundations::{LocatableSelector, Scope};
+use typst::model::Document;
+use typst::syntax::Span;
+use typst::World;
+
+use super::TypstServer;
+
+/// Returned when a query's selector can't be used
+#[derive(Debug, thiserror::Error)]
+#[error("invalid selector: {0}")]
+pub struct InvalidSelector(EcoString);
+
+impl TypstServer {
+    /// Compiles the document and finds the elements matching the selector, like `typst query`
+    pub async fn run_query(
+        &self,
+        uri: &Url,
+        selector: String,
+        field: Option<String>,
+    ) -> anyhow::Result<Vec<JsonValue>> {
+        let (document, _) = self.compile_source(uri).await?;
+        let Some(document) = document else {
+            anyhow::bail!("failed to generate document after compilation");
+        };
+
+        let results = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| query(&world, &document, &selector, field.as_deref()))
+            .await?;
+
+        Ok(results)
+    }
+}
+
+/// Finds the elements in the document matching the selector, which is Typst code like `heading`
+/// or `<label>`. If `field` is given, gives that field of each element instead of the whole
+/// element, skipping elements without it.
+fn query(
+    world: &dyn World,
+    document: &Document,
+    selector: &str,
+    field: Option<&str>,
+) -> Result<Vec<JsonValue>, InvalidSelector> {
+    let selector = eval_string(
+        world.track(),
+        selector,
+        Span::detached(),
+        EvalMode::Code,
+        Scope::default(),
+    )
+    .map_err(|errors| {
+        let message = errors.first().map(|error| error.message.clone());
+        InvalidSelector(message.unwrap_or_default())
+    })?
+    .cast::<LocatableSelector>()
+    .map_err(InvalidSelector)?;
+
+    let elements = document.introspector.query(&selector.0);
+    let results = elements
+        .into_iter()
+        .map(|element| element.into_inner())
+        .filter_map(|element| match field {
+            Some(field) => serde_json::to_value(element.get_by_name(field)?).ok(),
+            None => serde_json::to_value(element).ok(),
+        })
+        .collect();
+
+    Ok(results)
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use comemo::Prehashed;
+    use typst::diag::{FileError, FileResult};
+    use typst::eval::Tracer;
+    use typst::foundations::{Bytes, Datetime};
+    use typst::syntax::{FileId, Source};
+    use typst::text::{Font, FontBook};
+    use typst::Library;
+
+    use crate::workspace::font_manager::FontManager;
+    use crate::workspace::TYPST_STDLIB;
+
+    use super::*;
+
+    /// A world with a single source file and the embedded fonts
+    pub struct SingleFileWorld {
+        pub main: Source,
+        pub fonts: FontManager,
+    }
+
+    impl World for SingleFileWorld {
+        fn library(&self) -> &Prehashed<Library> {
+            &TYPST_STDLIB
+        }
+
+        fn book(&self) -> &Prehashed<FontBook> {
+            self.fonts.book()
+        }
+
+        fn main(&self) -> Source {
+            self.main.clone()
+        }
+
+        fn source(&self, id: FileId) -> FileResult<Source> {
+            if id == self.main.id() {
+                Ok(self.main.clone())
+            } else {
+                Err(FileError::NotFound(id.vpath().as_rootless_path().into()))
+            }
+        }
+
+        fn file(&self, id: FileId) -> FileResult<Bytes> {
+            Err(FileError::NotFound(id.vpath().as_rootless_path().into()))
+        }
+
+        fn font(&self, index: usize) -> Option<Font> {
+            self.fonts.font(index)
+        }
+
+        fn today(&self, _: Option<i64>) -> Option<Datetime> {
+            None
+        }
+    }
+
+    #[test]
+    fn query_headings() {
+        let world = SingleFileWorld {
+            main: Source::detached("= Intro\nHello\n= Usage <usage>\nWorld"),
+            fonts: FontManager::builder().with_embedded().build(),
+        };
+        let document = typst::compile(&world, &mut Tracer::default()).unwrap();
+
+        let headings = query(&world, &document, "heading", None).unwrap();
+        assert_eq!(headings.len(), 2);
+        assert_eq!(headings[0]["func"], "heading");
+
+        let labelled = query(&world, &document, "<usage>", Some("level")).unwrap();
+        assert_eq!(labelled, [JsonValue::from(1)]);
+
+        assert!(query(&world, &document, "1 +", None).is_err());
+    }
+}
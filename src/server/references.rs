@@ -0,0 +1,350 @@
+use tower_lsp::lsp_types::{Location, Url};
+use typst::diag::EcoString;
+use typst::syntax::{is_id_continue, FileId, LinkedNode, Source, SyntaxKind};
+use typst::World;
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+
+use super::definition::{
+    definition_of, ident_at, is_ident, Definition, DefinitionKind, SourceLoader,
+};
+use super::TypstServer;
+
+/// Something which can be referred to by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Symbol {
+    /// A name bound in code. Only identifiers which resolve to the same definition match, so
+    /// shadowing bindings with the same name are told apart.
+    Binding {
+        name: EcoString,
+        definition: Definition,
+    },
+    /// A label, along with its `@` references. Labels are global to a document, so everything with
+    /// the same name matches.
+    Label(EcoString),
+}
+
+/// A place where a symbol is named
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+    pub id: FileId,
+    /// The range of just the name, without the brackets of a label or the `@` of a reference
+    pub range: TypstRange,
+    pub is_declaration: bool,
+}
+
+impl TypstServer {
+    /// Finds everywhere in the workspace the symbol at `position` is named
+    #[tracing::instrument(skip(self))]
+    pub async fn find_references(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+        include_declaration: bool,
+    ) -> anyhow::Result<Vec<Location>> {
+        let position_encoding = self.position_encoding();
+        let (_, full_id) = self.project_and_full_id(uri).await?;
+        let files = self.workspace_sources().await;
+
+        let references = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| {
+                let Ok(source) = world.source(full_id.into()) else {
+                    return Vec::new();
+                };
+                let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+                let Some(symbol) = symbol_at(&world, &source, offset) else {
+                    return Vec::new();
+                };
+
+                files
+                    .into_iter()
+                    .filter_map(|(uri, id)| Some((uri, world.source(id).ok()?)))
+                    .flat_map(|(uri, source)| {
+                        occurrences(&world, &source, &symbol)
+                            .into_iter()
+                            .filter(|occurrence| include_declaration || !occurrence.is_declaration)
+                            .map(|occurrence| Location {
+                                uri: uri.clone(),
+                                range: typst_to_lsp::range(
+                                    occurrence.range,
+                                    &source,
+                                    position_encoding,
+                                )
+                                .raw_range,
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            })
+            .await;
+
+        Ok(references)
+    }
+
+    /// The Typst sources known in the workspace, sorted by URI
+    pub async fn workspace_sources(&self) -> Vec<(Url, FileId)> {
+        let Ok(workspace) = self.read_workspace().await else {
+            return Vec::new();
+        };
+
+        let mut files: Vec<_> = workspace
+            .known_uris()
+            .into_iter()
+            .filter(|uri| uri.path().ends_with(".typ"))
+            .filter_map(|uri| {
+                let full_id = workspace.full_id(&uri).ok()?;
+                Some((uri, full_id.into()))
+            })
+            .collect();
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        files
+    }
+}
+
+/// Gets the symbol at `offset`, which is either an identifier with a known definition, or a label
+/// or reference
+pub fn symbol_at(loader: &dyn SourceLoader, source: &Source, offset: usize) -> Option<Symbol> {
+    let root = LinkedNode::new(source.root());
+
+    if let Some(ident) = ident_at(&root, offset) {
+        let definition = definition_of(loader, source, &ident)?;
+        let name = definition_name(loader, &definition).unwrap_or_else(|| ident.text().clone());
+        return Some(Symbol::Binding { name, definition });
+    }
+
+    [offset, offset + 1]
+        .into_iter()
+        .filter_map(|offset| root.leaf_at(offset))
+        .find_map(|leaf| label_name(&leaf).map(|(name, _)| Symbol::Label(name.into())))
+}
+
+/// The name as written at the definition, which may differ from the name at a use, e.g. because
+/// of `import "file.typ": name as other`
+fn definition_name(loader: &dyn SourceLoader, definition: &Definition) -> Option<EcoString> {
+    if definition.kind == DefinitionKind::Module {
+        return None;
+    }
+
+    let source = loader.load_source(definition.id)?;
+    source.text().get(definition.range.clone()).map(Into::into)
+}
+
+/// If `node` is a label or reference, gets the label's name and the range of the name
+pub fn label_name<'a>(node: &'a LinkedNode) -> Option<(&'a str, TypstRange)> {
+    let range = node.range();
+    let text = node.text().as_str();
+
+    match node.kind() {
+        SyntaxKind::Label => {
+            let name = text.strip_prefix('<')?.strip_suffix('>')?;
+            Some((name, range.start + 1..range.end - 1))
+        }
+        SyntaxKind::RefMarker => Some((text.strip_prefix('@')?, range.start + 1..range.end)),
+        _ => None,
+    }
+}
+
+/// A label attached to content, like `<name>`, or a reference to one, like `@name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelUse {
+    pub name: EcoString,
+    /// The range of the name, without the `<>` or `@`
+    pub range: TypstRange,
+    /// Whether the label is attached here, rather than referenced
+    pub is_label: bool,
+}
+
+/// Every label and reference to one in the source, in order
+pub fn label_uses(source: &Source) -> Vec<LabelUse> {
+    let mut uses = Vec::new();
+    collect_label_uses(&LinkedNode::new(source.root()), &mut uses);
+    uses
+}
+
+fn collect_label_uses(node: &LinkedNode, uses: &mut Vec<LabelUse>) {
+    if let Some((name, range)) = label_name(node) {
+        uses.push(LabelUse {
+            name: name.into(),
+            range,
+            is_label: node.kind() == SyntaxKind::Label,
+        });
+    }
+
+    for child in node.children() {
+        collect_label_uses(&child, uses);
+    }
+}
+
+/// Whether the character can be part of a label's name
+pub fn is_label_char(c: char) -> bool {
+    is_id_continue(c) || matches!(c, ':' | '.')
+}
+
+/// Whether `name` can be written as a label, like `<name>`, and referenced, like `@name`
+pub fn is_label_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_label_char)
+}
+
+/// Finds everywhere in `source` the symbol is named
+pub fn occurrences(loader: &dyn SourceLoader, source: &Source, symbol: &Symbol) -> Vec<Occurrence> {
+    if let Symbol::Label(name) = symbol {
+        return label_uses(source)
+            .into_iter()
+            .filter(|label| label.name == *name)
+            .map(|label| Occurrence {
+                id: source.id(),
+                range: label.range,
+                is_declaration: label.is_label,
+            })
+            .collect();
+    }
+
+    let mut found = Vec::new();
+    collect_occurrences(
+        loader,
+        source,
+        symbol,
+        &LinkedNode::new(source.root()),
+        &mut found,
+    );
+    found
+}
+
+fn collect_occurrences(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    symbol: &Symbol,
+    node: &LinkedNode,
+    found: &mut Vec<Occurrence>,
+) {
+    match symbol {
+        Symbol::Binding { name, definition } if is_ident(node) && node.text() == name => {
+            if definition_of(loader, source, node).as_ref() == Some(definition) {
+                found.push(Occurrence {
+                    id: source.id(),
+                    range: node.range(),
+                    is_declaration: source.id() == definition.id
+                        && node.range() == definition.range,
+                });
+            }
+        }
+        _ => {}
+    }
+
+    for child in node.children() {
+        collect_occurrences(loader, source, symbol, &child, found);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::definition::test::Fixture;
+
+    use super::*;
+
+    /// Finds the occurrences in each of `paths` of the symbol at the `n`th occurrence of `needle`
+    /// in `path`
+    fn references(
+        fixture: &Fixture,
+        (path, needle, n): (&str, &str, usize),
+        paths: &[&str],
+    ) -> Vec<Occurrence> {
+        let source = fixture.source(path);
+        let (offset, _) = source.text().match_indices(needle).nth(n).unwrap();
+        let symbol = symbol_at(fixture, source, offset + 1).unwrap();
+
+        paths
+            .iter()
+            .flat_map(|path| occurrences(fixture, fixture.source(path), &symbol))
+            .collect()
+    }
+
+    #[test]
+    fn label_referenced_in_another_file() {
+        let fixture = Fixture::default()
+            .with("main.typ", "= Intro <intro>\n#include \"chapter.typ\"")
+            .with("chapter.typ", "See @intro for details.");
+
+        let found = references(
+            &fixture,
+            ("chapter.typ", "intro", 0),
+            &["main.typ", "chapter.typ"],
+        );
+
+        assert_eq!(
+            found,
+            vec![
+                Occurrence {
+                    id: Fixture::id("main.typ"),
+                    range: 9..14,
+                    is_declaration: true,
+                },
+                Occurrence {
+                    id: Fixture::id("chapter.typ"),
+                    range: 5..10,
+                    is_declaration: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowed_binding_does_not_match() {
+        let fixture = Fixture::default().with("main.typ", "#let x = 1\n#{ let x = 2; x }\n#x");
+
+        let found = references(&fixture, ("main.typ", "x", 3), &["main.typ"]);
+        let ranges: Vec<_> = found
+            .into_iter()
+            .map(|occurrence| occurrence.range)
+            .collect();
+
+        assert_eq!(ranges, vec![5..6, 30..31]);
+    }
+
+    #[test]
+    fn imported_binding_in_another_file() {
+        let fixture = Fixture::default()
+            .with("main.typ", "#import \"lib.typ\": add\n#add(1, 2)")
+            .with("lib.typ", "#let add(a, b) = a + b");
+
+        let found = references(&fixture, ("lib.typ", "add", 0), &["lib.typ", "main.typ"]);
+        let ranges: Vec<_> = found
+            .into_iter()
+            .map(|occurrence| (occurrence.id, occurrence.range))
+            .collect();
+
+        assert_eq!(
+            ranges,
+            vec![
+                (Fixture::id("lib.typ"), 5..8),
+                (Fixture::id("main.typ"), 19..22),
+                (Fixture::id("main.typ"), 24..27),
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_and_references_are_found() {
+        let source = Source::detached("= Intro <intro>\nSee @intro and @fig:a.\n#figure[] <fig:a>");
+
+        let uses: Vec<_> = label_uses(&source)
+            .into_iter()
+            .map(|label| (label.name, label.is_label))
+            .collect();
+
+        assert_eq!(
+            uses,
+            [
+                ("intro".into(), true),
+                ("intro".into(), false),
+                ("fig:a".into(), false),
+                ("fig:a".into(), true),
+            ]
+        );
+        assert!(is_label_name("fig:a-1.b"));
+        assert!(!is_label_name("a b"));
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use tower_lsp::lsp_types::{PrepareRenameResponse, TextEdit, Url, WorkspaceEdit};
+use typst::diag::EcoString;
+use typst::syntax::{is_ident, LinkedNode, Source};
+use typst::World;
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+
+use super::definition::{ident_at, is_bound_alongside, DefinitionKind, SourceLoader};
+use super::references::{is_label_name, label_name, occurrences, symbol_at, Occurrence, Symbol};
+use super::TypstServer;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RenameError {
+    #[error("nothing to rename here")]
+    NoSymbol,
+    #[error("cannot rename a module")]
+    Module,
+    #[error("cannot rename something defined in a package")]
+    InPackage,
+    #[error("`{0}` is not a valid identifier")]
+    InvalidIdentifier(String),
+    #[error("`{0}` is not a valid label name")]
+    InvalidLabel(String),
+    #[error("`{0}` is already defined in the same scope")]
+    Collision(String),
+    #[error("the label `{0}` already exists")]
+    LabelExists(String),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl RenameError {
+    /// Whether the request can't be fulfilled as asked, as opposed to an internal error
+    pub fn is_invalid_request(&self) -> bool {
+        !matches!(self, Self::Other(_))
+    }
+}
+
+impl TypstServer {
+    /// Gets the range and current name of what would be renamed at `position`
+    #[tracing::instrument(skip(self))]
+    pub async fn prepare_rename(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> Result<PrepareRenameResponse, RenameError> {
+        let position_encoding = self.position_encoding();
+        let (_, full_id) = self
+            .project_and_full_id(uri)
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        self.thread_with_world(uri)
+            .await
+            .map_err(anyhow::Error::from)?
+            .run(move |world| {
+                let source = world
+                    .source(full_id.into())
+                    .map_err(|err| anyhow!("could not read source: {err}"))?;
+                let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+
+                let (range, name) = renameable_at(&world, &source, offset)?;
+                Ok(PrepareRenameResponse::RangeWithPlaceholder {
+                    range: typst_to_lsp::range(range, &source, position_encoding).raw_range,
+                    placeholder: name.to_string(),
+                })
+            })
+            .await
+    }
+
+    /// Renames the symbol at `position` everywhere in the workspace. Labels are renamed along with
+    /// their references.
+    #[tracing::instrument(skip(self))]
+    pub async fn rename(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+        new_name: String,
+    ) -> Result<WorkspaceEdit, RenameError> {
+        let position_encoding = self.position_encoding();
+        let (_, full_id) = self
+            .project_and_full_id(uri)
+            .await
+            .map_err(anyhow::Error::from)?;
+        let files = self.workspace_sources().await;
+
+        self.thread_with_world(uri)
+            .await
+            .map_err(anyhow::Error::from)?
+            .run(move |world| {
+                let source = world
+                    .source(full_id.into())
+                    .map_err(|err| anyhow!("could not read source: {err}"))?;
+                let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+                let symbol = symbol_at(&world, &source, offset).ok_or(RenameError::NoSymbol)?;
+
+                let (uris, sources): (Vec<_>, Vec<_>) = files
+                    .into_iter()
+                    .filter_map(|(uri, id)| Some((uri, world.source(id).ok()?)))
+                    .unzip();
+                let renamed = rename_occurrences(&world, &sources, &symbol, &new_name)?;
+
+                let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+                for (uri, source) in uris.into_iter().zip(&sources) {
+                    let edits: Vec<_> = renamed
+                        .iter()
+                        .filter(|occurrence| occurrence.id == source.id())
+                        .map(|occurrence| TextEdit {
+                            range: typst_to_lsp::range(
+                                occurrence.range.clone(),
+                                source,
+                                position_encoding,
+                            )
+                            .raw_range,
+                            new_text: new_name.clone(),
+                        })
+                        .collect();
+                    if !edits.is_empty() {
+                        changes.insert(uri, edits);
+                    }
+                }
+
+                Ok(WorkspaceEdit {
+                    changes: Some(changes),
+                    ..Default::default()
+                })
+            })
+            .await
+    }
+}
+
+/// Gets the range and name of the renameable symbol at `offset`
+fn renameable_at(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    offset: usize,
+) -> Result<(TypstRange, EcoString), RenameError> {
+    let symbol = symbol_at(loader, source, offset).ok_or(RenameError::NoSymbol)?;
+    check_renameable(&symbol)?;
+
+    let root = LinkedNode::new(source.root());
+    if let Some(ident) = ident_at(&root, offset) {
+        return Ok((ident.range(), ident.text().clone()));
+    }
+
+    [offset, offset + 1]
+        .into_iter()
+        .filter_map(|offset| root.leaf_at(offset))
+        .find_map(|leaf| label_name(&leaf).map(|(name, range)| (range, name.into())))
+        .ok_or(RenameError::NoSymbol)
+}
+
+fn check_renameable(symbol: &Symbol) -> Result<(), RenameError> {
+    match symbol {
+        Symbol::Binding { definition, .. } if definition.kind == DefinitionKind::Module => {
+            Err(RenameError::Module)
+        }
+        Symbol::Binding { definition, .. } if definition.id.package().is_some() => {
+            Err(RenameError::InPackage)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the symbol can be renamed to `new_name`, then finds every occurrence of it in
+/// `sources` which needs to change
+pub fn rename_occurrences(
+    loader: &dyn SourceLoader,
+    sources: &[Source],
+    symbol: &Symbol,
+    new_name: &str,
+) -> Result<Vec<Occurrence>, RenameError> {
+    check_renameable(symbol)?;
+
+    match symbol {
+        Symbol::Binding { definition, .. } => {
+            if !is_ident(new_name) {
+                return Err(RenameError::InvalidIdentifier(new_name.to_owned()));
+            }
+
+            let source = loader
+                .load_source(definition.id)
+                .ok_or_else(|| anyhow!("could not read the file defining the symbol"))?;
+            if is_bound_alongside(loader, &source, definition, new_name) {
+                return Err(RenameError::Collision(new_name.to_owned()));
+            }
+        }
+        Symbol::Label(_) => {
+            if !is_label_name(new_name) {
+                return Err(RenameError::InvalidLabel(new_name.to_owned()));
+            }
+
+            let existing = Symbol::Label(new_name.into());
+            let exists = sources.iter().any(|source| {
+                occurrences(loader, source, &existing)
+                    .iter()
+                    .any(|occurrence| occurrence.is_declaration)
+            });
+            if exists {
+                return Err(RenameError::LabelExists(new_name.to_owned()));
+            }
+        }
+    }
+
+    Ok(sources
+        .iter()
+        .flat_map(|source| occurrences(loader, source, symbol))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::definition::test::Fixture;
+
+    use super::*;
+
+    fn rename(
+        fixture: &Fixture,
+        (path, needle): (&str, &str),
+        paths: &[&str],
+        new_name: &str,
+    ) -> Result<Vec<Occurrence>, RenameError> {
+        let source = fixture.source(path);
+        let offset = source.text().find(needle).unwrap();
+        let symbol = symbol_at(fixture, source, offset + 1).unwrap();
+
+        let sources: Vec<_> = paths
+            .iter()
+            .map(|path| fixture.source(path).clone())
+            .collect();
+        rename_occurrences(fixture, &sources, &symbol, new_name)
+    }
+
+    fn label_fixture() -> Fixture {
+        Fixture::default()
+            .with("main.typ", "= Intro <intro>\n#include \"chapter.typ\"")
+            .with("chapter.typ", "See @intro for details.")
+    }
+
+    #[test]
+    fn label_rename_spans_files() {
+        let fixture = label_fixture();
+
+        let renamed = rename(
+            &fixture,
+            ("main.typ", "intro"),
+            &["main.typ", "chapter.typ"],
+            "start",
+        )
+        .unwrap();
+        let ranges: Vec<_> = renamed
+            .into_iter()
+            .map(|occurrence| (occurrence.id, occurrence.range))
+            .collect();
+
+        assert_eq!(
+            ranges,
+            vec![
+                (Fixture::id("main.typ"), 9..14),
+                (Fixture::id("chapter.typ"), 5..10),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_label_name() {
+        let fixture = label_fixture();
+
+        let result = rename(
+            &fixture,
+            ("chapter.typ", "intro"),
+            &["main.typ", "chapter.typ"],
+            "two words",
+        );
+
+        assert!(matches!(result, Err(RenameError::InvalidLabel(_))));
+    }
+
+    #[test]
+    fn collision_in_same_scope() {
+        let fixture = Fixture::default().with("main.typ", "#let a = 1\n#let b = 2\n#a");
+
+        let result = rename(&fixture, ("main.typ", "a"), &["main.typ"], "b");
+
+        assert!(matches!(result, Err(RenameError::Collision(_))));
+    }
+}
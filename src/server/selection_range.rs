@@ -1,23 +1,39 @@
+use std::iter;
+
 use tower_lsp::lsp_types::SelectionRange;
 use typst::syntax::{LinkedNode, Source};
 
 use crate::config::PositionEncoding;
-use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition};
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
 
 use super::TypstServer;
 
-fn range_for_node(
+/// The ranges of the node and its ancestors, from innermost to outermost. Ancestors covering the
+/// same range as their child, like `Math` as the only child of a node, are skipped, so each range
+/// strictly contains the one before it. For example, a math atom expands to the math inside an
+/// equation, then to the equation including its `$` delimiters.
+fn expanding_ranges(node: &LinkedNode) -> Vec<TypstRange> {
+    let mut ranges: Vec<TypstRange> = Vec::new();
+    for node in iter::successors(Some(node.clone()), |node| node.parent().cloned()) {
+        let range = node.range();
+        if ranges.last() != Some(&range) {
+            ranges.push(range);
+        }
+    }
+    ranges
+}
+
+fn selection_range(
     source: &Source,
     position_encoding: PositionEncoding,
-    node: &LinkedNode,
-) -> SelectionRange {
-    let range = typst_to_lsp::range(node.range(), source, position_encoding);
-    SelectionRange {
-        range: range.raw_range,
-        parent: node
-            .parent()
-            .map(|node| Box::new(range_for_node(source, position_encoding, node))),
-    }
+    ranges: Vec<TypstRange>,
+) -> Option<SelectionRange> {
+    ranges.into_iter().rev().fold(None, |parent, range| {
+        Some(SelectionRange {
+            range: typst_to_lsp::range(range, source, position_encoding).raw_range,
+            parent: parent.map(Box::new),
+        })
+    })
 }
 
 impl TypstServer {
@@ -26,15 +42,45 @@ impl TypstServer {
         source: &Source,
         positions: &[LspPosition],
     ) -> Option<Vec<SelectionRange>> {
-        let position_encoding = self.const_config().position_encoding;
+        let position_encoding = self.position_encoding();
         let mut ranges = Vec::new();
         for &position in positions {
             let typst_offset =
                 lsp_to_typst::position_to_offset(position, position_encoding, source);
             let tree = LinkedNode::new(source.root());
             let leaf = tree.leaf_at(typst_offset)?;
-            ranges.push(range_for_node(source, position_encoding, &leaf));
+            ranges.push(selection_range(
+                source,
+                position_encoding,
+                expanding_ranges(&leaf),
+            )?);
         }
         Some(ranges)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn math_expands_to_equation_delimiters() {
+        let source = Source::detached("Sum $a+b$ here");
+        let root = LinkedNode::new(source.root());
+        let leaf = root.leaf_at(6).unwrap();
+
+        assert_eq!(expanding_ranges(&leaf), [5..6, 5..8, 4..9, 0..14]);
+    }
+
+    #[test]
+    fn selection_ranges_nest_outwards() {
+        let source = Source::detached("$a$");
+        let selection =
+            selection_range(&source, PositionEncoding::Utf16, vec![1..2, 0..3]).unwrap();
+
+        assert_eq!(selection.range.start.character, 1);
+        let parent = selection.parent.unwrap();
+        assert_eq!(parent.range.end.character, 3);
+        assert!(parent.parent.is_none());
+    }
+}
@@ -1,22 +1,151 @@
-use tower_lsp::lsp_types::{SemanticToken, SemanticTokensEdit};
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use siphasher::sip128::{Hasher128, SipHasher13};
+
+use tower_lsp::lsp_types::{SemanticToken, SemanticTokensEdit, Url};
+
+use crate::lsp_typst_boundary::TypstRange;
+use crate::workspace::fs::lsp::SourceEdit;
+
+/// Encoded tokens, each paired with the offset in the source it was computed from
+#[derive(Debug, Clone, Default)]
+pub struct EncodedTokens {
+    pub tokens: Vec<SemanticToken>,
+    pub offsets: Vec<usize>,
+    /// Hash of the text the tokens were computed from, to check that later edits were made to
+    /// that same text
+    pub source_hash: u128,
+}
+
+pub fn text_hash(text: &str) -> u128 {
+    let mut hasher = SipHasher13::new();
+    hasher.write(text.as_bytes());
+    hasher.finish128().as_u128()
+}
 
 #[derive(Debug)]
 struct CachedTokens {
-    tokens: Vec<SemanticToken>,
-    id: u64,
+    uri: Url,
+    tokens: EncodedTokens,
+    id: String,
+}
+
+/// The part of a source which changed since its tokens were last computed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dirty {
+    Range {
+        /// Covers every reparsed range, in the current text
+        range: TypstRange,
+        /// How many bytes longer the current text is than the text the tokens were computed from
+        len_delta: isize,
+        /// Hash of the text the first of the edits was made to
+        since: u128,
+    },
+    All,
+}
+
+impl Dirty {
+    /// Without the hash of the edited text, nothing can be spliced, since the tokens can't be
+    /// checked against it
+    fn from_edit(edit: SourceEdit, since: Option<u128>) -> Self {
+        match (edit, since) {
+            (
+                SourceEdit::Range {
+                    replaced,
+                    inserted_len,
+                    reparsed,
+                },
+                Some(since),
+            ) => Self::Range {
+                range: reparsed,
+                len_delta: inserted_len as isize - replaced.len() as isize,
+                since,
+            },
+            _ => Self::All,
+        }
+    }
+
+    /// Extends this with an edit made to the current text
+    fn merge(self, edit: SourceEdit) -> Self {
+        let (
+            Self::Range {
+                range,
+                len_delta,
+                since,
+            },
+            SourceEdit::Range {
+                replaced,
+                inserted_len,
+                reparsed,
+            },
+        ) = (self, &edit)
+        else {
+            return Self::All;
+        };
+
+        let edit_delta = *inserted_len as isize - replaced.len() as isize;
+        let shift = |offset: usize| (offset as isize + edit_delta) as usize;
+
+        // Map the old dirty range through the edit, expanding it to cover the edit if they overlap
+        let start = if range.start <= replaced.start {
+            range.start
+        } else if range.start >= replaced.end {
+            shift(range.start)
+        } else {
+            replaced.start
+        };
+        let end = if range.end <= replaced.start {
+            range.end
+        } else if range.end >= replaced.end {
+            shift(range.end)
+        } else {
+            replaced.start + inserted_len
+        };
+
+        Self::Range {
+            range: start.min(reparsed.start)..end.max(reparsed.end),
+            len_delta: len_delta + edit_delta,
+            since,
+        }
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct Cache {
     last_sent: Option<CachedTokens>,
-    next_id: u64,
+    /// Changes to each source since its tokens were last computed
+    dirty: HashMap<Url, Dirty>,
 }
 
 impl Cache {
-    pub fn try_take_result(&mut self, id: &str) -> Option<Vec<SemanticToken>> {
-        let id = id.parse::<u64>().ok()?;
+    /// Records edits made to a source, so the next delta only needs to retokenize what changed.
+    /// `before` is the text the edits were made to, if known.
+    pub fn mark_edited(
+        &mut self,
+        uri: &Url,
+        before: Option<&str>,
+        edits: impl IntoIterator<Item = SourceEdit>,
+    ) {
+        let since = before.map(text_hash);
+        for edit in edits {
+            let dirty = match self.dirty.remove(uri) {
+                Some(dirty) => dirty.merge(edit),
+                None => Dirty::from_edit(edit, since),
+            };
+            self.dirty.insert(uri.clone(), dirty);
+        }
+    }
+
+    /// Takes what changed in a source since its tokens were last computed, which is `None` if
+    /// nothing did
+    pub fn take_dirty(&mut self, uri: &Url) -> Option<Dirty> {
+        self.dirty.remove(uri)
+    }
+
+    pub fn try_take_result(&mut self, uri: &Url, id: &str) -> Option<EncodedTokens> {
         match self.last_sent.take() {
-            Some(cached) if cached.id == id => Some(cached.tokens),
+            Some(cached) if cached.id == id && cached.uri == *uri => Some(cached.tokens),
             Some(cached) => {
                 // replace after taking
                 self.last_sent = Some(cached);
@@ -26,18 +155,34 @@ impl Cache {
         }
     }
 
-    pub fn cache_result(&mut self, tokens: Vec<SemanticToken>) -> String {
-        let id = self.get_next_id();
-        let cached = CachedTokens { tokens, id };
+    pub fn cache_result(&mut self, uri: Url, tokens: EncodedTokens) -> String {
+        self.dirty.remove(&uri);
+
+        let id = result_id(&uri, &tokens.tokens);
+        let cached = CachedTokens {
+            uri,
+            tokens,
+            id: id.clone(),
+        };
         self.last_sent = Some(cached);
-        id.to_string()
+        id
     }
+}
 
-    fn get_next_id(&mut self) -> u64 {
-        let id = self.next_id;
-        self.next_id += 1;
-        id
+/// Identifies tokens by their content rather than by a counter. A counter restarts with the
+/// server, so a client could send an ID from the previous session which now names different
+/// tokens, and get a delta against the wrong tokens. Equal IDs always mean equal tokens.
+fn result_id(uri: &Url, tokens: &[SemanticToken]) -> String {
+    let mut hasher = SipHasher13::new();
+    hasher.write(uri.as_str().as_bytes());
+    for token in tokens {
+        hasher.write_u32(token.delta_line);
+        hasher.write_u32(token.delta_start);
+        hasher.write_u32(token.length);
+        hasher.write_u32(token.token_type);
+        hasher.write_u32(token.token_modifiers_bitset);
     }
+    format!("{:032x}", hasher.finish128().as_u128())
 }
 
 pub fn token_delta(from: &[SemanticToken], to: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
@@ -73,3 +218,43 @@ pub fn token_delta(from: &[SemanticToken], to: &[SemanticToken]) -> Vec<Semantic
         }]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokens(lengths: &[u32]) -> EncodedTokens {
+        EncodedTokens {
+            tokens: lengths
+                .iter()
+                .map(|&length| SemanticToken {
+                    delta_line: 1,
+                    length,
+                    ..Default::default()
+                })
+                .collect(),
+            offsets: vec![0; lengths.len()],
+            source_hash: 0,
+        }
+    }
+
+    #[test]
+    fn result_ids_are_stable_across_sessions() {
+        let uri = Url::parse("file:///project/main.typ").unwrap();
+
+        let id = Cache::default().cache_result(uri.clone(), tokens(&[3, 4]));
+        let mut restarted = Cache::default();
+
+        // The restarted server never sent these tokens, so it can't give a delta against them
+        assert!(restarted.try_take_result(&uri, &id).is_none());
+
+        let same_id = restarted.cache_result(uri.clone(), tokens(&[3, 4]));
+        let other_id = restarted.cache_result(uri.clone(), tokens(&[3, 5]));
+        assert_eq!(id, same_id);
+        assert_ne!(id, other_id);
+        assert_eq!(
+            restarted.try_take_result(&uri, &other_id).unwrap().tokens,
+            tokens(&[3, 5]).tokens
+        );
+    }
+}
@@ -1,15 +1,19 @@
-use itertools::Itertools;
 use strum::IntoEnumIterator;
 use tower_lsp::lsp_types::{
-    Registration, SemanticToken, SemanticTokensEdit, SemanticTokensFullOptions,
-    SemanticTokensLegend, SemanticTokensOptions, Unregistration,
+    Position, Registration, SemanticToken, SemanticTokensEdit, SemanticTokensFullOptions,
+    SemanticTokensLegend, SemanticTokensOptions, Unregistration, Url,
 };
 use typst::diag::EcoString;
+use typst::foundations::Value;
 use typst::syntax::{ast, LinkedNode, Source, SyntaxKind};
 
-use self::delta::token_delta;
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspRange, LspRawRange, TypstRange};
+use crate::workspace::TYPST_STDLIB;
+
+use self::delta::{text_hash, token_delta, Dirty, EncodedTokens};
 use self::modifier_set::ModifierSet;
-use self::token_encode::encode_tokens;
+use self::token_encode::{encode_tokens, reencode_token};
 use self::typst_tokens::{Modifier, TokenType};
 
 use super::TypstServer;
@@ -53,52 +57,235 @@ pub fn get_semantic_tokens_options() -> SemanticTokensOptions {
     SemanticTokensOptions {
         legend: get_legend(),
         full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+        range: Some(true),
         ..Default::default()
     }
 }
 
 impl TypstServer {
-    #[tracing::instrument(skip(self))]
-    pub fn get_semantic_tokens_full(&self, source: &Source) -> (Vec<SemanticToken>, String) {
-        let encoding = self.const_config().position_encoding;
-
-        let root = LinkedNode::new(source.root());
+    #[tracing::instrument(skip(self, source))]
+    pub fn get_semantic_tokens_full(
+        &self,
+        source: &Source,
+        uri: &Url,
+    ) -> (Vec<SemanticToken>, String) {
+        let encoding = self.position_encoding();
 
-        let tokens = tokenize_tree(&root, ModifierSet::empty());
-        let encoded_tokens = encode_tokens(tokens, source, encoding);
-        let output_tokens = encoded_tokens.map(|(token, _)| token).collect_vec();
+        let encoded = full_tokens(source, encoding);
+        let output_tokens = encoded.tokens.clone();
 
         let result_id = self
             .semantic_tokens_delta_cache
             .write()
-            .cache_result(output_tokens.clone());
+            .cache_result(uri.clone(), encoded);
 
         (output_tokens, result_id)
     }
 
+    /// Computes tokens only for the part of `source` in `range`. Range results aren't cached, since
+    /// clients only use them until full tokens are ready.
+    #[tracing::instrument(skip(self, source))]
+    pub fn get_semantic_tokens_range(
+        &self,
+        source: &Source,
+        range: LspRawRange,
+    ) -> Vec<SemanticToken> {
+        let encoding = self.position_encoding();
+        let range = lsp_to_typst::range(&LspRange::new(range, encoding), source);
+
+        range_tokens(source, range, encoding).tokens
+    }
+
+    /// Computes the tokens of `source` as edits to a previous result. Only the part of the source
+    /// which changed since then is retokenized; the rest is spliced in from the previous result.
+    #[tracing::instrument(skip(self, source))]
     pub fn try_semantic_tokens_delta_from_result_id(
         &self,
         source: &Source,
+        uri: &Url,
         result_id: &str,
     ) -> (Result<Vec<SemanticTokensEdit>, Vec<SemanticToken>>, String) {
-        let cached = self
+        let encoding = self.position_encoding();
+
+        let (cached, dirty) = {
+            let mut cache = self.semantic_tokens_delta_cache.write();
+            (cache.try_take_result(uri, result_id), cache.take_dirty(uri))
+        };
+
+        let Some(cached) = cached else {
+            let (tokens, result_id) = self.get_semantic_tokens_full(source, uri);
+            return (Err(tokens), result_id);
+        };
+
+        let encoded = match dirty {
+            None => cached.clone(),
+            Some(Dirty::Range {
+                range,
+                len_delta,
+                since,
+            }) => splice_tokens(source, &cached, range, since, len_delta, encoding)
+                .unwrap_or_else(|| full_tokens(source, encoding)),
+            Some(Dirty::All) => full_tokens(source, encoding),
+        };
+        let edits = token_delta(&cached.tokens, &encoded.tokens);
+
+        let result_id = self
             .semantic_tokens_delta_cache
             .write()
-            .try_take_result(result_id);
+            .cache_result(uri.clone(), encoded);
+
+        (Ok(edits), result_id)
+    }
+}
 
-        // this call will overwrite the cache, so need to read from cache first
-        let (tokens, result_id) = self.get_semantic_tokens_full(source);
+fn full_tokens(source: &Source, encoding: PositionEncoding) -> EncodedTokens {
+    let root = LinkedNode::new(source.root());
 
-        match cached {
-            Some(cached) => (Ok(token_delta(&cached, &tokens)), result_id),
-            None => (Err(tokens), result_id),
+    let tokens = tokenize_tree(&root, ModifierSet::empty());
+    let (tokens, offsets) = encode_tokens(tokens, source, encoding, Position::new(0, 0)).unzip();
+
+    EncodedTokens {
+        tokens,
+        offsets,
+        source_hash: text_hash(source.text()),
+    }
+}
+
+/// Tokenizes only nodes overlapping `range`. Tokens are still encoded relative to the start of the
+/// source, so the first token's position is absolute, as clients expect.
+fn range_tokens(source: &Source, range: TypstRange, encoding: PositionEncoding) -> EncodedTokens {
+    let root = LinkedNode::new(source.root());
+
+    let tokens = tokenize_tree_in_range(&root, ModifierSet::empty(), range);
+    let (tokens, offsets) = encode_tokens(tokens, source, encoding, Position::new(0, 0)).unzip();
+
+    EncodedTokens {
+        tokens,
+        offsets,
+        source_hash: text_hash(source.text()),
+    }
+}
+
+/// Past this fraction of the source, splicing saves too little over retokenizing everything
+const MAX_SPLICE_FRACTION: f64 = 0.5;
+
+/// Retokenizes the smallest subtree containing `dirty`, and splices the result into tokens
+/// previously computed for the text before the edits, whose hash is `since`. Returns `None` if the
+/// whole tree would need to be retokenized anyway, or if the cached tokens don't match the text
+/// before the edits, like when an edit was missed.
+///
+/// This relies on tokens being in order of offset and never empty, so the tokens of any subtree are
+/// contiguous and can be found by offset alone.
+fn splice_tokens(
+    source: &Source,
+    cached: &EncodedTokens,
+    dirty: TypstRange,
+    since: u128,
+    len_delta: isize,
+    encoding: PositionEncoding,
+) -> Option<EncodedTokens> {
+    if cached.source_hash != since {
+        return None;
+    }
+
+    // Widen the range so edits at the boundary of a node are attributed to its parent
+    let widened = dirty.start.saturating_sub(1)..(dirty.end + 1).min(source.len_bytes());
+    let node = covering_node(LinkedNode::new(source.root()), &widened);
+    let too_large = node.len() as f64 > source.len_bytes() as f64 * MAX_SPLICE_FRACTION;
+    if node.parent().is_none() || too_large {
+        return None;
+    }
+
+    let start = node.offset();
+    let old_end = usize::try_from(node.range().end as isize - len_delta).ok()?;
+
+    // Ancestors starting at the same offset as `node` have tokens at that offset before it
+    let mut modifiers = ModifierSet::empty();
+    let mut ancestors_at_start = 0;
+    let mut ancestor = node.parent();
+    while let Some(parent) = ancestor {
+        modifiers = modifiers | modifiers_from_node(parent);
+        if parent.offset() == start && tokenize_single_node(parent, modifiers).is_some() {
+            ancestors_at_start += 1;
         }
+        ancestor = parent.parent();
+    }
+
+    let prefix_len = cached.offsets.partition_point(|&offset| offset < start) + ancestors_at_start;
+    let suffix_start = cached.offsets.partition_point(|&offset| offset < old_end);
+    if prefix_len > suffix_start {
+        return None;
+    }
+
+    let mut tokens = cached.tokens[..prefix_len].to_vec();
+    let mut offsets = cached.offsets[..prefix_len].to_vec();
+
+    let position_of = |offset: usize| typst_to_lsp::offset_to_position(offset, encoding, source);
+    let prefix_end = offsets
+        .last()
+        .copied()
+        .map_or(Position::new(0, 0), position_of);
+
+    let middle = encode_tokens(
+        tokenize_tree(&node, modifiers),
+        source,
+        encoding,
+        prefix_end,
+    );
+    for (token, offset) in middle {
+        tokens.push(token);
+        offsets.push(offset);
+    }
+
+    let shift = |offset: usize| (offset as isize + len_delta) as usize;
+    let suffix = cached.tokens[suffix_start..]
+        .iter()
+        .zip(&cached.offsets[suffix_start..]);
+    for (index, (token, &offset)) in suffix.enumerate() {
+        let offset = shift(offset);
+        // Tokens are relative to the previous one, so only the first token after the splice moves
+        let token = if index == 0 {
+            let last_position = offsets
+                .last()
+                .copied()
+                .map_or(Position::new(0, 0), position_of);
+            reencode_token(token, offset, &last_position, source, encoding)
+        } else {
+            *token
+        };
+        tokens.push(token);
+        offsets.push(offset);
     }
+
+    Some(EncodedTokens {
+        tokens,
+        offsets,
+        source_hash: text_hash(source.text()),
+    })
 }
 
+/// Finds the deepest node which contains `range`
+fn covering_node<'a>(node: LinkedNode<'a>, range: &TypstRange) -> LinkedNode<'a> {
+    let child = node.children().find(|child| {
+        let child_range = child.range();
+        child_range.start <= range.start && range.end <= child_range.end
+    });
+
+    match child {
+        Some(child) => covering_node(child, range),
+        None => node,
+    }
+}
+
+/// Tokenizes just the node itself. Empty nodes never produce tokens, since they are invisible.
 fn tokenize_single_node(node: &LinkedNode, modifiers: ModifierSet) -> Option<Token> {
+    if node.is_empty() {
+        return None;
+    }
+
     let is_leaf = node.children().next().is_none();
 
+    let modifiers = modifiers | modifiers_from_token(node);
     token_from_node(node)
         .or_else(|| is_leaf.then_some(TokenType::Text))
         .map(|token_type| Token::new(token_type, modifiers, node))
@@ -118,6 +305,26 @@ fn tokenize_tree<'a>(
     Box::new(token.chain(children))
 }
 
+/// Tokenize a node and its children, skipping any which are entirely outside `range`
+fn tokenize_tree_in_range<'a>(
+    root: &LinkedNode<'a>,
+    parent_modifiers: ModifierSet,
+    range: TypstRange,
+) -> Box<dyn Iterator<Item = Token> + 'a> {
+    let root_range = root.range();
+    if root_range.end <= range.start || range.end <= root_range.start {
+        return Box::new(std::iter::empty());
+    }
+
+    let modifiers = parent_modifiers | modifiers_from_node(root);
+
+    let token = tokenize_single_node(root, modifiers).into_iter();
+    let children = root
+        .children()
+        .flat_map(move |child| tokenize_tree_in_range(&child, modifiers, range.clone()));
+    Box::new(token.chain(children))
+}
+
 pub struct Token {
     pub token_type: TokenType,
     pub modifiers: ModifierSet,
@@ -151,6 +358,55 @@ fn modifiers_from_node(node: &LinkedNode) -> ModifierSet {
     }
 }
 
+/// Determines the [`Modifier`]s to be applied to just the token for a node, not its children
+fn modifiers_from_token(node: &LinkedNode) -> ModifierSet {
+    match node.kind() {
+        SyntaxKind::Ident if is_deprecated_ident(node) => ModifierSet::new(&[Modifier::Deprecated]),
+        _ => ModifierSet::empty(),
+    }
+}
+
+/// Standard library functions which Typst is phasing out. Typst doesn't mark deprecation in the
+/// functions themselves, so they are listed here.
+const DEPRECATED_FUNCTIONS: &[&str] = &["locate", "style"];
+
+/// Whether the identifier refers to a deprecated standard library function. Local bindings
+/// shadowing the function aren't considered.
+fn is_deprecated_ident(ident: &LinkedNode) -> bool {
+    let name = ident.text();
+    DEPRECATED_FUNCTIONS.contains(&name.as_str())
+        && matches!(TYPST_STDLIB.global.scope().get(name), Some(Value::Func(_)))
+        && is_reference(ident)
+}
+
+/// Whether the identifier refers to a value, like the callee in `style(..)`, rather than naming
+/// something, like the argument name in `text(style: "italic")`, the field in `x.style`, or the
+/// binding in `let style = ..`
+fn is_reference(ident: &LinkedNode) -> bool {
+    let Some(parent) = ident.parent() else {
+        return true;
+    };
+    let follows = |kind: SyntaxKind| {
+        std::iter::successors(ident.prev_sibling(), LinkedNode::prev_sibling)
+            .any(|sibling| sibling.kind() == kind)
+    };
+
+    match parent.kind() {
+        // The name comes first, and the value after it
+        SyntaxKind::Named => ident.prev_sibling().is_some(),
+        // The target comes first, and the field after it
+        SyntaxKind::FieldAccess => ident.prev_sibling().is_none(),
+        SyntaxKind::LetBinding => follows(SyntaxKind::Eq),
+        SyntaxKind::Closure => follows(SyntaxKind::Arrow) || follows(SyntaxKind::Eq),
+        SyntaxKind::ForLoop => follows(SyntaxKind::In),
+        SyntaxKind::Params
+        | SyntaxKind::Destructuring
+        | SyntaxKind::ImportItems
+        | SyntaxKind::RenamedImportItem => false,
+        _ => true,
+    }
+}
+
 /// Determines the best [`TokenType`] for an entire node and its children, if any. If there is no
 /// single `TokenType`, or none better than `Text`, returns `None`.
 ///
@@ -175,7 +431,12 @@ fn token_from_node(node: &LinkedNode) -> Option<TokenType> {
         | Semicolon | Colon => Some(TokenType::Punctuation),
         Linebreak | Escape | Shorthand => Some(TokenType::Escape),
         Link => Some(TokenType::Link),
-        Raw => Some(TokenType::Raw),
+        // Only raw blocks with a language tag are split up, so the tag stands out
+        Raw if !has_raw_lang(node) => Some(TokenType::Raw),
+        Raw => Option::None,
+        RawLang => Some(TokenType::RawLang),
+        RawDelim => Some(TokenType::Delimiter),
+        Text | RawTrimmed if node.parent_kind() == Some(Raw) => Some(TokenType::Raw),
         Label => Some(TokenType::Label),
         RefMarker => Some(TokenType::Ref),
         Heading | HeadingMarker => Some(TokenType::Heading),
@@ -198,6 +459,11 @@ fn token_from_node(node: &LinkedNode) -> Option<TokenType> {
     }
 }
 
+fn has_raw_lang(raw: &LinkedNode) -> bool {
+    raw.children()
+        .any(|child| child.kind() == SyntaxKind::RawLang)
+}
+
 // TODO: differentiate also using tokens in scope, not just context
 fn is_function_ident(ident: &LinkedNode) -> bool {
     let Some(next) = ident.next_leaf() else {
@@ -213,8 +479,29 @@ fn is_function_ident(ident: &LinkedNode) -> bool {
     function_call || function_content
 }
 
+/// Whether the identifier is the name of a named argument or parameter. Named pairs in
+/// dictionaries are keys, not parameters, so they aren't included.
+fn is_parameter_name(ident: &LinkedNode) -> bool {
+    let Some(named) = ident
+        .parent()
+        .filter(|parent| parent.kind() == SyntaxKind::Named)
+    else {
+        return false;
+    };
+    let is_name = named
+        .cast::<ast::Named>()
+        .map_or(false, |named| named.name().span() == ident.span());
+    is_name
+        && matches!(
+            named.parent_kind(),
+            Some(SyntaxKind::Args | SyntaxKind::Params)
+        )
+}
+
 fn token_from_ident(ident: &LinkedNode) -> TokenType {
-    if is_function_ident(ident) {
+    if is_parameter_name(ident) {
+        TokenType::Parameter
+    } else if is_function_ident(ident) {
         TokenType::Function
     } else {
         TokenType::Interpolated
@@ -233,3 +520,163 @@ fn token_from_hashtag(hashtag: &LinkedNode) -> Option<TokenType> {
         .as_ref()
         .and_then(token_from_node)
 }
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::lsp_types::SemanticTokenType;
+
+    use super::*;
+
+    /// Edits `text`, then checks that splicing in just the changed tokens gives the same tokens as
+    /// retokenizing everything
+    fn assert_splice_matches_full(text: &str, replace: TypstRange, with: &str) {
+        let encoding = PositionEncoding::Utf16;
+        let mut source = Source::detached(text);
+        let cached = full_tokens(&source, encoding);
+
+        let len_delta = with.len() as isize - replace.len() as isize;
+        let reparsed = source.edit(replace, with);
+
+        let spliced = splice_tokens(
+            &source,
+            &cached,
+            reparsed,
+            cached.source_hash,
+            len_delta,
+            encoding,
+        )
+        .unwrap_or_else(|| full_tokens(&source, encoding));
+        let full = full_tokens(&source, encoding);
+
+        assert_eq!(spliced.tokens, full.tokens);
+        assert_eq!(spliced.offsets, full.offsets);
+    }
+
+    const TEXT: &str =
+        "= Heading\n\nSome *strong* text.\n\n#let x = 1 + 2\n\n#box[Nested _emph_]\n";
+
+    #[test]
+    fn splice_insertion_in_markup() {
+        assert_splice_matches_full(TEXT, 17..17, "very ");
+    }
+
+    #[test]
+    fn splice_deletion_in_code() {
+        assert_splice_matches_full(TEXT, 40..44, "");
+    }
+
+    #[test]
+    fn splice_edit_adding_line() {
+        assert_splice_matches_full(TEXT, 56..56, "\n#x");
+    }
+
+    #[test]
+    fn single_character_edit_gives_small_delta() {
+        let encoding = PositionEncoding::Utf16;
+        let mut source = Source::detached(TEXT);
+        let cached = full_tokens(&source, encoding);
+
+        let reparsed = source.edit(17..17, "x");
+        let spliced =
+            splice_tokens(&source, &cached, reparsed, cached.source_hash, 1, encoding).unwrap();
+        let edits = token_delta(&cached.tokens, &spliced.tokens);
+
+        assert_eq!(edits.len(), 1);
+        let data = edits[0].data.as_ref().unwrap();
+        assert!(data.len() <= 2, "delta replaced {} tokens", data.len());
+    }
+
+    #[test]
+    fn splice_rejects_missed_edits() {
+        let encoding = PositionEncoding::Utf16;
+        let mut source = Source::detached(TEXT);
+        let cached = full_tokens(&source, encoding);
+
+        source.edit(0..0, "missed ");
+        let since = text_hash(source.text());
+        let reparsed = source.edit(30..30, "x");
+
+        assert!(splice_tokens(&source, &cached, reparsed, since, 1, encoding).is_none());
+    }
+
+    #[test]
+    fn splice_rejects_missed_edits_of_same_length() {
+        let encoding = PositionEncoding::Utf16;
+        let mut source = Source::detached(TEXT);
+        let cached = full_tokens(&source, encoding);
+
+        // The length doesn't change, so only the content shows the edit was missed
+        source.edit(11..15, "Many");
+        let since = text_hash(source.text());
+        let reparsed = source.edit(30..30, "x");
+
+        assert!(splice_tokens(&source, &cached, reparsed, since, 1, encoding).is_none());
+    }
+
+    #[test]
+    fn named_argument_is_parameter() {
+        let source = Source::detached("#text(size: 12pt)[x] #let d = (size: 1)");
+        let root = LinkedNode::new(source.root());
+        let token_types: Vec<_> = tokenize_tree(&root, ModifierSet::empty())
+            .filter(|token| token.source == "size")
+            .map(|token| SemanticTokenType::from(token.token_type))
+            .collect();
+
+        assert_eq!(
+            token_types,
+            [SemanticTokenType::PARAMETER, SemanticTokenType::new("pol")]
+        );
+    }
+
+    #[test]
+    fn raw_block_language_is_tokenized() {
+        let source = Source::detached("```rust\nfn main() {}\n```\n`inline`");
+        let root = LinkedNode::new(source.root());
+        let tokens: Vec<_> = tokenize_tree(&root, ModifierSet::empty())
+            .map(|token| {
+                let token_type = SemanticTokenType::from(token.token_type);
+                (token.source.to_string(), token_type.as_str().to_owned())
+            })
+            .collect();
+
+        let token_type_of = |text: &str| {
+            let (_, token_type) = tokens.iter().find(|(source, _)| source == text).unwrap();
+            token_type.as_str()
+        };
+        assert_eq!(token_type_of("```"), "delim");
+        assert_eq!(token_type_of("rust"), "rawlang");
+        assert_eq!(token_type_of("fn main() {}"), "raw");
+        assert_eq!(token_type_of("`inline`"), "raw");
+    }
+
+    #[test]
+    fn deprecated_call_is_marked() {
+        let source = Source::detached("#locate(loc => none) #box[] #text(style: \"italic\")[a]");
+        let root = LinkedNode::new(source.root());
+        let tokens: Vec<_> = tokenize_tree(&root, ModifierSet::empty()).collect();
+
+        let deprecated = Modifier::Deprecated.bitmask();
+        let deprecated_tokens = |name: &str| {
+            tokens
+                .iter()
+                .filter(|token| token.source == name)
+                .filter(|token| token.modifiers.bitset() & deprecated != 0)
+                .count()
+        };
+        assert_eq!(deprecated_tokens("locate"), 1);
+        assert_eq!(deprecated_tokens("box"), 0);
+        assert_eq!(deprecated_tokens("style"), 0);
+    }
+
+    #[test]
+    fn range_tokenizes_only_overlapping_nodes() {
+        let encoding = PositionEncoding::Utf16;
+        let source = Source::detached(TEXT.repeat(500));
+
+        let full = full_tokens(&source, encoding);
+        let range = range_tokens(&source, 0..TEXT.len() * 2, encoding);
+
+        assert!(range.tokens.len() * 100 < full.tokens.len());
+        assert_eq!(range.tokens, full.tokens[..range.tokens.len()]);
+    }
+}
@@ -1,5 +1,4 @@
 use tower_lsp::lsp_types::{Position, SemanticToken};
-use typst::diag::EcoString;
 use typst::syntax::Source;
 
 use crate::config::PositionEncoding;
@@ -8,25 +7,46 @@ use crate::lsp_typst_boundary::typst_to_lsp;
 
 use super::Token;
 
+/// Encodes tokens relative to each other, starting from `start`, which is the position of the
+/// token before the first one, if any. Each encoded token is paired with its offset in `source`.
 pub(super) fn encode_tokens<'a>(
     tokens: impl Iterator<Item = Token> + 'a,
     source: &'a Source,
     encoding: PositionEncoding,
-) -> impl Iterator<Item = (SemanticToken, EcoString)> + 'a {
-    tokens.scan(Position::new(0, 0), move |last_position, token| {
-        let (encoded_token, source_code, position) =
-            encode_token(token, last_position, source, encoding);
+    start: Position,
+) -> impl Iterator<Item = (SemanticToken, usize)> + 'a {
+    tokens.scan(start, move |last_position, token| {
+        let offset = token.offset;
+        let (encoded_token, position) = encode_token(token, last_position, source, encoding);
         *last_position = position;
-        Some((encoded_token, source_code))
+        Some((encoded_token, offset))
     })
 }
 
+/// Re-encodes an already encoded token, now at `offset`, relative to a new previous position
+pub(super) fn reencode_token(
+    token: &SemanticToken,
+    offset: usize,
+    last_position: &Position,
+    source: &Source,
+    encoding: PositionEncoding,
+) -> SemanticToken {
+    let position = typst_to_lsp::offset_to_position(offset, encoding, source);
+    let delta = last_position.delta(&position);
+
+    SemanticToken {
+        delta_line: delta.delta_line,
+        delta_start: delta.delta_start,
+        ..*token
+    }
+}
+
 fn encode_token(
     token: Token,
     last_position: &Position,
     source: &Source,
     encoding: PositionEncoding,
-) -> (SemanticToken, EcoString, Position) {
+) -> (SemanticToken, Position) {
     let position = typst_to_lsp::offset_to_position(token.offset, encoding, source);
     let delta = last_position.delta(&position);
 
@@ -40,5 +60,5 @@ fn encode_token(
         token_modifiers_bitset: token.modifiers.bitset(),
     };
 
-    (lsp_token, token.source, position)
+    (lsp_token, position)
 }
@@ -8,6 +8,7 @@ const PUNCTUATION: SemanticTokenType = SemanticTokenType::new("punct");
 const ESCAPE: SemanticTokenType = SemanticTokenType::new("escape");
 const LINK: SemanticTokenType = SemanticTokenType::new("link");
 const RAW: SemanticTokenType = SemanticTokenType::new("raw");
+const RAW_LANG: SemanticTokenType = SemanticTokenType::new("rawlang");
 const LABEL: SemanticTokenType = SemanticTokenType::new("label");
 const REF: SemanticTokenType = SemanticTokenType::new("ref");
 const HEADING: SemanticTokenType = SemanticTokenType::new("heading");
@@ -31,12 +32,16 @@ pub enum TokenType {
     Number,
     Function,
     Decorator,
+    /// Names of named arguments and parameters, like `size` in `text(size: 12pt)`
+    Parameter,
     // Custom types
     Bool,
     Punctuation,
     Escape,
     Link,
     Raw,
+    /// The language tag of a raw block, like `rust` in ```` ```rust ````
+    RawLang,
     Label,
     Ref,
     Heading,
@@ -65,11 +70,13 @@ impl From<TokenType> for SemanticTokenType {
             Number => Self::NUMBER,
             Function => Self::FUNCTION,
             Decorator => Self::DECORATOR,
+            Parameter => Self::PARAMETER,
             Bool => BOOL,
             Punctuation => PUNCTUATION,
             Escape => ESCAPE,
             Link => LINK,
             Raw => RAW,
+            RawLang => RAW_LANG,
             Label => LABEL,
             Ref => REF,
             Heading => HEADING,
@@ -93,6 +100,7 @@ pub enum Modifier {
     Strong,
     Emph,
     Math,
+    Deprecated,
 }
 
 impl Modifier {
@@ -113,6 +121,7 @@ impl From<Modifier> for SemanticTokenModifier {
             Strong => STRONG,
             Emph => EMPH,
             Math => MATH,
+            Deprecated => Self::DEPRECATED,
         }
     }
 }
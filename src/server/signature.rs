@@ -4,7 +4,8 @@ use tower_lsp::lsp_types::{
     SignatureInformation, Url,
 };
 use tracing::trace;
-use typst::foundations::{Func, ParamInfo, Scopes, Value};
+use typst::diag::EcoString;
+use typst::foundations::{Array, Dict, Func, ParamInfo, Scopes, Str, Type, Value};
 use typst::syntax::{ast, LinkedNode, Source, SyntaxKind};
 
 use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstOffset};
@@ -25,11 +26,8 @@ impl TypstServer {
         };
 
         let signature = self.scope_with_source(uri).await?.run(|source, _| {
-            let typst_offset = lsp_to_typst::position_to_offset(
-                position,
-                self.const_config().position_encoding,
-                source,
-            );
+            let typst_offset =
+                lsp_to_typst::position_to_offset(position, self.position_encoding(), source);
 
             get_signature_info_at_offset(source, typst_offset, &scopes).map(|signature| {
                 SignatureHelp {
@@ -69,13 +67,41 @@ fn get_signature_info_at_offset(
     })
 }
 
+/// Arguments already given to a function with `.with(..)`
+#[derive(Debug, Default)]
+struct BoundArgs {
+    named: Vec<EcoString>,
+    positional: usize,
+}
+
+impl BoundArgs {
+    fn add(&mut self, args: ast::Args) {
+        for arg in args.items() {
+            match arg {
+                ast::Arg::Pos(_) => self.positional += 1,
+                ast::Arg::Named(named) => self.named.push(named.name().get().clone()),
+                // Can't tell what a spread binds
+                ast::Arg::Spread(_) => {}
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-struct ParamInFunction<'a> {
+pub(super) struct ParamInFunction<'a> {
     function: &'a Func,
     param_index: Option<usize>,
 }
 
 impl<'a> ParamInFunction<'a> {
+    /// Describes `function` itself, outside of any call
+    pub fn of_function(function: &'a Func) -> Self {
+        Self {
+            function,
+            param_index: None,
+        }
+    }
+
     #[tracing::instrument(skip(scopes), ret)]
     pub fn at_offset(
         source: &Source,
@@ -86,15 +112,15 @@ impl<'a> ParamInFunction<'a> {
         let leaf = tree.leaf_at(typst_offset)?;
         trace!("got leaf");
 
-        Self::at_leaf(&leaf, scopes)
+        Self::at_leaf(source, &leaf, scopes)
     }
 
-    fn at_leaf(leaf: &LinkedNode, scopes: &'a Scopes) -> Option<Self> {
-        let (ident, args) = Self::surrounding_function_syntax(leaf)?;
-        let function = Self::function_value(scopes, &ident)?;
-        trace!(?function, "got function");
+    fn at_leaf(source: &Source, leaf: &LinkedNode, scopes: &'a Scopes) -> Option<Self> {
+        let (callee, args, is_set) = Self::surrounding_function_syntax(leaf)?;
+        let (function, bound) = Self::function_value(source, scopes, callee)?;
+        trace!(?function, ?bound, "got function");
 
-        let param_index = Self::param_index_at_leaf(leaf, function, args);
+        let param_index = Self::param_index_at_leaf(leaf, function, args, &bound, is_set);
 
         Some(Self {
             function,
@@ -102,17 +128,30 @@ impl<'a> ParamInFunction<'a> {
         })
     }
 
-    fn param_index_at_leaf(leaf: &LinkedNode, function: &Func, args: ast::Args) -> Option<usize> {
+    fn param_index_at_leaf(
+        leaf: &LinkedNode,
+        function: &Func,
+        args: ast::Args,
+        bound: &BoundArgs,
+        is_set: bool,
+    ) -> Option<usize> {
         let deciding = Self::deciding_syntax(leaf);
         let params = function.params()?;
-        let param_index = Self::find_param_index(&deciding, params, args)?;
+        // Parameters bound by `with` can't be given again, and `set` rules only take settable ones
+        let available = |param: &ParamInfo| {
+            !bound.named.iter().any(|name| name == param.name) && (!is_set || param.settable)
+        };
+        let param_index =
+            Self::find_param_index(&deciding, params, args, bound.positional, available)?;
         trace!(param_index, "got param index");
         Some(param_index)
     }
 
+    /// Finds the callee and arguments of the call or `set` rule around `leaf`, and whether it is a
+    /// `set` rule
     fn surrounding_function_syntax<'b>(
         leaf: &'b LinkedNode,
-    ) -> Option<(ast::Ident<'b>, ast::Args<'b>)> {
+    ) -> Option<(ast::Expr<'b>, ast::Args<'b>, bool)> {
         let parent = leaf.parent()?;
         let parent = match parent.kind() {
             SyntaxKind::Named => parent.parent()?,
@@ -121,24 +160,113 @@ impl<'a> ParamInFunction<'a> {
         let args = parent.cast::<ast::Args>()?;
         let grand = parent.parent()?;
         let expr = grand.cast::<ast::Expr>()?;
-        let callee = match expr {
-            ast::Expr::FuncCall(call) => call.callee(),
-            ast::Expr::Set(set) => set.target(),
-            _ => return None,
+        match expr {
+            ast::Expr::FuncCall(call) => Some((call.callee(), args, false)),
+            ast::Expr::Set(set) => Some((set.target(), args, true)),
+            _ => None,
+        }
+    }
+
+    /// Resolves the function being called, along with the arguments already given to it by
+    /// `.with(..)`, either in the callee itself or in the `let` binding defining it
+    fn function_value(
+        source: &Source,
+        scopes: &'a Scopes,
+        callee: ast::Expr,
+    ) -> Option<(&'a Func, BoundArgs)> {
+        match callee {
+            ast::Expr::Ident(ident) => {
+                let Ok(Value::Func(function)) = scopes.get(ident.as_str()) else {
+                    return None;
+                };
+                let mut bound = BoundArgs::default();
+                if let Some(with_args) = Self::let_bound_with_args(source, &ident) {
+                    bound.add(with_args);
+                }
+                Some((function, bound))
+            }
+            ast::Expr::FieldAccess(access) => {
+                let Value::Func(function) =
+                    Self::field_value(scopes, access.target(), access.field().as_str())?
+                else {
+                    return None;
+                };
+                Some((function, BoundArgs::default()))
+            }
+            ast::Expr::FuncCall(call) => {
+                let (target, with_args) = Self::with_call(call)?;
+                let (function, mut bound) = Self::function_value(source, scopes, target)?;
+                bound.add(with_args);
+                Some((function, bound))
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolves `target.field`, which may be a method of the target's type or a definition in a
+    /// module or element function. The target is only known if it is a variable or a literal.
+    fn field_value(scopes: &'a Scopes, target: ast::Expr, field: &str) -> Option<&'a Value> {
+        let value = match target {
+            ast::Expr::Ident(ident) => scopes.get(ident.as_str()).ok()?,
+            ast::Expr::Parenthesized(parenthesized) => {
+                return Self::field_value(scopes, parenthesized.expr(), field)
+            }
+            literal => return Self::literal_type(literal)?.scope().get(field),
         };
-        let callee = match callee {
-            ast::Expr::Ident(callee) => callee,
+
+        match value {
+            Value::Module(module) => module.scope().get(field),
+            Value::Type(ty) => ty.scope().get(field),
+            Value::Func(function) => function
+                .scope()
+                .and_then(|scope| scope.get(field))
+                .or_else(|| value.ty().scope().get(field)),
+            value => value.ty().scope().get(field),
+        }
+    }
+
+    fn literal_type(literal: ast::Expr) -> Option<Type> {
+        let ty = match literal {
+            ast::Expr::Bool(_) => Type::of::<bool>(),
+            ast::Expr::Int(_) => Type::of::<i64>(),
+            ast::Expr::Float(_) => Type::of::<f64>(),
+            ast::Expr::Str(_) => Type::of::<Str>(),
+            ast::Expr::Array(_) => Type::of::<Array>(),
+            ast::Expr::Dict(_) => Type::of::<Dict>(),
             _ => return None,
         };
+        Some(ty)
+    }
 
-        Some((callee, args))
+    /// If `call` is like `f.with(..)`, gets `f` and the arguments given to `with`
+    fn with_call(call: ast::FuncCall) -> Option<(ast::Expr, ast::Args)> {
+        let ast::Expr::FieldAccess(access) = call.callee() else {
+            return None;
+        };
+        (access.field().as_str() == "with").then(|| (access.target(), call.args()))
     }
 
-    fn function_value<'b>(scopes: &'b Scopes, ident: &ast::Ident) -> Option<&'b Func> {
-        match scopes.get(ident.as_str()) {
-            Ok(Value::Func(function)) => Some(function),
-            _ => None,
-        }
+    /// If `ident` is defined by a top-level binding like `#let ident = f.with(..)`, gets the
+    /// arguments given to `with`
+    fn let_bound_with_args<'b>(source: &'b Source, ident: &ast::Ident) -> Option<ast::Args<'b>> {
+        let markup = source.root().cast::<ast::Markup>()?;
+        markup.exprs().find_map(|expr| {
+            let ast::Expr::Let(binding) = expr else {
+                return None;
+            };
+            let ast::LetBindingKind::Normal(ast::Pattern::Normal(ast::Expr::Ident(name))) =
+                binding.kind()
+            else {
+                return None;
+            };
+            if name.as_str() != ident.as_str() {
+                return None;
+            }
+            let ast::Expr::FuncCall(call) = binding.init()? else {
+                return None;
+            };
+            Self::with_call(call).map(|(_, args)| args)
+        })
     }
 
     /// Find the piece of syntax that decides what we're completing.
@@ -160,6 +288,8 @@ impl<'a> ParamInFunction<'a> {
         deciding: &LinkedNode,
         params: &[ParamInfo],
         args: ast::Args,
+        bound_positional: usize,
+        available: impl Fn(&ParamInfo) -> bool,
     ) -> Option<usize> {
         match deciding.kind() {
             // After colon: "func(param:|)", "func(param: |)".
@@ -175,9 +305,9 @@ impl<'a> ParamInFunction<'a> {
                 let next = deciding.next_leaf();
                 let following_param = next.as_ref().and_then(|next| next.cast::<ast::Ident>());
                 match following_param {
-                    Some(next) => params
-                        .iter()
-                        .position(|param| param.named && param.name.starts_with(next.as_str())),
+                    Some(next) => params.iter().position(|param| {
+                        param.named && available(param) && param.name.starts_with(next.as_str())
+                    }),
                     None => {
                         let positional_args_so_far = args
                             .items()
@@ -186,9 +316,9 @@ impl<'a> ParamInFunction<'a> {
                         params
                             .iter()
                             .enumerate()
-                            .filter(|(_, param)| param.positional)
+                            .filter(|(_, param)| param.positional && available(param))
                             .map(|(i, _)| i)
-                            .nth(positional_args_so_far)
+                            .nth(bound_positional + positional_args_so_far)
                     }
                 }
             }
@@ -250,3 +380,57 @@ impl<'a> ParamInFunction<'a> {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::workspace::TYPST_STDLIB;
+
+    use super::*;
+
+    fn active_param(text: &str) -> Option<&'static str> {
+        let mut scopes = Scopes::new(Some(&TYPST_STDLIB));
+        // Stands in for `big` as evaluated, which has the same parameters as `text`
+        let text_func = scopes.get("text").unwrap().clone();
+        scopes.top.define("big", text_func);
+
+        let source = Source::detached(text);
+        let offset = text.rfind("si").unwrap() + 2;
+        let param_in_function = ParamInFunction::at_offset(&source, offset, &scopes)?;
+        let params = param_in_function.function.params()?;
+
+        Some(params[param_in_function.param_index()?].name)
+    }
+
+    #[test]
+    fn named_param_by_prefix() {
+        assert_eq!(active_param("#text(si)"), Some("size"));
+    }
+
+    #[test]
+    fn method_call() {
+        let scopes = Scopes::new(Some(&TYPST_STDLIB));
+        let source = Source::detached("#(\"a\",).push()");
+
+        let param_in_function = ParamInFunction::at_offset(&source, 13, &scopes).unwrap();
+
+        assert_eq!(param_in_function.function_name(), "push");
+        assert_eq!(param_in_function.param_index(), Some(0));
+    }
+
+    #[test]
+    fn unknown_method_receiver() {
+        let scopes = Scopes::new(Some(&TYPST_STDLIB));
+        let source = Source::detached("#unknown.push()");
+
+        assert!(ParamInFunction::at_offset(&source, 14, &scopes).is_none());
+    }
+
+    #[test]
+    fn param_bound_by_with_is_skipped() {
+        assert_eq!(active_param("#text.with(size: 12pt)(si)"), None);
+        assert_eq!(
+            active_param("#let big = text.with(size: 12pt)\n#big(si)"),
+            None
+        );
+    }
+}
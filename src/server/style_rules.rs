@@ -0,0 +1,138 @@
+use std::collections::{HashSet, VecDeque};
+
+use serde::Serialize;
+use tower_lsp::lsp_types::{Range, Url};
+use typst::syntax::{ast, FileId, LinkedNode, Source};
+
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::typst_to_lsp;
+
+use super::TypstServer;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StyleRuleKind {
+    Set,
+    Show,
+}
+
+/// A `set` or `show` rule as written in the source
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StyleRule {
+    pub kind: StyleRuleKind,
+    /// The target of a `set` rule, or the selector of a `show` rule. `None` for `show: ..` rules,
+    /// which apply to everything after them.
+    pub selector: Option<String>,
+    /// The arguments of a `set` rule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+    /// The `if` condition of a `set` rule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    /// The transformation of a `show` rule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transform: Option<String>,
+    pub range: Range,
+}
+
+/// The style rules found in a single file
+#[derive(Debug, Serialize)]
+pub struct FileStyleRules {
+    pub uri: Url,
+    pub rules: Vec<StyleRule>,
+}
+
+impl TypstServer {
+    /// Collects style rules from the syntax of `uri` and every file it imports or includes, without
+    /// compiling, so this works even if the project has errors. Files appear in the order they are
+    /// first reached.
+    #[tracing::instrument(skip(self))]
+    pub async fn list_style_rules(&self, uri: &Url) -> anyhow::Result<Vec<FileStyleRules>> {
+        let position_encoding = self.position_encoding();
+        let (project, full_id) = self.project_and_full_id(uri).await?;
+
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([FileId::from(full_id)]);
+
+        while let Some(id) = queue.pop_front() {
+            if !visited.insert(id) {
+                continue;
+            }
+
+            let Ok(uri) = project.full_id_to_uri(project.fill_id(id)).await else {
+                continue;
+            };
+            let Ok(source) = project.read_source_by_uri(&uri) else {
+                continue;
+            };
+
+            let mut rules = Vec::new();
+            let mut imports = Vec::new();
+            collect_rules(
+                &LinkedNode::new(source.root()),
+                &source,
+                position_encoding,
+                &mut rules,
+                &mut imports,
+            );
+
+            queue.extend(imports.into_iter().map(|path| id.join(&path)));
+            files.push(FileStyleRules { uri, rules });
+        }
+
+        Ok(files)
+    }
+}
+
+fn text_of<'a>(node: impl ast::AstNode<'a>) -> String {
+    node.to_untyped().clone().into_text().to_string()
+}
+
+/// Walks the tree, recording style rules and the paths of local imports and includes
+fn collect_rules(
+    node: &LinkedNode,
+    source: &Source,
+    position_encoding: PositionEncoding,
+    rules: &mut Vec<StyleRule>,
+    imports: &mut Vec<String>,
+) {
+    let range = || typst_to_lsp::range(node.range(), source, position_encoding).raw_range;
+
+    if let Some(set) = node.cast::<ast::SetRule>() {
+        rules.push(StyleRule {
+            kind: StyleRuleKind::Set,
+            selector: Some(text_of(set.target())),
+            arguments: Some(text_of(set.args())),
+            condition: set.condition().map(text_of),
+            transform: None,
+            range: range(),
+        });
+    } else if let Some(show) = node.cast::<ast::ShowRule>() {
+        rules.push(StyleRule {
+            kind: StyleRuleKind::Show,
+            selector: show.selector().map(text_of),
+            arguments: None,
+            condition: None,
+            transform: Some(text_of(show.transform())),
+            range: range(),
+        });
+    } else if let Some(import) = node.cast::<ast::ModuleImport>() {
+        imports.extend(local_path(import.source()));
+    } else if let Some(include) = node.cast::<ast::ModuleInclude>() {
+        imports.extend(local_path(include.source()));
+    }
+
+    for child in node.children() {
+        collect_rules(&child, source, position_encoding, rules, imports);
+    }
+}
+
+/// The path of an import or include, if it is a string literal naming a file rather than a package
+fn local_path(source: ast::Expr) -> Option<String> {
+    match source {
+        ast::Expr::Str(path) if !path.get().starts_with('@') => Some(path.get().to_string()),
+        _ => None,
+    }
+}
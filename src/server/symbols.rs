@@ -1,12 +1,19 @@
+use std::sync::Arc;
+
 use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use itertools::Itertools;
 use tower_lsp::lsp_types::*;
+use tracing::{trace, warn};
 use typst::syntax::{ast, LinkedNode, Source, SyntaxKind};
 
-use crate::{config::PositionEncoding, lsp_typst_boundary::typst_to_lsp};
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::{typst_to_lsp, TypstRange};
 
 use super::TypstServer;
 
-/// Get all symbols for a node recursively.
+/// Get all symbols for a node recursively. This descends into every child, including code blocks
+/// like `#{ ... }`, so definitions wrapped in a block are found just like top-level ones.
 pub fn get_symbols<'a>(
     node: LinkedNode<'a>,
     source: &'a Source,
@@ -14,13 +21,97 @@ pub fn get_symbols<'a>(
     query_string: Option<&'a str>,
     position_encoding: PositionEncoding,
 ) -> Box<dyn Iterator<Item = Result<SymbolInformation>> + 'a> {
-    let own_symbol = get_ident(&node, source, uri, query_string, position_encoding).transpose();
-    let children_symbols = node
-        .children()
-        .flat_map(move |child| get_symbols(child, source, uri, query_string, position_encoding));
+    let mut sections = Vec::new();
+    collect_sections(&LinkedNode::new(source.root()), &mut sections);
+
+    let symbols = symbols_in(
+        node,
+        source,
+        uri,
+        query_string,
+        position_encoding,
+        &sections,
+    )
+    .collect_vec();
+    Box::new(symbols.into_iter())
+}
+
+fn symbols_in<'a>(
+    node: LinkedNode<'a>,
+    source: &'a Source,
+    uri: &'a Url,
+    query_string: Option<&'a str>,
+    position_encoding: PositionEncoding,
+    sections: &'a [Section],
+) -> Box<dyn Iterator<Item = Result<SymbolInformation>> + 'a> {
+    let own_symbol = get_ident(
+        &node,
+        source,
+        uri,
+        query_string,
+        position_encoding,
+        sections,
+    )
+    .transpose();
+    let children_symbols = node.children().flat_map(move |child| {
+        symbols_in(
+            child,
+            source,
+            uri,
+            query_string,
+            position_encoding,
+            sections,
+        )
+    });
     Box::new(children_symbols.chain(own_symbol))
 }
 
+/// A heading, which contains everything after it up to the next heading of the same or a higher
+/// level
+#[derive(Debug)]
+struct Section {
+    /// The range of the heading itself
+    range: TypstRange,
+    level: usize,
+    name: String,
+}
+
+impl Section {
+    /// Where the section ends: before the next heading of the same or a higher level, or at the
+    /// end of the source, without trailing whitespace
+    fn end(&self, following: &[Section], source: &Source) -> usize {
+        let start = self.range.start;
+        let end = following
+            .iter()
+            .find(|section| section.level <= self.level)
+            .map_or(source.text().len(), |section| section.range.start);
+        start + source.text()[start..end].trim_end().len()
+    }
+}
+
+fn collect_sections(node: &LinkedNode, sections: &mut Vec<Section>) {
+    if let Some(heading) = node.cast::<ast::Heading>() {
+        sections.push(Section {
+            range: node.range(),
+            level: heading.depth().get(),
+            name: heading.body().to_untyped().clone().into_text().to_string(),
+        });
+    }
+
+    for child in node.children() {
+        collect_sections(&child, sections);
+    }
+}
+
+/// The name of the heading closest before `offset` with a level below `max_level`
+fn container_name(sections: &[Section], offset: usize, max_level: usize) -> Option<String> {
+    sections
+        .iter()
+        .rev()
+        .find(|section| section.range.start < offset && section.level < max_level)
+        .map(|section| section.name.clone())
+}
+
 /// Get symbol for a leaf node of a valid type, or `None` if the node is an invalid type.
 #[allow(deprecated)]
 fn get_ident(
@@ -29,6 +120,7 @@ fn get_ident(
     uri: &Url,
     query_string: Option<&str>,
     position_encoding: PositionEncoding,
+    sections: &[Section],
 ) -> Result<Option<SymbolInformation>> {
     match node.kind() {
         SyntaxKind::Label => {
@@ -50,7 +142,7 @@ fn get_ident(
                     uri: uri.clone(),
                     range: typst_to_lsp::range(node.range(), source, position_encoding).raw_range,
                 },
-                container_name: None,
+                container_name: container_name(sections, node.offset(), usize::MAX),
             };
             Ok(Some(symbol))
         }
@@ -91,7 +183,7 @@ fn get_ident(
                     uri: uri.clone(),
                     range: typst_to_lsp::range(node.range(), source, position_encoding).raw_range,
                 },
-                container_name: None,
+                container_name: container_name(sections, node.offset(), usize::MAX),
             };
             Ok(Some(symbol))
         }
@@ -108,10 +200,11 @@ fn get_ident(
             let Some(parent) = node.parent() else {
                 return Ok(None);
             };
-            let kind = match parent.kind() {
-                SyntaxKind::Heading => SymbolKind::NAMESPACE,
-                _ => return Ok(None),
+            let Some(heading) = parent.cast::<ast::Heading>() else {
+                return Ok(None);
             };
+            let kind = SymbolKind::NAMESPACE;
+            let level = heading.depth().get();
             let symbol = SymbolInformation {
                 name,
                 kind,
@@ -121,7 +214,7 @@ fn get_ident(
                     uri: uri.clone(),
                     range: typst_to_lsp::range(node.range(), source, position_encoding).raw_range,
                 },
-                container_name: None,
+                container_name: container_name(sections, parent.offset(), level),
             };
             Ok(Some(symbol))
         }
@@ -129,6 +222,90 @@ fn get_ident(
     }
 }
 
+/// Gets symbols as a tree, where each heading contains its subheadings and the symbols in its
+/// section
+#[allow(deprecated)]
+pub fn get_nested_symbols(
+    source: &Source,
+    uri: &Url,
+    position_encoding: PositionEncoding,
+) -> Result<Vec<DocumentSymbol>> {
+    let root = LinkedNode::new(source.root());
+    let lsp_range = |range| typst_to_lsp::range(range, source, position_encoding).raw_range;
+
+    let mut sections = Vec::new();
+    collect_sections(&root, &mut sections);
+    sections.retain(|section| !section.name.is_empty());
+
+    // Headings are taken from their sections, which know where they end
+    let headings = sections.iter().enumerate().map(|(index, section)| {
+        let symbol = DocumentSymbol {
+            name: section.name.clone(),
+            detail: None,
+            kind: SymbolKind::NAMESPACE,
+            tags: None,
+            deprecated: None, // do not use, deprecated, use `tags` instead
+            range: lsp_range(section.range.start..section.end(&sections[index + 1..], source)),
+            selection_range: lsp_range(section.range.clone()),
+            children: None,
+        };
+        (Some(section.level), symbol)
+    });
+    let others: Vec<_> = get_symbols(root, source, uri, None, position_encoding)
+        .filter_ok(|symbol| symbol.kind != SymbolKind::NAMESPACE)
+        .map_ok(|symbol| {
+            let symbol = DocumentSymbol {
+                name: symbol.name,
+                detail: None,
+                kind: symbol.kind,
+                tags: None,
+                deprecated: None, // do not use, deprecated, use `tags` instead
+                range: symbol.location.range,
+                selection_range: symbol.location.range,
+                children: None,
+            };
+            (None, symbol)
+        })
+        .try_collect()?;
+
+    let items = headings
+        .chain(others)
+        .sorted_by_key(|(_, symbol)| symbol.range.start);
+
+    let mut roots = Vec::new();
+    let mut stack: Vec<(usize, DocumentSymbol)> = Vec::new();
+    for (level, symbol) in items {
+        match level {
+            Some(level) => {
+                while stack.last().is_some_and(|(open, _)| *open >= level) {
+                    let (_, done) = stack.pop().unwrap();
+                    attach(&mut stack, &mut roots, done);
+                }
+                stack.push((level, symbol));
+            }
+            None => attach(&mut stack, &mut roots, symbol),
+        }
+    }
+
+    while let Some((_, done)) = stack.pop() {
+        attach(&mut stack, &mut roots, done);
+    }
+
+    Ok(roots)
+}
+
+/// Adds `symbol` as a child of the innermost open symbol, or as a root if there is none
+fn attach(
+    stack: &mut [(usize, DocumentSymbol)],
+    roots: &mut Vec<DocumentSymbol>,
+    symbol: DocumentSymbol,
+) {
+    match stack.last_mut() {
+        Some((_, parent)) => parent.children.get_or_insert_with(Vec::new).push(symbol),
+        None => roots.push(symbol),
+    }
+}
+
 impl TypstServer {
     pub fn document_symbols<'a>(
         &'a self,
@@ -136,15 +313,117 @@ impl TypstServer {
         uri: &'a Url,
         query_string: Option<&'a str>,
     ) -> impl Iterator<Item = Result<SymbolInformation>> + 'a {
-        let const_config = self.const_config();
+        let position_encoding = self.position_encoding();
 
         let root = LinkedNode::new(source.root());
-        get_symbols(
-            root,
-            source,
-            uri,
-            query_string,
-            const_config.position_encoding,
-        )
+        get_symbols(root, source, uri, query_string, position_encoding)
+    }
+
+    /// Gets symbols from every known source in the workspace, reading sources concurrently
+    pub async fn workspace_symbols(
+        &self,
+        query_string: Option<&str>,
+    ) -> Result<Vec<SymbolInformation>> {
+        let workspace = Arc::new(self.read_workspace_owned().await?);
+
+        let uris = workspace.known_uris();
+        trace!(?uris, "getting sources for these URIs");
+
+        let reads = uris.into_iter().map(|uri| {
+            let workspace = Arc::clone(&workspace);
+            tokio::task::spawn_blocking(move || {
+                workspace.read_source(&uri).map(|source| (uri, source))
+            })
+        });
+        let uris_sources = join_all(reads)
+            .await
+            .into_iter()
+            .filter_map(|read| match read {
+                Ok(Ok(uri_source)) => Some(uri_source),
+                Ok(Err(err)) => {
+                    warn!(%err, "could not read source");
+                    None
+                }
+                Err(err) => {
+                    warn!(%err, "could not read source");
+                    None
+                }
+            })
+            .collect_vec();
+
+        let symbols: Vec<_> = uris_sources
+            .iter()
+            .flat_map(|(uri, source)| self.document_symbols(source, uri, query_string))
+            .try_collect()?;
+
+        // The same source may be known under several URIs which refer to the same file
+        Ok(symbols
+            .into_iter()
+            .unique_by(|symbol| (symbol.name.clone(), symbol.location.clone()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn symbols_of(text: &str) -> Vec<SymbolInformation> {
+        let source = Source::detached(text);
+        let uri = Url::parse("file:///test.typ").unwrap();
+        let root = LinkedNode::new(source.root());
+        get_symbols(root, &source, &uri, None, PositionEncoding::Utf16)
+            .try_collect()
+            .unwrap()
+    }
+
+    #[test]
+    fn function_in_code_block() {
+        let symbols = symbols_of("#{\n  let add(a, b) = a + b\n  let total = 1\n}\n");
+
+        let names_kinds = symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind))
+            .collect_vec();
+
+        assert!(names_kinds.contains(&("add", SymbolKind::FUNCTION)));
+        assert!(names_kinds.contains(&("total", SymbolKind::VARIABLE)));
+    }
+
+    #[test]
+    fn let_in_section_has_section_as_container() {
+        let symbols = symbols_of("= Intro\n== Setup\n#let x = 1\n= Outro\n<end>");
+
+        let containers = symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.container_name.as_deref()))
+            .collect_vec();
+
+        assert!(containers.contains(&("Intro", None)));
+        assert!(containers.contains(&("Setup", Some("Intro"))));
+        assert!(containers.contains(&("x", Some("Setup"))));
+        assert!(containers.contains(&("Outro", None)));
+        assert!(containers.contains(&("end", Some("Outro"))));
+    }
+
+    #[test]
+    fn nested_headings() {
+        let source = Source::detached("= A\n== B\n#let x = 1\n\n= C\n");
+        let uri = Url::parse("file:///test.typ").unwrap();
+
+        let symbols = get_nested_symbols(&source, &uri, PositionEncoding::Utf16).unwrap();
+
+        let names = |symbols: &[DocumentSymbol]| {
+            symbols
+                .iter()
+                .map(|symbol| symbol.name.clone())
+                .collect_vec()
+        };
+        assert_eq!(names(&symbols), ["A", "C"]);
+        let b = symbols[0].children.as_deref().unwrap();
+        assert_eq!(names(b), ["B"]);
+        let x = b[0].children.as_deref().unwrap();
+        assert_eq!(names(x), ["x"]);
+        assert_eq!(symbols[0].range.end, Position::new(2, 10));
     }
 }
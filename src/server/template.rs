@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use itertools::Itertools;
+use serde::Serialize;
+use tower_lsp::lsp_types::Url;
+use typst::syntax::package::{PackageManifest, PackageSpec};
+use typst::syntax::VirtualPath;
+use walkdir::WalkDir;
+
+use crate::workspace::package::PackageId;
+
+use super::TypstServer;
+
+/// A file which instantiating a template would create
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateFile {
+    /// Path relative to the new project's root, separated by `/`
+    pub path: String,
+    /// Where the file is copied from
+    pub uri: Url,
+    /// The file's contents, if it is UTF-8 text. Other files, like images, should be copied from
+    /// `uri`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// The files a template would create, for the client to confirm and write
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateScaffold {
+    pub package: String,
+    /// Path of the file to compile, relative to the new project's root
+    pub entrypoint: String,
+    pub files: Vec<TemplateFile>,
+}
+
+impl TypstServer {
+    /// Resolves a template package and lists the files it would create, like `typst init`, but
+    /// without writing anything
+    #[tracing::instrument(skip(self))]
+    pub async fn instantiate_template(
+        &self,
+        spec: &PackageSpec,
+    ) -> anyhow::Result<TemplateScaffold> {
+        let workspace = self.read_workspace().await?;
+        let package = workspace
+            .package_manager()
+            .package(PackageId::new_external(spec.clone()))
+            .await?;
+
+        let manifest_uri = package.vpath_to_uri(&VirtualPath::new("typst.toml"))?;
+        let manifest = workspace.read_bytes(&manifest_uri)?;
+        let manifest: PackageManifest = toml::from_str(std::str::from_utf8(&manifest)?)
+            .with_context(|| format!("could not parse manifest of {spec}"))?;
+        manifest.validate(spec).map_err(|err| anyhow!(err))?;
+
+        let Some(template) = manifest.template else {
+            bail!("package {spec} is not a template");
+        };
+
+        let template_uri = package.vpath_to_uri(&VirtualPath::new(template.path.as_str()))?;
+        let template_dir = template_uri
+            .to_file_path()
+            .map_err(|()| anyhow!("template directory {template_uri} is not a local path"))?;
+
+        let files = WalkDir::new(&template_dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_ok(|entry| entry.file_type().is_file())
+            .map(|entry| template_file(&template_dir, entry?.path()))
+            .try_collect()?;
+
+        Ok(TemplateScaffold {
+            package: spec.to_string(),
+            entrypoint: template.entrypoint.to_string(),
+            files,
+        })
+    }
+}
+
+fn template_file(template_dir: &Path, path: &Path) -> anyhow::Result<TemplateFile> {
+    let relative = path.strip_prefix(template_dir)?;
+    let uri = Url::from_file_path(path)
+        .map_err(|()| anyhow!("could not convert {} to URI", path.display()))?;
+    let text = String::from_utf8(std::fs::read(path)?).ok();
+
+    Ok(TemplateFile {
+        path: relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .join("/"),
+        uri,
+        text,
+    })
+}
@@ -0,0 +1,177 @@
+use tower_lsp::lsp_types::{Location, Url};
+use typst::foundations::Value;
+use typst::syntax::{ast, LinkedNode, Source, Span};
+use typst::World;
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+
+use super::definition::{definition_of, find_definition, ident_at, Definition, SourceLoader};
+use super::TypstServer;
+
+impl TypstServer {
+    /// Finds where the type of the value bound to the identifier at `position` is defined. For
+    /// values made by calling a function, this is the function, and for aliases, this is what they
+    /// alias. Otherwise, this is where the identifier is bound. Names with no binding in the
+    /// source, like those from a wildcard import, are looked up in the evaluated module, where
+    /// functions remember where they were defined.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_type_definition(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Location>> {
+        let position_encoding = self.position_encoding();
+        let (project, full_id) = self.project_and_full_id(uri).await?;
+
+        let source = self
+            .scope_with_source(uri)
+            .await?
+            .run(|source, _| source.clone());
+        let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+        let root = LinkedNode::new(source.root());
+        let Some(ident) = ident_at(&root, offset) else {
+            return Ok(None);
+        };
+
+        // Bindings in the source come first, so a local binding shadowing a top-level one with
+        // the same name isn't mistaken for it
+        let definition = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| {
+                let source = world.source(full_id.into()).ok()?;
+                find_type_definition(&world, &source, offset)
+            })
+            .await;
+        if let Some(definition) = definition {
+            let uri = project
+                .full_id_to_uri(project.fill_id(definition.id))
+                .await?;
+            let source = project.read_source_by_uri(&uri)?;
+            let range = typst_to_lsp::range(definition.range, &source, position_encoding).raw_range;
+            return Ok(Some(Location { uri, range }));
+        }
+
+        let Some(module) = self.eval_source(uri).await?.0 else {
+            return Ok(None);
+        };
+        let Some(Value::Func(func)) = module.scope().get(ident.text()) else {
+            return Ok(None);
+        };
+        let span = func.span();
+        let Some(id) = span.id() else {
+            return Ok(None);
+        };
+
+        let uri = project.full_id_to_uri(project.fill_id(id)).await?;
+        let source = project.read_source_by_uri(&uri)?;
+        let location = function_name_range(&source, span).map(|range| Location {
+            uri,
+            range: typst_to_lsp::range(range, &source, position_encoding).raw_range,
+        });
+        Ok(location)
+    }
+}
+
+/// The range of the name of the function defined at `span`, or of the whole definition if the
+/// function has no name
+fn function_name_range(source: &Source, span: Span) -> Option<TypstRange> {
+    let node = source.find(span)?;
+    let closure = std::iter::successors(Some(node.clone()), |node| node.parent().cloned())
+        .find_map(|node| node.cast::<ast::Closure>());
+
+    match closure.and_then(|closure| closure.name()) {
+        Some(name) => source.range(name.span()),
+        None => Some(node.range()),
+    }
+}
+
+/// Finds the type definition of the identifier at `offset` without evaluating anything. A binding
+/// like `let alias = other` or `let value = make(..)` resolves to the definition of `other` or
+/// `make`, and other bindings resolve to themselves.
+fn find_type_definition(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    offset: usize,
+) -> Option<Definition> {
+    let binding = find_definition(loader, source, offset)?;
+    let init = loader
+        .load_source(binding.id)
+        .and_then(|binding_source| init_definition(loader, &binding_source, &binding));
+    Some(init.unwrap_or(binding))
+}
+
+fn init_definition(
+    loader: &dyn SourceLoader,
+    source: &Source,
+    binding: &Definition,
+) -> Option<Definition> {
+    let root = LinkedNode::new(source.root());
+    let name = ident_at(&root, binding.range.start)?;
+    let init = name.parent()?.cast::<ast::LetBinding>()?.init()?;
+
+    let target = match init {
+        ast::Expr::Ident(ident) => ident,
+        ast::Expr::FuncCall(call) => match call.callee() {
+            ast::Expr::Ident(ident) => ident,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let target = root.find(target.span())?;
+    definition_of(loader, source, &target)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::definition::test::Fixture;
+
+    use super::*;
+
+    #[test]
+    fn alias_of_user_function() {
+        let fixture = Fixture::default().with(
+            "main.typ",
+            "#let double(x) = x * 2\n#let g = double\n#let y = double(2)\n#g #y",
+        );
+        let source = fixture.source("main.typ");
+        let text = source.text();
+
+        let alias = find_type_definition(&fixture, source, text.rfind('g').unwrap()).unwrap();
+        assert_eq!(alias.range, 5..11);
+
+        let call = find_type_definition(&fixture, source, text.rfind('y').unwrap()).unwrap();
+        assert_eq!(call.range, 5..11);
+    }
+
+    #[test]
+    fn local_binding_shadows_top_level() {
+        let fixture = Fixture::default().with(
+            "main.typ",
+            "#let f(x) = x\n#let g(f) = {\n  let h = f\n  h\n}",
+        );
+        let source = fixture.source("main.typ");
+        let text = source.text();
+
+        let shadowed = find_type_definition(&fixture, source, text.rfind('h').unwrap()).unwrap();
+        let parameter = text.find("g(f)").unwrap() + 2;
+        assert_eq!(shadowed.range, parameter..parameter + 1);
+    }
+
+    #[test]
+    fn closure_span_resolves_to_name() {
+        let source = Source::detached("#let double(x) = x * 2");
+        let closure = LinkedNode::new(source.root())
+            .leaf_at(12)
+            .unwrap()
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .clone();
+        assert!(closure.is::<ast::Closure>());
+
+        assert_eq!(function_name_range(&source, closure.span()), Some(5..11));
+    }
+}
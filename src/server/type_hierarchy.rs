@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{SymbolKind, TypeHierarchyItem, Url};
+use typst::diag::EcoString;
+use typst::foundations::{Scope, Value};
+use typst::syntax::{ast, LinkedNode, Source, SyntaxKind};
+use typst::World;
+
+use crate::config::PositionEncoding;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange};
+
+use super::definition::ident_at;
+use super::TypstServer;
+
+/// Methods which narrow a selector, like `where` in `heading.where(level: 1)`, so a selector using
+/// them still selects the element they are called on
+const SELECTOR_METHODS: &[&str] = &["where", "or", "and", "before", "after"];
+
+/// What a type hierarchy item stands for, kept in its `data` so the supertypes and subtypes
+/// requests don't need to find it again
+#[derive(Debug, Serialize, Deserialize)]
+struct TypeHierarchyData {
+    /// The element function, like `heading` or `math.equation`
+    element: EcoString,
+    /// Whether the item is a `show` rule customizing the element, rather than the element itself
+    rule: bool,
+}
+
+/// A `show` rule whose selector names an element
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShowRule {
+    pub element: EcoString,
+    /// The range of the whole rule
+    pub range: TypstRange,
+    /// The range of the element in the selector
+    pub element_range: TypstRange,
+}
+
+impl TypstServer {
+    /// Finds the element named at `position`, either in a `show` rule's selector or anywhere else
+    /// it is used, to start a type hierarchy from
+    #[tracing::instrument(skip(self))]
+    pub async fn prepare_element_hierarchy(
+        &self,
+        uri: &Url,
+        position: LspPosition,
+    ) -> anyhow::Result<Option<Vec<TypeHierarchyItem>>> {
+        let position_encoding = self.position_encoding();
+        let source = self
+            .scope_with_source(uri)
+            .await?
+            .run(|source, _| source.clone());
+
+        let offset = lsp_to_typst::position_to_offset(position, position_encoding, &source);
+        let Some((element, range)) = element_at(&source, offset) else {
+            return Ok(None);
+        };
+
+        let name = element.clone();
+        let is_element = self
+            .thread_with_world(uri)
+            .await?
+            .run(move |world| is_element(world.library().global.scope(), &name))
+            .await;
+        if !is_element {
+            return Ok(None);
+        }
+
+        let item = element_item(uri.clone(), element, range, &source, position_encoding);
+        Ok(Some(vec![item]))
+    }
+
+    /// The supertype of a `show` rule is the element it customizes. Elements have no supertypes.
+    pub fn get_element_supertypes(&self, item: TypeHierarchyItem) -> Vec<TypeHierarchyItem> {
+        let Some(data) = item_data(&item).filter(|data| data.rule) else {
+            return Vec::new();
+        };
+
+        // Builtin elements have no source, so point to where the rule names the element
+        vec![TypeHierarchyItem {
+            name: data.element.to_string(),
+            kind: SymbolKind::CLASS,
+            tags: None,
+            detail: Some("element".to_owned()),
+            uri: item.uri,
+            range: item.selection_range,
+            selection_range: item.selection_range,
+            data: serde_json::to_value(TypeHierarchyData {
+                element: data.element,
+                rule: false,
+            })
+            .ok(),
+        }]
+    }
+
+    /// The subtypes of an element are the `show` rules across the workspace which customize it.
+    /// Rules have no subtypes.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_element_subtypes(&self, item: TypeHierarchyItem) -> Vec<TypeHierarchyItem> {
+        let Some(data) = item_data(&item).filter(|data| !data.rule) else {
+            return Vec::new();
+        };
+
+        let position_encoding = self.position_encoding();
+
+        let mut subtypes = Vec::new();
+        for (uri, _) in self.workspace_sources().await {
+            let Ok(scope) = self.scope_with_source(&uri).await else {
+                continue;
+            };
+            let items = scope.run(|source, _| {
+                show_rules(source, &data.element)
+                    .into_iter()
+                    .map(|rule| rule_item(uri.clone(), rule, source, position_encoding))
+                    .collect::<Vec<_>>()
+            });
+            subtypes.extend(items);
+        }
+
+        subtypes
+    }
+}
+
+/// Finds the `show` rules in the source whose selector names the element, like `show heading: ..`
+/// or `show heading.where(level: 1): ..` for `heading`
+pub fn show_rules(source: &Source, element: &str) -> Vec<ShowRule> {
+    let mut rules = Vec::new();
+    collect_show_rules(&LinkedNode::new(source.root()), source, &mut rules);
+    rules.retain(|rule| rule.element == element);
+    rules
+}
+
+fn collect_show_rules(node: &LinkedNode, source: &Source, rules: &mut Vec<ShowRule>) {
+    if let Some(rule) = node.cast::<ast::ShowRule>() {
+        let selected = rule
+            .selector()
+            .and_then(|selector| selector_element(source, selector));
+        if let Some((element, element_range)) = selected {
+            rules.push(ShowRule {
+                element,
+                range: node.range(),
+                element_range,
+            });
+        }
+    }
+
+    for child in node.children() {
+        collect_show_rules(&child, source, rules);
+    }
+}
+
+/// The element a selector selects, along with where it is named. Selectors which aren't elements,
+/// like strings, labels, and regular expressions, give `None`.
+fn selector_element(source: &Source, selector: ast::Expr) -> Option<(EcoString, TypstRange)> {
+    match selector {
+        ast::Expr::Ident(ident) => Some((ident.get().clone(), source.range(ident.span())?)),
+        ast::Expr::FuncCall(call) => selector_element(source, call.callee()),
+        ast::Expr::FieldAccess(access) if SELECTOR_METHODS.contains(&access.field().as_str()) => {
+            selector_element(source, access.target())
+        }
+        ast::Expr::FieldAccess(access) => {
+            // An element in a module, like `math.equation`
+            let range = source.range(access.span())?;
+            Some((source.text()[range.clone()].into(), range))
+        }
+        _ => None,
+    }
+}
+
+/// The possible element at `offset`. In a `show` rule's selector, this is the selected element,
+/// and elsewhere it is the identifier at the offset.
+fn element_at(source: &Source, offset: usize) -> Option<(EcoString, TypstRange)> {
+    let root = LinkedNode::new(source.root());
+    let leaf = root.leaf_at(offset)?;
+
+    let in_selector = std::iter::successors(Some(leaf.clone()), |node| node.parent().cloned())
+        .find(|node| node.kind() == SyntaxKind::ShowRule)
+        .and_then(|node| {
+            let selector = node.cast::<ast::ShowRule>()?.selector()?;
+            if !source.range(selector.span())?.contains(&offset) {
+                return None;
+            }
+            selector_element(source, selector)
+        });
+    if in_selector.is_some() {
+        return in_selector;
+    }
+
+    let ident = ident_at(&root, offset)?;
+    let name = ident.cast::<ast::Ident>()?.get().clone();
+    Some((name, ident.range()))
+}
+
+/// Whether the name, which may be a path like `math.equation`, is an element function in scope
+fn is_element(scope: &Scope, name: &str) -> bool {
+    let mut parts = name.split('.');
+    let Some(mut value) = parts.next().and_then(|first| scope.get(first)) else {
+        return false;
+    };
+    for part in parts {
+        let Value::Module(module) = value else {
+            return false;
+        };
+        let Some(next) = module.scope().get(part) else {
+            return false;
+        };
+        value = next;
+    }
+
+    matches!(value, Value::Func(func) if func.element().is_some())
+}
+
+fn item_data(item: &TypeHierarchyItem) -> Option<TypeHierarchyData> {
+    serde_json::from_value(item.data.clone()?).ok()
+}
+
+fn element_item(
+    uri: Url,
+    element: EcoString,
+    range: TypstRange,
+    source: &Source,
+    position_encoding: PositionEncoding,
+) -> TypeHierarchyItem {
+    let range = typst_to_lsp::range(range, source, position_encoding).raw_range;
+    TypeHierarchyItem {
+        name: element.to_string(),
+        kind: SymbolKind::CLASS,
+        tags: None,
+        detail: Some("element".to_owned()),
+        uri,
+        range,
+        selection_range: range,
+        data: serde_json::to_value(TypeHierarchyData {
+            element,
+            rule: false,
+        })
+        .ok(),
+    }
+}
+
+fn rule_item(
+    uri: Url,
+    rule: ShowRule,
+    source: &Source,
+    position_encoding: PositionEncoding,
+) -> TypeHierarchyItem {
+    // Only the first line of the rule, since its transformation may be a long block
+    let text = &source.text()[rule.range.clone()];
+    let name = text.lines().next().unwrap_or(text).trim_end();
+
+    TypeHierarchyItem {
+        name: name.to_owned(),
+        kind: SymbolKind::OBJECT,
+        tags: None,
+        detail: Some("show rule".to_owned()),
+        uri,
+        range: typst_to_lsp::range(rule.range, source, position_encoding).raw_range,
+        selection_range: typst_to_lsp::range(rule.element_range, source, position_encoding)
+            .raw_range,
+        data: serde_json::to_value(TypeHierarchyData {
+            element: rule.element,
+            rule: true,
+        })
+        .ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn show_heading_rule_is_subtype_of_heading() {
+        let source = Source::detached(
+            "#show heading: set text(red)\n#show heading.where(level: 1): it => it\n#show strong: it => it\n#show \"x\": [y]",
+        );
+
+        let rules = show_rules(&source, "heading");
+
+        let elements: Vec<_> = rules
+            .iter()
+            .map(|rule| &source.text()[rule.element_range.clone()])
+            .collect();
+        assert_eq!(elements, ["heading", "heading"]);
+        assert_eq!(rules[0].range, 1..28);
+    }
+
+    #[test]
+    fn element_in_selector_is_found() {
+        let source = Source::detached("#show math.equation: box\n#heading[A]");
+
+        let in_rule = element_at(&source, 12).map(|(name, _)| name);
+        let elsewhere = element_at(&source, 28).map(|(name, _)| name);
+
+        assert_eq!(in_rule.as_deref(), Some("math.equation"));
+        assert_eq!(elsewhere.as_deref(), Some("heading"));
+    }
+}
@@ -1,91 +1,398 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 
+use anyhow::Context;
 use comemo::Track;
 use tower_lsp::lsp_types::Url;
+use tracing::trace;
+use typst::diag::{SourceDiagnostic, SourceResult};
 use typst::engine::Route;
 use typst::eval::Tracer;
 use typst::foundations::Module;
 use typst::model::Document;
+use typst::syntax::{ast, LinkedNode, Source};
+use typst::text::FontBook;
 use typst::World;
 
+use crate::config::Config;
+use crate::ext::PathExt;
 use crate::lsp_typst_boundary::typst_to_lsp;
+use crate::workspace::world::ProjectWorld;
 
+use super::compile_status::{CompileStatus, CompileStatusNotification};
 use super::diagnostics::DiagnosticsMap;
+use super::lints::unused_diagnostics;
 use super::TypstServer;
 
+/// Counts the edits to each document, so a compile can tell whether a newer edit arrived while it
+/// was running
+#[derive(Debug, Default)]
+pub struct CompileGenerations {
+    latest: parking_lot::Mutex<HashMap<Url, u64>>,
+}
+
+impl CompileGenerations {
+    /// Records an edit to the document, returning its generation
+    pub fn next(&self, uri: &Url) -> u64 {
+        let mut latest = self.latest.lock();
+        let generation = latest.entry(uri.clone()).or_default();
+        *generation += 1;
+        *generation
+    }
+
+    pub fn is_current(&self, uri: &Url, generation: u64) -> bool {
+        self.latest.lock().get(uri).copied().unwrap_or_default() == generation
+    }
+}
+
+/// The results of a compile, with its status to report to the client once they are published
+pub type Compiled = (Option<Arc<Document>>, DiagnosticsMap, CompileStatus);
+
 impl TypstServer {
     #[tracing::instrument(skip(self, uri), fields(%uri))]
     pub async fn compile_source(
         &self,
         uri: &Url,
     ) -> anyhow::Result<(Option<Arc<Document>>, DiagnosticsMap)> {
-        let doc = self
+        let (document, diagnostics, _) = self.compile_source_with_status(uri).await?;
+        Ok((document, diagnostics))
+    }
+
+    /// Compiles the source, also returning the status to report when publishing the results
+    pub async fn compile_source_with_status(&self, uri: &Url) -> anyhow::Result<Compiled> {
+        self.compile_source_unless_superseded(uri, None)
+            .await?
+            .context("compile without a generation was superseded")
+    }
+
+    /// Compiles the source, unless an edit newer than `generation` arrived by the time compilation
+    /// finished, in which case the results are stale and `None` is returned. A compile for the
+    /// newer edit will follow, so publishing the stale results would only make them flicker.
+    pub async fn compile_source_if_current(
+        &self,
+        uri: &Url,
+        generation: u64,
+    ) -> anyhow::Result<Option<Compiled>> {
+        self.compile_source_unless_superseded(uri, Some(generation))
+            .await
+    }
+
+    /// Stale results are discarded before they replace the cached document and dependencies,
+    /// which a newer compile may already have updated
+    async fn compile_source_unless_superseded(
+        &self,
+        uri: &Url,
+        generation: Option<u64>,
+    ) -> anyhow::Result<Option<Compiled>> {
+        let evict = cache_eviction(
+            &self.config.read().await,
+            self.typst_thread.worker_count(),
+            comemo::evict,
+        );
+        let warn_unknown_fonts = !self.config.read().await.ignore_unknown_fonts;
+        let const_config = self.const_config().context("server not initialized")?;
+        let (source, project) = self
             .scope_with_source(uri)
             .await?
-            .run2(|source, project| async move {
-                let (document, diagnostics) = self
-                    .thread_with_world((source, project.clone()))
-                    .await?
-                    .run(|world| {
-                        comemo::evict(30);
-
-                        let mut tracer = Tracer::default();
-                        let result = typst::compile(&world, &mut tracer);
-
-                        let mut diagnostics = tracer.warnings();
-                        match result {
-                            Ok(document) => (Some(Arc::new(document)), diagnostics),
-                            Err(errors) => {
-                                diagnostics.extend_from_slice(&errors);
-                                (None, diagnostics)
-                            }
-                        }
-                    })
-                    .await;
+            .run2(|source, project| (source, project));
+
+        let (document, diagnostics, dependencies, elapsed) = self
+            .thread_with_world((source.clone(), project.clone()))
+            .await?
+            .run(|world| {
+                evict();
 
-                let diagnostics =
-                    typst_to_lsp::diagnostics(&project, diagnostics.as_ref(), self.const_config())
-                        .await;
+                let mut tracer = Tracer::default();
+                let start = Instant::now();
+                let result = typst::compile(&world, &mut tracer);
+                let elapsed = start.elapsed();
 
-                let res: anyhow::Result<(Option<Arc<Document>>, DiagnosticsMap)> =
-                    Ok((document, diagnostics));
-                res
+                let (document, mut diagnostics) = with_warnings(result, tracer.warnings());
+                let font_warnings = if warn_unknown_fonts {
+                    dependency_font_warnings(&world)
+                } else {
+                    Vec::new()
+                };
+                for warning in font_warnings {
+                    if !diagnostics.iter().any(|diag| diag.span == warning.span) {
+                        diagnostics.push(warning);
+                    }
+                }
+                (
+                    document.map(Arc::new),
+                    diagnostics,
+                    world.dependencies(),
+                    elapsed,
+                )
             })
-            .await?;
-        if let Some(doc) = &doc.0 {
-            *self.document.lock().await = doc.clone();
+            .await;
+
+        if let Some(generation) = generation {
+            if !self.compile_generations.is_current(uri, generation) {
+                trace!(%uri, generation, "discarding superseded compile");
+                return Ok(None);
+            }
+        }
+
+        let mut dependency_uris = Vec::with_capacity(dependencies.len());
+        for &id in &dependencies {
+            if let Ok(dependency) = project.full_id_to_uri(project.fill_id(id)).await {
+                dependency_uris.push(dependency);
+            }
         }
-        Ok(doc)
+        self.dependencies
+            .write()
+            .update(uri.clone(), dependency_uris);
+
+        let mut diagnostics =
+            typst_to_lsp::diagnostics(&project, diagnostics.iter(), &dependencies, const_config)
+                .await;
+        if self.config.read().await.lint_unused {
+            let position_encoding = self.position_encoding();
+            diagnostics
+                .entry(uri.clone())
+                .or_default()
+                .extend(unused_diagnostics(&source, position_encoding));
+        }
+
+        if let Some(document) = &document {
+            self.documents.insert(uri.clone(), document.clone());
+        }
+        let status = CompileStatus::new(uri.clone(), elapsed, document.as_deref());
+
+        Ok(Some((document, diagnostics, status)))
+    }
+
+    /// Tells the client how the compile whose results were just published went
+    pub async fn report_compile_status(&self, status: CompileStatus) {
+        self.client
+            .send_notification::<CompileStatusNotification>(status)
+            .await;
     }
 
     #[tracing::instrument(skip(self, uri), fields(%uri))]
     pub async fn eval_source(&self, uri: &Url) -> anyhow::Result<(Option<Module>, DiagnosticsMap)> {
-        let result = self
+        let evict = cache_eviction(
+            &self.config.read().await,
+            self.typst_thread.worker_count(),
+            comemo::evict,
+        );
+        let const_config = self.const_config().context("server not initialized")?;
+        let (module, diagnostics, dependencies) = self
             .thread_with_world(uri)
             .await?
             .run(|world| {
-                comemo::evict(30);
+                evict();
 
                 let route = Route::default();
                 let mut tracer = Tracer::default();
-                typst::eval::eval(
+                let result = typst::eval::eval(
                     (&world as &dyn World).track(),
                     route.track(),
                     tracer.track_mut(),
                     &world.main(),
-                )
+                );
+
+                let (module, diagnostics) = with_warnings(result, tracer.warnings());
+                (module, diagnostics, world.dependencies())
             })
             .await;
 
-        let (module, errors) = match result {
-            Ok(module) => (Some(module), Default::default()),
-            Err(errors) => (Default::default(), errors),
-        };
-
         let (project, _) = self.project_and_full_id(uri).await?;
         let diagnostics =
-            typst_to_lsp::diagnostics(&project, errors.as_ref(), self.const_config()).await;
+            typst_to_lsp::diagnostics(&project, diagnostics.iter(), &dependencies, const_config)
+                .await;
 
         Ok((module, diagnostics))
     }
 }
+
+/// Evicts memoized results which weren't used in as many runs as configured, to be called before
+/// each Typst run. Runs on every worker age the shared cache, so the age is scaled by the number
+/// of `workers` to count runs of a single project. `evict` is `comemo::evict` outside of tests.
+fn cache_eviction(
+    config: &Config,
+    workers: usize,
+    evict: fn(usize),
+) -> impl FnOnce() + Send + 'static {
+    let age = config.cache_eviction_age().saturating_mul(workers);
+    move || evict(age)
+}
+
+/// Collects the warnings from a Typst run along with its errors, if it failed. Warnings are kept
+/// even when the run succeeds, so they are published too.
+fn with_warnings<T>(
+    result: SourceResult<T>,
+    warnings: impl IntoIterator<Item = SourceDiagnostic>,
+) -> (Option<T>, Vec<SourceDiagnostic>) {
+    let mut diagnostics: Vec<_> = warnings.into_iter().collect();
+    match result {
+        Ok(value) => (Some(value), diagnostics),
+        Err(errors) => {
+            diagnostics.extend(errors);
+            (None, diagnostics)
+        }
+    }
+}
+
+/// Warns about unknown fonts in every Typst source the compile reached, so fonts set in included
+/// files are checked too
+fn dependency_font_warnings(world: &ProjectWorld) -> Vec<SourceDiagnostic> {
+    world
+        .dependencies()
+        .into_iter()
+        .filter(|id| id.vpath().as_rootless_path().is_typst())
+        .filter_map(|id| world.source(id).ok())
+        .flat_map(|source| unknown_font_warnings(&source, world.book()))
+        .collect()
+}
+
+/// Warns about font families named in `font` arguments which aren't among the loaded fonts, since
+/// Typst silently falls back to another font for them
+fn unknown_font_warnings(source: &Source, book: &FontBook) -> Vec<SourceDiagnostic> {
+    let mut warnings = Vec::new();
+    collect_unknown_fonts(&LinkedNode::new(source.root()), book, &mut warnings);
+    warnings
+}
+
+fn collect_unknown_fonts(node: &LinkedNode, book: &FontBook, warnings: &mut Vec<SourceDiagnostic>) {
+    if let Some(named) = node.cast::<ast::Named>() {
+        if named.name().as_str() == "font" {
+            let families: Vec<_> = match named.expr() {
+                ast::Expr::Str(family) => vec![family],
+                ast::Expr::Array(array) => array
+                    .items()
+                    .filter_map(|item| match item {
+                        ast::ArrayItem::Pos(ast::Expr::Str(family)) => Some(family),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            for family in families {
+                let name = family.get();
+                let known = book
+                    .families()
+                    .any(|(known, _)| known.eq_ignore_ascii_case(&name));
+                if !known {
+                    let message = format!("unknown font family: {name}");
+                    let warning = SourceDiagnostic::warning(family.span(), message)
+                        .with_hint("the font is not installed in a directory the server searches");
+                    warnings.push(warning);
+                }
+            }
+        }
+    }
+
+    for child in node.children() {
+        collect_unknown_fonts(&child, book, warnings);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use typst::diag::Severity;
+    use typst::syntax::Span;
+
+    use crate::config::DEFAULT_CACHE_EVICTION_AGE;
+    use crate::workspace::font_manager::FontManager;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn configured_eviction_age_is_evicted() {
+        thread_local! {
+            static EVICTED: Cell<Option<usize>> = const { Cell::new(None) };
+        }
+        fn record(age: usize) {
+            EVICTED.with(|evicted| evicted.set(Some(age)));
+        }
+
+        let mut config = Config::default();
+        let update = serde_json::json!({ "cacheEvictionAge": 0 });
+        config
+            .update_by_map(update.as_object().unwrap())
+            .await
+            .unwrap();
+        cache_eviction(&config, 4, record)();
+
+        assert_eq!(EVICTED.with(Cell::get), Some(0));
+    }
+
+    #[test]
+    fn eviction_age_is_scaled_by_workers() {
+        thread_local! {
+            static EVICTED: Cell<Option<usize>> = const { Cell::new(None) };
+        }
+        fn record(age: usize) {
+            EVICTED.with(|evicted| evicted.set(Some(age)));
+        }
+
+        let config = Config::default();
+        cache_eviction(&config, 4, record)();
+
+        assert_eq!(
+            EVICTED.with(Cell::get),
+            Some(4 * DEFAULT_CACHE_EVICTION_AGE)
+        );
+    }
+
+    #[test]
+    fn superseded_compile_is_not_current() {
+        let generations = CompileGenerations::default();
+        let main = Url::parse("file:///project/main.typ").unwrap();
+        let chapter = Url::parse("file:///project/chapter.typ").unwrap();
+
+        let superseded = generations.next(&main);
+        let latest = generations.next(&main);
+        let other = generations.next(&chapter);
+
+        assert!(!generations.is_current(&main, superseded));
+        assert!(generations.is_current(&main, latest));
+        assert!(generations.is_current(&chapter, other));
+    }
+
+    #[test]
+    fn unknown_font_family_is_warned() {
+        let fonts = FontManager::builder().with_embedded().build();
+        let source = Source::detached(
+            "#set text(font: \"Linux Libertine\")\n#text(font: (\"Nonexistent Sans\", \"DejaVu Sans Mono\"))[x]",
+        );
+
+        let warnings = unknown_font_warnings(&source, fonts.book());
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "unknown font family: Nonexistent Sans");
+    }
+
+    #[test]
+    fn warnings_kept_on_success() {
+        let warning = SourceDiagnostic::warning(Span::detached(), "this is unused");
+
+        let (value, diagnostics) = with_warnings(Ok(1), [warning]);
+
+        assert_eq!(value, Some(1));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn warnings_come_before_errors() {
+        let warning = SourceDiagnostic::warning(Span::detached(), "this is unused");
+        let error = SourceDiagnostic::error(Span::detached(), "this is wrong");
+
+        let (value, diagnostics) =
+            with_warnings::<()>(Err([error].into_iter().collect()), [warning]);
+
+        assert_eq!(value, None);
+        let severities: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.severity)
+            .collect();
+        assert_eq!(severities, vec![Severity::Warning, Severity::Error]);
+    }
+}
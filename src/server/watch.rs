@@ -1,24 +1,39 @@
+use std::collections::BTreeSet;
+
 use tower_lsp::lsp_types::{
     DidChangeWatchedFilesRegistrationOptions, FileChangeType, FileEvent, FileSystemWatcher,
-    GlobPattern, Registration,
+    GlobPattern, Registration, Url,
 };
+use tracing::error;
 
+use crate::ext::PathExt;
+use crate::workspace::fs::local::LocalFs;
 use crate::workspace::Workspace;
 
+use super::document::CompileTarget;
 use super::TypstServer;
 
-static WATCH_TYPST_FILES_REGISTRATION_ID: &str = "watch_typst_files";
+static WATCH_FILES_REGISTRATION_ID: &str = "watch_files";
 static WATCH_FILES_METHOD: &str = "workspace/didChangeWatchedFiles";
 
+/// Extensions of the files documents are likely to depend on: sources, bibliographies, data files,
+/// images, and fonts. Watching every file would also report version control and build output,
+/// including the PDFs the server exports itself.
+const WATCHED_EXTENSIONS: &[&str] = &[
+    "typ", "typc", "bib", "yml", "yaml", "csv", "json", "toml", "xml", "svg", "png", "jpg", "jpeg",
+    "gif", "otf", "ttf",
+];
+
 impl TypstServer {
     pub fn get_watcher_registration(&self) -> Registration {
+        let glob = format!("**/*.{{{}}}", WATCHED_EXTENSIONS.join(","));
         Registration {
-            id: WATCH_TYPST_FILES_REGISTRATION_ID.to_owned(),
+            id: WATCH_FILES_REGISTRATION_ID.to_owned(),
             method: WATCH_FILES_METHOD.to_owned(),
             register_options: Some(
                 serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
                     watchers: vec![FileSystemWatcher {
-                        glob_pattern: GlobPattern::String("**/*".to_owned()),
+                        glob_pattern: GlobPattern::String(glob),
                         kind: None,
                     }],
                 })
@@ -31,10 +46,95 @@ impl TypstServer {
         let uri = event.uri;
 
         match event.typ {
-            FileChangeType::CREATED => workspace.new_local(uri),
+            // Only sources need to be known before they are read, so workspace-wide features find
+            // them. Other files are cached when they are first read.
+            FileChangeType::CREATED if is_source(&uri) => workspace.new_local(uri),
             FileChangeType::CHANGED => workspace.invalidate_local(uri),
             FileChangeType::DELETED => workspace.delete_local(&uri),
             _ => (),
         }
     }
+
+    /// Keeps only the last event for each URI, since clients may report several changes to the
+    /// same file at once, and only its final state matters
+    pub fn coalesce_file_events(events: Vec<FileEvent>) -> Vec<FileEvent> {
+        let mut coalesced: Vec<FileEvent> = Vec::with_capacity(events.len());
+        for event in events {
+            coalesced.retain(|previous| previous.uri != event.uri);
+            coalesced.push(event);
+        }
+        coalesced
+    }
+
+    /// Recompiles the documents which depended on any of the changed files when they were last
+    /// compiled, so their diagnostics don't go stale when an imported file changes outside the
+    /// editor
+    pub async fn recompile_dependents(&self, changed: &[Url]) {
+        let dependents: BTreeSet<Url> = {
+            let dependencies = self.dependencies.read();
+            changed
+                .iter()
+                .flat_map(|uri| dependencies.dependents_of(uri))
+                .collect()
+        };
+
+        self.recompile_all(dependents).await;
+    }
+
+    /// Recompiles the other documents depending on a file edited in the editor. The edit itself
+    /// only compiles the file, or the pinned main file, so that one is skipped.
+    pub async fn recompile_edit_dependents(&self, edited: &Url) {
+        let compiled = {
+            let config = self.config.read().await;
+            CompileTarget::on_change(config.export_pdf, config.main_file.as_ref(), edited).uri
+        };
+
+        let dependents: BTreeSet<Url> = self
+            .dependencies
+            .read()
+            .dependents_of(edited)
+            .into_iter()
+            .filter(|main| *main != compiled)
+            .collect();
+
+        self.recompile_all(dependents).await;
+    }
+
+    pub async fn recompile_all(&self, mains: BTreeSet<Url>) {
+        for main in mains {
+            if let Err(err) = self.on_source_changed(&main).await {
+                error!(%err, %main, "could not recompile document after a dependency changed");
+            }
+        }
+    }
+}
+
+fn is_source(uri: &Url) -> bool {
+    LocalFs::uri_to_path(uri).is_ok_and(|path| path.is_typst())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn events_for_same_uri_are_coalesced() {
+        let main = Url::parse("file:///project/main.typ").unwrap();
+        let other = Url::parse("file:///project/other.typ").unwrap();
+        let events = vec![
+            FileEvent::new(main.clone(), FileChangeType::CREATED),
+            FileEvent::new(other.clone(), FileChangeType::CHANGED),
+            FileEvent::new(main.clone(), FileChangeType::DELETED),
+        ];
+
+        let coalesced = TypstServer::coalesce_file_events(events);
+
+        assert_eq!(
+            coalesced,
+            vec![
+                FileEvent::new(other, FileChangeType::CHANGED),
+                FileEvent::new(main, FileChangeType::DELETED),
+            ]
+        );
+    }
 }
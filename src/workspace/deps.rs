@@ -0,0 +1,98 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use tower_lsp::lsp_types::Url;
+
+/// Tracks, for each main file, the files its document depended on when it was last compiled. A
+/// change to a file only affects the documents of the main files depending on it, so only those
+/// need to be compiled again.
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    dependencies: HashMap<Url, HashSet<Url>>,
+}
+
+impl DependencyGraph {
+    /// Replaces the dependencies of a main file with those read by its latest compilation
+    pub fn update(&mut self, main: Url, dependencies: impl IntoIterator<Item = Url>) {
+        self.dependencies
+            .insert(main, dependencies.into_iter().collect());
+    }
+
+    /// The files the main file's document depended on when it was last compiled
+    pub fn dependencies_of(&self, main: &Url) -> Vec<Url> {
+        self.dependencies
+            .get(main)
+            .map(|dependencies| dependencies.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The main files which have been compiled
+    pub fn mains(&self) -> BTreeSet<Url> {
+        self.dependencies.keys().cloned().collect()
+    }
+
+    pub fn remove(&mut self, main: &Url) {
+        self.dependencies.remove(main);
+    }
+
+    /// The main files whose documents depend on the file, sorted by URI. A main file depends on
+    /// itself once it has been compiled.
+    pub fn dependents_of(&self, uri: &Url) -> Vec<Url> {
+        let mut dependents: Vec<_> = self
+            .dependencies
+            .iter()
+            .filter(|(_, dependencies)| dependencies.contains(uri))
+            .map(|(main, _)| main.clone())
+            .collect();
+        dependents.sort();
+        dependents
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn uri(name: &str) -> Url {
+        Url::parse(&format!("file:///project/{name}")).unwrap()
+    }
+
+    #[test]
+    fn import_chain() {
+        // `main.typ` imports `chapter.typ`, which imports `utils.typ`
+        let mut graph = DependencyGraph::default();
+        graph.update(
+            uri("main.typ"),
+            [uri("main.typ"), uri("chapter.typ"), uri("utils.typ")],
+        );
+        graph.update(uri("chapter.typ"), [uri("chapter.typ"), uri("utils.typ")]);
+
+        assert_eq!(
+            graph.dependents_of(&uri("utils.typ")),
+            [uri("chapter.typ"), uri("main.typ")]
+        );
+        assert_eq!(graph.dependents_of(&uri("main.typ")), [uri("main.typ")]);
+        assert!(graph.dependents_of(&uri("unrelated.typ")).is_empty());
+        assert_eq!(
+            graph.mains(),
+            BTreeSet::from([uri("chapter.typ"), uri("main.typ")])
+        );
+    }
+
+    #[test]
+    fn recompiling_replaces_dependencies() {
+        let mut graph = DependencyGraph::default();
+        graph.update(
+            uri("main.typ"),
+            [uri("main.typ"), uri("chapter.typ"), uri("utils.typ")],
+        );
+
+        // The import of `chapter.typ` was removed
+        graph.update(uri("main.typ"), [uri("main.typ")]);
+
+        assert!(graph.dependents_of(&uri("chapter.typ")).is_empty());
+        assert!(graph.dependents_of(&uri("utils.typ")).is_empty());
+
+        graph.remove(&uri("main.typ"));
+        assert!(graph.dependents_of(&uri("main.typ")).is_empty());
+    }
+}
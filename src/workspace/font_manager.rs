@@ -1,14 +1,23 @@
 //! Derived from https://github.com/typst/typst/blob/main/cli/src/main.rs
 
 use core::fmt;
+use std::ffi::OsStr;
+use std::fs;
 use std::path::{Path, PathBuf};
 
 use comemo::Prehashed;
-use fontdb::{Database, Source};
+#[cfg(all(
+    unix,
+    feature = "fontconfig",
+    not(any(target_os = "macos", target_os = "android"))
+))]
+use itertools::Itertools;
 use once_cell::sync::OnceCell;
+use rayon::prelude::*;
+use serde::Serialize;
 use tracing::error;
 use typst::foundations::Bytes;
-use typst::text::{Font, FontBook, FontInfo};
+use typst::text::{Font, FontBook, FontInfo, FontStyle};
 
 use super::fs::local::LocalFs;
 use super::fs::FsError;
@@ -44,6 +53,37 @@ impl FontManager {
     pub fn clear(&mut self) {
         self.fonts.iter_mut().for_each(|font| font.invalidate());
     }
+
+    /// Describes every loaded font, in ID order
+    pub fn fonts_metadata(&self) -> Vec<FontMetadata> {
+        self.fonts
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| {
+                let info = self.book.info(id)?;
+                Some(FontMetadata {
+                    family: info.family.clone(),
+                    style: match info.variant.style {
+                        FontStyle::Normal => "normal",
+                        FontStyle::Italic => "italic",
+                        FontStyle::Oblique => "oblique",
+                    },
+                    weight: info.variant.weight.to_number(),
+                    path: slot.path.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A loaded font, as described to users
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FontMetadata {
+    pub family: String,
+    pub style: &'static str,
+    pub weight: u16,
+    /// `None` if the font is embedded in the server
+    pub path: Option<PathBuf>,
 }
 
 impl fmt::Debug for FontManager {
@@ -160,31 +200,184 @@ impl Builder {
 
     /// Search for fonts in the system font directories.
     fn search_system(&mut self) {
-        let mut db = Database::new();
-
         // System fonts have second priority.
-        db.load_system_fonts();
-
-        for face in db.faces() {
-            let path = match &face.source {
-                Source::File(path) | Source::SharedFile(path, _) => path,
-                // We never add binary sources to the database, so there
-                // shouldn't be any.
-                Source::Binary(_) => continue,
-            };
-
-            let info = db
-                .with_face_data(face.id, FontInfo::new)
-                .expect("database must contain this font");
+        self.push_font_files(&system_font_files());
+    }
 
-            if let Some(info) = info {
+    /// Adds the faces of each font file. Parsing every file is what makes searching slow, so files
+    /// are parsed in parallel, but their faces are added in the files' order so font IDs are the
+    /// same every time.
+    fn push_font_files(&mut self, paths: &[PathBuf]) {
+        for (path, faces) in paths.iter().zip(font_file_faces(paths)) {
+            for (index, info) in faces {
                 self.book.push(info);
                 self.fonts.push(FontSlot {
                     path: Some(path.clone()),
-                    index: face.index,
+                    index,
                     font: OnceCell::new(),
                 });
             }
         }
     }
 }
+
+/// Reads the index and metadata of each face in each file, in the same order as `paths`. Files
+/// which can't be read or parsed have no faces.
+fn font_file_faces(paths: &[PathBuf]) -> Vec<Vec<(u32, FontInfo)>> {
+    paths.par_iter().map(|path| read_faces(path)).collect()
+}
+
+fn read_faces(path: &Path) -> Vec<(u32, FontInfo)> {
+    let Ok(data) = fs::read(path) else {
+        return Vec::new();
+    };
+    Font::iter(data.into())
+        .map(|font| (font.index(), font.info().clone()))
+        .collect()
+}
+
+/// The font files in the system font directories, sorted within each directory so they are found
+/// in the same order every time
+#[cfg(not(all(
+    unix,
+    feature = "fontconfig",
+    not(any(target_os = "macos", target_os = "android"))
+)))]
+fn system_font_files() -> Vec<PathBuf> {
+    system_font_dirs()
+        .iter()
+        .flat_map(|dir| {
+            walkdir::WalkDir::new(dir)
+                .follow_links(true)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(Result::ok)
+        })
+        .filter(|entry| entry.file_type().is_file() && is_font_file(entry.path()))
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Only fontdb reads fontconfig's configuration of where fonts are, so it finds the files. It
+/// parses them while doing so, but only this first pass is serial.
+#[cfg(all(
+    unix,
+    feature = "fontconfig",
+    not(any(target_os = "macos", target_os = "android"))
+))]
+fn system_font_files() -> Vec<PathBuf> {
+    let mut db = fontdb::Database::new();
+    db.load_system_fonts();
+
+    db.faces()
+        .filter_map(|face| match &face.source {
+            fontdb::Source::File(path) | fontdb::Source::SharedFile(path, _) => Some(path.clone()),
+            // We never add binary sources to the database, so there shouldn't be any.
+            fontdb::Source::Binary(_) => None,
+        })
+        .unique()
+        .collect()
+}
+
+/// The directories fontdb searches for system fonts, without fontconfig
+#[cfg(not(all(
+    unix,
+    feature = "fontconfig",
+    not(any(target_os = "macos", target_os = "android"))
+)))]
+fn system_font_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+
+    if cfg!(target_os = "windows") {
+        let system_root = std::env::var_os("SYSTEMROOT")
+            .map_or_else(|| PathBuf::from("C:\\Windows"), PathBuf::from);
+        dirs.push(system_root.join("Fonts"));
+        if let Some(profile) = std::env::var_os("USERPROFILE").map(PathBuf::from) {
+            dirs.push(profile.join("AppData\\Local\\Microsoft\\Windows\\Fonts"));
+            dirs.push(profile.join("AppData\\Roaming\\Microsoft\\Windows\\Fonts"));
+        }
+    } else if cfg!(target_os = "macos") {
+        dirs.push("/Library/Fonts".into());
+        dirs.push("/System/Library/Fonts".into());
+        // Downloadable fonts, location varies on major macOS releases
+        let assets = fs::read_dir("/System/Library/AssetsV2")
+            .into_iter()
+            .flatten();
+        dirs.extend(
+            assets
+                .filter_map(Result::ok)
+                .filter(|entry| {
+                    entry
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with("com_apple_MobileAsset_Font")
+                })
+                .map(|entry| entry.path()),
+        );
+        dirs.push("/Network/Library/Fonts".into());
+        dirs.extend(home.map(|home| home.join("Library/Fonts")));
+    } else if cfg!(target_os = "redox") {
+        dirs.push("/ui/fonts".into());
+    } else if cfg!(unix) {
+        dirs.push("/usr/share/fonts/".into());
+        dirs.push("/usr/local/share/fonts/".into());
+        if let Some(home) = home {
+            dirs.push(home.join(".fonts"));
+            dirs.push(home.join(".local/share/fonts"));
+        }
+    }
+
+    dirs
+}
+
+fn is_font_file(path: &Path) -> bool {
+    path.extension().and_then(OsStr::to_str).is_some_and(|ext| {
+        ["ttf", "ttc", "otf", "otc"]
+            .iter()
+            .any(|font| ext.eq_ignore_ascii_case(font))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parallel_scan_matches_serial() {
+        let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/fonts"));
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| is_font_file(path))
+            .collect();
+        paths.sort();
+        assert!(paths.len() > 1);
+
+        let serial: Vec<_> = paths.iter().map(|path| read_faces(path)).collect();
+        let parallel = font_file_faces(&paths);
+
+        assert_eq!(serial, parallel);
+        assert!(parallel.iter().all(|faces| !faces.is_empty()));
+    }
+
+    #[test]
+    fn embedded_fonts_are_listed() {
+        let fonts = FontManager::builder().with_embedded().build();
+
+        let metadata = fonts.fonts_metadata();
+
+        assert!(metadata.contains(&FontMetadata {
+            family: "Linux Libertine".to_owned(),
+            style: "normal",
+            weight: 400,
+            path: None,
+        }));
+        assert!(metadata.contains(&FontMetadata {
+            family: "Linux Libertine".to_owned(),
+            style: "italic",
+            weight: 700,
+            path: None,
+        }));
+    }
+}
@@ -8,7 +8,7 @@ use tracing::trace;
 use typst::foundations::Bytes;
 use typst::syntax::Source;
 
-use crate::ext::PathExt;
+use crate::ext::{PathExt, UrlExt};
 use crate::workspace::package::manager::PackageManager;
 
 use super::local::LocalFs;
@@ -41,6 +41,13 @@ impl<Fs: ReadProvider> KnownUriProvider for Cache<Fs> {
 }
 
 impl<Fs: ReadProvider> Cache<Fs> {
+    pub fn new(fs: Fs) -> Self {
+        Self {
+            entries: FrozenMap::default(),
+            fs,
+        }
+    }
+
     /// Gives a reference to the wrapped [`ReadProvider`]. Note that this can cause cache
     /// invalidation errors if the inner reference writes to a cached file without the cache being
     /// notified.
@@ -66,12 +73,26 @@ impl<Fs: ReadProvider> Cache<Fs> {
         self.entry_mut(uri);
     }
 
+    /// Forgets the contents of the file, if they were cached. Files which were never read get no
+    /// entry, so changes to files documents don't use don't grow the cache.
     pub fn invalidate(&mut self, uri: Url) {
-        self.entry_mut(uri).invalidate()
+        if let Some(entry) = self.entries.as_mut().get_mut(&uri) {
+            entry.invalidate()
+        }
     }
 
+    /// Removes the file at the URI. Deleting a directory produces a single event for the
+    /// directory itself, so every file under the URI is removed too.
     pub fn delete(&mut self, uri: &Url) {
-        self.entries.as_mut().remove(uri);
+        let mut deleted = uri.clone();
+        let path = deleted.path().trim_end_matches('/').to_owned();
+        if !path.is_empty() {
+            deleted.set_path(&path);
+        }
+
+        self.entries
+            .as_mut()
+            .retain(|key, _| deleted.make_relative_rooted(key).is_err());
     }
 
     pub fn clear(&mut self) {
@@ -137,8 +158,33 @@ impl CacheEntry {
             .get_or_try_init(|| fs.read_source(uri, package_manager))
     }
 
+    /// Forgets both representations of the file. Data files, like JSON loaded by `json`, are only
+    /// ever read as bytes, so clearing just the source would leave them stale.
     pub fn invalidate(&mut self) {
         self.source.take();
         self.bytes.take();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deleting_directory_removes_contained_files() {
+        let mut cache = Cache::new(LocalFs::new(false));
+        let uri = |path: &str| Url::parse(&format!("file:///project/{path}")).unwrap();
+        cache.cache_new(uri("chapters/one.typ"));
+        cache.cache_new(uri("chapters/two.typ"));
+        cache.cache_new(uri("chapters-old/three.typ"));
+        cache.cache_new(uri("main.typ"));
+
+        cache.delete(&uri("chapters/"));
+
+        let known = cache.known_uris();
+        assert_eq!(
+            known,
+            HashSet::from([uri("chapters-old/three.typ"), uri("main.typ")])
+        );
+    }
+}
@@ -19,7 +19,10 @@ use super::{FsError, FsResult, ReadProvider, SourceSearcher, WriteProvider};
 /// but are meaningless when interpreted as local paths without accounting for the project or
 /// package root. So, for consistency, we avoid using these Typst paths and prefer filesystem paths.
 #[derive(Debug, Default)]
-pub struct LocalFs {}
+pub struct LocalFs {
+    /// Whether to decode sources which aren't UTF-8 as Latin-1 rather than failing
+    encoding_fallback: bool,
+}
 
 impl ReadProvider for LocalFs {
     fn read_bytes(&self, uri: &Url, _: &PackageManager) -> FsResult<Bytes> {
@@ -34,7 +37,13 @@ impl ReadProvider for LocalFs {
             return Err(FsError::NotSource);
         }
 
-        let text = Self::read_path_string(&path)?;
+        let bytes = Self::read_path_raw(&path)?;
+        let text = decode_source(bytes, self.encoding_fallback).map_err(|offset| {
+            FsError::InvalidUtf8 {
+                path: path.clone(),
+                offset,
+            }
+        })?;
         let full_id = package_manager.full_id(uri)?;
         Ok(Source::new(full_id.into(), text))
     }
@@ -67,6 +76,10 @@ impl SourceSearcher for LocalFs {
 }
 
 impl LocalFs {
+    pub fn new(encoding_fallback: bool) -> Self {
+        Self { encoding_fallback }
+    }
+
     pub fn uri_to_path(uri: &Url) -> Result<PathBuf, UriToFsPathError> {
         Self::verify_local(uri)?
             .to_file_path()
@@ -92,15 +105,53 @@ impl LocalFs {
         fs::read(path).map_err(|err| FsError::from_local_io(err, path))
     }
 
-    pub fn read_path_string(path: &Path) -> FsResult<String> {
-        fs::read_to_string(path).map_err(|err| FsError::from_local_io(err, path))
-    }
-
     pub fn write_path_raw(path: &Path, data: &[u8]) -> FsResult<()> {
         fs::write(path, data).map_err(|err| FsError::from_local_io(err, path))
     }
 }
 
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16_LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16_BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Decodes the text of a source file. Byte order marks are stripped, and UTF-16 is recognized by
+/// its byte order mark. Other files must be UTF-8, unless `latin1_fallback` is set, in which case
+/// files which aren't are decoded as Latin-1. On failure, gives the offset of the first invalid
+/// byte.
+fn decode_source(bytes: Vec<u8>, latin1_fallback: bool) -> Result<String, usize> {
+    if let Some(utf8) = bytes.strip_prefix(UTF8_BOM) {
+        return String::from_utf8(utf8.to_vec())
+            .map_err(|err| UTF8_BOM.len() + err.utf8_error().valid_up_to());
+    }
+    if let Some(utf16) = bytes.strip_prefix(UTF16_LE_BOM) {
+        return decode_utf16(utf16, u16::from_le_bytes);
+    }
+    if let Some(utf16) = bytes.strip_prefix(UTF16_BE_BOM) {
+        return decode_utf16(utf16, u16::from_be_bytes);
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(err) if latin1_fallback => Ok(err.into_bytes().into_iter().map(char::from).collect()),
+        Err(err) => Err(err.utf8_error().valid_up_to()),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, usize> {
+    let units = bytes.chunks(2).map(|pair| match *pair {
+        [first, second] => unit([first, second]),
+        // A trailing odd byte can't be part of a valid unit
+        _ => 0xDC00,
+    });
+
+    let mut text = String::with_capacity(bytes.len() / 2);
+    for (i, c) in char::decode_utf16(units).enumerate() {
+        // The BOM is two bytes, and each unit before the invalid one is two bytes
+        text.push(c.map_err(|_| 2 + 2 * i)?);
+    }
+    Ok(text)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum UriToFsPathError {
     #[error("cannot convert to path since scheme of URI is not `file`")]
@@ -119,6 +170,7 @@ pub enum FsPathToUriError {
 mod test {
     use temp_dir::TempDir;
 
+    use crate::config::PackageDownloadOptions;
     use crate::workspace::package::external::manager::ExternalPackageManager;
 
     use super::*;
@@ -134,7 +186,10 @@ mod test {
         let local_fs = LocalFs::default();
 
         let root_uri = LocalFs::path_to_uri(temp_dir.path()).unwrap();
-        let package_manager = PackageManager::new(vec![root_uri], ExternalPackageManager::new());
+        let package_manager = PackageManager::new(
+            vec![root_uri],
+            ExternalPackageManager::new(&PackageDownloadOptions::default(), &[]),
+        );
 
         let basic_path = temp_dir.child(BASIC_SOURCE_PATH);
         let basic_uri = LocalFs::path_to_uri(basic_path).unwrap();
@@ -157,6 +212,52 @@ mod test {
             "file contents were unexpected when reading as bytes"
         );
     }
+
+    #[test]
+    fn scripts_are_sources() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.child("main.typ"), "#import \"lib.typc\": x").unwrap();
+        fs::write(temp_dir.child("lib.typc"), "let x = 1").unwrap();
+        fs::write(temp_dir.child("data.csv"), "a,b").unwrap();
+
+        let local_fs = LocalFs::default();
+        let root_uri = LocalFs::path_to_uri(temp_dir.path()).unwrap();
+        let package_manager = PackageManager::new(
+            vec![root_uri.clone()],
+            ExternalPackageManager::new(&PackageDownloadOptions::default(), &[]),
+        );
+
+        let mut sources = local_fs.search_sources(&root_uri).unwrap();
+        sources.sort();
+        let script_uri = LocalFs::path_to_uri(temp_dir.child("lib.typc")).unwrap();
+        let main_uri = LocalFs::path_to_uri(temp_dir.child("main.typ")).unwrap();
+        assert_eq!(sources, [script_uri.clone(), main_uri]);
+
+        let script = local_fs
+            .read_source(&script_uri, &package_manager)
+            .expect("error reading script as source");
+        assert_eq!(script.text(), "let x = 1");
+    }
+
+    #[test]
+    fn utf8_with_bom() {
+        let text = decode_source(b"\xEF\xBB\xBF= Title".to_vec(), false).unwrap();
+        assert_eq!(text, "= Title");
+    }
+
+    #[test]
+    fn latin1() {
+        let bytes = b"caf\xE9".to_vec();
+
+        assert_eq!(decode_source(bytes.clone(), false), Err(3));
+        assert_eq!(decode_source(bytes, true).unwrap(), "café");
+    }
+
+    #[test]
+    fn utf16_with_bom() {
+        let text = decode_source(b"\xFF\xFEh\x00i\x00".to_vec(), false).unwrap();
+        assert_eq!(text, "hi");
+    }
 }
 
 #[cfg(test)]
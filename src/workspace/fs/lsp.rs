@@ -6,11 +6,28 @@ use typst::foundations::Bytes;
 use typst::syntax::Source;
 
 use crate::config::PositionEncoding;
-use crate::lsp_typst_boundary::LspRange;
+use crate::lsp_typst_boundary::{LspRange, TypstRange};
 use crate::workspace::package::manager::PackageManager;
 
 use super::{FsError, FsResult, KnownUriProvider, ReadProvider};
 
+/// Describes how an edit changed a source
+#[derive(Debug, Clone)]
+pub enum SourceEdit {
+    /// Part of the text was replaced
+    Range {
+        /// The replaced range, in the text before the edit
+        replaced: TypstRange,
+        /// Length in bytes of the replacement text
+        inserted_len: usize,
+        /// The range Typst reparsed, in the text after the edit. The syntax tree outside this range
+        /// is unchanged, other than being shifted.
+        reparsed: TypstRange,
+    },
+    /// The whole text was replaced
+    All,
+}
+
 /// Implements the Typst filesystem on source files provided by an LSP client
 #[derive(Debug, Default)]
 pub struct LspFs {
@@ -56,29 +73,36 @@ impl LspFs {
         uri: &Url,
         changes: impl IntoIterator<Item = TextDocumentContentChangeEvent>,
         position_encoding: PositionEncoding,
-    ) {
+    ) -> Vec<SourceEdit> {
         let Ok(source) = self.read_source_mut(uri) else {
-            return;
+            return Vec::new();
         };
         changes
             .into_iter()
-            .for_each(|change| Self::apply_one_change(source, change, position_encoding));
+            .map(|change| Self::apply_one_change(source, change, position_encoding))
+            .collect()
     }
 
     fn apply_one_change(
         source: &mut Source,
         change: TextDocumentContentChangeEvent,
         position_encoding: PositionEncoding,
-    ) {
+    ) -> SourceEdit {
         let replacement = change.text;
 
         match change.range {
             Some(lsp_range) => {
                 let range = LspRange::new(lsp_range, position_encoding).into_range_on(source);
-                source.edit(range, &replacement);
+                let reparsed = source.edit(range.clone(), &replacement);
+                SourceEdit::Range {
+                    replaced: range,
+                    inserted_len: replacement.len(),
+                    reparsed,
+                }
             }
             None => {
                 source.replace(&replacement);
+                SourceEdit::All
             }
         }
     }
@@ -99,3 +123,25 @@ impl LspFs {
             .ok_or_else(|| FsError::NotProvided(anyhow!("URI not found")))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::lsp_types::{Position, Range};
+
+    use super::*;
+
+    #[test]
+    fn edits_keep_crlf_line_endings() {
+        let mut source = Source::detached("ab\r\ncd\r\n");
+        let change = TextDocumentContentChangeEvent {
+            // Replaces the rest of the first line, as some clients send it
+            range: Some(Range::new(Position::new(0, 1), Position::new(0, 3))),
+            range_length: None,
+            text: "x".to_owned(),
+        };
+
+        LspFs::apply_one_change(&mut source, change, PositionEncoding::Utf16);
+
+        assert_eq!(source.text(), "ax\r\ncd\r\n");
+    }
+}
@@ -9,7 +9,7 @@ use crate::workspace::package::manager::PackageManager;
 
 use super::cache::Cache;
 use super::local::LocalFs;
-use super::lsp::LspFs;
+use super::lsp::{LspFs, SourceEdit};
 use super::{FsResult, KnownUriProvider, ReadProvider, WriteProvider};
 
 /// Composes [`ReadProvider`]s and [`WriteProvider`]s into a single provider for a workspace
@@ -48,6 +48,15 @@ impl KnownUriProvider for FsManager {
 }
 
 impl FsManager {
+    /// Creates a manager which decodes local sources which aren't UTF-8 as Latin-1 if
+    /// `encoding_fallback` is set
+    pub fn new(encoding_fallback: bool) -> Self {
+        Self {
+            lsp: LspFs::default(),
+            local: Cache::new(LocalFs::new(encoding_fallback)),
+        }
+    }
+
     #[tracing::instrument]
     pub fn register_files(&mut self, root: &Url) -> FsResult<()> {
         self.local.register_files(root)
@@ -71,7 +80,7 @@ impl FsManager {
         uri: &Url,
         changes: impl IntoIterator<Item = TextDocumentContentChangeEvent>,
         position_encoding: PositionEncoding,
-    ) {
+    ) -> Vec<SourceEdit> {
         self.lsp.edit(uri, changes, position_encoding)
     }
 
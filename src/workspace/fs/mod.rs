@@ -49,6 +49,8 @@ pub enum FsError {
     NotFoundLocal(PathBuf),
     #[error(transparent)]
     Package(#[from] PackageError),
+    #[error("`{path}` is not valid UTF-8, starting at byte {offset}")]
+    InvalidUtf8 { path: PathBuf, offset: usize },
     #[error(transparent)]
     OtherIo(io::Error),
     #[error("the provider does not provide the requested URI")]
@@ -73,6 +75,7 @@ impl FsError {
             Self::NotSource => FileError::NotSource,
             Self::NotFoundLocal(path) => FileError::NotFound(path),
             Self::Package(err) => err.convert(id),
+            Self::InvalidUtf8 { .. } => FileError::InvalidUtf8,
             Self::OtherIo(err) => FileError::from_io(err, id.vpath().as_rooted_path()),
             Self::NotProvided(_) | Self::UriJoin(_) | Self::Other(_) => {
                 FileError::Other(Some(self.to_string().into()))
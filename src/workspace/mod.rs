@@ -32,6 +32,7 @@
 //! context needed to interpret it, which is a project.
 
 use std::collections::HashSet;
+use std::sync::Arc;
 
 use comemo::Prehashed;
 use itertools::Itertools;
@@ -44,16 +45,19 @@ use typst::foundations::Bytes;
 use typst::syntax::Source;
 use typst::Library;
 
-use crate::config::PositionEncoding;
+use crate::config::{Config, FontOptions, PositionEncoding};
 use crate::ext::InitializeParamsExt;
 
 use self::font_manager::FontManager;
+use self::fs::lsp::SourceEdit;
 use self::fs::manager::FsManager;
 use self::fs::{FsResult, KnownUriProvider, ReadProvider, WriteProvider};
 use self::package::external::manager::ExternalPackageManager;
+use self::package::external::DownloadProgress;
 use self::package::manager::PackageManager;
 use self::package::{FullFileId, Package};
 
+pub mod deps;
 pub mod font_manager;
 pub mod fs;
 pub mod package;
@@ -72,13 +76,20 @@ pub struct Workspace {
 }
 
 impl Workspace {
-    pub fn new(params: &InitializeParams) -> Self {
+    pub fn new(
+        params: &InitializeParams,
+        config: &Config,
+        download_progress: Arc<dyn DownloadProgress>,
+    ) -> Self {
         let root_paths = params.root_uris();
+        let external_packages =
+            ExternalPackageManager::new(&config.package_downloads, &config.local_package_roots)
+                .with_progress(download_progress);
 
         Self {
-            fs: FsManager::default(),
-            fonts: FontManager::builder().with_system().with_embedded().build(),
-            packages: PackageManager::new(root_paths, ExternalPackageManager::new()),
+            fs: FsManager::new(config.source_encoding_fallback),
+            fonts: create_font_manager(config.fonts),
+            packages: PackageManager::new(root_paths, external_packages),
         }
     }
 
@@ -148,7 +159,7 @@ impl Workspace {
         uri: &Url,
         changes: impl IntoIterator<Item = TextDocumentContentChangeEvent>,
         position_encoding: PositionEncoding,
-    ) {
+    ) -> Vec<SourceEdit> {
         self.fs.edit_lsp(uri, changes, position_encoding)
     }
 
@@ -176,6 +187,15 @@ impl Workspace {
         Ok(())
     }
 
+    /// Searches for fonts again, picking up fonts installed or removed since the last search
+    pub fn set_fonts(&mut self, fonts: FontManager) {
+        self.fonts = fonts;
+    }
+
+    pub fn clear_downloaded_packages(&mut self) -> std::io::Result<()> {
+        self.packages.clear_downloads()
+    }
+
     pub fn clear(&mut self) -> FsResult<()> {
         self.fonts.clear();
         self.fs.clear();
@@ -183,3 +203,42 @@ impl Workspace {
         Ok(())
     }
 }
+
+/// Loads the fonts the options allow. Searching for system fonts can take seconds.
+pub fn create_font_manager(options: FontOptions) -> FontManager {
+    let mut builder = FontManager::builder();
+    if !options.ignore_system {
+        builder = builder.with_system();
+    }
+    if !options.ignore_embedded {
+        builder = builder.with_embedded();
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn has_family(fonts: &FontManager, family: &str) -> bool {
+        fonts.book().select_family(family).next().is_some()
+    }
+
+    #[test]
+    fn embedded_fonts_can_be_ignored() {
+        let embedded_only = FontOptions {
+            ignore_embedded: false,
+            ignore_system: true,
+        };
+        let none = FontOptions {
+            ignore_embedded: true,
+            ignore_system: true,
+        };
+
+        assert!(has_family(
+            &create_font_manager(embedded_only),
+            "linux libertine"
+        ));
+        assert!(!has_family(&create_font_manager(none), "linux libertine"));
+    }
+}
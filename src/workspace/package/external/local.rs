@@ -3,10 +3,13 @@ use std::str::FromStr;
 
 use async_compression::tokio::bufread::GzipDecoder;
 use async_trait::async_trait;
+use tokio::fs;
 use tokio::io::{AsyncBufRead, AsyncRead};
 use tokio_tar::Archive;
 use tower_lsp::lsp_types::Url;
-use typst::syntax::package::PackageSpec;
+use tracing::warn;
+use typst::diag::EcoString;
+use typst::syntax::package::{PackageManifest, PackageSpec};
 use typst::syntax::VirtualPath;
 
 use crate::workspace::fs::local::LocalFs;
@@ -20,6 +23,7 @@ pub struct LocalProvider {
     root: PathBuf,
 }
 
+#[async_trait]
 impl ExternalPackageProvider for LocalProvider {
     fn package(&self, spec: &PackageSpec) -> Option<Package> {
         let path = self.fs_path(spec);
@@ -43,6 +47,48 @@ impl ExternalPackageProvider for LocalProvider {
 
         Some(full_id)
     }
+
+    async fn packages(&self) -> Vec<(PackageSpec, Option<EcoString>)> {
+        let mut packages = Vec::new();
+        for namespace in subdirs(&self.root).await {
+            for name in subdirs(&namespace).await {
+                for version in subdirs(&name).await {
+                    let relative = version.strip_prefix(&self.root).expect("should be in root");
+                    let Some((spec, _)) = Self::split_spec(relative) else {
+                        continue;
+                    };
+
+                    let Ok(manifest) = fs::read_to_string(version.join("typst.toml")).await else {
+                        continue;
+                    };
+                    let description = toml::from_str::<PackageManifest>(&manifest)
+                        .ok()
+                        .and_then(|manifest| manifest.package.description);
+                    packages.push((spec, description));
+                }
+            }
+        }
+        packages
+    }
+}
+
+/// The directories in a directory, or none if it can't be read
+async fn subdirs(path: &Path) -> Vec<PathBuf> {
+    let mut subdirs = Vec::new();
+    let Ok(mut entries) = fs::read_dir(path).await else {
+        return subdirs;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        // Follows symlinks, so linked package directories are listed too
+        if fs::metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            subdirs.push(path);
+        }
+    }
+    subdirs
 }
 
 impl LocalProvider {
@@ -50,6 +96,10 @@ impl LocalProvider {
         Self { root: root_dir }
     }
 
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
     fn fs_path(&self, spec: &PackageSpec) -> PathBuf {
         let subdir = format!("{}/{}/{}/", spec.namespace, spec.name, spec.version);
         self.root.join(subdir)
@@ -90,6 +140,15 @@ impl RepoRetrievalDest for LocalProvider {
         let path = self.fs_path(spec);
         let decompressed = self.decompress(package_tar_gz);
         self.unpack_to(decompressed, &path).await?;
+
+        // Don't leave behind a package which could later be mistaken for the requested one
+        if let Err(err) = Self::verify_manifest(&path, spec).await {
+            if let Err(err) = fs::remove_dir_all(&path).await {
+                warn!(%err, ?path, "could not delete package with mismatched manifest");
+            }
+            return Err(err);
+        }
+
         Ok(Package::new(
             LocalFs::path_to_uri(path).expect("should be absolute"),
         ))
@@ -111,4 +170,75 @@ impl LocalProvider {
             .await
             .map_err(RepoError::from_archive_error)
     }
+
+    /// Checks that the manifest of an unpacked package is for the requested package
+    async fn verify_manifest(path: &Path, spec: &PackageSpec) -> RepoResult<()> {
+        let manifest = fs::read_to_string(path.join("typst.toml"))
+            .await
+            .map_err(|err| {
+                RepoError::ManifestMismatch(format!("could not read manifest: {err}").into())
+            })?;
+        let manifest: PackageManifest = toml::from_str(&manifest).map_err(|err| {
+            RepoError::ManifestMismatch(format!("invalid manifest: {err}").into())
+        })?;
+
+        let info = &manifest.package;
+        if info.name != spec.name || info.version != spec.version {
+            let message = format!(
+                "expected {}:{}, found {}:{}",
+                spec.name, spec.version, info.name, info.version
+            );
+            return Err(RepoError::ManifestMismatch(message.into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use async_compression::tokio::bufread::GzipEncoder;
+    use temp_dir::TempDir;
+    use tokio::io::AsyncReadExt;
+    use tokio_tar::{Builder, Header};
+
+    use super::*;
+
+    async fn tar_gz(manifest: &str) -> Vec<u8> {
+        let mut header = Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        let mut builder = Builder::new(Vec::new());
+        builder
+            .append_data(&mut header, "typst.toml", manifest.as_bytes())
+            .await
+            .unwrap();
+        let tar = builder.into_inner().await.unwrap();
+
+        let mut tar_gz = Vec::new();
+        GzipEncoder::new(tar.as_slice())
+            .read_to_end(&mut tar_gz)
+            .await
+            .unwrap();
+        tar_gz
+    }
+
+    #[tokio::test]
+    async fn mismatched_version_is_rejected_and_removed() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = LocalProvider::new(temp_dir.path().to_owned());
+        let spec = PackageSpec::from_str("@preview/example:0.1.0").unwrap();
+        let manifest = r#"[package]
+name = "example"
+version = "0.2.0"
+entrypoint = "lib.typ"
+"#;
+
+        let tar_gz = tar_gz(manifest).await;
+        let result = provider.store_tar_gz(&spec, tar_gz.as_slice()).await;
+
+        assert!(matches!(result, Err(RepoError::ManifestMismatch(_))));
+        assert!(!provider.fs_path(&spec).exists());
+    }
 }
@@ -1,3 +1,7 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use anyhow::anyhow;
 use tokio::io::AsyncReadExt;
 use tokio::sync::OnceCell;
@@ -6,11 +10,12 @@ use tracing::{info, warn};
 use typst::diag::EcoString;
 use typst::syntax::package::{PackageSpec, PackageVersion};
 
+use crate::config::PackageDownloadOptions;
 use crate::workspace::package::manager::{ExternalPackageError, ExternalPackageResult};
 use crate::workspace::package::{FullFileId, Package};
 
 use super::local::LocalProvider;
-use super::{ExternalPackageProvider, RepoProvider, RepoRetrievalDest};
+use super::{DownloadProgress, ExternalPackageProvider, RepoProvider, RepoRetrievalDest};
 
 #[cfg(feature = "remote-packages")]
 type DefaultRepoProvider = Option<super::remote_repo::RemoteRepoProvider>;
@@ -18,13 +23,19 @@ type DefaultRepoProvider = Option<super::remote_repo::RemoteRepoProvider>;
 type DefaultRepoProvider = ();
 
 #[cfg(feature = "remote-packages")]
-fn get_default_repo_provider() -> DefaultRepoProvider {
-    super::remote_repo::RemoteRepoProvider::new()
-        .map_err(|err| warn!(%err, "could not get repo provider for Typst packages"))
-        .ok()
+fn get_default_repo_provider(
+    options: &PackageDownloadOptions,
+) -> anyhow::Result<DefaultRepoProvider> {
+    if options.offline {
+        return Ok(None);
+    }
+
+    super::remote_repo::RemoteRepoProvider::new(options).map(Some)
 }
 #[cfg(not(feature = "remote-packages"))]
-fn get_default_repo_provider() -> DefaultRepoProvider {}
+fn get_default_repo_provider(_: &PackageDownloadOptions) -> anyhow::Result<DefaultRepoProvider> {
+    Ok(())
+}
 
 #[derive(Debug)]
 pub struct ExternalPackageManager<
@@ -34,6 +45,11 @@ pub struct ExternalPackageManager<
     providers: Vec<Box<dyn ExternalPackageProvider>>,
     cache: Option<Dest>,
     repo: Repo,
+    /// Whether to never use `repo`
+    offline: bool,
+    /// Why `repo` couldn't be set up from the download options, if it couldn't
+    repo_error: Option<anyhow::Error>,
+    progress: Arc<dyn DownloadProgress>,
     packages: OnceCell<Vec<(PackageSpec, Option<EcoString>)>>,
 }
 
@@ -41,7 +57,7 @@ impl ExternalPackageManager {
     // TODO: allow configuration of these directories
     // i.e. the paths `<config>/typst/` and `<cache>/typst/` should be customizable
     #[tracing::instrument]
-    pub fn new() -> Self {
+    pub fn new(download_options: &PackageDownloadOptions, local_roots: &[PathBuf]) -> Self {
         let user = dirs::data_dir()
             .map(|path| path.join("typst/packages/"))
             .map(LocalProvider::new)
@@ -54,6 +70,22 @@ impl ExternalPackageManager {
             warn!("could not get user external package directory");
         }
 
+        let configured = local_roots
+            .iter()
+            .filter(|root| {
+                let usable = root.is_absolute() && root.is_dir();
+                if !usable {
+                    warn!(
+                        ?root,
+                        "skipping local package root which is not an existing absolute directory"
+                    );
+                }
+                usable
+            })
+            .cloned()
+            .map(LocalProvider::new)
+            .map(|provider| Box::new(provider) as Box<dyn ExternalPackageProvider>);
+
         let cache = dirs::cache_dir()
             .map(|path| path.join("typst/packages/"))
             .map(LocalProvider::new);
@@ -64,24 +96,58 @@ impl ExternalPackageManager {
             warn!("could not get external package cache");
         }
 
-        let providers = [
-            user,
-            cache
-                .clone()
-                .map(Box::new)
-                .map(|cache| cache as Box<dyn ExternalPackageProvider>),
-        ]
-        .into_iter()
-        .flatten()
-        .collect();
+        // Configured roots are searched before the cache, so they can't be shadowed by downloads
+        let providers = user
+            .into_iter()
+            .chain(configured)
+            .chain(
+                cache
+                    .clone()
+                    .map(Box::new)
+                    .map(|cache| cache as Box<dyn ExternalPackageProvider>),
+            )
+            .collect();
+
+        let (repo, repo_error) = match get_default_repo_provider(download_options) {
+            Ok(repo) => (repo, None),
+            Err(err) => {
+                warn!(%err, "could not get repo provider for Typst packages");
+                (DefaultRepoProvider::default(), Some(err))
+            }
+        };
 
         Self {
             providers,
             cache,
-            repo: get_default_repo_provider(),
+            repo,
+            offline: download_options.offline,
+            repo_error,
+            progress: Arc::new(()),
             packages: OnceCell::default(),
         }
     }
+
+    /// The error which disabled package downloads, if the download options were invalid
+    pub fn repo_error(&self) -> Option<&anyhow::Error> {
+        self.repo_error.as_ref()
+    }
+
+    pub fn with_progress(self, progress: Arc<dyn DownloadProgress>) -> Self {
+        Self { progress, ..self }
+    }
+
+    /// Deletes every downloaded package, so they are downloaded again when next used. Packages in
+    /// the user's and configured package directories are kept.
+    pub fn clear_downloads(&mut self) -> std::io::Result<()> {
+        if let Some(cache) = &self.cache {
+            if cache.root().is_dir() {
+                info!(cache = ?cache.root(), "deleting downloaded packages");
+                std::fs::remove_dir_all(cache.root())?;
+            }
+        }
+        self.packages = OnceCell::default();
+        Ok(())
+    }
 }
 
 impl<Dest: RepoRetrievalDest, Repo: RepoProvider> ExternalPackageManager<Dest, Repo> {
@@ -105,16 +171,27 @@ impl<Dest: RepoRetrievalDest, Repo: RepoProvider> ExternalPackageManager<Dest, R
 
     #[tracing::instrument]
     async fn download_to_cache(&self, spec: &PackageSpec) -> ExternalPackageResult<Package> {
-        if let Some(cache) = &self.cache {
-            Ok(cache.store_from(&self.repo, spec).await?)
-        } else {
-            Err(ExternalPackageError::Other(anyhow!(
-                "nowhere to download package {spec}"
-            )))
+        if self.offline {
+            return Err(ExternalPackageError::Offline(spec.clone()));
         }
+
+        let Some(cache) = &self.cache else {
+            return Err(ExternalPackageError::Other(anyhow!(
+                "nowhere to download package {spec}"
+            )));
+        };
+
+        let _progress = ProgressGuard::begin(self.progress.as_ref(), spec);
+        let tar_gz = Box::into_pin(self.repo.retrieve_tar_gz(spec).await?);
+        self.progress.report(spec, "Extracting");
+        Ok(cache.store_tar_gz(spec, tar_gz).await?)
     }
 
     async fn packages_inner(&self) -> ExternalPackageResult<Vec<(PackageSpec, Option<EcoString>)>> {
+        if self.offline {
+            return Ok(Vec::new());
+        }
+
         let mut buf = vec![];
         let mut index = Box::into_pin(self.repo.retrieve_index().await?);
         index.read_to_end(&mut buf).await.map_err(|err| {
@@ -154,27 +231,62 @@ impl<Dest: RepoRetrievalDest, Repo: RepoProvider> ExternalPackageManager<Dest, R
     pub async fn packages(&self) -> &[(PackageSpec, Option<EcoString>)] {
         self.packages
             .get_or_init(|| async {
-                match self.packages_inner().await {
+                let mut packages = match self.packages_inner().await {
                     Ok(index) => index,
                     Err(err) => {
                         warn!(%err, "could not get packages from repo provider");
                         vec![]
                     }
+                };
+
+                // Packages on this machine may not be in the index, like `@local` packages, and
+                // are all that's available when the index can't be downloaded
+                let mut known: HashSet<_> = packages.iter().map(|(spec, _)| spec.clone()).collect();
+                for provider in self.providers() {
+                    for (spec, description) in provider.packages().await {
+                        if known.insert(spec.clone()) {
+                            packages.push((spec, description));
+                        }
+                    }
                 }
+
+                packages
             })
             .await
             .as_slice()
     }
 }
 
+/// Ends download progress when dropped, so it ends however the download finishes
+struct ProgressGuard<'a> {
+    progress: &'a dyn DownloadProgress,
+    spec: &'a PackageSpec,
+}
+
+impl<'a> ProgressGuard<'a> {
+    fn begin(progress: &'a dyn DownloadProgress, spec: &'a PackageSpec) -> Self {
+        progress.begin(spec);
+        Self { progress, spec }
+    }
+}
+
+impl Drop for ProgressGuard<'_> {
+    fn drop(&mut self) {
+        self.progress.end(self.spec);
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use std::path::PathBuf;
     use std::str::FromStr;
 
+    use async_trait::async_trait;
+    use temp_dir::TempDir;
     use tokio::fs;
+    use tokio::io::AsyncBufRead;
 
     use crate::workspace::fs::local::LocalFs;
+    use crate::workspace::package::external::{RepoError, RepoResult};
 
     use super::*;
 
@@ -182,13 +294,92 @@ mod test {
     async fn local_package() {
         let example_local_package = ExampleLocalPackage::set_up().await;
         let spec = example_local_package.spec();
-        let external_package_manager = ExternalPackageManager::new();
+        let external_package_manager =
+            ExternalPackageManager::new(&PackageDownloadOptions::default(), &[]);
 
         let package = external_package_manager.package(&spec).await.unwrap();
 
         assert_eq!(example_local_package.package(), package);
     }
 
+    #[tokio::test]
+    async fn package_in_configured_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let package_root = temp_dir.path().join("local/only-in-custom-root/0.1.0");
+        fs::create_dir_all(&package_root).await.unwrap();
+        fs::write(package_root.join("typst.toml"), "")
+            .await
+            .unwrap();
+
+        let manager = ExternalPackageManager::new(
+            &PackageDownloadOptions::default(),
+            &[temp_dir.path().to_owned()],
+        );
+        let spec = PackageSpec::from_str("@local/only-in-custom-root:0.1.0").unwrap();
+
+        let package = manager.package(&spec).await.unwrap();
+        let lib_uri = LocalFs::path_to_uri(package_root.join("lib.typ")).unwrap();
+        let full_id = manager.full_id(&lib_uri).unwrap();
+
+        assert_eq!(
+            package,
+            Package::new(LocalFs::path_to_uri(&package_root).unwrap())
+        );
+        assert_eq!(full_id.spec(), Some(&spec));
+    }
+
+    #[cfg(feature = "remote-packages")]
+    #[test]
+    fn invalid_registry_url_is_reported() {
+        let options = PackageDownloadOptions {
+            registry_url: Some("not a url".to_owned()),
+            ..Default::default()
+        };
+
+        let manager = ExternalPackageManager::new(&options, &[]);
+
+        let err = manager.repo_error().unwrap();
+        assert!(err.to_string().contains("not a url"));
+    }
+
+    /// Fails the test if the package manager tries to use the network
+    #[derive(Debug)]
+    struct NoNetworkRepo;
+
+    #[async_trait]
+    impl RepoProvider for NoNetworkRepo {
+        async fn retrieve_tar_gz(
+            &self,
+            spec: &PackageSpec,
+        ) -> RepoResult<Box<dyn AsyncBufRead + Send>> {
+            panic!("tried to download {spec} in offline mode")
+        }
+
+        async fn retrieve_index(&self) -> RepoResult<Box<dyn AsyncBufRead + Send>> {
+            panic!("tried to download index in offline mode")
+        }
+    }
+
+    #[tokio::test]
+    async fn offline_does_not_download() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = ExternalPackageManager {
+            providers: Vec::new(),
+            cache: Some(LocalProvider::new(temp_dir.path().to_owned())),
+            repo: NoNetworkRepo,
+            offline: true,
+            repo_error: None,
+            progress: Arc::new(()),
+            packages: OnceCell::default(),
+        };
+        let spec = PackageSpec::from_str("@preview/example:0.1.0").unwrap();
+
+        let result = manager.package(&spec).await;
+
+        assert!(matches!(result, Err(ExternalPackageError::Offline(_))));
+        assert!(manager.packages().await.is_empty());
+    }
+
     pub struct ExampleLocalPackage {
         root: PathBuf,
     }
@@ -251,4 +442,103 @@ This is an *example!*
             std::fs::remove_dir_all(&self.root).unwrap();
         }
     }
+
+    #[tokio::test]
+    async fn offline_lists_local_packages() {
+        let temp_dir = TempDir::new().unwrap();
+        let package_root = temp_dir.path().join("preview/example/0.1.0");
+        fs::create_dir_all(&package_root).await.unwrap();
+        let manifest = r#"[package]
+name = "example"
+version = "0.1.0"
+entrypoint = "lib.typ"
+description = "An example package."
+"#;
+        fs::write(package_root.join("typst.toml"), manifest)
+            .await
+            .unwrap();
+
+        let cache = LocalProvider::new(temp_dir.path().to_owned());
+        let manager = ExternalPackageManager {
+            providers: vec![Box::new(cache.clone())],
+            cache: Some(cache),
+            repo: NoNetworkRepo,
+            offline: true,
+            repo_error: None,
+            progress: Arc::new(()),
+            packages: OnceCell::default(),
+        };
+
+        let packages = manager.packages().await;
+
+        assert_eq!(
+            packages,
+            [(
+                PackageSpec::from_str("@preview/example:0.1.0").unwrap(),
+                Some("An example package.".into())
+            )]
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingProgress {
+        events: parking_lot::Mutex<Vec<String>>,
+    }
+
+    impl DownloadProgress for RecordingProgress {
+        fn begin(&self, spec: &PackageSpec) {
+            self.events.lock().push(format!("begin {spec}"));
+        }
+
+        fn report(&self, spec: &PackageSpec, message: &str) {
+            self.events.lock().push(format!("report {spec}: {message}"));
+        }
+
+        fn end(&self, spec: &PackageSpec) {
+            self.events.lock().push(format!("end {spec}"));
+        }
+    }
+
+    /// Has no packages, like a repo which is unreachable
+    #[derive(Debug)]
+    struct EmptyRepo;
+
+    #[async_trait]
+    impl RepoProvider for EmptyRepo {
+        async fn retrieve_tar_gz(
+            &self,
+            spec: &PackageSpec,
+        ) -> RepoResult<Box<dyn AsyncBufRead + Send>> {
+            Err(RepoError::NotFound(anyhow!("no package {spec}")))
+        }
+
+        async fn retrieve_index(&self) -> RepoResult<Box<dyn AsyncBufRead + Send>> {
+            Err(RepoError::NotFound(anyhow!("no index")))
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_download_ends_progress() {
+        let temp_dir = TempDir::new().unwrap();
+        let progress = Arc::new(RecordingProgress::default());
+        let manager = ExternalPackageManager {
+            providers: Vec::new(),
+            cache: Some(LocalProvider::new(temp_dir.path().to_owned())),
+            repo: EmptyRepo,
+            offline: false,
+            repo_error: None,
+            progress: progress.clone(),
+            packages: OnceCell::default(),
+        };
+        let spec = PackageSpec::from_str("@preview/example:0.1.0").unwrap();
+
+        assert!(manager.package(&spec).await.is_err());
+        assert_eq!(
+            *progress.events.lock(),
+            vec![
+                "begin @preview/example:0.1.0".to_owned(),
+                "end @preview/example:0.1.0".to_owned(),
+            ]
+        );
+    }
 }
@@ -15,12 +15,16 @@ pub mod manager;
 pub mod remote_repo;
 
 /// Provides access to external packages
+#[async_trait]
 pub trait ExternalPackageProvider: fmt::Debug + Send + Sync {
     /// The package, if it is provided by this provider
     fn package(&self, spec: &PackageSpec) -> Option<Package>;
 
     /// The full ID of a file, if the file is provided by this provider
     fn full_id(&self, uri: &Url) -> Option<FullFileId>;
+
+    /// The packages this provider has, with their descriptions
+    async fn packages(&self) -> Vec<(PackageSpec, Option<EcoString>)>;
 }
 
 /// Provides access to package repositories. At present, this is only [https://packages.typst.org].
@@ -72,6 +76,21 @@ impl<R: RepoProvider> RepoProvider for Option<R> {
     }
 }
 
+/// Reports the progress of package downloads, for example to show it in the editor. Each
+/// [`begin`](Self::begin) is followed by exactly one [`end`](Self::end) for the same package, even
+/// if the download fails.
+pub trait DownloadProgress: fmt::Debug + Send + Sync {
+    fn begin(&self, spec: &PackageSpec);
+    fn report(&self, spec: &PackageSpec, message: &str);
+    fn end(&self, spec: &PackageSpec);
+}
+
+impl DownloadProgress for () {
+    fn begin(&self, _: &PackageSpec) {}
+    fn report(&self, _: &PackageSpec, _: &str) {}
+    fn end(&self, _: &PackageSpec) {}
+}
+
 #[async_trait]
 pub trait RepoRetrievalDest: fmt::Debug + Sync {
     async fn store_tar_gz(
@@ -79,15 +98,6 @@ pub trait RepoRetrievalDest: fmt::Debug + Sync {
         spec: &PackageSpec,
         package_tar_gz: impl AsyncBufRead + Unpin + Send,
     ) -> RepoResult<Package>;
-
-    async fn store_from<R: RepoProvider>(
-        &self,
-        repo: &R,
-        spec: &PackageSpec,
-    ) -> RepoResult<Package> {
-        let tar_gz = Box::into_pin(repo.retrieve_tar_gz(spec).await?);
-        self.store_tar_gz(spec, tar_gz).await
-    }
 }
 
 pub type RepoResult<T> = Result<T, RepoError>;
@@ -99,7 +109,7 @@ type NetworkError = std::convert::Infallible;
 
 #[derive(thiserror::Error, Debug)]
 pub enum RepoError {
-    #[error("cannot download packages in namespace `{0}`, only namespace `preview`")]
+    #[error("cannot download packages in namespace `{0}` from the package registry")]
     InvalidNamespace(EcoString),
     #[error("could not find package")]
     NotFound(#[source] anyhow::Error),
@@ -107,6 +117,8 @@ pub enum RepoError {
     Network(NetworkError),
     #[error("could not extract archive")]
     MalformedArchive(#[source] io::Error),
+    #[error("package manifest does not match the requested package: {0}")]
+    ManifestMismatch(EcoString),
     #[error("error writing to local filesystem")]
     LocalFs(#[source] io::Error),
 }
@@ -176,7 +188,7 @@ impl RepoError {
                 TypstPackageError::NotFound(spec.clone())
             }
             Self::Network(_) => TypstPackageError::NetworkFailed(Some(self.to_string().into())),
-            Self::MalformedArchive(_) => {
+            Self::MalformedArchive(_) | Self::ManifestMismatch(_) => {
                 TypstPackageError::MalformedArchive(Some(self.to_string().into()))
             }
             Self::LocalFs(_) => TypstPackageError::Other(Some(self.to_string().into())),
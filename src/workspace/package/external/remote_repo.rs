@@ -1,16 +1,19 @@
 use std::path::Path;
 use std::time::Duration;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use async_compression::tokio::bufread::GzipDecoder;
 use async_trait::async_trait;
 use futures::TryStreamExt;
-use reqwest::{Client, Url};
+use reqwest::{Client, Proxy, Url};
 use tokio::io::{AsyncBufRead, AsyncRead};
 use tokio_tar::Archive;
 use tokio_util::io::StreamReader;
+use typst::diag::EcoString;
 use typst::syntax::package::PackageSpec;
 
+use crate::config::PackageDownloadOptions;
+
 use super::{RepoError, RepoProvider, RepoResult};
 
 const TYPST_REPO_BASE_URL: &str = "https://packages.typst.org/";
@@ -20,6 +23,8 @@ const PREVIEW_NAMESPACE: &str = "preview";
 #[derive(Debug)]
 pub struct RemoteRepoProvider {
     base_url: Url,
+    /// Namespaces packages may be downloaded from
+    namespaces: Vec<EcoString>,
     client: Client,
 }
 
@@ -29,11 +34,7 @@ impl RepoProvider for RemoteRepoProvider {
         &self,
         spec: &PackageSpec,
     ) -> RepoResult<Box<dyn AsyncBufRead + Send>> {
-        // We don't know how packages will change once they leave preview, so restrict downloads to
-        // preview for now
-        if spec.namespace != PREVIEW_NAMESPACE {
-            return Err(RepoError::InvalidNamespace(spec.namespace.clone()));
-        }
+        self.check_namespace(spec)?;
 
         let url = self.url(spec);
         let downloaded = self.download_raw(url).await?;
@@ -49,26 +50,63 @@ impl RepoProvider for RemoteRepoProvider {
 }
 
 impl RemoteRepoProvider {
-    pub fn new() -> anyhow::Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .connect_timeout(Duration::from_secs(5))
+    pub fn new(options: &PackageDownloadOptions) -> anyhow::Result<Self> {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(options.timeout_secs))
+            .connect_timeout(Duration::from_secs(5));
+        // Without an explicit proxy, `reqwest` uses the system's proxy configuration
+        if let Some(proxy) = &options.proxy {
+            let proxy = Proxy::all(proxy).with_context(|| format!("invalid proxy `{proxy}`"))?;
+            builder = builder.proxy(proxy);
+        }
+        let client = builder
             .build()
             .context("couldn't read system configuration for HTTP client")?;
 
+        // We don't know how packages will change once they leave preview, so restrict downloads
+        // from the official registry to preview for now. Custom registries may host others.
+        let mut namespaces = vec![PREVIEW_NAMESPACE.into()];
+        let base_url = match &options.registry_url {
+            Some(registry_url) => {
+                namespaces.extend(options.registry_namespaces.iter().map(Into::into));
+                Self::parse_base_url(registry_url)?
+            }
+            None => Url::parse(TYPST_REPO_BASE_URL).unwrap(),
+        };
+
         Ok(Self {
-            base_url: Url::parse(TYPST_REPO_BASE_URL).unwrap(),
+            base_url,
+            namespaces,
             client,
         })
     }
 
-    #[tracing::instrument(skip(path), fields(path = %path.as_ref().display()))]
-    pub async fn download_to(&self, spec: &PackageSpec, path: impl AsRef<Path>) -> RepoResult<()> {
-        // We don't know how packages will change once they leave preview, so restrict downloads to
-        // preview for now
-        if spec.namespace != PREVIEW_NAMESPACE {
+    fn check_namespace(&self, spec: &PackageSpec) -> RepoResult<()> {
+        if !self.namespaces.contains(&spec.namespace) {
             return Err(RepoError::InvalidNamespace(spec.namespace.clone()));
         }
+        Ok(())
+    }
+
+    /// Parses an absolute base URL, adding a trailing `/` if needed so that joining paths onto it
+    /// keeps its last segment
+    fn parse_base_url(base_url: &str) -> anyhow::Result<Url> {
+        let mut base_url = Url::parse(base_url)
+            .with_context(|| format!("registry URL `{base_url}` is not an absolute URL"))?;
+        if base_url.cannot_be_a_base() {
+            bail!("registry URL `{base_url}` cannot be a base URL");
+        }
+
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+        Ok(base_url)
+    }
+
+    #[tracing::instrument(skip(path), fields(path = %path.as_ref().display()))]
+    pub async fn download_to(&self, spec: &PackageSpec, path: impl AsRef<Path>) -> RepoResult<()> {
+        self.check_namespace(spec)?;
 
         let url = self.url(spec);
         let downloaded = self.download_raw(url).await?;
@@ -117,14 +155,8 @@ impl RemoteRepoProvider {
 
 impl Default for RemoteRepoProvider {
     fn default() -> Self {
-        Self {
-            base_url: Url::parse(TYPST_REPO_BASE_URL).unwrap(),
-            client: Client::builder()
-                .timeout(Duration::from_secs(30))
-                .connect_timeout(Duration::from_secs(5))
-                .build()
-                .expect("couldn't read system configuration for HTTP client"),
-        }
+        Self::new(&PackageDownloadOptions::default())
+            .expect("couldn't read system configuration for HTTP client")
     }
 }
 
@@ -143,7 +175,7 @@ mod test {
 
         let spec = "@preview/example:0.1.0".parse().unwrap();
 
-        let provider = RemoteRepoProvider::new().unwrap();
+        let provider = RemoteRepoProvider::default();
         provider.download_to(&spec, target).await?;
 
         let all_exist = try_join_all(vec![
@@ -162,4 +194,49 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn custom_registry_url() {
+        let options = PackageDownloadOptions {
+            registry_url: Some("https://mirror.example.com/typst".to_owned()),
+            ..Default::default()
+        };
+        let provider = RemoteRepoProvider::new(&options).unwrap();
+        let spec = "@preview/example:0.1.0".parse().unwrap();
+
+        assert_eq!(
+            provider.url(&spec).as_str(),
+            "https://mirror.example.com/typst/preview/example-0.1.0.tar.gz"
+        );
+    }
+
+    #[test]
+    fn custom_registry_namespaces() {
+        let namespaces = vec!["internal".to_owned()];
+        let custom = RemoteRepoProvider::new(&PackageDownloadOptions {
+            registry_url: Some("https://mirror.example.com/".to_owned()),
+            registry_namespaces: namespaces.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let official = RemoteRepoProvider::new(&PackageDownloadOptions {
+            registry_namespaces: namespaces,
+            ..Default::default()
+        })
+        .unwrap();
+        let spec = "@internal/example:0.1.0".parse().unwrap();
+
+        assert!(custom.check_namespace(&spec).is_ok());
+        assert!(official.check_namespace(&spec).is_err());
+    }
+
+    #[test]
+    fn relative_registry_url_is_rejected() {
+        let options = PackageDownloadOptions {
+            registry_url: Some("mirror/typst".to_owned()),
+            ..Default::default()
+        };
+
+        assert!(RemoteRepoProvider::new(&options).is_err());
+    }
 }
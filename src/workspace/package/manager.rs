@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::iter;
 
 use anyhow::anyhow;
 use itertools::Itertools;
@@ -6,14 +7,15 @@ use tower_lsp::lsp_types::{Url, WorkspaceFoldersChangeEvent};
 use tracing::{error, info, trace, warn};
 use typst::diag::{EcoString, FileError, PackageError as TypstPackageError};
 use typst::syntax::package::PackageSpec;
-use typst::syntax::FileId;
+use typst::syntax::{FileId, VirtualPath};
 
 use crate::ext::{UriError, UrlExt};
+use crate::workspace::fs::local::LocalFs;
 use crate::workspace::fs::{FsError, FsResult};
 use crate::workspace::package::external::manager::ExternalPackageManager;
 
 use super::external::RepoError;
-use super::{FullFileId, Package, PackageId, PackageIdInner};
+use super::{in_memory_vpath, FullFileId, Package, PackageId, PackageIdInner};
 
 /// Determines canonical [`Package`]s and [`FileId`]s for URIs based on the current set of
 /// [`Package`]s. That is, it will associate to any given URI the same ID and project for the
@@ -26,9 +28,15 @@ use super::{FullFileId, Package, PackageId, PackageIdInner};
 /// Note also that taking just the ID may not uniquely identify a file. If there are multiple
 /// non-package projects, it is possible that two have a file with the same relative path, in which
 /// case their IDs will be identical.
+///
+/// Roots are matched with their symlinks resolved, so a file reached through a symlinked root and
+/// through the root it links to gets the same ID. The ID uses the root as the client sent it, so
+/// URIs given back to the client are in the form the client knows.
 #[derive(Debug)]
 pub struct PackageManager {
     current: HashMap<Url, Package>,
+    /// The packages in `current`, by the same root, but with symlinks in the root resolved
+    canonical: HashMap<Url, Package>,
     external: ExternalPackageManager,
 }
 
@@ -39,10 +47,15 @@ impl PackageManager {
             .into_iter()
             .map(|uri| (uri.clone(), Package::new(uri)))
             .collect();
+        let canonical = canonical_packages(&current);
 
         info!(?current, ?external, "initialized package manager");
 
-        Self { current, external }
+        Self {
+            current,
+            canonical,
+            external,
+        }
     }
 
     pub async fn package(&self, id: PackageId) -> PackageResult<Package> {
@@ -74,21 +87,42 @@ impl PackageManager {
             .full_id(uri)
             .or_else(|| self.current_full_id(uri))
             .or_else(|| self.current_single_file_full_id(uri))
+            .or_else(|| self.in_memory_full_id(uri))
             .ok_or_else(|| FsError::NotProvided(anyhow!("could not find provider for URI")))
     }
 
     fn current_full_id(&self, uri: &Url) -> Option<FullFileId> {
+        // The URI as given, and as reached through the canonical form of each root containing it.
+        // Only the roots are resolved, so symlinks inside a root are left alone.
+        let locations = iter::once(uri.clone())
+            .chain(self.current.iter().filter_map(|(root, package)| {
+                let path = package.uri_to_vpath(uri).ok()?;
+                self.canonical.get(root)?.vpath_to_uri(&path).ok()
+            }))
+            .collect_vec();
+
         let candidates = self
-            .current
+            .canonical
             .iter()
-            .filter_map(|(root, package)| Some((root, package.uri_to_vpath(uri).ok()?)))
+            .filter_map(|(root, canonical)| {
+                let path = locations
+                    .iter()
+                    .find_map(|location| canonical.uri_to_vpath(location).ok())?;
+                Some((root, path))
+            })
             .inspect(|(package_root, path)| trace!(%package_root, ?path, %uri, "considering candidate for full id"));
 
         // Our candidates are projects containing a URI, so we expect to get a set of
         // subdirectories. The "best" is the "most specific", that is, the project that is a
-        // subdirectory of the rest. This should have the longest length.
+        // subdirectory of the rest. This should have the longest length. A symlinked root and its
+        // target are equally specific, so the least root is taken, to always choose the same one.
         let (best_package_root, best_path) =
-            candidates.max_by_key(|(_, path)| path.as_rootless_path().components().count())?;
+            candidates.max_by(|(left_root, left), (right_root, right)| {
+                let depth = |path: &VirtualPath| path.as_rootless_path().components().count();
+                depth(left)
+                    .cmp(&depth(right))
+                    .then_with(|| right_root.cmp(left_root))
+            })?;
 
         let package_id = PackageId::new_current(best_package_root.clone());
         let full_file_id = FullFileId::new(package_id, best_path);
@@ -116,6 +150,16 @@ impl PackageManager {
         Some(full_file_id)
     }
 
+    /// Documents like `untitled:Untitled-1` have no parent directory, so they are their own package
+    fn in_memory_full_id(&self, uri: &Url) -> Option<FullFileId> {
+        if !uri.cannot_be_a_base() {
+            return None;
+        }
+
+        let package_id = PackageId::new_current(uri.clone());
+        Some(FullFileId::new(package_id, in_memory_vpath(uri)))
+    }
+
     #[tracing::instrument]
     pub fn handle_change_event(&mut self, event: &WorkspaceFoldersChangeEvent) {
         let removed = event.removed.iter().map(|folder| &folder.uri).collect_vec();
@@ -127,6 +171,7 @@ impl PackageManager {
 
         self.current.retain(|uri, _| !removed.contains(&uri));
         self.current.extend(added);
+        self.canonical = canonical_packages(&self.current);
 
         info!(current = ?self.current, "updated current packages");
     }
@@ -138,6 +183,31 @@ impl PackageManager {
     pub async fn packages(&self) -> &[(PackageSpec, Option<EcoString>)] {
         self.external.packages().await
     }
+
+    pub fn clear_downloads(&mut self) -> std::io::Result<()> {
+        self.external.clear_downloads()
+    }
+
+    pub fn download_error(&self) -> Option<&anyhow::Error> {
+        self.external.repo_error()
+    }
+}
+
+fn canonical_packages(current: &HashMap<Url, Package>) -> HashMap<Url, Package> {
+    current
+        .keys()
+        .map(|root| (root.clone(), Package::new(canonicalize(root))))
+        .collect()
+}
+
+/// Resolves symlinks in a local root. This touches the file system, so it is only done when the
+/// roots change, not for every lookup. Roots which can't be resolved are kept as they are.
+fn canonicalize(root: &Url) -> Url {
+    LocalFs::uri_to_path(root)
+        .ok()
+        .and_then(|path| path.canonicalize().ok())
+        .and_then(|path| LocalFs::path_to_uri(path).ok())
+        .unwrap_or_else(|| root.clone())
 }
 
 pub type PackageResult<T> = Result<T, PackageError>;
@@ -181,6 +251,8 @@ pub enum ExternalPackageError {
     Repo(#[from] RepoError),
     #[error("the path was invalid inside the package")]
     InvalidPath(#[from] UriError),
+    #[error("package {0} is not cached locally, and downloads are disabled in offline mode")]
+    Offline(PackageSpec),
     #[error(transparent)]
     Other(anyhow::Error),
 }
@@ -194,9 +266,77 @@ impl ExternalPackageError {
 
         match self {
             Self::Repo(err) => FileError::Package(err.convert(spec)),
+            Self::Offline(_) => {
+                FileError::Package(TypstPackageError::Other(Some(self.to_string().into())))
+            }
             Self::InvalidPath(_) | Self::Other(_) => {
                 FileError::Other(Some(self.to_string().into()))
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use crate::config::PackageDownloadOptions;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_root_gives_single_id() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.child("target");
+        let link = temp_dir.child("link");
+        std::fs::create_dir(&target).unwrap();
+        std::fs::write(target.join("main.typ"), "Hello").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let target_uri = LocalFs::path_to_uri(&target).unwrap();
+        let link_uri = LocalFs::path_to_uri(&link).unwrap();
+        let manager = PackageManager::new(
+            vec![target_uri.clone(), link_uri.clone()],
+            ExternalPackageManager::new(&PackageDownloadOptions::default(), &[]),
+        );
+
+        let through_target = manager
+            .full_id(&LocalFs::path_to_uri(target.join("main.typ")).unwrap())
+            .unwrap();
+        let through_link = manager
+            .full_id(&LocalFs::path_to_uri(link.join("main.typ")).unwrap())
+            .unwrap();
+
+        assert_eq!(through_target, through_link);
+        assert_eq!(
+            through_target.package(),
+            PackageId::new_current(link_uri.min(target_uri))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_subdirectory_stays_in_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.child("project");
+        let shared = temp_dir.child("shared");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::write(shared.join("template.typ"), "Hello").unwrap();
+        std::os::unix::fs::symlink(&shared, root.join("template")).unwrap();
+
+        let root_uri = LocalFs::path_to_uri(&root).unwrap();
+        let manager = PackageManager::new(
+            vec![root_uri.clone()],
+            ExternalPackageManager::new(&PackageDownloadOptions::default(), &[]),
+        );
+
+        let full_id = manager
+            .full_id(&LocalFs::path_to_uri(root.join("template/template.typ")).unwrap())
+            .unwrap();
+
+        assert_eq!(full_id.package(), PackageId::new_current(root_uri));
+        assert_eq!(full_id.vpath(), &VirtualPath::new("template/template.typ"));
+    }
+}
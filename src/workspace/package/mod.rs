@@ -6,7 +6,7 @@ use tower_lsp::lsp_types::Url;
 use typst::syntax::package::PackageSpec;
 use typst::syntax::{FileId, VirtualPath};
 
-use crate::ext::{UriResult, UrlExt, VirtualPathExt};
+use crate::ext::{UriError, UriResult, UrlExt, VirtualPathExt};
 
 pub mod external;
 pub mod manager;
@@ -29,14 +29,35 @@ impl Package {
 
     /// Converts a path in the package to a URI
     pub fn vpath_to_uri(&self, vpath: &VirtualPath) -> UriResult<Url> {
+        if self.root.cannot_be_a_base() {
+            return match *vpath == in_memory_vpath(&self.root) {
+                true => Ok(self.root.clone()),
+                false => Err(UriError::PathEscapesRoot),
+            };
+        }
+
         self.root.clone().join_rooted(vpath)
     }
 
     pub fn uri_to_vpath(&self, uri: &Url) -> UriResult<VirtualPath> {
+        if self.root.cannot_be_a_base() {
+            return match *uri == self.root {
+                true => Ok(in_memory_vpath(uri)),
+                false => Err(UriError::PathEscapesRoot),
+            };
+        }
+
         self.root.make_relative_rooted(uri)
     }
 }
 
+/// The path of a document which only exists in the editor, like `untitled:Untitled-1`. Such a URI
+/// has no directory to contain other files, so the document is a package by itself, with the URI
+/// as its root.
+pub fn in_memory_vpath(uri: &Url) -> VirtualPath {
+    VirtualPath::new(uri.path())
+}
+
 impl fmt::Debug for Package {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Package")
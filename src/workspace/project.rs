@@ -7,7 +7,7 @@ use tower_lsp::lsp_types::Url;
 use typst::diag::EcoString;
 use typst::foundations::Bytes;
 use typst::syntax::package::PackageSpec;
-use typst::syntax::{FileId, Source};
+use typst::syntax::{ast, FileId, Source, SyntaxKind};
 use typst::text::{Font, FontBook};
 use typst::Library;
 
@@ -32,6 +32,11 @@ impl Project {
         }
     }
 
+    /// The package this project is in
+    pub fn package_id(&self) -> PackageId {
+        self.current
+    }
+
     fn workspace(&self) -> &Workspace {
         &self.workspace
     }
@@ -82,6 +87,18 @@ impl Project {
         let full_id = self.fill_id(id);
         let uri = self.full_id_to_uri(full_id).await?;
         let source = self.read_source_by_uri(&uri)?;
+
+        // A file in a nested workspace folder is identified relative to that folder. When it is
+        // reached from this project, like when included by a pinned main file, its spans must use
+        // this project's ID, or diagnostics in it would resolve against the wrong root.
+        if source.id() != id {
+            return Ok(Source::new(id, source.text().to_owned()));
+        }
+
+        if is_script(id) {
+            return Ok(Source::new(id, script_as_markup(source.text())));
+        }
+
         Ok(source)
     }
 
@@ -93,6 +110,45 @@ impl Project {
     }
 }
 
+/// Whether the file is a `.typc` script, which is written in code mode
+fn is_script(id: FileId) -> bool {
+    id.vpath()
+        .as_rootless_path()
+        .extension()
+        .is_some_and(|ext| ext == "typc")
+}
+
+/// Typst only evaluates markup modules, so a script is given to it as markup, with each top-level
+/// statement embedded with `#`. Expressions which can't be embedded as they are, like `x + 1`, are
+/// put in parentheses. Lines stay the same, so only the columns of diagnostics may shift.
+fn script_as_markup(text: &str) -> String {
+    let root = typst::syntax::parse_code(text);
+    let mut markup = String::with_capacity(text.len());
+    let mut offset = 0;
+    for child in root.children() {
+        let child_text = &text[offset..offset + child.len()];
+        offset += child.len();
+
+        if child.cast::<ast::Expr>().is_none() {
+            markup.push_str(child_text);
+        } else if matches!(
+            child.kind(),
+            SyntaxKind::Unary
+                | SyntaxKind::Binary
+                | SyntaxKind::Closure
+                | SyntaxKind::DestructAssignment
+        ) {
+            markup.push_str("#(");
+            markup.push_str(child_text);
+            markup.push(')');
+        } else {
+            markup.push('#');
+            markup.push_str(child_text);
+        }
+    }
+    markup
+}
+
 impl fmt::Debug for Project {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Project")
@@ -118,3 +174,19 @@ impl From<UriToFsPathError> for UriToIdError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn script_statements_are_embedded() {
+        let script =
+            "let double(n) = n * 2\n// Doubled\nlet x = double(1); import \"a.typ\"\nx + 1";
+
+        assert_eq!(
+            script_as_markup(script),
+            "#let double(n) = n * 2\n// Doubled\n#let x = double(1); #import \"a.typ\"\n#(x + 1)"
+        );
+    }
+}
@@ -1,14 +1,19 @@
+use std::path::PathBuf;
+
 use comemo::Prehashed;
 use futures::Future;
+use indexmap::IndexSet;
+use itertools::Itertools;
 use tokio::runtime;
 use tower_lsp::lsp_types::Url;
-use typst::diag::{EcoString, FileResult};
+use typst::diag::{EcoString, FileError, FileResult};
 use typst::foundations::{Bytes, Datetime};
 use typst::syntax::package::PackageSpec;
 use typst::syntax::{FileId, Source};
 use typst::text::{Font, FontBook};
 use typst::{Library, World};
 
+use crate::workspace::fs::local::LocalFs;
 use crate::workspace::fs::{FsError, FsResult};
 use crate::workspace::project::Project;
 
@@ -28,6 +33,8 @@ pub struct ProjectWorld {
     /// Current time. Will be cached lazily for consistency throughout a compilation.
     now: Now,
     handle: runtime::Handle,
+    /// Files successfully read by Typst through this world, in the order they were first read
+    dependencies: parking_lot::Mutex<IndexSet<FileId>>,
 }
 
 impl ProjectWorld {
@@ -37,7 +44,25 @@ impl ProjectWorld {
             main,
             now: Now::new(),
             handle,
+            dependencies: Default::default(),
+        }
+    }
+
+    /// Files Typst has read through this world so far, starting with the main file. After
+    /// compiling, these are the files the document depends on.
+    pub fn dependencies(&self) -> Vec<FileId> {
+        let dependencies = self.dependencies.lock();
+        std::iter::once(self.main.id())
+            .chain(dependencies.iter().copied())
+            .unique()
+            .collect()
+    }
+
+    fn record_dependency<T>(&self, id: FileId, result: FileResult<T>) -> FileResult<T> {
+        if result.is_ok() {
+            self.dependencies.lock().insert(id);
         }
+        result
     }
 
     pub fn now(&self) -> Option<Datetime> {
@@ -58,6 +83,26 @@ impl ProjectWorld {
         self.project.write_raw(uri, data)
     }
 
+    /// Replaces the path in a "file not found" error with the location we actually tried to read,
+    /// so users see e.g. `/abs/path/logo.png` instead of just `logo.png`. If the location cannot
+    /// be resolved, the error is returned unchanged.
+    fn with_resolved_path(&self, id: FileId, err: FileError) -> FileError {
+        let FileError::NotFound(path) = err else {
+            return err;
+        };
+        if path.is_absolute() {
+            return FileError::NotFound(path);
+        }
+
+        let full_id = self.project.fill_id(id);
+        let resolved = self
+            .block(self.project.full_id_to_uri(full_id))
+            .ok()
+            .map(|uri| LocalFs::uri_to_path(&uri).unwrap_or_else(|_| PathBuf::from(uri.as_str())));
+
+        FileError::NotFound(resolved.unwrap_or(path))
+    }
+
     /// Runs a `Future` in a non-async function, blocking until completion
     ///
     /// `comemo` doesn't support async, so Typst can't, so we're stuck with this for now to run
@@ -85,14 +130,20 @@ impl World for ProjectWorld {
 
     #[tracing::instrument]
     fn source(&self, id: FileId) -> FileResult<Source> {
-        self.block(self.project.read_source_by_id(id))
+        let result = self
+            .block(self.project.read_source_by_id(id))
             .map_err(|err: FsError| err.report_and_convert(id))
+            .map_err(|err| self.with_resolved_path(id, err));
+        self.record_dependency(id, result)
     }
 
     #[tracing::instrument]
     fn file(&self, id: FileId) -> FileResult<Bytes> {
-        self.block(self.project.read_bytes_by_id(id))
+        let result = self
+            .block(self.project.read_bytes_by_id(id))
             .map_err(|err: FsError| err.report_and_convert(id))
+            .map_err(|err| self.with_resolved_path(id, err));
+        self.record_dependency(id, result)
     }
 
     #[tracing::instrument]
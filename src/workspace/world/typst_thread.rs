@@ -1,9 +1,12 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
 use std::sync::mpsc;
 use std::thread;
 
 use tokio::runtime;
 use tokio::sync::oneshot;
-use tracing::{trace, warn};
+use tracing::{info_span, trace, warn, Span};
 use typst::syntax::Source;
 
 use crate::workspace::project::Project;
@@ -12,30 +15,65 @@ use super::ProjectWorld;
 
 pub type Task = Box<dyn FnOnce(runtime::Handle) + Send + 'static>;
 
+/// The span each piece of work runs in on the Typst thread. Its parent is the span of the caller,
+/// so the time spent on the thread can be attributed to the operation which asked for it.
+pub const TASK_SPAN: &str = "typst_task";
+
+/// The most threads Typst work is spread over, even on machines with more cores. Compiling a
+/// single document already uses more threads for layout, so this only needs to cover the few
+/// projects likely to be open at once.
+///
+/// Typst's memoization cache is shared by all workers, and each run on any of them ages it. The
+/// configured eviction age counts runs of one project, so it is multiplied by the number of
+/// workers; see [`worker_count`](TypstThread::worker_count).
+const MAX_WORKERS: usize = 4;
+
+/// A small pool of threads running Typst work, so compiling one project doesn't wait for
+/// another. Work for the same project always runs on the same thread, so it is never compiled
+/// twice at once and the memoized results from its last compilation stay warm.
 pub struct TypstThread {
-    sender: parking_lot::Mutex<mpsc::Sender<Request>>,
+    workers: Vec<parking_lot::Mutex<mpsc::Sender<Request>>>,
 }
 
 impl Default for TypstThread {
     fn default() -> Self {
+        let workers = thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(MAX_WORKERS);
+        Self::with_workers(workers)
+    }
+}
+
+impl TypstThread {
+    pub fn with_workers(count: usize) -> Self {
         let handle = runtime::Handle::current();
-        let (sender, receiver) = mpsc::channel::<Request>();
 
-        thread::spawn(move || {
-            while let Ok(request) = receiver.recv() {
-                trace!("got new request on Typst thread");
-                request.run(handle.clone());
-                trace!("completed request on Typst thread");
-            }
-        });
+        let workers = (0..count.max(1))
+            .map(|index| {
+                let (sender, receiver) = mpsc::channel::<Request>();
+                let handle = handle.clone();
 
-        Self {
-            sender: parking_lot::Mutex::new(sender),
-        }
+                thread::spawn(move || {
+                    while let Ok(request) = receiver.recv() {
+                        trace!(index, "got new request on Typst thread");
+                        request.run(handle.clone());
+                        trace!(index, "completed request on Typst thread");
+                    }
+                });
+
+                parking_lot::Mutex::new(sender)
+            })
+            .collect();
+
+        Self { workers }
+    }
+
+    /// How many threads run Typst work. Cache eviction ages are scaled by this, so one project's
+    /// runs don't evict another's memoized results sooner than configured.
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
     }
-}
 
-impl TypstThread {
     #[tracing::instrument(skip(self, f))]
     pub async fn run_with_world<Ret: Send + 'static>(
         &self,
@@ -43,22 +81,49 @@ impl TypstThread {
         world_main: Source,
         f: impl FnOnce(ProjectWorld) -> Ret + Send + 'static,
     ) -> Ret {
+        let package = world_project.package_id();
         let f_prime = move |handle| {
             let world = ProjectWorld::new(world_project, world_main, handle);
             f(world)
         };
 
-        self.run(f_prime).await
+        self.run_for(package, f_prime).await
     }
 
+    /// Runs work which doesn't belong to any project
     #[tracing::instrument(skip_all)]
     pub async fn run<Ret: Send + 'static>(
         &self,
         f: impl FnOnce(runtime::Handle) -> Ret + Send + 'static,
+    ) -> Ret {
+        self.run_on_worker(0, f).await
+    }
+
+    /// Runs work on the thread for `key`, which is the same for equal keys
+    #[tracing::instrument(skip_all)]
+    pub async fn run_for<Ret: Send + 'static>(
+        &self,
+        key: impl Hash,
+        f: impl FnOnce(runtime::Handle) -> Ret + Send + 'static,
+    ) -> Ret {
+        self.run_on_worker(self.worker_for(key), f).await
+    }
+
+    fn worker_for(&self, key: impl Hash) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.workers.len() as u64) as usize
+    }
+
+    async fn run_on_worker<Ret: Send + 'static>(
+        &self,
+        worker: usize,
+        f: impl FnOnce(runtime::Handle) -> Ret + Send + 'static,
     ) -> Ret {
         let (sender, receiver) = oneshot::channel();
+        let caller = Span::current();
         let f_prime = move |handle| {
-            let t = f(handle);
+            let t = info_span!(parent: &caller, TASK_SPAN).in_scope(|| f(handle));
             if sender.send(t).is_err() {
                 // Receiver was dropped. The main thread may have exited, or the request may have
                 // been cancelled.
@@ -66,14 +131,14 @@ impl TypstThread {
             }
         };
 
-        self.send_request(Request::new(f_prime));
+        self.send_request(worker, Request::new(f_prime));
 
         receiver.await.unwrap()
     }
 
     #[tracing::instrument(skip_all)]
-    fn send_request(&self, request: Request) {
-        let sender = self.sender.lock();
+    fn send_request(&self, worker: usize, request: Request) {
+        let sender = self.workers[worker].lock();
         sender.send(request).unwrap();
     }
 }
@@ -91,3 +156,46 @@ impl Request {
         (self.task)(handle);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Barrier};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn independent_work_runs_concurrently() {
+        let pool = TypstThread::with_workers(2);
+        let first = 0;
+        let second = (1..)
+            .find(|&key| pool.worker_for(key) != pool.worker_for(first))
+            .unwrap();
+
+        // Each task waits for the other to start, which only finishes if they run at once
+        let barrier = Arc::new(Barrier::new(2));
+        let wait = |barrier: Arc<Barrier>| {
+            move |_: runtime::Handle| {
+                barrier.wait();
+            }
+        };
+        let both = async {
+            tokio::join!(
+                pool.run_for(first, wait(Arc::clone(&barrier))),
+                pool.run_for(second, wait(Arc::clone(&barrier))),
+            )
+        };
+
+        tokio::time::timeout(Duration::from_secs(10), both)
+            .await
+            .expect("work for different keys should not be serialized");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn same_key_runs_on_same_thread() {
+        let pool = TypstThread::with_workers(4);
+        let thread_id = || pool.run_for("project", |_| thread::current().id());
+
+        assert_eq!(thread_id().await, thread_id().await);
+    }
+}